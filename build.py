@@ -122,6 +122,11 @@ def make_parser():
         action='store_true',
         help='Enable feature gpucodec, only available on windows now.'
     )
+    parser.add_argument(
+        '--openh264',
+        action='store_true',
+        help='Enable feature openh264, software H.264 without hwcodec'
+    )
     parser.add_argument(
         '--portable',
         action='store_true',
@@ -132,6 +137,11 @@ def make_parser():
         action='store_true',
         help='Build with unix file copy paste feature'
     )
+    parser.add_argument(
+        '--pipewire-audio',
+        action='store_true',
+        help='Build with native PipeWire audio capture on Linux'
+    )
     parser.add_argument(
         '--flatpak',
         action='store_true',
@@ -284,6 +294,8 @@ def get_features(args):
         features.append('hwcodec')
     if args.gpucodec:
         features.append('gpucodec')
+    if args.openh264:
+        features.append('openh264')
     if args.flutter:
         features.append('flutter')
         features.append('flutter_texture_render')
@@ -293,6 +305,8 @@ def get_features(args):
         features.append('appimage')
     if args.unix_file_copy_paste:
         features.append('unix-file-copy-paste')
+    if args.pipewire_audio:
+        features.append('pipewire-audio')
     print("features:", features)
     return features
 
@@ -306,7 +320,8 @@ Version: %s
 Architecture: %s
 Maintainer: rustdesk <info@rustdesk.com>
 Homepage: https://rustdesk.com
-Depends: libgtk-3-0, libxcb-randr0, libxdo3, libxfixes3, libxcb-shape0, libxcb-xfixes0, libasound2, libsystemd0, curl, libva-drm2, libva-x11-2, libvdpau1, libgstreamer-plugins-base1.0-0, libpam0g, libappindicator3-1, gstreamer1.0-pipewire
+Depends: libgtk-3-0, libxcb-randr0, libxcb-damage0, libxdo3, libxfixes3, libxcb-shape0, libxcb-xfixes0, libasound2, libsystemd0, curl, libva-drm2, libva-x11-2, libvdpau1, libgstreamer-plugins-base1.0-0, libpam0g, libappindicator3-1, gstreamer1.0-pipewire
+Recommends: xclip, wl-clipboard
 Description: A remote control software.
 
 """ % (version, get_arch())
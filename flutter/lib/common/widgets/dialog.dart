@@ -1488,6 +1488,59 @@ showAuditDialog(FFI ffi) async {
   });
 }
 
+showClipboardHistoryDialog(FFI ffi) async {
+  final res = await bind.sessionGetClipboardHistory(sessionId: ffi.sessionId);
+  List<dynamic> entries = [];
+  try {
+    entries = jsonDecode(res);
+  } catch (e) {
+    debugPrint('Failed to decode the clipboard history: $e');
+  }
+  ffi.dialogManager.show((setState, close, context) {
+    return CustomAlertDialog(
+      title: Text(translate('Clipboard history')),
+      content: SizedBox(
+        width: 400,
+        height: 300,
+        child: entries.isEmpty
+            ? Center(child: Text(translate('Empty')))
+            : ListView.builder(
+                itemCount: entries.length,
+                itemBuilder: (context, index) {
+                  final entry = entries[index];
+                  final time = DateTime.fromMillisecondsSinceEpoch(
+                      entry['time'] as int);
+                  return ListTile(
+                    leading: Icon(entry['sent'] == true
+                        ? Icons.upload_rounded
+                        : Icons.download_rounded),
+                    title: Text(entry['text'] as String,
+                        maxLines: 2, overflow: TextOverflow.ellipsis),
+                    subtitle: Text(
+                        '${time.hour.toString().padLeft(2, '0')}:${time.minute.toString().padLeft(2, '0')}'),
+                    trailing: Tooltip(
+                      message: translate('Send to the remote clipboard'),
+                      child: IconButton(
+                        icon: Icon(Icons.send_rounded),
+                        onPressed: () {
+                          bind.sessionPushClipboardHistory(
+                              sessionId: ffi.sessionId, index: index);
+                          close();
+                        },
+                      ),
+                    ),
+                  );
+                },
+              ),
+      ),
+      actions: [
+        dialogButton('Close', onPressed: close, isOutline: true),
+      ],
+      onCancel: close,
+    );
+  });
+}
+
 void showConfirmSwitchSidesDialog(
     SessionID sessionId, String id, OverlayDialogManager dialogManager) async {
   dialogManager.show((setState, close, context) {
@@ -1909,3 +1962,102 @@ void showWindowsSessionsDialog(
     );
   });
 }
+
+void showShareWindowDialog(Map<String, dynamic> evt, SessionID sessionId,
+    OverlayDialogManager dialogManager) {
+  final display = int.tryParse(evt['display'] ?? '') ?? 0;
+  // Wayland, the window is picked in the screencast dialog of the peer.
+  final pickOnPeer = evt['pick_on_peer'] == 'true';
+  List<dynamic> windows = [];
+  try {
+    windows = json.decode(evt['windows']);
+  } catch (e) {
+    print(e);
+  }
+  dialogManager.show((setState, close, context) {
+    select(int window) {
+      bind.sessionSwitchWindow(
+          sessionId: sessionId, display: display, window: window);
+      close();
+    }
+
+    Widget content;
+    if (pickOnPeer) {
+      content = Text(translate('share_window_pick_on_peer_tip'));
+    } else if (windows.isEmpty) {
+      content = Text(translate('No windows to share'));
+    } else {
+      content = ConstrainedBox(
+        constraints: BoxConstraints(maxHeight: 400, minWidth: 400),
+        child: SingleChildScrollView(
+          child: Column(
+            children: windows
+                .map((w) => ListTile(
+                      title: Text(w['title'] ?? ''),
+                      subtitle: Text(
+                          '${w['app'] ?? ''} ${w['width']}x${w['height']}'),
+                      onTap: () => select(int.tryParse(w['id'] ?? '') ?? 0),
+                    ))
+                .toList(),
+          ),
+        ),
+      );
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Share a window')),
+      content: content,
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        if (pickOnPeer) dialogButton('OK', onPressed: () => select(1)),
+      ],
+      onCancel: close,
+    );
+  });
+}
+
+void showAudioSourceDialog(Map<String, dynamic> evt, SessionID sessionId,
+    OverlayDialogManager dialogManager) {
+  List<dynamic> applications = [];
+  try {
+    applications = json.decode(evt['applications']);
+  } catch (e) {
+    print(e);
+  }
+  dialogManager.show((setState, close, context) {
+    select(String application) {
+      bind.sessionSetAudioSource(
+          sessionId: sessionId, application: application);
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Audio source')),
+      content: ConstrainedBox(
+        constraints: BoxConstraints(maxHeight: 400, minWidth: 400),
+        child: SingleChildScrollView(
+          child: Column(
+            children: [
+              ListTile(
+                title: Text(translate('Whole system audio')),
+                onTap: () => select(''),
+              ),
+              if (applications.isEmpty)
+                ListTile(
+                    title: Text(translate('No applications playing audio'))),
+              ...applications.map((a) => ListTile(
+                    title: Text(a['name'] ?? ''),
+                    subtitle: Text('pid ${a['pid'] ?? ''}'),
+                    onTap: () => select(a['id'] ?? ''),
+                  )),
+            ],
+          ),
+        ),
+      ),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+      ],
+      onCancel: close,
+    );
+  });
+}
@@ -1,5 +1,6 @@
 import 'dart:io';
 
+import 'package:file_picker/file_picker.dart';
 import 'package:flutter/material.dart';
 import 'package:flutter/services.dart';
 import 'package:flutter_hbb/common/widgets/dialog.dart';
@@ -563,6 +564,34 @@ abstract class BasePeerCard extends StatelessWidget {
     );
   }
 
+  @protected
+  MenuEntryBase<String> _screenshotAction(String id) {
+    return MenuEntryButton<String>(
+      childBuilder: (TextStyle? style) => Text(
+        translate('Take screenshot'),
+        style: style,
+      ),
+      proc: () async {
+        final path = await FilePicker.platform.saveFile(
+            dialogTitle: translate('Take screenshot'),
+            fileName: '$id.png',
+            type: FileType.custom,
+            allowedExtensions: ['png']);
+        if (path == null) return;
+        // Uses the remembered password of the peer.
+        bind.mainTakeScreenshot(id: id, password: '', display: 0, path: path);
+        var status = await bind.mainGetAsyncStatus();
+        while (status == ' ') {
+          await Future.delayed(const Duration(milliseconds: 100));
+          status = await bind.mainGetAsyncStatus();
+        }
+        showToast(translate(status.isEmpty ? 'Successful' : status));
+      },
+      padding: menuPadding,
+      dismissOnClicked: true,
+    );
+  }
+
   /// Only available on Windows.
   @protected
   MenuEntryBase<String> _createShortCutAction(String id) {
@@ -866,6 +895,7 @@ class RecentPeerCard extends BasePeerCard {
 
     if (isDesktop && peer.platform != kPeerPlatformAndroid) {
       menuItems.add(_tcpTunnelingAction(context, peer.id));
+      menuItems.add(_screenshotAction(peer.id));
     }
     // menuItems.add(await _openNewConnInOptAction(peer.id));
     menuItems.add(await _forceAlwaysRelayAction(peer.id));
@@ -920,6 +950,7 @@ class FavoritePeerCard extends BasePeerCard {
     ];
     if (isDesktop && peer.platform != kPeerPlatformAndroid) {
       menuItems.add(_tcpTunnelingAction(context, peer.id));
+      menuItems.add(_screenshotAction(peer.id));
     }
     // menuItems.add(await _openNewConnInOptAction(peer.id));
     menuItems.add(await _forceAlwaysRelayAction(peer.id));
@@ -974,6 +1005,7 @@ class DiscoveredPeerCard extends BasePeerCard {
 
     if (isDesktop && peer.platform != kPeerPlatformAndroid) {
       menuItems.add(_tcpTunnelingAction(context, peer.id));
+      menuItems.add(_screenshotAction(peer.id));
     }
     // menuItems.add(await _openNewConnInOptAction(peer.id));
     menuItems.add(await _forceAlwaysRelayAction(peer.id));
@@ -1024,6 +1056,7 @@ class AddressBookPeerCard extends BasePeerCard {
     ];
     if (isDesktop && peer.platform != kPeerPlatformAndroid) {
       menuItems.add(_tcpTunnelingAction(context, peer.id));
+      menuItems.add(_screenshotAction(peer.id));
     }
     // menuItems.add(await _openNewConnInOptAction(peer.id));
     menuItems.add(await _forceAlwaysRelayAction(peer.id));
@@ -1092,6 +1125,7 @@ class MyGroupPeerCard extends BasePeerCard {
     ];
     if (isDesktop && peer.platform != kPeerPlatformAndroid) {
       menuItems.add(_tcpTunnelingAction(context, peer.id));
+      menuItems.add(_screenshotAction(peer.id));
     }
     // menuItems.add(await _openNewConnInOptAction(peer.id));
     // menuItems.add(await _forceAlwaysRelayAction(peer.id));
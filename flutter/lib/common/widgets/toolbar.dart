@@ -150,6 +150,23 @@ List<TTextMenu> toolbarControls(BuildContext context, String id, FFI ffi) {
           onPressed: () => connect(context, id, isTcpTunneling: true)),
     );
   }
+  // audio source
+  if (perms['audio'] != false && pi.platform == kPeerPlatformLinux) {
+    v.add(
+      TTextMenu(
+          child: Text(translate('Audio source')),
+          onPressed: () =>
+              bind.sessionGetAudioApplications(sessionId: sessionId)),
+    );
+  }
+  // clipboard history
+  if (ffiModel.keyboard && perms['clipboard'] != false) {
+    v.add(
+      TTextMenu(
+          child: Text(translate('Clipboard history')),
+          onPressed: () => showClipboardHistoryDialog(ffi)),
+    );
+  }
   // note
   if (bind
       .sessionGetAuditServerSync(sessionId: sessionId, typ: "conn")
@@ -372,6 +389,34 @@ Future<List<TRadioMenu<String>>> toolbarCodec(
   ];
 }
 
+Future<List<TRadioMenu<String>>> toolbarAudioQuality(
+    BuildContext context, String id, FFI ffi) async {
+  final sessionId = ffi.sessionId;
+  if (ffi.ffiModel.permissions['audio'] == false) return [];
+  var groupValue =
+      await bind.sessionGetOption(sessionId: sessionId, arg: 'audio-quality') ??
+          '';
+  if (groupValue.isEmpty) groupValue = 'balanced';
+  onChanged(String? value) async {
+    if (value == null) return;
+    await bind.sessionSetAudioQuality(sessionId: sessionId, value: value);
+  }
+
+  TRadioMenu<String> radio(String label, String value) {
+    return TRadioMenu<String>(
+        child: Text(translate(label)),
+        value: value,
+        groupValue: groupValue,
+        onChanged: onChanged);
+  }
+
+  return [
+    radio('Best', 'best'),
+    radio('Balanced', 'balanced'),
+    radio('Low bandwidth', 'low'),
+  ];
+}
+
 Future<List<TToggleMenu>> toolbarDisplayToggle(
     BuildContext context, String id, FFI ffi) async {
   List<TToggleMenu> v = [];
@@ -441,6 +486,32 @@ Future<List<TToggleMenu>> toolbarDisplayToggle(
         },
         child: Text(translate('Mute'))));
   }
+  // delay the video to the audio
+  if (perms['audio'] != false) {
+    final option = 'sync-audio-video';
+    final value =
+        bind.sessionGetToggleOptionSync(sessionId: sessionId, arg: option);
+    v.add(TToggleMenu(
+        value: value,
+        onChanged: (value) {
+          if (value == null) return;
+          bind.sessionToggleOption(sessionId: sessionId, value: option);
+        },
+        child: Text(translate('Sync video with audio'))));
+  }
+  // voice call to a virtual microphone, taken by the next voice call
+  if (perms['audio'] != false && pi.platform == kPeerPlatformLinux) {
+    final option = 'voice-call-virtual-microphone';
+    final value =
+        bind.sessionGetToggleOptionSync(sessionId: sessionId, arg: option);
+    v.add(TToggleMenu(
+        value: value,
+        onChanged: (value) {
+          if (value == null) return;
+          bind.sessionToggleOption(sessionId: sessionId, value: option);
+        },
+        child: Text(translate('Use my voice as the remote microphone'))));
+  }
   // file copy and paste
   if (ffiModel.keyboard &&
       perms['file'] != false &&
@@ -477,6 +548,19 @@ Future<List<TToggleMenu>> toolbarDisplayToggle(
             : null,
         child: Text(translate('Disable clipboard'))));
   }
+  // keep the clipboard history after the session ends
+  if (ffiModel.keyboard && perms['clipboard'] != false) {
+    final option = 'keep-clipboard-history';
+    final value =
+        bind.sessionGetToggleOptionSync(sessionId: sessionId, arg: option);
+    v.add(TToggleMenu(
+        value: value,
+        onChanged: (value) {
+          if (value == null) return;
+          bind.sessionToggleOption(sessionId: sessionId, value: option);
+        },
+        child: Text(translate('Keep clipboard history'))));
+  }
   // lock after session end
   if (ffiModel.keyboard) {
     final enabled = !ffiModel.viewOnly;
@@ -544,6 +628,20 @@ Future<List<TToggleMenu>> toolbarDisplayToggle(
         child: Text(translate('True color (4:4:4)'))));
   }
 
+  // Screen content, ignored by older peers. AV1 is always tuned for it.
+  if (codec_format == "VP8" || codec_format == "VP9") {
+    final option = 'screen-content';
+    final value =
+        bind.sessionGetToggleOptionSync(sessionId: sessionId, arg: option);
+    v.add(TToggleMenu(
+        value: value,
+        onChanged: (value) async {
+          if (value == null) return;
+          await bind.sessionToggleOption(sessionId: sessionId, value: option);
+        },
+        child: Text(translate('Sharp text (screen content)'))));
+  }
+
   if (isMobile) {
     v.addAll(toolbarKeyboardToggles(ffi));
   }
@@ -354,6 +354,12 @@ class _GeneralState extends State<_General> {
       children.add(_OptionCheckBox(
           context, 'Allow linux headless', 'allow-linux-headless'));
     }
+    if (bind.mainShowOption(key: 'allow-linux-headless-virtual-display')) {
+      children.add(_OptionCheckBox(
+          context,
+          'Allow virtual displays in linux headless',
+          'allow-linux-headless-virtual-display'));
+    }
     return _Card(title: 'Other', children: children);
   }
 
@@ -476,6 +482,14 @@ class _GeneralState extends State<_General> {
       return _Card(title: 'Recording', children: [
         _OptionCheckBox(context, 'Automatically record incoming sessions',
             'allow-auto-record-incoming'),
+        _OptionCheckBox(
+            context,
+            'Automatically record outgoing sessions for replay',
+            'allow-auto-record-replay'),
+        _OptionCheckBox(
+            context,
+            'Record the typed text and clipboard for replay',
+            'record-outgoing-text'),
         Row(
           children: [
             Text('${translate("Directory")}:'),
@@ -705,6 +719,9 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
             _OptionCheckBox(
                 context, 'Enable recording session', 'enable-record-session',
                 enabled: enabled, fakeValue: fakeValue),
+            // Screen share mode allows screenshots too.
+            _OptionCheckBox(context, 'Enable screenshot', 'enable-screenshot',
+                enabled: enabled, fakeValue: fakeValue == null ? null : true),
             if (Platform.isWindows)
               _OptionCheckBox(
                   context, 'Enable blocking user input', 'enable-block-input',
@@ -492,6 +492,7 @@ class _RemoteToolbarState extends State<RemoteToolbar> {
       toolbarItems.add(_ChatMenu(id: widget.id, ffi: widget.ffi));
       toolbarItems.add(_VoiceCallMenu(id: widget.id, ffi: widget.ffi));
     }
+    toolbarItems.add(_ServerRecordIndicator());
     toolbarItems.add(_RecordMenu());
     toolbarItems.add(_CloseMenu(id: widget.id, ffi: widget.ffi));
     final toolbarBorderRadius = BorderRadius.all(Radius.circular(4.0));
@@ -1046,11 +1047,13 @@ class _DisplayMenuState extends State<_DisplayMenu> {
       scrollStyle(),
       imageQuality(),
       codec(),
+      audioQuality(),
       _ResolutionsMenu(
         id: widget.id,
         ffi: widget.ffi,
         screenAdjustor: _screenAdjustor,
       ),
+      shareWindow(),
       // We may add this feature if it is needed and we have an EV certificate.
       // _VirtualDisplayMenu(
       //   id: widget.id,
@@ -1098,6 +1101,29 @@ class _DisplayMenuState extends State<_DisplayMenu> {
     );
   }
 
+  shareWindow() {
+    final display = pi.currentDisplay;
+    if (pi.platform != kPeerPlatformLinux ||
+        display < 0 ||
+        display >= pi.displays.length) {
+      return Offstage();
+    }
+    final shared = pi.displays[display].window != 0;
+    return MenuButton(
+        child: Text(
+            translate(shared ? 'Share the whole display' : 'Share a window')),
+        onPressed: () {
+          if (shared) {
+            bind.sessionSwitchWindow(
+                sessionId: ffi.sessionId, display: display, window: 0);
+          } else {
+            bind.sessionGetWindowList(
+                sessionId: ffi.sessionId, display: display);
+          }
+        },
+        ffi: ffi);
+  }
+
   viewStyle() {
     return futureBuilder(
         future: toolbarViewStyle(context, widget.id, widget.ffi),
@@ -1198,6 +1224,27 @@ class _DisplayMenuState extends State<_DisplayMenu> {
         });
   }
 
+  audioQuality() {
+    return futureBuilder(
+        future: toolbarAudioQuality(context, id, ffi),
+        hasData: (data) {
+          final v = data as List<TRadioMenu<String>>;
+          if (v.isEmpty) return Offstage();
+
+          return _SubmenuButton(
+              ffi: widget.ffi,
+              child: Text(translate('Audio quality')),
+              menuChildren: v
+                  .map((e) => RdoMenuButton(
+                      value: e.value,
+                      groupValue: e.groupValue,
+                      onChanged: e.onChanged,
+                      child: e.child,
+                      ffi: ffi))
+                  .toList());
+        });
+  }
+
   toggles() {
     return futureBuilder(
         future: toolbarDisplayToggle(context, id, ffi),
@@ -1876,6 +1923,23 @@ class _RecordMenu extends StatelessWidget {
   }
 }
 
+class _ServerRecordIndicator extends StatelessWidget {
+  const _ServerRecordIndicator({Key? key}) : super(key: key);
+
+  @override
+  Widget build(BuildContext context) {
+    var recordingModel = Provider.of<RecordingModel>(context);
+    if (!recordingModel.serverRecording) return Offstage();
+    return _IconMenuButton(
+      assetName: 'assets/rec.svg',
+      tooltip: translate('Remote side is recording this session'),
+      onPressed: () {},
+      color: _ToolbarTheme.redColor,
+      hoverColor: _ToolbarTheme.hoverRedColor,
+    );
+  }
+}
+
 class _CloseMenu extends StatelessWidget {
   final String id;
   final FFI ffi;
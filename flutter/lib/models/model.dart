@@ -321,6 +321,17 @@ class FfiModel with ChangeNotifier {
         closeConnection(id: peer_id);
       } else if (name == 'portable_service_running') {
         _handlePortableServiceRunning(peerId, evt);
+      } else if (name == 'update_server_record_status') {
+        parent.target?.recordingModel
+            .updateServerRecordStatus(evt['on'] == 'true');
+      } else if (name == 'window_list') {
+        if (parent.target != null) {
+          showShareWindowDialog(evt, sessionId, parent.target!.dialogManager);
+        }
+      } else if (name == 'audio_applications') {
+        if (parent.target != null) {
+          showAudioSourceDialog(evt, sessionId, parent.target!.dialogManager);
+        }
       } else if (name == 'on_url_scheme_received') {
         // currently comes from "_url" ipc of mac and dbus of linux
         onUrlSchemeReceived(evt);
@@ -481,6 +492,7 @@ class FfiModel with ChangeNotifier {
     newDisplay.width = int.tryParse(evt['width']) ?? newDisplay.width;
     newDisplay.height = int.tryParse(evt['height']) ?? newDisplay.height;
     newDisplay.cursorEmbedded = int.tryParse(evt['cursor_embedded']) == 1;
+    newDisplay.window = int.tryParse(evt['window'] ?? '') ?? 0;
     newDisplay.originalWidth =
         int.tryParse(evt['original_width']) ?? kInvalidResolutionValue;
     newDisplay.originalHeight =
@@ -2026,6 +2038,14 @@ class RecordingModel with ChangeNotifier {
   RecordingModel(this.parent);
   bool _start = false;
   get start => _start;
+  // The controlled side records this session, see `enforce-record-incoming`.
+  bool _serverRecording = false;
+  get serverRecording => _serverRecording;
+
+  updateServerRecordStatus(bool on) {
+    _serverRecording = on;
+    notifyListeners();
+  }
 
   onSwitchDisplay() {
     if (isIOS || !_start) return;
@@ -2073,6 +2093,7 @@ class RecordingModel with ChangeNotifier {
   }
 
   onClose() async {
+    _serverRecording = false;
     if (isIOS) return;
     final sessionId = parent.target?.sessionId;
     if (sessionId == null) return;
@@ -2397,6 +2418,8 @@ class Display {
   int width = 0;
   int height = 0;
   bool cursorEmbedded = false;
+  // The window shared instead of the whole display, 0 if none.
+  int window = 0;
   int originalWidth = kInvalidResolutionValue;
   int originalHeight = kInvalidResolutionValue;
   double _scale = 1.0;
@@ -2422,7 +2445,8 @@ class Display {
       other.y == y &&
       other.width == width &&
       other.height == height &&
-      other.cursorEmbedded == cursorEmbedded;
+      other.cursorEmbedded == cursorEmbedded &&
+      other.window == window;
 
   bool get isOriginalResolutionSet =>
       originalWidth != kInvalidResolutionValue &&
@@ -28,6 +28,10 @@ use super::fuse::FuseServer;
 /// clipboard implementation of x11
 pub mod x11;
 
+#[cfg(target_os = "linux")]
+/// clipboard implementation of wayland
+pub mod wayland;
+
 #[cfg(target_os = "macos")]
 /// clipboard implementation of macos
 pub mod ns_clipboard;
@@ -75,16 +79,15 @@ trait SysClipboard: Send + Sync {
 
 #[cfg(target_os = "linux")]
 fn get_sys_clipboard(ignore_path: &PathBuf) -> Result<Box<dyn SysClipboard>, CliprdrError> {
-    #[cfg(feature = "wayland")]
-    {
-        unimplemented!()
-    }
-    #[cfg(not(feature = "wayland"))]
-    {
-        use x11::*;
-        let x11_clip = X11Clipboard::new(ignore_path)?;
-        Ok(Box::new(x11_clip) as Box<_>)
+    // XWayland only sees the clipboard of the X11 apps, so it is the fallback on Wayland.
+    if wayland::is_supported() {
+        use wayland::*;
+        let wl_clip = WaylandClipboard::new(ignore_path)?;
+        return Ok(Box::new(wl_clip) as Box<_>);
     }
+    use x11::*;
+    let x11_clip = X11Clipboard::new(ignore_path)?;
+    Ok(Box::new(x11_clip) as Box<_>)
 }
 
 #[cfg(target_os = "macos")]
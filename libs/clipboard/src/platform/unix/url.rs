@@ -4,8 +4,13 @@ use crate::CliprdrError;
 
 // on x11, path will be encode as
 // "/home/rustdesk/pictures/🖼️.png" -> "file:///home/rustdesk/pictures/%F0%9F%96%BC%EF%B8%8F.png"
-// url encode and decode is needed
-const ENCODE_SET: percent_encoding::AsciiSet = percent_encoding::CONTROLS.add(b' ').remove(b'/');
+// url encode and decode is needed, '%' too or a name with an escape in it is decoded
+const ENCODE_SET: percent_encoding::AsciiSet = percent_encoding::CONTROLS
+    .add(b' ')
+    .add(b'%')
+    .add(b'#')
+    .add(b'?')
+    .remove(b'/');
 
 pub(super) fn encode_path_to_uri(path: &PathBuf) -> String {
     let encoded = percent_encoding::percent_encode(path.to_str().unwrap().as_bytes(), &ENCODE_SET)
@@ -0,0 +1,276 @@
+use std::{
+    collections::BTreeSet,
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::process::CommandExt,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use hbb_common::log;
+use parking_lot::Mutex;
+
+use crate::{platform::unix::send_format_list, CliprdrError};
+
+use super::{encode_path_to_uri, parse_plain_uri_list, SysClipboard};
+
+// Wayland only lets the focused window touch the clipboard, unless it goes through the
+// data-control protocol of wlroots, KDE and recent GNOME, which `wl-copy` and `wl-paste` of
+// wl-clipboard use.
+const TEXT_URI_LIST: &str = "text/uri-list";
+// `wl-paste --watch` exits at once without the data-control protocol.
+const WATCH_PROBE_TIME: Duration = Duration::from_millis(300);
+
+/// Whether the clipboard of a Wayland session can be used, `wl-clipboard` is needed, and a
+/// compositor with data-control so that the copies are watched.
+pub fn is_supported() -> bool {
+    if std::env::var_os("WAYLAND_DISPLAY").is_none() {
+        return false;
+    }
+    let mut watch = match command("wl-paste")
+        .args(["--watch", "true"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(watch) => watch,
+        Err(e) => {
+            log::error!(
+                "wl-clipboard is required for the files of the wayland clipboard: {:?}",
+                e
+            );
+            return false;
+        }
+    };
+    let start = Instant::now();
+    while start.elapsed() < WATCH_PROBE_TIME {
+        match watch.try_wait() {
+            Ok(None) => std::thread::sleep(Duration::from_millis(20)),
+            Ok(Some(status)) => {
+                log::error!(
+                    "wl-paste cannot watch the clipboard, {}: {}",
+                    status,
+                    stderr(&mut watch)
+                );
+                return false;
+            }
+            Err(_) => break,
+        }
+    }
+    watch.kill().ok();
+    watch.wait().ok();
+    true
+}
+
+fn stderr(child: &mut Child) -> String {
+    let mut err = String::new();
+    if let Some(mut stderr) = child.stderr.take() {
+        stderr.read_to_string(&mut err).ok();
+    }
+    err.trim().to_owned()
+}
+
+fn encode_uri_list(paths: &[PathBuf]) -> String {
+    let uri_list: Vec<String> = paths.iter().map(encode_path_to_uri).collect();
+    uri_list.join("\n")
+}
+
+// The child is killed when this process exits, the files it offers are gone with the FUSE.
+fn command(program: &str) -> Command {
+    let mut cmd = Command::new(program);
+    unsafe {
+        cmd.pre_exec(|| {
+            libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM);
+            Ok(())
+        });
+    }
+    cmd
+}
+
+fn paste(args: &[&str]) -> Result<Vec<u8>, CliprdrError> {
+    let output = command("wl-paste")
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| {
+            log::debug!("wayland clipboard get content fail: {:?}", e);
+            CliprdrError::ClipboardInternalError
+        })?;
+    // fails if the clipboard is empty
+    if !output.status.success() {
+        return Ok(vec![]);
+    }
+    Ok(output.stdout)
+}
+
+pub struct WaylandClipboard {
+    ignore_path: PathBuf,
+    /// the `wl-copy` serving the file list set last, it exits when another one is copied
+    copy_process: Mutex<Option<Child>>,
+
+    former_file_list: Mutex<Vec<PathBuf>>,
+}
+
+impl WaylandClipboard {
+    pub fn new(ignore_path: &PathBuf) -> Result<Self, CliprdrError> {
+        Ok(Self {
+            ignore_path: ignore_path.to_owned(),
+            copy_process: Mutex::new(None),
+            former_file_list: Mutex::new(vec![]),
+        })
+    }
+
+    fn wait_file_list(&self) -> Result<Option<Vec<PathBuf>>, CliprdrError> {
+        let types = paste(&["--list-types"])?;
+        if !String::from_utf8_lossy(&types)
+            .lines()
+            .any(|t| t.trim() == TEXT_URI_LIST)
+        {
+            return Ok(None);
+        }
+        let v = paste(&["--no-newline", "--type", TEXT_URI_LIST])?;
+        let p = parse_plain_uri_list(v)?;
+        Ok(Some(p))
+    }
+
+    // Send the format list if the files in the clipboard changed.
+    fn check_file_list(&self) -> Result<(), CliprdrError> {
+        let paths = match self.wait_file_list() {
+            Ok(Some(paths)) => paths,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                log::warn!("failed to get file list from clipboard: {}", e);
+                return Ok(());
+            }
+        };
+
+        let filtered = paths
+            .into_iter()
+            .filter(|pb| !pb.starts_with(&self.ignore_path))
+            .collect::<Vec<_>>();
+
+        if filtered.is_empty() {
+            return Ok(());
+        }
+
+        {
+            let mut former = self.former_file_list.lock();
+
+            let filtered_st: BTreeSet<_> = filtered.iter().collect();
+            let former_st = former.iter().collect::<BTreeSet<_>>();
+            if filtered_st == former_st {
+                return Ok(());
+            }
+
+            *former = filtered;
+        }
+
+        send_format_list(0)
+    }
+}
+
+impl SysClipboard for WaylandClipboard {
+    fn set_file_list(&self, paths: &[PathBuf]) -> Result<(), CliprdrError> {
+        *self.former_file_list.lock() = paths.to_vec();
+
+        let uri_list = encode_uri_list(paths);
+        // The files are read from the FUSE only when they are pasted.
+        let mut child = command("wl-copy")
+            .args(["--foreground", "--type", TEXT_URI_LIST])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| {
+                log::error!("failed to run wl-copy: {:?}", e);
+                CliprdrError::ClipboardInternalError
+            })?;
+        // wl-copy reads until stdin is closed.
+        let res = match child.stdin.take() {
+            Some(mut stdin) => stdin.write_all(uri_list.as_bytes()),
+            None => Ok(()),
+        };
+        if let Some(mut former) = self.copy_process.lock().replace(child) {
+            former.kill().ok();
+            former.wait().ok();
+        }
+        res.map_err(|_| CliprdrError::ClipboardInternalError)
+    }
+
+    fn start(&self) {
+        {
+            // clear cached file list
+            *self.former_file_list.lock() = vec![];
+        }
+        // `echo` prints a line on every copy, instead of polling the clipboard.
+        let mut watch = match command("wl-paste")
+            .args(["--watch", "echo"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(watch) => watch,
+            Err(e) => {
+                log::error!("failed to watch wayland clipboard: {:?}", e);
+                return;
+            }
+        };
+        let Some(stdout) = watch.stdout.take() else {
+            return;
+        };
+        // whether wl-paste stopped, not this
+        let mut exited = true;
+        for line in BufReader::new(stdout).lines() {
+            if line.is_err() {
+                break;
+            }
+            if let Err(e) = self.check_file_list() {
+                log::warn!("failed to send format list: {}", e);
+                exited = false;
+                break;
+            }
+        }
+        watch.kill().ok();
+        let status = watch.wait();
+        if exited {
+            log::error!(
+                "wl-paste stopped watching the clipboard, {:?}: {}",
+                status,
+                stderr(&mut watch)
+            );
+        }
+        log::debug!("stop listening file list on wayland clipboard");
+    }
+
+    fn get_file_list(&self) -> Vec<PathBuf> {
+        self.former_file_list.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uri_list() {
+        let fuse = PathBuf::from("/tmp/rustdesk/fuse");
+        let paths = vec![
+            fuse.join("report 100%.pdf"),
+            fuse.join("dir/#1 ?.txt"),
+            fuse.join("%E4%B8%AD/文件.png"),
+            PathBuf::from("/home/rustdesk/a b"),
+        ];
+        let uri_list = encode_uri_list(&paths);
+        assert_eq!(
+            uri_list.lines().next(),
+            Some("file:///tmp/rustdesk/fuse/report%20100%25.pdf")
+        );
+        let parsed = parse_plain_uri_list(uri_list.into_bytes()).unwrap();
+        assert_eq!(parsed, paths);
+        // the files of the peer are not sent back to it
+        assert_eq!(parsed.iter().filter(|p| !p.starts_with(&fuse)).count(), 1);
+    }
+}
@@ -22,9 +22,9 @@ use crate::{
     compress::{compress, decompress},
     log,
     password_security::{
-        decrypt_str_or_original, decrypt_vec_or_original, encrypt_str_or_original,
-        encrypt_vec_or_original, symmetric_crypt,
+        decrypt_str_or_original, encrypt_str_or_original, encrypt_vec_or_original, symmetric_crypt,
     },
+    secret_store,
 };
 
 pub const RENDEZVOUS_TIMEOUT: u64 = 12_000;
@@ -35,10 +35,17 @@ pub const COMPRESS_LEVEL: i32 = 3;
 const SERIAL: i32 = 3;
 const PASSWORD_ENC_VERSION: &str = "00";
 const ENCRYPT_MAX_LEN: usize = 128;
+const PEER_SECRET_OPTIONS: [&str; 3] = ["rdp_password", "os-username", "os-password"];
+const SECRET_ACCOUNT_SOCKS: &str = "socks/password";
+const SECRET_ACCOUNT_2FA: &str = "2fa";
+const OPTION_2FA: &str = "2fa";
 
 // config2 options
 #[cfg(target_os = "linux")]
 pub const CONFIG_OPTION_ALLOW_LINUX_HEADLESS: &str = "allow-linux-headless";
+#[cfg(target_os = "linux")]
+pub const CONFIG_OPTION_ALLOW_LINUX_HEADLESS_VIRTUAL_DISPLAY: &str =
+    "allow-linux-headless-virtual-display";
 
 #[cfg(target_os = "macos")]
 lazy_static::lazy_static! {
@@ -419,15 +426,25 @@ fn patch(path: PathBuf) -> PathBuf {
 impl Config2 {
     fn load() -> Config2 {
         let mut config = Config::load_::<Config2>("2");
+        let mut store = false;
         if let Some(mut socks) = config.socks {
-            let (password, _, store) =
-                decrypt_str_or_original(&socks.password, PASSWORD_ENC_VERSION);
+            let (password, store1) = secret_store::load_str(&socks.password);
             socks.password = password;
             config.socks = Some(socks);
-            if store {
-                config.store();
+            store |= store1;
+        }
+        // The 2fa option is already encrypted by `auth_2fa`, only the keyring reference is resolved here.
+        if let Some(v) = config.options.get_mut(OPTION_2FA) {
+            if let Some(secret) = secret_store::get(v) {
+                store |= !secret_store::enabled() && !secret.is_empty();
+                *v = secret;
+            } else {
+                store |= secret_store::enabled();
             }
         }
+        if store {
+            config.store();
+        }
         config
     }
 
@@ -436,19 +453,54 @@ impl Config2 {
     }
 
     fn store(&self) {
+        if let Err(err) = self.try_store() {
+            log::error!("Failed to store config: {}", err);
+        }
+    }
+
+    fn try_store(&self) -> crate::ResultType<()> {
         let mut config = self.clone();
         if let Some(mut socks) = config.socks {
-            socks.password =
-                encrypt_str_or_original(&socks.password, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN);
+            socks.password = secret_store::put(SECRET_ACCOUNT_SOCKS, &socks.password)
+                .unwrap_or_else(|| {
+                    encrypt_str_or_original(&socks.password, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN)
+                });
             config.socks = Some(socks);
         }
-        Config::store_(&config, "2");
+        if let Some(v) = config.options.get_mut(OPTION_2FA) {
+            if let Some(reference) = secret_store::put(SECRET_ACCOUNT_2FA, v) {
+                *v = reference;
+            }
+        }
+        store_path(Self::file(), config)
     }
 
     pub fn get() -> Config2 {
         return CONFIG2.read().unwrap().clone();
     }
 
+    /// Store the secrets of Config2 and all peers again with the current `secret_store` backend,
+    /// used to move them into or out of the keyring. Returns the number of peers rewritten.
+    ///
+    /// Fails if any secret could not be read or written, the entries moved out of a disabled
+    /// keyring are only deleted from it when everything was stored.
+    pub fn rekey_secrets() -> crate::ResultType<usize> {
+        CONFIG2.read().unwrap().try_store()?;
+        let peers = Config::peers(None);
+        for (id, _, c) in peers.iter() {
+            c.try_store(id)?;
+        }
+        let errors = secret_store::take_errors();
+        if !errors.is_empty() {
+            crate::bail!("{}", errors.join("\n"));
+        }
+        let n = secret_store::purge_disabled()?;
+        if n > 0 {
+            log::info!("Deleted {} secrets from the keyring", n);
+        }
+        Ok(peers.len())
+    }
+
     pub fn set(cfg: Config2) -> bool {
         let mut lock = CONFIG2.write().unwrap();
         if *lock == cfg {
@@ -1024,15 +1076,13 @@ impl PeerConfig {
             Ok(config) => {
                 let mut config: PeerConfig = config;
                 let mut store = false;
-                let (password, _, store2) =
-                    decrypt_vec_or_original(&config.password, PASSWORD_ENC_VERSION);
+                let (password, store2) = secret_store::load_vec(&config.password);
                 config.password = password;
                 store = store || store2;
-                for opt in ["rdp_password", "os-username", "os-password"] {
+                for opt in PEER_SECRET_OPTIONS {
                     if let Some(v) = config.options.get_mut(opt) {
-                        let (encrypted, _, store2) =
-                            decrypt_str_or_original(v, PASSWORD_ENC_VERSION);
-                        *v = encrypted;
+                        let (decrypted, store2) = secret_store::load_str(v);
+                        *v = decrypted;
                         store = store || store2;
                     }
                 }
@@ -1054,22 +1104,40 @@ impl PeerConfig {
     }
 
     pub fn store(&self, id: &str) {
+        if let Err(err) = self.try_store(id) {
+            log::error!("Failed to store config: {}", err);
+        }
+    }
+
+    fn try_store(&self, id: &str) -> crate::ResultType<()> {
         let _lock = CONFIG.read().unwrap();
         let mut config = self.clone();
-        config.password =
-            encrypt_vec_or_original(&config.password, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN);
-        for opt in ["rdp_password", "os-username", "os-password"] {
+        config.password = secret_store::store_vec(
+            &secret_store::peer_account(id, "password"),
+            &config.password,
+        )
+        .unwrap_or_else(|| {
+            encrypt_vec_or_original(&config.password, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN)
+        });
+        for opt in PEER_SECRET_OPTIONS {
             if let Some(v) = config.options.get_mut(opt) {
-                *v = encrypt_str_or_original(v, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN)
+                *v = secret_store::put(&secret_store::peer_account(id, opt), v).unwrap_or_else(
+                    || encrypt_str_or_original(v, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN),
+                )
             }
         }
-        if let Err(err) = store_path(Self::path(id), config) {
-            log::error!("Failed to store config: {}", err);
-        }
+        store_path(Self::path(id), config)?;
         NEW_STORED_PEER_CONFIG.lock().unwrap().insert(id.to_owned());
+        Ok(())
     }
 
     pub fn remove(id: &str) {
+        if secret_store::enabled() {
+            secret_store::forget(&secret_store::peer_account(id, "password"));
+            for opt in PEER_SECRET_OPTIONS {
+                secret_store::forget(&secret_store::peer_account(id, opt));
+            }
+        }
         fs::remove_file(Self::path(id)).ok();
     }
 
@@ -38,6 +38,7 @@ pub use tokio_socks;
 pub use tokio_socks::IntoTargetAddr;
 pub use tokio_socks::TargetAddr;
 pub mod password_security;
+pub mod secret_store;
 pub use chrono;
 pub use directories_next;
 pub use libc;
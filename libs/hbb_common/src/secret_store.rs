@@ -0,0 +1,660 @@
+// Secrets that used to live only in the toml files (peer passwords, socks password, 2fa)
+// can be kept in the OS keyring instead. The toml file then only holds a reference:
+// KEYRING_PREFIX followed by the account name, e.g. "01:keyring:peer/123456789/password".
+// ':' is not in the base64 alphabet, so a reference is never mistaken for an encrypted value.
+//
+// If no keyring is reachable (no session bus, root service, non-linux), callers fall back
+// to `password_security`, so nothing changes for them.
+//
+// A reference that can not be resolved (keyring locked, gone or not answering) reads as "",
+// but is written back unchanged on the next store, so the secret is not lost.
+
+use crate::{
+    config::APP_NAME,
+    log,
+    password_security::{decrypt_str_or_original, decrypt_vec_or_original},
+    ResultType,
+};
+use sodiumoxide::base64;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, RwLock},
+};
+
+pub const KEYRING_PREFIX: &str = "01:keyring:";
+const LEGACY_VERSION: &str = "00";
+
+pub trait SecretBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn get(&self, account: &str) -> ResultType<Option<String>>;
+    fn set(&self, account: &str, secret: &str) -> ResultType<()>;
+    fn delete(&self, account: &str) -> ResultType<()>;
+    /// All the secrets of this app at once, account -> secret. Lets the first `get` fill the
+    /// cache instead of asking the keyring for every peer.
+    fn list(&self) -> ResultType<HashMap<String, String>> {
+        Ok(HashMap::new())
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref STORE: SecretStore = Default::default();
+}
+
+#[derive(Default)]
+struct SecretStore {
+    // None until the first use, the probe spawns a process and must not run on every access.
+    backend: RwLock<Option<Option<Arc<dyn SecretBackend>>>>,
+    // Kept after `disable` so that `--rekey legacy` can still read the keyring entries it moves.
+    disabled: Mutex<Option<Arc<dyn SecretBackend>>>,
+    cache: Mutex<HashMap<String, String>>,
+    // whether `cache` has been filled by `SecretBackend::list`
+    listed: Mutex<bool>,
+    // account -> reference, for the references `get` failed to resolve
+    unresolved: Mutex<HashMap<String, String>>,
+    // accounts read through the disabled backend, deleted from it by `purge_disabled`
+    moved: Mutex<HashSet<String>>,
+    errors: Mutex<Vec<String>>,
+}
+
+fn detect() -> Option<Arc<dyn SecretBackend>> {
+    #[cfg(target_os = "linux")]
+    match linux::SecretService::probe() {
+        Ok(true) => {
+            log::info!("Secret Service is available, secrets are stored in the keyring");
+            return Some(Arc::new(linux::SecretService));
+        }
+        Ok(false) => {}
+        Err(err) => log::warn!("Secret Service is not usable: {}", err),
+    }
+    None
+}
+
+#[inline]
+fn reference(account: &str) -> String {
+    format!("{}{}", KEYRING_PREFIX, account)
+}
+
+impl SecretStore {
+    fn backend(&self) -> Option<Arc<dyn SecretBackend>> {
+        if let Some(b) = self.backend.read().unwrap().as_ref() {
+            return b.clone();
+        }
+        let mut lock = self.backend.write().unwrap();
+        lock.get_or_insert_with(detect).clone()
+    }
+
+    fn set_backend(&self, b: Option<Arc<dyn SecretBackend>>) {
+        self.clear_cache();
+        *self.backend.write().unwrap() = Some(b);
+    }
+
+    fn clear_cache(&self) {
+        self.cache.lock().unwrap().clear();
+        *self.listed.lock().unwrap() = false;
+    }
+
+    fn list(&self, b: &Arc<dyn SecretBackend>) {
+        let mut listed = self.listed.lock().unwrap();
+        if *listed {
+            return;
+        }
+        *listed = true;
+        match b.list() {
+            Ok(secrets) => {
+                let mut cache = self.cache.lock().unwrap();
+                for (account, secret) in secrets {
+                    cache.entry(account).or_insert(secret);
+                }
+            }
+            // Not fatal, `get` asks for every secret on its own.
+            Err(err) => log::warn!("Failed to list the secrets in {}: {}", b.name(), err),
+        }
+    }
+
+    fn disable(&self) {
+        if let Some(b) = self.backend() {
+            *self.disabled.lock().unwrap() = Some(b);
+        }
+        // Read them again, so that `moved` sees every entry taken from the keyring.
+        self.clear_cache();
+        *self.backend.write().unwrap() = Some(None);
+    }
+
+    fn error(&self, err: String) {
+        log::error!("{}", err);
+        self.errors.lock().unwrap().push(err);
+    }
+
+    fn put(&self, account: &str, secret: &str) -> Option<String> {
+        if secret.is_empty() {
+            if let Some(r) = self.unresolved.lock().unwrap().get(account) {
+                return Some(r.clone());
+            }
+        } else {
+            self.unresolved.lock().unwrap().remove(account);
+        }
+        let b = self.backend()?;
+        if secret.is_empty() {
+            self.forget(account);
+            return None;
+        }
+        if self.cache.lock().unwrap().get(account).map(|s| s.as_str()) != Some(secret) {
+            if let Err(err) = b.set(account, secret) {
+                self.error(format!(
+                    "Failed to store secret {} in {}: {}",
+                    account,
+                    b.name(),
+                    err
+                ));
+                return None;
+            }
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(account.to_owned(), secret.to_owned());
+        }
+        Some(reference(account))
+    }
+
+    fn get(&self, stored: &str) -> Option<String> {
+        if !is_reference(stored) {
+            return None;
+        }
+        let account = &stored[KEYRING_PREFIX.len()..];
+        if let Some(v) = self.cache.lock().unwrap().get(account) {
+            return Some(v.clone());
+        }
+        let disabled = self.disabled.lock().unwrap().clone();
+        let (b, moved) = match (self.backend(), disabled) {
+            (Some(b), _) => (b, false),
+            (None, Some(b)) => (b, true),
+            (None, None) => {
+                self.error(format!(
+                    "Secret {} is in the keyring, but no keyring is available",
+                    account
+                ));
+                self.unresolved
+                    .lock()
+                    .unwrap()
+                    .insert(account.to_owned(), stored.to_owned());
+                return Some("".to_owned());
+            }
+        };
+        if !moved {
+            self.list(&b);
+            if let Some(v) = self.cache.lock().unwrap().get(account) {
+                return Some(v.clone());
+            }
+        }
+        let err = match b.get(account) {
+            Ok(Some(v)) => {
+                self.cache
+                    .lock()
+                    .unwrap()
+                    .insert(account.to_owned(), v.clone());
+                if moved {
+                    self.moved.lock().unwrap().insert(account.to_owned());
+                }
+                return Some(v);
+            }
+            Ok(None) => format!("Secret {} not found in {}", account, b.name()),
+            Err(err) => format!(
+                "Failed to read secret {} from {}: {}",
+                account,
+                b.name(),
+                err
+            ),
+        };
+        self.error(err);
+        self.unresolved
+            .lock()
+            .unwrap()
+            .insert(account.to_owned(), stored.to_owned());
+        Some("".to_owned())
+    }
+
+    fn forget(&self, account: &str) {
+        self.cache.lock().unwrap().remove(account);
+        self.unresolved.lock().unwrap().remove(account);
+        if let Some(b) = self.backend() {
+            if let Err(err) = b.delete(account) {
+                log::debug!(
+                    "Failed to delete secret {} from {}: {}",
+                    account,
+                    b.name(),
+                    err
+                );
+            }
+        }
+    }
+
+    fn purge_disabled(&self) -> ResultType<usize> {
+        let Some(b) = self.disabled.lock().unwrap().clone() else {
+            return Ok(0);
+        };
+        let moved: Vec<String> = self.moved.lock().unwrap().drain().collect();
+        for account in moved.iter() {
+            b.delete(account)?;
+        }
+        Ok(moved.len())
+    }
+
+    fn load_str(&self, stored: &str) -> (String, bool) {
+        if let Some(v) = self.get(stored) {
+            let store = self.backend().is_none() && !v.is_empty();
+            return (v, store);
+        }
+        let (v, succ, store) = decrypt_str_or_original(stored, LEGACY_VERSION);
+        (v, store || succ && self.backend().is_some())
+    }
+
+    fn load_vec(&self, stored: &[u8]) -> (Vec<u8>, bool) {
+        let s = String::from_utf8_lossy(stored);
+        if let Some(v) = self.get(&s) {
+            let v = base64::decode(v, base64::Variant::Original).unwrap_or_default();
+            let store = self.backend().is_none() && !v.is_empty();
+            return (v, store);
+        }
+        let (v, succ, store) = decrypt_vec_or_original(stored, LEGACY_VERSION);
+        (v, store || succ && self.backend().is_some())
+    }
+
+    fn store_vec(&self, account: &str, secret: &[u8]) -> Option<Vec<u8>> {
+        let secret = if secret.is_empty() {
+            "".to_owned()
+        } else {
+            base64::encode(secret, base64::Variant::Original)
+        };
+        self.put(account, &secret).map(|s| s.into_bytes())
+    }
+}
+
+/// Name of the backend in use, "file" for the legacy scheme.
+pub fn backend_name() -> &'static str {
+    STORE.backend().map(|b| b.name()).unwrap_or("file")
+}
+
+#[inline]
+pub fn enabled() -> bool {
+    STORE.backend().is_some()
+}
+
+/// Use `b` instead of the detected backend, e.g. for platforms without a builtin one.
+pub fn set_backend(b: Arc<dyn SecretBackend>) {
+    STORE.set_backend(Some(b));
+}
+
+/// Stop using the keyring in this process. New stores go to the config files,
+/// references already written are still resolved.
+pub fn disable() {
+    STORE.disable();
+}
+
+pub fn peer_account(id: &str, name: &str) -> String {
+    format!("peer/{id}/{name}")
+}
+
+#[inline]
+pub fn is_reference(s: &str) -> bool {
+    s.len() > KEYRING_PREFIX.len() && s.starts_with(KEYRING_PREFIX)
+}
+
+/// Put `secret` into the keyring, returns the reference to write to the config file.
+/// Returns `None` if the keyring is not used, the caller should store the secret itself.
+pub fn put(account: &str, secret: &str) -> Option<String> {
+    STORE.put(account, secret)
+}
+
+/// Resolve a reference written by `put`. Returns `None` if `stored` is not a reference.
+pub fn get(stored: &str) -> Option<String> {
+    STORE.get(stored)
+}
+
+pub fn forget(account: &str) {
+    STORE.forget(account)
+}
+
+/// Errors of the keyring since the last call, a rekey must not report success with any of them.
+pub fn take_errors() -> Vec<String> {
+    std::mem::take(&mut *STORE.errors.lock().unwrap())
+}
+
+/// Delete the entries moved out of the keyring after `disable`, once they are stored in the
+/// config files. Returns the number of deleted entries.
+pub fn purge_disabled() -> ResultType<usize> {
+    STORE.purge_disabled()
+}
+
+// String: secret
+// bool: whether should store, either re-encrypt or move it into the keyring
+pub fn load_str(stored: &str) -> (String, bool) {
+    STORE.load_str(stored)
+}
+
+pub fn load_vec(stored: &[u8]) -> (Vec<u8>, bool) {
+    STORE.load_vec(stored)
+}
+
+pub fn store_vec(account: &str, secret: &[u8]) -> Option<Vec<u8>> {
+    STORE.store_vec(account, secret)
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        process::{Child, Command, Output, Stdio},
+    };
+
+    // libsecret's command line client, talks to gnome-keyring, kwallet (>= 5.97) and keepassxc.
+    const SECRET_TOOL: &str = "secret-tool";
+    // A locked keyring may wait for an unlock prompt nobody answers.
+    const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+    pub struct SecretService;
+
+    impl SecretService {
+        /// Ok(false) if there is no session bus or no secret-tool, Err if the keyring
+        /// does not answer.
+        pub fn probe() -> ResultType<bool> {
+            if std::env::var("DBUS_SESSION_BUS_ADDRESS")
+                .unwrap_or_default()
+                .is_empty()
+            {
+                return Ok(false);
+            }
+            let child = match Self::spawn(&["lookup"], Self::attributes("__probe__"), None) {
+                Ok(child) => child,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+                Err(err) => return Err(err.into()),
+            };
+            // Exit code is 1 both for "not found" and errors, only errors print something.
+            let output = Self::wait(child)?;
+            if !output.stderr.is_empty() {
+                crate::bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+            }
+            Ok(true)
+        }
+
+        fn attributes(account: &str) -> Vec<String> {
+            vec![
+                "service".to_owned(),
+                APP_NAME.read().unwrap().clone(),
+                "account".to_owned(),
+                account.to_owned(),
+            ]
+        }
+
+        fn spawn(
+            args: &[&str],
+            attributes: Vec<String>,
+            stdin: Option<&str>,
+        ) -> std::io::Result<Child> {
+            let mut child = Command::new(SECRET_TOOL)
+                .args(args)
+                .args(attributes)
+                .stdin(if stdin.is_some() {
+                    Stdio::piped()
+                } else {
+                    Stdio::null()
+                })
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?;
+            if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+                pipe.write_all(input.as_bytes())?;
+            }
+            Ok(child)
+        }
+
+        // Err on timeout, the secret then stays unresolved.
+        fn wait(mut child: Child) -> ResultType<Output> {
+            // Read in a thread, a full pipe would block the child until the timeout.
+            let (mut stdout, mut stderr) = (child.stdout.take(), child.stderr.take());
+            let reader = std::thread::spawn(move || {
+                let (mut out, mut err) = (Vec::new(), Vec::new());
+                if let Some(pipe) = stdout.as_mut() {
+                    pipe.read_to_end(&mut out).ok();
+                }
+                if let Some(pipe) = stderr.as_mut() {
+                    pipe.read_to_end(&mut err).ok();
+                }
+                (out, err)
+            });
+            let start = std::time::Instant::now();
+            let status = loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if start.elapsed() > TIMEOUT {
+                    child.kill().ok();
+                    child.wait().ok();
+                    crate::bail!("{} timed out", SECRET_TOOL);
+                }
+                std::thread::sleep(std::time::Duration::from_millis(20));
+            };
+            let (stdout, stderr) = reader.join().unwrap_or_default();
+            Ok(Output {
+                status,
+                stdout,
+                stderr,
+            })
+        }
+
+        fn run(args: &[&str], account: &str, stdin: Option<&str>) -> ResultType<Output> {
+            Self::wait(Self::spawn(args, Self::attributes(account), stdin)?)
+        }
+    }
+
+    // The output of `secret-tool search --all`, one block per item:
+    //   [/org/freedesktop/secrets/collection/login/1]
+    //   label = ...
+    //   secret = ...
+    //   created = ...
+    //   modified = ...
+    //   schema = ...
+    //   attribute.account = ...
+    // The secret may span lines, it ends at "created = ".
+    // Locked items are listed without their secret and skipped.
+    pub(super) fn parse_search(output: &str) -> HashMap<String, String> {
+        let mut out = HashMap::new();
+        let mut account = None;
+        let mut secret: Option<String> = None;
+        let mut in_secret = false;
+        let mut flush = |account: &mut Option<String>, secret: &mut Option<String>| {
+            if let (Some(a), Some(s)) = (account.take(), secret.take()) {
+                out.insert(a, s);
+            }
+        };
+        for line in output.lines() {
+            if line.starts_with('[') && line.ends_with(']') {
+                flush(&mut account, &mut secret);
+                in_secret = false;
+            } else if let Some(v) = line.strip_prefix("secret = ") {
+                secret = Some(v.to_owned());
+                in_secret = true;
+            } else if line.starts_with("created = ") {
+                in_secret = false;
+            } else if in_secret {
+                if let Some(s) = secret.as_mut() {
+                    s.push('\n');
+                    s.push_str(line);
+                }
+            } else if let Some(v) = line.strip_prefix("attribute.account = ") {
+                account = Some(v.to_owned());
+            }
+        }
+        flush(&mut account, &mut secret);
+        out
+    }
+
+    impl SecretBackend for SecretService {
+        fn name(&self) -> &'static str {
+            "secret-service"
+        }
+
+        fn get(&self, account: &str) -> ResultType<Option<String>> {
+            let output = Self::run(&["lookup"], account, None)?;
+            if output.status.success() {
+                Ok(Some(String::from_utf8(output.stdout)?))
+            } else if output.stderr.is_empty() {
+                Ok(None)
+            } else {
+                crate::bail!("{}", String::from_utf8_lossy(&output.stderr).trim())
+            }
+        }
+
+        fn set(&self, account: &str, secret: &str) -> ResultType<()> {
+            let label = format!("--label={} {}", APP_NAME.read().unwrap(), account);
+            let output = Self::run(&["store", &label], account, Some(secret))?;
+            if !output.status.success() {
+                crate::bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+            }
+            Ok(())
+        }
+
+        fn delete(&self, account: &str) -> ResultType<()> {
+            let output = Self::run(&["clear"], account, None)?;
+            if !output.status.success() && !output.stderr.is_empty() {
+                crate::bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+            }
+            Ok(())
+        }
+
+        fn list(&self) -> ResultType<HashMap<String, String>> {
+            let service = vec!["service".to_owned(), APP_NAME.read().unwrap().clone()];
+            let output = Self::wait(Self::spawn(&["search", "--all"], service, None)?)?;
+            // Exit code is 1 if nothing is found.
+            if !output.status.success() && !output.stderr.is_empty() {
+                crate::bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+            }
+            Ok(parse_search(&String::from_utf8_lossy(&output.stdout)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::password_security::encrypt_str_or_original;
+
+    #[derive(Default)]
+    struct MemoryBackend(Mutex<HashMap<String, String>>);
+
+    impl SecretBackend for MemoryBackend {
+        fn name(&self) -> &'static str {
+            "memory"
+        }
+
+        fn get(&self, account: &str) -> ResultType<Option<String>> {
+            Ok(self.0.lock().unwrap().get(account).cloned())
+        }
+
+        fn set(&self, account: &str, secret: &str) -> ResultType<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(account.to_owned(), secret.to_owned());
+            Ok(())
+        }
+
+        fn delete(&self, account: &str) -> ResultType<()> {
+            self.0.lock().unwrap().remove(account);
+            Ok(())
+        }
+    }
+
+    // A store of its own, the global one is shared with the other tests.
+    fn store(b: Option<Arc<dyn SecretBackend>>) -> SecretStore {
+        let store = SecretStore::default();
+        store.set_backend(b);
+        store
+    }
+
+    #[test]
+    fn test_keyring_and_migration() {
+        let legacy = encrypt_str_or_original("secret", LEGACY_VERSION, 128);
+        let backend = Arc::new(MemoryBackend::default());
+        let store = store(Some(backend.clone()));
+
+        // legacy values are decrypted and flagged for moving into the keyring
+        assert_eq!(store.load_str(&legacy), ("secret".to_owned(), true));
+
+        let account = peer_account("123", "password");
+        let reference = store.put(&account, "secret").unwrap();
+        assert!(is_reference(&reference));
+        assert_eq!(store.load_str(&reference), ("secret".to_owned(), false));
+
+        let reference = store.store_vec(&account, &[1, 2, 3]).unwrap();
+        assert_eq!(store.load_vec(&reference), (vec![1, 2, 3], false));
+
+        // after disabling, references are still readable and flagged for moving back
+        store.disable();
+        assert_eq!(store.load_vec(&reference), (vec![1, 2, 3], true));
+        assert!(store.put(&account, "secret").is_none());
+        assert!(backend.0.lock().unwrap().contains_key(&account));
+        // and deleted from the keyring once moved
+        assert_eq!(store.purge_disabled().unwrap(), 1);
+        assert!(backend.0.lock().unwrap().is_empty());
+        assert!(store.errors.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unresolved() {
+        let store = store(None);
+        let reference = reference(&peer_account("123", "password"));
+        // no keyring: the secret reads as empty, but the reference is kept on store
+        assert_eq!(store.load_str(&reference), ("".to_owned(), false));
+        assert_eq!(
+            store.put(&peer_account("123", "password"), ""),
+            Some(reference.clone())
+        );
+        assert_eq!(store.errors.lock().unwrap().len(), 1);
+        // a new secret replaces it
+        assert_eq!(store.put(&peer_account("123", "password"), "new"), None);
+        assert_eq!(store.put(&peer_account("123", "password"), ""), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_search() {
+        let output = "[/org/freedesktop/secrets/collection/login/1]
+label = RustDesk peer/123/password
+secret = line1
+line2
+created = 2024-01-01 00:00:00
+modified = 2024-01-01 00:00:00
+attribute.service = RustDesk
+attribute.account = peer/123/password
+[/org/freedesktop/secrets/collection/login/2]
+label = RustDesk 2fa
+created = 2024-01-01 00:00:00
+modified = 2024-01-01 00:00:00
+attribute.account = 2fa
+[/org/freedesktop/secrets/collection/login/3]
+label = RustDesk socks
+secret = = x
+created = 2024-01-01 00:00:00
+modified = 2024-01-01 00:00:00
+attribute.account = socks
+";
+        let secrets = linux::parse_search(output);
+        assert_eq!(secrets.len(), 2);
+        assert_eq!(secrets["peer/123/password"], "line1\nline2");
+        assert_eq!(secrets["socks"], "= x");
+    }
+
+    #[test]
+    fn test_is_reference() {
+        assert!(is_reference("01:keyring:2fa"));
+        assert!(!is_reference(KEYRING_PREFIX));
+        // plain or encrypted values starting with the version are not references
+        assert!(!is_reference("01peer/123/password"));
+        assert!(!is_reference(&encrypt_str_or_original(
+            "secret",
+            LEGACY_VERSION,
+            128
+        )));
+    }
+}
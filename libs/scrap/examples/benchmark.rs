@@ -21,7 +21,7 @@ const USAGE: &'static str = "
 Codec benchmark.
 
 Usage:
-  benchmark [--count=COUNT] [--quality=QUALITY] [--i444]
+  benchmark [--count=COUNT] [--quality=QUALITY] [--i444] [--screen-content]
   benchmark (-h | --help)
 
 Options:
@@ -30,6 +30,8 @@ Options:
   --quality=QUALITY     Video quality [default: Balanced].
                         Valid values: Best, Balanced, Low.
   --i444                I444.
+  --screen-content      Tune for screen content, and encode the last frame
+                        losslessly as it is done once the screen is still.
 ";
 
 #[derive(Debug, serde::Deserialize, Clone, Copy)]
@@ -37,6 +39,7 @@ struct Args {
     flag_count: usize,
     flag_quality: Quality,
     flag_i444: bool,
+    flag_screen_content: bool,
 }
 
 #[derive(Debug, serde::Deserialize, Clone, Copy)]
@@ -67,8 +70,8 @@ fn main() {
     let height = c.height();
 
     println!(
-        "benchmark {}x{} quality:{:?}, i444:{:?}",
-        width, height, quality, args.flag_i444
+        "benchmark {}x{} quality:{:?}, i444:{:?}, screen content:{:?}",
+        width, height, quality, args.flag_i444, args.flag_screen_content
     );
     let quality = match quality {
         Quality::Best => Q::Best,
@@ -84,9 +87,18 @@ fn main() {
             quality,
             yuv_count,
             if codec == VP8 { false } else { args.flag_i444 },
+            args.flag_screen_content,
         )
     });
-    test_av1(&mut c, width, height, quality, yuv_count, args.flag_i444);
+    test_av1(
+        &mut c,
+        width,
+        height,
+        quality,
+        yuv_count,
+        args.flag_i444,
+        args.flag_screen_content,
+    );
     #[cfg(feature = "hwcodec")]
     {
         hw::test(&mut c, width, height, quality, yuv_count);
@@ -101,6 +113,7 @@ fn test_vpx(
     quality: Q,
     yuv_count: usize,
     i444: bool,
+    screen_content: bool,
 ) {
     let config = EncoderCfg::VPX(VpxEncoderConfig {
         width: width as _,
@@ -110,6 +123,7 @@ fn test_vpx(
         keyframe_interval: None,
     });
     let mut encoder = VpxEncoder::new(config, i444).unwrap();
+    encoder.set_screen_content(screen_content);
     let mut vpxs = vec![];
     let start = Instant::now();
     let mut size = 0;
@@ -159,6 +173,27 @@ fn test_vpx(
         time_sum / yuv_count as _,
         size / yuv_count
     );
+    if screen_content && encoder.support_lossless() {
+        let tmp_timer = Instant::now();
+        encoder.set_lossless(true).unwrap();
+        let mut size = 0;
+        for ref frame in encoder
+            .encode(start.elapsed().as_millis() as _, &yuv, STRIDE_ALIGN)
+            .unwrap()
+        {
+            size += frame.data.len();
+        }
+        for ref frame in encoder.flush().unwrap() {
+            size += frame.data.len();
+        }
+        encoder.set_lossless(false).unwrap();
+        println!(
+            "{:?} lossless frame: {:?}, {} byte",
+            codec_id,
+            tmp_timer.elapsed(),
+            size
+        );
+    }
 
     let mut decoder = VpxDecoder::new(VpxDecoderConfig { codec: codec_id }).unwrap();
     let start = Instant::now();
@@ -180,6 +215,7 @@ fn test_av1(
     quality: Q,
     yuv_count: usize,
     i444: bool,
+    screen_content: bool,
 ) {
     let config = EncoderCfg::AOM(AomEncoderConfig {
         width: width as _,
@@ -188,6 +224,7 @@ fn test_av1(
         keyframe_interval: None,
     });
     let mut encoder = AomEncoder::new(config, i444).unwrap();
+    encoder.set_screen_content(screen_content);
     let start = Instant::now();
     let mut size = 0;
     let mut av1s: Vec<Vec<u8>> = vec![];
@@ -228,6 +265,23 @@ fn test_av1(
         time_sum / yuv_count as _,
         size / yuv_count
     );
+    if screen_content && encoder.support_lossless() {
+        let tmp_timer = Instant::now();
+        encoder.set_lossless(true).unwrap();
+        let mut size = 0;
+        for ref frame in encoder
+            .encode(start.elapsed().as_millis() as _, &yuv, STRIDE_ALIGN)
+            .unwrap()
+        {
+            size += frame.data.len();
+        }
+        encoder.set_lossless(false).unwrap();
+        println!(
+            "AV1 lossless frame: {:?}, {} byte",
+            tmp_timer.elapsed(),
+            size
+        );
+    }
     let mut decoder = AomDecoder::new().unwrap();
     let start = Instant::now();
     for av1 in av1s {
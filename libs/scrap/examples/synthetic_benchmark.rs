@@ -0,0 +1,646 @@
+use docopt::Docopt;
+use hbb_common::{
+    env_logger::{init_from_env, Env, DEFAULT_FILTER_ENV},
+    log,
+    message_proto::{video_frame, VideoFrame},
+    protobuf::Message as _,
+};
+use scrap::{
+    aom::AomEncoderConfig,
+    codec::{Decoder, Encoder, EncoderCfg, Quality as Q},
+    convert_rgba_to_yuv, CodecFormat, DirtyRect, EncodeInput, ImageFormat, ImageRgb, Pixfmt,
+    VpxEncoderConfig, VpxVideoCodecId,
+};
+use std::time::{Duration, Instant};
+
+// Headless and reproducible, unlike the benchmark example which encodes live capture.
+// Every scene is generated from a fixed seed, one json object per scene and codec is printed,
+// logs go to stderr.
+//
+// cargo run --package scrap --example synthetic_benchmark --release --features openh264
+
+const USAGE: &'static str = "
+Codec benchmark on synthetic screen content.
+
+Usage:
+  synthetic_benchmark [--frames=FRAMES] [--width=WIDTH] [--height=HEIGHT] [--fps=FPS] [--quality=QUALITY] [--scenes=SCENES] [--codecs=CODECS] [--i444] [--screen-content]
+  synthetic_benchmark (-h | --help)
+
+Options:
+  -h --help             Show this screen.
+  --frames=FRAMES       Frame count of every scene [default: 150].
+  --width=WIDTH         Frame width [default: 1920].
+  --height=HEIGHT       Frame height [default: 1080].
+  --fps=FPS             Frame rate, for the bitrate and timestamps [default: 30].
+  --quality=QUALITY     Video quality [default: Balanced].
+                        Valid values: Best, Balanced, Low.
+  --scenes=SCENES       Comma separated scenes [default: idle,scrolling_text,window_drag,video].
+  --codecs=CODECS       Comma separated codecs [default: vp8,vp9,av1,h264].
+                        h264 is the software encoder, built with the openh264 feature.
+  --i444                I444, ignored by VP8 and H264.
+  --screen-content      Tune for screen content.
+";
+
+#[derive(Debug, serde::Deserialize, Clone)]
+struct Args {
+    flag_frames: usize,
+    flag_width: usize,
+    flag_height: usize,
+    flag_fps: usize,
+    flag_quality: Quality,
+    flag_scenes: String,
+    flag_codecs: String,
+    flag_i444: bool,
+    flag_screen_content: bool,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy)]
+enum Quality {
+    Best,
+    Balanced,
+    Low,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scene {
+    Idle,
+    ScrollingText,
+    WindowDrag,
+    Video,
+}
+
+impl Scene {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "idle" => Some(Scene::Idle),
+            "scrolling_text" => Some(Scene::ScrollingText),
+            "window_drag" => Some(Scene::WindowDrag),
+            "video" => Some(Scene::Video),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Scene::Idle => "idle",
+            Scene::ScrollingText => "scrolling_text",
+            Scene::WindowDrag => "window_drag",
+            Scene::Video => "video",
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Report {
+    scene: &'static str,
+    codec: String,
+    width: usize,
+    height: usize,
+    frames: usize,
+    fps: usize,
+    quality: Quality,
+    i444: bool,
+    screen_content: bool,
+    // frames the encoder or the decoder did not output
+    dropped: usize,
+    key_frames: usize,
+    bitrate_kbps: f64,
+    avg_frame_bytes: usize,
+    max_frame_bytes: usize,
+    // conversion to yuv included
+    encode_ms: f64,
+    decode_ms: f64,
+    // every source frame against the frame shown for it, the last decoded one if it is dropped
+    psnr: f64,
+    ssim: f64,
+}
+
+fn main() {
+    init_from_env(Env::default().filter_or(DEFAULT_FILTER_ENV, "info"));
+    let mut args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.deserialize())
+        .unwrap_or_else(|e| e.exit());
+    // yuv 420 needs even sizes
+    args.flag_width = args.flag_width.max(64) & !1;
+    args.flag_height = args.flag_height.max(64) & !1;
+    args.flag_fps = args.flag_fps.max(1);
+    let scenes: Vec<Scene> = args
+        .flag_scenes
+        .split(',')
+        .filter_map(|s| {
+            let scene = Scene::from_name(s.trim());
+            if scene.is_none() {
+                log::error!("unknown scene: {s}");
+            }
+            scene
+        })
+        .collect();
+    let codecs: Vec<&str> = args.flag_codecs.split(',').map(|s| s.trim()).collect();
+    let quality = match args.flag_quality {
+        Quality::Best => Q::Best,
+        Quality::Balanced => Q::Balanced,
+        Quality::Low => Q::Low,
+    };
+    for scene in scenes {
+        for codec in codecs.iter() {
+            let Some((cfg, format, i444)) = encoder_config(codec, &args, quality) else {
+                continue;
+            };
+            match run(scene, codec, cfg, format, i444, &args) {
+                Ok(report) => {
+                    println!("{}", serde_json::to_string(&report).unwrap_or_default());
+                }
+                Err(e) => log::error!("{} {codec}: {e:?}", scene.name()),
+            }
+        }
+    }
+}
+
+fn encoder_config(codec: &str, args: &Args, quality: Q) -> Option<(EncoderCfg, CodecFormat, bool)> {
+    let (width, height, i444) = (args.flag_width, args.flag_height, args.flag_i444);
+    let vpx = |codec| {
+        EncoderCfg::VPX(VpxEncoderConfig {
+            width: width as _,
+            height: height as _,
+            quality,
+            codec,
+            keyframe_interval: None,
+        })
+    };
+    match codec {
+        "vp8" => Some((vpx(VpxVideoCodecId::VP8), CodecFormat::VP8, false)),
+        "vp9" => Some((vpx(VpxVideoCodecId::VP9), CodecFormat::VP9, i444)),
+        "av1" => Some((
+            EncoderCfg::AOM(AomEncoderConfig {
+                width: width as _,
+                height: height as _,
+                quality,
+                keyframe_interval: None,
+            }),
+            CodecFormat::AV1,
+            i444,
+        )),
+        #[cfg(feature = "openh264")]
+        "h264" => Some((
+            EncoderCfg::OpenH264(scrap::openh264::OpenH264EncoderConfig {
+                width,
+                height,
+                quality,
+                keyframe_interval: None,
+            }),
+            CodecFormat::H264,
+            false,
+        )),
+        #[cfg(not(feature = "openh264"))]
+        "h264" => {
+            log::warn!("h264 skipped, build with the openh264 feature");
+            None
+        }
+        _ => {
+            log::error!("unknown codec: {codec}");
+            None
+        }
+    }
+}
+
+fn run(
+    scene: Scene,
+    codec: &str,
+    cfg: EncoderCfg,
+    format: CodecFormat,
+    i444: bool,
+    args: &Args,
+) -> hbb_common::ResultType<Report> {
+    let (width, height, fps) = (args.flag_width, args.flag_height, args.flag_fps);
+    let mut encoder = Encoder::new(cfg, i444)?;
+    encoder.set_screen_content(args.flag_screen_content);
+    let mut decoder = Decoder::new(format, None);
+    let mut generator = Generator::new(scene, width, height, fps);
+    let mut yuv = Vec::new();
+    let mut mid_data = Vec::new();
+    let mut rgb = ImageRgb::new(ImageFormat::ARGB, 1);
+    let mut texture = std::ptr::null_mut();
+    let mut pixelbuffer = true;
+    let mut chroma = None;
+    let (mut encode_time, mut decode_time) = (Duration::ZERO, Duration::ZERO);
+    let (mut size, mut max_size, mut key_frames, mut dropped) = (0, 0, 0, 0);
+    let mut encoded = 0;
+    let (mut psnr_sum, mut ssim_sum, mut measured) = (0.0, 0.0, 0);
+    // luma of the last frame decoded
+    let mut shown: Option<Vec<u8>> = None;
+    for i in 0..args.flag_frames {
+        let rects = generator.next_frame(i);
+        let timer = Instant::now();
+        convert_rgba_to_yuv(
+            &generator.canvas.data,
+            Pixfmt::BGRA,
+            width,
+            height,
+            encoder.yuvfmt(),
+            &mut yuv,
+            &mut mid_data,
+        )?;
+        encoder.set_dirty_rects(rects.as_deref());
+        let ms = (i * 1000 / fps) as i64;
+        let vf = encoder.encode_to_message(EncodeInput::YUV(&yuv), ms);
+        encode_time += timer.elapsed();
+        let decoded = 'frame: {
+            let Ok(vf) = vf else {
+                break 'frame false;
+            };
+            encoded += 1;
+            let bytes = vf.compute_size() as usize;
+            size += bytes;
+            max_size = max_size.max(bytes);
+            key_frames += encoded_frames(&vf)
+                .map(|f| f.iter().filter(|f| f.key).count())
+                .unwrap_or_default();
+            let Some(union) = &vf.union else {
+                break 'frame false;
+            };
+            let timer = Instant::now();
+            let decoded = decoder.handle_video_frame(
+                union,
+                &mut rgb,
+                &mut texture,
+                &mut pixelbuffer,
+                &mut chroma,
+            );
+            decode_time += timer.elapsed();
+            matches!(decoded, Ok(true)) && rgb.w == width && rgb.h == height
+        };
+        if decoded {
+            shown = Some(luma(&rgb.raw, rgb.raw.len() / height, width, height));
+        } else {
+            dropped += 1;
+        }
+        // The peer still sees the last frame decoded while the new ones are dropped.
+        let Some(dst) = &shown else {
+            continue;
+        };
+        let src = luma(&generator.canvas.data, width * 4, width, height);
+        psnr_sum += psnr(&src, dst);
+        ssim_sum += ssim(&src, dst, width, height);
+        measured += 1;
+    }
+    let frames = args.flag_frames.max(1);
+    let encoded = encoded.max(1);
+    let measured_f = measured.max(1) as f64;
+    Ok(Report {
+        scene: scene.name(),
+        codec: codec.to_owned(),
+        width,
+        height,
+        frames: args.flag_frames,
+        fps,
+        quality: args.flag_quality,
+        i444,
+        screen_content: args.flag_screen_content,
+        dropped,
+        key_frames,
+        bitrate_kbps: (size * 8 * fps) as f64 / frames as f64 / 1000.0,
+        avg_frame_bytes: size / encoded,
+        max_frame_bytes: max_size,
+        encode_ms: encode_time.as_secs_f64() * 1000.0 / frames as f64,
+        decode_ms: decode_time.as_secs_f64() * 1000.0 / encoded as f64,
+        psnr: psnr_sum / measured_f,
+        ssim: ssim_sum / measured_f,
+    })
+}
+
+fn encoded_frames(vf: &VideoFrame) -> Option<&[hbb_common::message_proto::EncodedVideoFrame]> {
+    match &vf.union {
+        Some(video_frame::Union::Vp8s(f))
+        | Some(video_frame::Union::Vp9s(f))
+        | Some(video_frame::Union::Av1s(f))
+        | Some(video_frame::Union::H264s(f))
+        | Some(video_frame::Union::H265s(f)) => Some(&f.frames),
+        _ => None,
+    }
+}
+
+// BT.601 full range luma of a BGRA image.
+fn luma(data: &[u8], stride: usize, width: usize, height: usize) -> Vec<u8> {
+    let mut y = Vec::with_capacity(width * height);
+    for row in 0..height {
+        for p in data[row * stride..row * stride + width * 4].chunks_exact(4) {
+            let (b, g, r) = (p[0] as u32, p[1] as u32, p[2] as u32);
+            y.push(((77 * r + 150 * g + 29 * b + 128) >> 8) as u8);
+        }
+    }
+    y
+}
+
+fn psnr(a: &[u8], b: &[u8]) -> f64 {
+    let sum: u64 = a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| {
+            let d = *x as i64 - *y as i64;
+            (d * d) as u64
+        })
+        .sum();
+    if sum == 0 {
+        // identical, capped so that the mean stays meaningful
+        return 100.0;
+    }
+    let mse = sum as f64 / a.len() as f64;
+    (10.0 * (255.0 * 255.0 / mse).log10()).min(100.0)
+}
+
+// Mean SSIM over non overlapping 8x8 windows.
+fn ssim(a: &[u8], b: &[u8], width: usize, height: usize) -> f64 {
+    const N: usize = 8;
+    let c1 = (0.01 * 255.0f64).powi(2);
+    let c2 = (0.03 * 255.0f64).powi(2);
+    let (mut sum, mut count) = (0.0, 0);
+    for by in (0..height - N + 1).step_by(N) {
+        for bx in (0..width - N + 1).step_by(N) {
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in by..by + N {
+                for x in bx..bx + N {
+                    let (pa, pb) = (a[y * width + x] as f64, b[y * width + x] as f64);
+                    sa += pa;
+                    sb += pb;
+                    saa += pa * pa;
+                    sbb += pb * pb;
+                    sab += pa * pb;
+                }
+            }
+            let n = (N * N) as f64;
+            let (ma, mb) = (sa / n, sb / n);
+            let va = saa / n - ma * ma;
+            let vb = sbb / n - mb * mb;
+            let cov = sab / n - ma * mb;
+            sum += ((2.0 * ma * mb + c1) * (2.0 * cov + c2))
+                / ((ma * ma + mb * mb + c1) * (va + vb + c2));
+            count += 1;
+        }
+    }
+    sum / count.max(1) as f64
+}
+
+fn hash(x: u64) -> u64 {
+    // splitmix64
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+const CHAR_W: usize = 8;
+const LINE_H: usize = 18;
+const TITLE_H: usize = 28;
+const TEXT: [u8; 4] = [32, 32, 32, 255];
+const PAPER: [u8; 4] = [250, 250, 250, 255];
+const TITLE: [u8; 4] = [200, 120, 40, 255];
+
+struct Canvas {
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; width * height * 4],
+        }
+    }
+
+    // Clipped to the canvas, returns the drawn rect.
+    fn clip(&self, x: i64, y: i64, w: usize, h: usize) -> Option<DirtyRect> {
+        let left = x.max(0) as usize;
+        let top = y.max(0) as usize;
+        let right = ((x + w as i64).max(0) as usize).min(self.width);
+        let bottom = ((y + h as i64).max(0) as usize).min(self.height);
+        if left >= right || top >= bottom {
+            return None;
+        }
+        Some(DirtyRect {
+            x: left,
+            y: top,
+            w: right - left,
+            h: bottom - top,
+        })
+    }
+
+    fn fill(&mut self, x: i64, y: i64, w: usize, h: usize, color: [u8; 4]) {
+        let Some(r) = self.clip(x, y, w, h) else {
+            return;
+        };
+        for row in r.y..r.y + r.h {
+            let start = (row * self.width + r.x) * 4;
+            for p in self.data[start..start + r.w * 4].chunks_exact_mut(4) {
+                p.copy_from_slice(&color);
+            }
+        }
+    }
+
+    fn copy_from(&mut self, other: &Canvas, r: DirtyRect) {
+        for row in r.y..r.y + r.h {
+            let start = (row * self.width + r.x) * 4;
+            self.data[start..start + r.w * 4].copy_from_slice(&other.data[start..start + r.w * 4]);
+        }
+    }
+
+    // Lines of pseudo text, `scroll` is in pixels from the first line of the document.
+    fn text(&mut self, x: i64, y: i64, w: usize, h: usize, seed: u64, scroll: usize) {
+        self.fill(x, y, w, h, PAPER);
+        let Some(r) = self.clip(x, y, w, h) else {
+            return;
+        };
+        for row in r.y..r.y + r.h {
+            let doc_y = (row as i64 - y) as usize + scroll;
+            let line = (doc_y / LINE_H) as u64;
+            let gy = doc_y % LINE_H;
+            // 7 rows of 5 pixels glyphs, doubled vertically
+            if gy < 2 || gy >= 16 {
+                continue;
+            }
+            let glyph_row = (gy - 2) / 2;
+            let line_hash = hash(seed ^ line.wrapping_mul(0x100000001b3));
+            let indent = (line_hash % 4) as usize * 4;
+            let len = if line_hash % 9 == 0 {
+                0
+            } else {
+                20 + (line_hash >> 8) as usize % 80
+            };
+            for col in r.x..r.x + r.w {
+                let doc_x = (col as i64 - x) as usize;
+                let cell = doc_x / CHAR_W;
+                let gx = doc_x % CHAR_W;
+                if cell < indent || cell >= indent + len || gx == 0 || gx > 5 {
+                    continue;
+                }
+                let ch = hash(line_hash ^ cell as u64);
+                // spaces between words
+                if ch % 6 == 0 {
+                    continue;
+                }
+                if (ch >> (8 + glyph_row * 5 + gx - 1)) & 1 == 1 {
+                    let i = (row * self.width + col) * 4;
+                    self.data[i..i + 4].copy_from_slice(&TEXT);
+                }
+            }
+        }
+    }
+
+    fn window(&mut self, x: i64, y: i64, w: usize, h: usize, seed: u64, scroll: usize) {
+        self.fill(x - 1, y - 1, w + 2, h + 2, [90, 90, 90, 255]);
+        self.fill(x, y, w, TITLE_H, TITLE);
+        self.fill(x + w as i64 - 24, y + 6, 16, 16, [60, 60, 220, 255]);
+        self.text(
+            x,
+            y + TITLE_H as i64,
+            w,
+            h.saturating_sub(TITLE_H),
+            seed,
+            scroll,
+        );
+    }
+}
+
+struct Generator {
+    scene: Scene,
+    fps: usize,
+    canvas: Canvas,
+    desktop: Canvas,
+    // x, y, w, h of the scene window
+    window: (i64, i64, usize, usize),
+    last_window: Option<DirtyRect>,
+}
+
+impl Generator {
+    fn new(scene: Scene, width: usize, height: usize, fps: usize) -> Self {
+        let mut desktop = Canvas::new(width, height);
+        for y in 0..height {
+            let t = (y * 255 / height) as u8;
+            desktop.fill(0, y as _, width, 1, [160 - t / 3, 90 + t / 4, 40, 255]);
+        }
+        let taskbar_y = height as i64 - 40;
+        desktop.fill(0, taskbar_y, width, 40, [40, 40, 40, 255]);
+        for i in 0..8 {
+            desktop.fill(12 + i * 48, taskbar_y + 6, 28, 28, [200, 200, 200, 255]);
+        }
+        // background windows
+        let (w, h) = (width * 2 / 5, height * 2 / 5);
+        desktop.window(40, 40, w, h, 1, 0);
+        desktop.window(width as i64 / 2, height as i64 / 3, w, h, 2, 0);
+        let window = match scene {
+            Scene::Idle | Scene::ScrollingText => (
+                width as i64 / 8,
+                height as i64 / 10,
+                width * 3 / 4,
+                height * 3 / 4,
+            ),
+            Scene::WindowDrag => (0, 0, width / 3, height / 3),
+            Scene::Video => {
+                let (w, h) = ((width / 2) & !1, (height / 2) & !1);
+                (width as i64 / 4, height as i64 / 6, w, h)
+            }
+        };
+        Self {
+            scene,
+            fps,
+            canvas: Canvas::new(width, height),
+            desktop,
+            window,
+            last_window: None,
+        }
+    }
+
+    // Draws frame `i`, returns the changed area, None if unknown.
+    fn next_frame(&mut self, i: usize) -> Option<Vec<DirtyRect>> {
+        let (width, height) = (self.canvas.width, self.canvas.height);
+        let (x, y, w, h) = self.window;
+        if i == 0 {
+            self.canvas.data.copy_from_slice(&self.desktop.data);
+        }
+        let mut rects = Vec::new();
+        match self.scene {
+            Scene::Idle => {
+                if i == 0 {
+                    self.canvas.window(x, y, w, h, 3, 0);
+                }
+                // the clock of the taskbar ticks every second, a blinking caret in the window
+                let clock = self
+                    .canvas
+                    .clip(width as i64 - 80, height as i64 - 30, 64, 20);
+                if i % self.fps == 0 {
+                    if let Some(r) = clock {
+                        let seconds = (i / self.fps) as u64;
+                        self.canvas
+                            .text(r.x as _, r.y as _, r.w, r.h, hash(seconds), 0);
+                        rects.push(r);
+                    }
+                }
+                let caret = (x + 200, y + TITLE_H as i64 + LINE_H as i64 * 3);
+                let on = (i * 2 / self.fps) % 2 == 0;
+                self.canvas
+                    .fill(caret.0, caret.1, 2, LINE_H, if on { TEXT } else { PAPER });
+                rects.extend(self.canvas.clip(caret.0, caret.1, 2, LINE_H));
+            }
+            Scene::ScrollingText => {
+                // three lines a second, like reading with a mouse wheel
+                let scroll = i * LINE_H * 3 / self.fps;
+                self.canvas.window(x, y, w, h, 4, scroll);
+                rects.extend(
+                    self.canvas
+                        .clip(x, y + TITLE_H as i64, w, h.saturating_sub(TITLE_H)),
+                );
+            }
+            Scene::WindowDrag => {
+                // across the screen and back in 4 seconds, along a slightly curved path
+                let period = self.fps * 4;
+                let t = (i % period) as f64 / period as f64;
+                let t = if t < 0.5 { t * 2.0 } else { 2.0 - t * 2.0 };
+                let nx = (t * (width - w) as f64) as i64;
+                let ny =
+                    ((height - h) as f64 * (0.2 + 0.3 * (t * std::f64::consts::PI).sin())) as i64;
+                if let Some(last) = self.last_window.take() {
+                    self.canvas.copy_from(&self.desktop, last);
+                    rects.push(last);
+                }
+                self.canvas.window(nx, ny, w, h, 5, 0);
+                self.last_window = self.canvas.clip(nx - 1, ny - 1, w + 2, h + 2);
+                rects.extend(self.last_window);
+            }
+            Scene::Video => {
+                if i == 0 {
+                    self.canvas.window(x, y, w, h + TITLE_H, 6, 0);
+                }
+                let top = y + TITLE_H as i64;
+                if let Some(r) = self.canvas.clip(x, top, w, h) {
+                    self.video(r, i);
+                    rects.push(r);
+                }
+            }
+        }
+        if i == 0 {
+            return None;
+        }
+        Some(rects)
+    }
+
+    // Smooth moving color fields with some grain, closer to camera content than to ui.
+    fn video(&mut self, r: DirtyRect, i: usize) {
+        let t = i as f32 / self.fps as f32;
+        for row in r.y..r.y + r.h {
+            let v = (row - r.y) as f32 / r.h as f32;
+            for col in r.x..r.x + r.w {
+                let u = (col - r.x) as f32 / r.w as f32;
+                let a = (u * 6.0 + t * 1.3).sin() + (v * 5.0 - t * 0.7).cos();
+                let b = ((u + v) * 4.0 + t * 2.1).sin();
+                let grain = (hash((i * r.w * r.h + row * r.w + col) as u64) % 16) as f32 - 8.0;
+                let p = (row * self.canvas.width + col) * 4;
+                self.canvas.data[p] = (128.0 + 60.0 * a + grain).clamp(0.0, 255.0) as u8;
+                self.canvas.data[p + 1] = (128.0 + 50.0 * b + grain).clamp(0.0, 255.0) as u8;
+                self.canvas.data[p + 2] = (128.0 + 40.0 * (a - b) + grain).clamp(0.0, 255.0) as u8;
+                self.canvas.data[p + 3] = 255;
+            }
+        }
+    }
+}
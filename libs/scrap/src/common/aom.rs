@@ -6,7 +6,7 @@
 
 include!(concat!(env!("OUT_DIR"), "/aom_ffi.rs"));
 
-use crate::codec::{base_bitrate, codec_thread_num, Quality};
+use crate::codec::{base_bitrate, codec_thread_num, LongTermRefs, Quality, RefAction, RefBuffer};
 use crate::{codec::EncoderApi, EncodeFrame, STRIDE_ALIGN};
 use crate::{common::GoogleImage, generate_call_macro, generate_call_ptr_macro, Error, Result};
 use crate::{EncodeInput, EncodeYuvFormat, Pixfmt};
@@ -55,6 +55,9 @@ pub struct AomEncoder {
     height: usize,
     i444: bool,
     yuvfmt: EncodeYuvFormat,
+    // (rc_min_quantizer, rc_max_quantizer, rc_dropframe_thresh) to restore after lossless frames.
+    lossy_rc: Option<(u32, u32, u32)>,
+    refs: LongTermRefs,
 }
 
 // https://webrtc.googlesource.com/src/+/refs/heads/main/modules/video_coding/codecs/av1/libaom_av1_encoder.cc
@@ -243,6 +246,8 @@ impl EncoderApi for AomEncoder {
                     height: config.height as _,
                     i444,
                     yuvfmt: Self::get_yuvfmt(config.width, config.height, i444),
+                    lossy_rc: None,
+                    refs: Default::default(),
                 })
             }
             _ => Err(anyhow!("encoder type mismatch")),
@@ -250,13 +255,19 @@ impl EncoderApi for AomEncoder {
     }
 
     fn encode_to_message(&mut self, input: EncodeInput, ms: i64) -> ResultType<VideoFrame> {
+        let action = self.refs.next();
+        let flags = Self::reference_flags(action);
         let mut frames = Vec::new();
         for ref frame in self
-            .encode(ms, input.yuv()?, STRIDE_ALIGN)
+            .encode_with_flags(ms, input.yuv()?, STRIDE_ALIGN, flags)
             .with_context(|| "Failed to encode")?
         {
             frames.push(Self::create_frame(frame));
         }
+        if let Some(frame) = frames.iter_mut().find(|f| f.pts == ms) {
+            frame.recovery = matches!(action, RefAction::Recover(_));
+            self.refs.encoded(action, ms, frame.key);
+        }
         if frames.len() > 0 {
             Ok(Self::create_video_frame(frames))
         } else {
@@ -296,10 +307,61 @@ impl EncoderApi for AomEncoder {
     fn support_abr(&self) -> bool {
         true
     }
+
+    fn support_lossless(&self) -> bool {
+        true
+    }
+
+    fn set_lossless(&mut self, lossless: bool) -> ResultType<()> {
+        if lossless == self.lossy_rc.is_some() {
+            return Ok(());
+        }
+        let mut c = unsafe { *self.ctx.config.enc.to_owned() };
+        if lossless {
+            self.lossy_rc = Some((
+                c.rc_min_quantizer,
+                c.rc_max_quantizer,
+                c.rc_dropframe_thresh,
+            ));
+            c.rc_min_quantizer = 0;
+            c.rc_max_quantizer = 0;
+            // A lossless frame is large, it must not be dropped by the rate control.
+            c.rc_dropframe_thresh = 0;
+        } else if let Some((q_min, q_max, drop_thresh)) = self.lossy_rc.take() {
+            c.rc_min_quantizer = q_min;
+            c.rc_max_quantizer = q_max;
+            c.rc_dropframe_thresh = drop_thresh;
+        }
+        call_aom!(aom_codec_enc_config_set(&mut self.ctx, &c));
+        call_aom!(aom_codec_control(
+            &mut self.ctx,
+            aome_enc_control_id::AV1E_SET_LOSSLESS as _,
+            lossless as i32
+        ));
+        Ok(())
+    }
+
+    fn support_recovery(&self) -> bool {
+        true
+    }
+
+    fn recover(&mut self, last_decoded_pts: i64) {
+        self.refs.recover(last_decoded_pts);
+    }
 }
 
 impl AomEncoder {
     pub fn encode(&mut self, pts: i64, data: &[u8], stride_align: usize) -> Result<EncodeFrames> {
+        self.encode_with_flags(pts, data, stride_align, 0)
+    }
+
+    fn encode_with_flags(
+        &mut self,
+        pts: i64,
+        data: &[u8],
+        stride_align: usize,
+        flags: aom_enc_frame_flags_t,
+    ) -> Result<EncodeFrames> {
         let bpp = if self.i444 { 24 } else { 12 };
         if data.len() < self.width * self.height * bpp / 8 {
             return Err(Error::FailedCall("len not enough".to_string()));
@@ -325,7 +387,7 @@ impl AomEncoder {
             &image,
             pts as _,
             1, // Duration
-            0, // Flags
+            flags,
         ));
 
         Ok(EncodeFrames {
@@ -334,6 +396,30 @@ impl AomEncoder {
         })
     }
 
+    // The last frame buffer is updated by every frame, the others only when asked to.
+    fn reference_flags(action: RefAction) -> aom_enc_frame_flags_t {
+        // A recovery frame must not depend on anything of the frames the decoder may have lost,
+        // including the entropy context and the motion vectors.
+        const RECOVER: u32 = AOM_EFLAG_NO_REF_LAST
+            | AOM_EFLAG_NO_REF_LAST2
+            | AOM_EFLAG_NO_REF_LAST3
+            | AOM_EFLAG_NO_REF_BWD
+            | AOM_EFLAG_NO_REF_ARF2
+            | AOM_EFLAG_NO_REF_FRAME_MVS
+            | AOM_EFLAG_SET_PRIMARY_REF_NONE
+            | AOM_EFLAG_NO_UPD_GF
+            | AOM_EFLAG_NO_UPD_ARF;
+        let flags = match action {
+            RefAction::Normal(None) => AOM_EFLAG_NO_UPD_GF | AOM_EFLAG_NO_UPD_ARF,
+            RefAction::Normal(Some(RefBuffer::Golden)) => AOM_EFLAG_NO_UPD_ARF,
+            RefAction::Normal(Some(RefBuffer::Altref)) => AOM_EFLAG_NO_UPD_GF,
+            RefAction::Recover(RefBuffer::Golden) => RECOVER | AOM_EFLAG_NO_REF_ARF,
+            RefAction::Recover(RefBuffer::Altref) => RECOVER | AOM_EFLAG_NO_REF_GF,
+            RefAction::Key => AOM_EFLAG_FORCE_KF,
+        };
+        flags as _
+    }
+
     #[inline]
     pub fn create_video_frame(frames: Vec<EncodedVideoFrame>) -> VideoFrame {
         let mut vf = VideoFrame::new();
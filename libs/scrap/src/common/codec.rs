@@ -11,6 +11,8 @@ use crate::gpucodec::*;
 use crate::hwcodec::*;
 #[cfg(feature = "mediacodec")]
 use crate::mediacodec::{MediaCodecDecoder, H264_DECODER_SUPPORT, H265_DECODER_SUPPORT};
+#[cfg(feature = "openh264")]
+use crate::openh264::*;
 use crate::{
     aom::{self, AomDecoder, AomEncoder, AomEncoderConfig},
     common::GoogleImage,
@@ -50,6 +52,8 @@ pub enum EncoderCfg {
     HW(HwEncoderConfig),
     #[cfg(feature = "gpucodec")]
     GPU(GpuEncoderConfig),
+    #[cfg(feature = "openh264")]
+    OpenH264(OpenH264EncoderConfig),
 }
 
 pub trait EncoderApi {
@@ -69,6 +73,30 @@ pub trait EncoderApi {
     fn bitrate(&self) -> u32;
 
     fn support_abr(&self) -> bool;
+
+    /// Hint for the next frame: only `rects` changed since the previous one.
+    fn set_dirty_rects(&mut self, _rects: Option<&[crate::DirtyRect]>) {}
+
+    /// Tune for text and UI rather than camera video. Only VP8 and VP9 have such a mode, AV1
+    /// is always tuned for it.
+    fn set_screen_content(&mut self, _screen_content: bool) {}
+
+    fn support_lossless(&self) -> bool {
+        false
+    }
+
+    /// Encode the following frames losslessly, apart from the chroma subsampling.
+    fn set_lossless(&mut self, _lossless: bool) -> ResultType<()> {
+        bail!("lossless is not supported")
+    }
+
+    fn support_recovery(&self) -> bool {
+        false
+    }
+
+    /// A decoder failed after the frame of `last_decoded_pts`. The next frame refers only to a
+    /// long-term reference the decoder still has, or is a key frame if there is none.
+    fn recover(&mut self, _last_decoded_pts: i64) {}
 }
 
 pub struct Encoder {
@@ -89,6 +117,82 @@ impl DerefMut for Encoder {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RefBuffer {
+    Golden = 0,
+    Altref = 1,
+}
+
+/// What the next frame refers to and updates, see `LongTermRefs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RefAction {
+    /// Refers to any buffer, updates the last frame buffer and the given one.
+    Normal(Option<RefBuffer>),
+    /// Refers only to the given buffer.
+    Recover(RefBuffer),
+    Key,
+}
+
+/// Long-term references of the VPX and AOM encoders. The golden and the altref buffer are
+/// updated in turn every `LongTermRefs::INTERVAL` frames and left alone otherwise, so that a
+/// decoder which lost frames can go on from one of them without a key frame.
+#[derive(Debug, Default)]
+pub(crate) struct LongTermRefs {
+    // pts of the frames in the golden and the altref buffer.
+    refs: [Option<i64>; 2],
+    frame_count: usize,
+    recovering: bool,
+}
+
+impl LongTermRefs {
+    const INTERVAL: usize = 60;
+
+    pub fn recover(&mut self, last_decoded_pts: i64) {
+        // Buffers updated after the last decoded frame are broken on the decoder.
+        for r in self.refs.iter_mut() {
+            if r.map_or(false, |pts| pts > last_decoded_pts) {
+                *r = None;
+            }
+        }
+        self.recovering = true;
+    }
+
+    pub fn next(&self) -> RefAction {
+        if self.recovering {
+            return match (self.refs[0], self.refs[1]) {
+                (None, None) => RefAction::Key,
+                (Some(g), Some(a)) if a > g => RefAction::Recover(RefBuffer::Altref),
+                (None, Some(_)) => RefAction::Recover(RefBuffer::Altref),
+                _ => RefAction::Recover(RefBuffer::Golden),
+            };
+        }
+        if self.frame_count % Self::INTERVAL == 0 {
+            if (self.frame_count / Self::INTERVAL) % 2 == 0 {
+                RefAction::Normal(Some(RefBuffer::Golden))
+            } else {
+                RefAction::Normal(Some(RefBuffer::Altref))
+            }
+        } else {
+            RefAction::Normal(None)
+        }
+    }
+
+    /// The frame encoded with `action` is output, i.e. not dropped by the rate control.
+    pub fn encoded(&mut self, action: RefAction, pts: i64, key: bool) {
+        self.frame_count += 1;
+        if key {
+            self.refs = [Some(pts); 2];
+            self.recovering = false;
+            return;
+        }
+        match action {
+            RefAction::Normal(Some(buffer)) => self.refs[buffer as usize] = Some(pts),
+            RefAction::Recover(_) => self.recovering = false,
+            _ => {}
+        }
+    }
+}
+
 pub struct Decoder {
     vp8: Option<VpxDecoder>,
     vp9: Option<VpxDecoder>,
@@ -105,6 +209,8 @@ pub struct Decoder {
     h264_media_codec: MediaCodecDecoder,
     #[cfg(feature = "mediacodec")]
     h265_media_codec: MediaCodecDecoder,
+    #[cfg(feature = "openh264")]
+    h264_sw: Option<OpenH264Decoder>,
     format: CodecFormat,
     valid: bool,
     #[cfg(feature = "hwcodec")]
@@ -154,6 +260,17 @@ impl Encoder {
                     Err(e)
                 }
             },
+            #[cfg(feature = "openh264")]
+            EncoderCfg::OpenH264(_) => match OpenH264Encoder::new(config, i444) {
+                Ok(sw) => Ok(Encoder {
+                    codec: Box::new(sw),
+                }),
+                Err(e) => {
+                    log::error!("new openh264 encoder failed: {e:?}");
+                    *ENCODE_CODEC_NAME.lock().unwrap() = CodecName::VP9;
+                    Err(e)
+                }
+            },
         }
     }
 
@@ -216,8 +333,10 @@ impl Encoder {
                 h265hw_encoding = best.h265.map_or(None, |c| Some(c.name));
             }
         }
-        let h264_useable =
-            _all_support_h264_decoding && (h264gpu_encoding || h264hw_encoding.is_some());
+        // The software encoder is only used if the peers ask for h264 and there is no hardware one.
+        let h264sw_encoding = cfg!(feature = "openh264") && _all_support_h264_decoding;
+        let h264_useable = _all_support_h264_decoding
+            && (h264gpu_encoding || h264hw_encoding.is_some() || h264sw_encoding);
         let h265_useable =
             _all_support_h265_decoding && (h265gpu_encoding || h265hw_encoding.is_some());
         let mut name = ENCODE_CODEC_NAME.lock().unwrap();
@@ -258,6 +377,8 @@ impl Encoder {
                     CodecName::H264GPU
                 } else if let Some(v) = h264hw_encoding {
                     CodecName::H264HW(v)
+                } else if h264sw_encoding {
+                    CodecName::H264SW
                 } else {
                     auto_codec
                 }
@@ -315,6 +436,10 @@ impl Encoder {
             encoding.h264 |= GpuEncoder::available(CodecName::H264GPU).len() > 0;
             encoding.h265 |= GpuEncoder::available(CodecName::H265GPU).len() > 0;
         }
+        #[cfg(feature = "openh264")]
+        {
+            encoding.h264 = true;
+        }
         encoding
     }
 
@@ -345,6 +470,8 @@ impl Encoder {
                     return;
                 }
             },
+            #[cfg(feature = "openh264")]
+            EncoderCfg::OpenH264(_) => CodecName::H264SW,
         };
         let current = ENCODE_CODEC_NAME.lock().unwrap().clone();
         if current != name {
@@ -368,6 +495,8 @@ impl Encoder {
             EncoderCfg::HW(_) => false,
             #[cfg(feature = "gpucodec")]
             EncoderCfg::GPU(_) => false,
+            #[cfg(feature = "openh264")]
+            EncoderCfg::OpenH264(_) => false,
         };
         prefer_i444 && i444_useable && !decodings.is_empty()
     }
@@ -431,6 +560,10 @@ impl Decoder {
                     0
                 };
         }
+        #[cfg(feature = "openh264")]
+        {
+            decoding.ability_h264 = 1;
+        }
         for unsupported in mark_unsupported {
             match unsupported {
                 CodecFormat::VP8 => decoding.ability_vp8 = 0,
@@ -453,6 +586,8 @@ impl Decoder {
         let (mut h264_vram, mut h265_vram) = (None, None);
         #[cfg(feature = "mediacodec")]
         let (mut h264_media_codec, mut h265_media_codec) = (None, None);
+        #[cfg(feature = "openh264")]
+        let mut h264_sw = None;
         let mut valid = false;
 
         match format {
@@ -506,6 +641,14 @@ impl Decoder {
                     }
                     valid = h264_media_codec.is_some();
                 }
+                #[cfg(feature = "openh264")]
+                if !valid {
+                    match OpenH264Decoder::new() {
+                        Ok(v) => h264_sw = Some(v),
+                        Err(e) => log::error!("create H264 software decoder failed: {}", e),
+                    }
+                    valid = h264_sw.is_some();
+                }
             }
             CodecFormat::H265 => {
                 #[cfg(feature = "gpucodec")]
@@ -558,6 +701,8 @@ impl Decoder {
             h264_media_codec,
             #[cfg(feature = "mediacodec")]
             h265_media_codec,
+            #[cfg(feature = "openh264")]
+            h264_sw,
             format,
             valid,
             #[cfg(feature = "hwcodec")]
@@ -604,7 +749,7 @@ impl Decoder {
                     bail!("av1 decoder not available");
                 }
             }
-            #[cfg(any(feature = "hwcodec", feature = "gpucodec"))]
+            #[cfg(any(feature = "hwcodec", feature = "gpucodec", feature = "openh264"))]
             video_frame::Union::H264s(h264s) => {
                 *chroma = Some(Chroma::I420);
                 #[cfg(feature = "gpucodec")]
@@ -616,6 +761,10 @@ impl Decoder {
                 if let Some(decoder) = &mut self.h264_ram {
                     return Decoder::handle_hw_video_frame(decoder, h264s, rgb, &mut self.i420);
                 }
+                #[cfg(feature = "openh264")]
+                if let Some(decoder) = &mut self.h264_sw {
+                    return Decoder::handle_openh264_video_frame(decoder, h264s, rgb);
+                }
                 Err(anyhow!("don't support h264!"))
             }
             #[cfg(any(feature = "hwcodec", feature = "gpucodec"))]
@@ -728,6 +877,22 @@ impl Decoder {
         return Ok(ret);
     }
 
+    // rgb [in/out] fmt and stride must be set in ImageRgb
+    #[cfg(feature = "openh264")]
+    fn handle_openh264_video_frame(
+        decoder: &mut OpenH264Decoder,
+        frames: &EncodedVideoFrames,
+        rgb: &mut ImageRgb,
+    ) -> ResultType<bool> {
+        let mut ret = false;
+        for h264 in frames.frames.iter() {
+            if decoder.decode(&h264.data, rgb)? {
+                ret = true;
+            }
+        }
+        Ok(ret)
+    }
+
     #[cfg(feature = "gpucodec")]
     fn handle_gpu_video_frame(
         decoder: &mut GpuDecoder,
@@ -893,3 +1058,65 @@ pub fn codec_thread_num(limit: usize) -> usize {
     }
     res
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Encode `n` frames as the encoder does, pts = frame number.
+    fn encode(refs: &mut LongTermRefs, pts: &mut i64, n: usize) {
+        for _ in 0..n {
+            let action = refs.next();
+            refs.encoded(action, *pts, action == RefAction::Key || *pts == 0);
+            *pts += 1;
+        }
+    }
+
+    #[test]
+    fn test_long_term_refs() {
+        let mut refs = LongTermRefs::default();
+        let mut pts = 0;
+        // the key frame fills both buffers, then golden and altref are updated in turn
+        assert_eq!(refs.next(), RefAction::Normal(Some(RefBuffer::Golden)));
+        encode(&mut refs, &mut pts, 1);
+        assert_eq!(refs.refs, [Some(0), Some(0)]);
+        assert_eq!(refs.next(), RefAction::Normal(None));
+        encode(&mut refs, &mut pts, LongTermRefs::INTERVAL - 1);
+        assert_eq!(refs.next(), RefAction::Normal(Some(RefBuffer::Altref)));
+        encode(&mut refs, &mut pts, LongTermRefs::INTERVAL);
+        assert_eq!(refs.refs, [Some(0), Some(60)]);
+        encode(&mut refs, &mut pts, 1);
+        assert_eq!(refs.refs, [Some(120), Some(60)]);
+
+        // lost after the golden update: go on from the altref
+        refs.recover(100);
+        assert_eq!(refs.refs, [None, Some(60)]);
+        assert_eq!(refs.next(), RefAction::Recover(RefBuffer::Altref));
+        encode(&mut refs, &mut pts, 1);
+        assert_eq!(refs.next(), RefAction::Normal(None));
+
+        // both newer than the last decoded frame: key frame
+        refs.recover(10);
+        assert_eq!(refs.next(), RefAction::Key);
+        encode(&mut refs, &mut pts, 1);
+        assert_eq!(refs.refs, [Some(pts - 1); 2]);
+
+        // from before the first frame, to ask for a key frame
+        refs.recover(i64::MIN);
+        assert_eq!(refs.next(), RefAction::Key);
+    }
+
+    #[test]
+    fn test_long_term_refs_newest() {
+        let mut refs = LongTermRefs::default();
+        let mut pts = 0;
+        encode(&mut refs, &mut pts, 2 * LongTermRefs::INTERVAL + 1);
+        assert_eq!(refs.refs, [Some(120), Some(60)]);
+        // both still valid: the newest is used
+        refs.recover(pts - 1);
+        assert_eq!(refs.next(), RefAction::Recover(RefBuffer::Golden));
+        // asked again before the recovery frame is out: the same buffer
+        refs.recover(pts - 1);
+        assert_eq!(refs.next(), RefAction::Recover(RefBuffer::Golden));
+    }
+}
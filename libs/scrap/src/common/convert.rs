@@ -8,19 +8,19 @@ include!(concat!(env!("OUT_DIR"), "/yuv_ffi.rs"));
 
 #[cfg(not(target_os = "ios"))]
 use crate::PixelBuffer;
-use crate::{generate_call_macro, EncodeYuvFormat, TraitPixelBuffer};
+use crate::{generate_call_macro, DirtyRect, EncodeYuvFormat, TraitPixelBuffer};
 use hbb_common::{bail, log, ResultType};
 
 generate_call_macro!(call_yuv, false);
 
-#[cfg(feature = "hwcodec")]
+#[cfg(any(feature = "hwcodec", feature = "openh264"))]
 pub mod hw {
     use super::*;
     use crate::ImageFormat;
-    #[cfg(target_os = "windows")]
+    #[cfg(all(target_os = "windows", feature = "hwcodec"))]
     use hwcodec::{ffmpeg::ffmpeg_linesize_offset_length, AVPixelFormat};
 
-    #[cfg(target_os = "windows")]
+    #[cfg(all(target_os = "windows", feature = "hwcodec"))]
     pub fn hw_nv12_to(
         fmt: ImageFormat,
         width: usize,
@@ -200,11 +200,42 @@ pub fn convert_to_yuv(
     dst: &mut Vec<u8>,
     mid_data: &mut Vec<u8>,
 ) -> ResultType<()> {
-    let src = captured.data();
+    convert_to_yuv_cropped(captured, None, dst_fmt, dst, mid_data)
+}
+
+/// Converts only the `crop` area of `captured` if set, `dst_fmt` is of the size of the area.
+pub fn convert_to_yuv_cropped(
+    captured: &PixelBuffer,
+    crop: Option<DirtyRect>,
+    dst_fmt: EncodeYuvFormat,
+    dst: &mut Vec<u8>,
+    mid_data: &mut Vec<u8>,
+) -> ResultType<()> {
+    let data = captured.data();
     let src_stride = captured.stride();
     let src_pixfmt = captured.pixfmt();
-    let src_width = captured.width();
-    let src_height = captured.height();
+    let (src, src_width, src_height) = match crop {
+        None => (data, captured.width(), captured.height()),
+        Some(r) => {
+            if src_pixfmt != crate::Pixfmt::BGRA && src_pixfmt != crate::Pixfmt::RGBA {
+                bail!("crop not support, {src_pixfmt:?}");
+            }
+            if r.w == 0 || r.h == 0 || r.x + r.w > captured.width() || r.y + r.h > captured.height()
+            {
+                bail!(
+                    "crop rect {r:?} out of ({}, {})",
+                    captured.width(),
+                    captured.height()
+                );
+            }
+            // The rows of the area keep the stride of the frame.
+            let offset = r.y * src_stride[0] + r.x * 4;
+            if data.len() < offset + src_stride[0] * (r.h - 1) + r.w * 4 {
+                bail!("wrong src len {} for crop rect {r:?}", data.len());
+            }
+            (&data[offset..], r.w, r.h)
+        }
+    };
     if src_width > dst_fmt.w || src_height > dst_fmt.h {
         bail!(
             "src rect > dst rect: ({src_width}, {src_height}) > ({},{})",
@@ -212,7 +243,7 @@ pub fn convert_to_yuv(
             dst_fmt.h
         );
     }
-    if src_pixfmt == crate::Pixfmt::BGRA || src_pixfmt == crate::Pixfmt::RGBA {
+    if crop.is_none() && (src_pixfmt == crate::Pixfmt::BGRA || src_pixfmt == crate::Pixfmt::RGBA) {
         if src.len() < src_stride[0] * src_height {
             bail!(
                 "wrong src len, {} < {} * {}",
@@ -222,6 +253,62 @@ pub fn convert_to_yuv(
             );
         }
     }
+    rgba_to_yuv(
+        src,
+        src_stride[0],
+        src_pixfmt,
+        src_width,
+        src_height,
+        dst_fmt,
+        dst,
+        mid_data,
+    )
+}
+
+/// Converts a tightly packed BGRA or RGBA image which does not come from a capturer,
+/// e.g. a generated one.
+pub fn convert_rgba_to_yuv(
+    src: &[u8],
+    src_pixfmt: crate::Pixfmt,
+    width: usize,
+    height: usize,
+    dst_fmt: EncodeYuvFormat,
+    dst: &mut Vec<u8>,
+    mid_data: &mut Vec<u8>,
+) -> ResultType<()> {
+    if src.len() < width * height * 4 {
+        bail!("wrong src len, {} < {} * {} * 4", src.len(), width, height);
+    }
+    if width > dst_fmt.w || height > dst_fmt.h {
+        bail!(
+            "src rect > dst rect: ({width}, {height}) > ({},{})",
+            dst_fmt.w,
+            dst_fmt.h
+        );
+    }
+    rgba_to_yuv(
+        src,
+        width * 4,
+        src_pixfmt,
+        width,
+        height,
+        dst_fmt,
+        dst,
+        mid_data,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn rgba_to_yuv(
+    src: &[u8],
+    src_stride: usize,
+    src_pixfmt: crate::Pixfmt,
+    src_width: usize,
+    src_height: usize,
+    dst_fmt: EncodeYuvFormat,
+    dst: &mut Vec<u8>,
+    mid_data: &mut Vec<u8>,
+) -> ResultType<()> {
     let align = |x:usize| {
         (x + 63) / 64 * 64
     };
@@ -241,7 +328,7 @@ pub fn convert_to_yuv(
             };
             call_yuv!(f(
                 src.as_ptr(),
-                src_stride[0] as _,
+                src_stride as _,
                 dst_y,
                 dst_stride_y as _,
                 dst_u,
@@ -268,7 +355,7 @@ pub fn convert_to_yuv(
             };
             call_yuv!(f(
                 src.as_ptr(),
-                src_stride[0] as _,
+                src_stride as _,
                 dst_y,
                 dst_stride_y as _,
                 dst_uv,
@@ -294,9 +381,9 @@ pub fn convert_to_yuv(
                 mid_data.resize(src.len(), 0);
                 call_yuv!(ABGRToARGB(
                     src.as_ptr(),
-                    src_stride[0] as _,
+                    src_stride as _,
                     mid_data.as_mut_ptr(),
-                    src_stride[0] as _,
+                    src_stride as _,
                     src_width as _,
                     src_height as _,
                 ));
@@ -304,7 +391,7 @@ pub fn convert_to_yuv(
             };
             call_yuv!(ARGBToI444(
                 src.as_ptr(),
-                src_stride[0] as _,
+                src_stride as _,
                 dst_y,
                 dst_stride_y as _,
                 dst_u,
@@ -324,3 +411,46 @@ pub fn convert_to_yuv(
     }
     Ok(())
 }
+
+/// Scales the planar yuv of `src_fmt` to the size of `dst_fmt`, for an encoder of another size.
+pub fn scale_yuv(
+    src_fmt: &EncodeYuvFormat,
+    src: &[u8],
+    dst_fmt: &EncodeYuvFormat,
+    dst: &mut Vec<u8>,
+) -> ResultType<()> {
+    let align = |x: usize| (x + 63) / 64 * 64;
+    let f = match (src_fmt.pixfmt, dst_fmt.pixfmt) {
+        (crate::Pixfmt::I420, crate::Pixfmt::I420) => I420Scale,
+        (crate::Pixfmt::I444, crate::Pixfmt::I444) => I444Scale,
+        (from, to) => bail!("scale not support, {from:?} -> {to:?}"),
+    };
+    dst.resize(
+        align(dst_fmt.h)
+            * (align(dst_fmt.stride[0]) + align(dst_fmt.stride[1]) + align(dst_fmt.stride[2])),
+        0,
+    );
+    let dst_y = dst.as_mut_ptr();
+    let dst_u = dst[dst_fmt.u..].as_mut_ptr();
+    let dst_v = dst[dst_fmt.v..].as_mut_ptr();
+    call_yuv!(f(
+        src.as_ptr(),
+        src_fmt.stride[0] as _,
+        src[src_fmt.u..].as_ptr(),
+        src_fmt.stride[1] as _,
+        src[src_fmt.v..].as_ptr(),
+        src_fmt.stride[2] as _,
+        src_fmt.w as _,
+        src_fmt.h as _,
+        dst_y,
+        dst_fmt.stride[0] as _,
+        dst_u,
+        dst_fmt.stride[1] as _,
+        dst_v,
+        dst_fmt.stride[2] as _,
+        dst_fmt.w as _,
+        dst_fmt.h as _,
+        FilterMode::kFilterBox,
+    ));
+    Ok(())
+}
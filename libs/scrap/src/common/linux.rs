@@ -4,7 +4,7 @@ use crate::{
         x11::{self},
         TraitCapturer,
     },
-    Frame,
+    Frame, WindowInfo, WindowState,
 };
 use std::{io, time::Duration};
 
@@ -43,6 +43,20 @@ impl TraitCapturer for Capturer {
             Capturer::WAYLAND(d) => d.frame(timeout),
         }
     }
+
+    fn window_state(&self) -> Option<WindowState> {
+        match self {
+            Capturer::X11(d) => d.window_state(),
+            Capturer::WAYLAND(_) => None,
+        }
+    }
+
+    fn invalidate(&mut self) {
+        match self {
+            Capturer::X11(d) => d.invalidate(),
+            Capturer::WAYLAND(_) => {}
+        }
+    }
 }
 
 pub enum Display {
@@ -73,6 +87,15 @@ impl Display {
         })
     }
 
+    /// Only X11, on Wayland the window is picked in the screencast dialog.
+    pub fn window(id: u64) -> io::Result<Display> {
+        if super::is_x11() {
+            Ok(Display::X11(x11::Display::window(id)?))
+        } else {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+
     pub fn width(&self) -> usize {
         match self {
             Display::X11(d) => d.width(),
@@ -115,3 +138,11 @@ impl Display {
         }
     }
 }
+
+pub fn windows() -> io::Result<Vec<WindowInfo>> {
+    if super::is_x11() {
+        x11::windows()
+    } else {
+        Ok(vec![])
+    }
+}
@@ -43,6 +43,8 @@ pub mod gpucodec;
 pub mod hwcodec;
 #[cfg(feature = "mediacodec")]
 pub mod mediacodec;
+#[cfg(feature = "openh264")]
+pub mod openh264;
 pub mod vpxcodec;
 pub use self::convert::*;
 pub const STRIDE_ALIGN: usize = 64; // commonly used in libvpx vpx_img_alloc caller
@@ -88,6 +90,39 @@ impl ImageRgb {
     pub fn stride(&self) -> usize {
         self.stride
     }
+
+    /// Scale up `factor` times by repeating the pixels, for the frames sent smaller.
+    pub fn scale_up(&mut self, factor: usize) {
+        if factor < 2 || self.w == 0 || self.h == 0 {
+            return;
+        }
+        let bytes_per_pixel = match self.fmt {
+            ImageFormat::Raw => 3,
+            ImageFormat::ARGB | ImageFormat::ABGR => 4,
+        };
+        let src_row = self.raw.len() / self.h;
+        let (w, h) = (self.w * factor, self.h * factor);
+        let align = self.stride.max(1);
+        let dst_row = (w * bytes_per_pixel + align - 1) / align * align;
+        let mut raw = vec![0; h * dst_row];
+        for (y, rows) in raw.chunks_exact_mut(dst_row * factor).enumerate() {
+            let src = &self.raw[y * src_row..];
+            let (row, rest) = rows.split_at_mut(dst_row);
+            for (x, pixel) in row[..w * bytes_per_pixel]
+                .chunks_exact_mut(bytes_per_pixel)
+                .enumerate()
+            {
+                let i = x / factor * bytes_per_pixel;
+                pixel.copy_from_slice(&src[i..i + bytes_per_pixel]);
+            }
+            for copy in rest.chunks_exact_mut(dst_row) {
+                copy.copy_from_slice(row);
+            }
+        }
+        self.raw = raw;
+        self.w = w;
+        self.h = h;
+    }
 }
 
 #[inline]
@@ -116,6 +151,37 @@ pub trait TraitCapturer {
 
     #[cfg(feature = "gpucodec")]
     fn set_output_texture(&mut self, texture: bool);
+
+    /// Where the captured window is, if capturing a window instead of a display.
+    fn window_state(&self) -> Option<WindowState> {
+        None
+    }
+
+    /// The next frame is a whole one even if nothing changed, e.g. another part of it is
+    /// encoded now. Capturers which return only changed frames implement it.
+    fn invalidate(&mut self) {}
+}
+
+/// A window which can be shared instead of a whole display.
+#[derive(Debug, Clone)]
+pub struct WindowInfo {
+    pub id: u64,
+    pub title: String,
+    pub app: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowState {
+    pub x: i32,
+    pub y: i32,
+    pub width: usize,
+    pub height: usize,
+    // The window, or another window of the same process, has the focus.
+    pub active: bool,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -135,6 +201,111 @@ impl Default for AdapterDevice {
     }
 }
 
+/// Changed area of a frame, in pixels of the captured display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
+impl DirtyRect {
+    pub fn area(&self) -> usize {
+        self.w * self.h
+    }
+
+    /// The part of the rect inside `area`, relative to the origin of `area`.
+    pub fn crop(&self, area: &DirtyRect) -> Option<DirtyRect> {
+        let x = self.x.max(area.x);
+        let y = self.y.max(area.y);
+        let right = (self.x + self.w).min(area.x + area.w);
+        let bottom = (self.y + self.h).min(area.y + area.h);
+        if right <= x || bottom <= y {
+            return None;
+        }
+        Some(DirtyRect {
+            x: x - area.x,
+            y: y - area.y,
+            w: right - x,
+            h: bottom - y,
+        })
+    }
+}
+
+/// More rectangles than this are merged into their bounding box.
+pub const MAX_DIRTY_RECTS: usize = 64;
+
+/// The areas changed since the last frame the encoder emitted. A frame dropped by the rate
+/// control is not on the peer, its areas are encoded with the next frame.
+#[derive(Debug, Default)]
+pub struct DirtyRegion {
+    rects: Vec<DirtyRect>,
+    // The whole frame.
+    full: bool,
+}
+
+impl DirtyRegion {
+    /// Add the changed areas of a captured frame, `None` if they are unknown.
+    pub fn add(&mut self, rects: Option<&[DirtyRect]>) {
+        let Some(rects) = rects else {
+            self.full = true;
+            self.rects.clear();
+            return;
+        };
+        if self.full {
+            return;
+        }
+        for r in rects {
+            if !self.rects.contains(r) {
+                self.rects.push(*r);
+            }
+        }
+        if self.rects.len() > MAX_DIRTY_RECTS {
+            let l = self.rects.iter().map(|r| r.x).min().unwrap_or_default();
+            let t = self.rects.iter().map(|r| r.y).min().unwrap_or_default();
+            let r = self
+                .rects
+                .iter()
+                .map(|r| r.x + r.w)
+                .max()
+                .unwrap_or_default();
+            let b = self
+                .rects
+                .iter()
+                .map(|r| r.y + r.h)
+                .max()
+                .unwrap_or_default();
+            self.rects.clear();
+            self.rects.push(DirtyRect {
+                x: l,
+                y: t,
+                w: r - l,
+                h: b - t,
+            });
+        }
+    }
+
+    /// The areas to encode, `None` for the whole frame.
+    pub fn rects(&self) -> Option<&[DirtyRect]> {
+        if self.full {
+            None
+        } else {
+            Some(&self.rects)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.full && self.rects.is_empty()
+    }
+
+    /// A frame is emitted, the peer is up to date.
+    pub fn clear(&mut self) {
+        self.full = false;
+        self.rects.clear();
+    }
+}
+
 pub trait TraitPixelBuffer {
     fn data(&self) -> &[u8];
 
@@ -145,6 +316,11 @@ pub trait TraitPixelBuffer {
     fn stride(&self) -> Vec<usize>;
 
     fn pixfmt(&self) -> Pixfmt;
+
+    /// Areas changed since the previous frame, `None` if unknown, i.e. the whole frame.
+    fn dirty_rects(&self) -> Option<&[DirtyRect]> {
+        None
+    }
 }
 
 #[cfg(not(any(target_os = "ios")))]
@@ -162,17 +338,36 @@ impl Frame<'_> {
         }
     }
 
+    pub fn dirty_rects(&self) -> Option<&[DirtyRect]> {
+        match self {
+            Frame::PixelBuffer(pixelbuffer) => pixelbuffer.dirty_rects(),
+            Frame::Texture(_) => None,
+        }
+    }
+
     pub fn to<'a>(
         &'a self,
         yuvfmt: EncodeYuvFormat,
         yuv: &'a mut Vec<u8>,
         mid_data: &mut Vec<u8>,
+    ) -> ResultType<EncodeInput> {
+        self.to_cropped(None, yuvfmt, yuv, mid_data)
+    }
+
+    /// Like `to`, only the `crop` area of the frame is converted if set.
+    pub fn to_cropped<'a>(
+        &'a self,
+        crop: Option<DirtyRect>,
+        yuvfmt: EncodeYuvFormat,
+        yuv: &'a mut Vec<u8>,
+        mid_data: &mut Vec<u8>,
     ) -> ResultType<EncodeInput> {
         match self {
             Frame::PixelBuffer(pixelbuffer) => {
-                convert_to_yuv(&pixelbuffer, yuvfmt, yuv, mid_data)?;
+                convert_to_yuv_cropped(&pixelbuffer, crop, yuvfmt, yuv, mid_data)?;
                 Ok(EncodeInput::YUV(yuv))
             }
+            Frame::Texture(_) if crop.is_some() => bail!("crop not support for texture"),
             Frame::Texture(texture) => Ok(EncodeInput::Texture(*texture)),
         }
     }
@@ -249,6 +444,7 @@ pub enum CodecName {
     H265HW(String),
     H264GPU,
     H265GPU,
+    H264SW,
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -280,7 +476,7 @@ impl From<&CodecName> for CodecFormat {
             CodecName::VP8 => Self::VP8,
             CodecName::VP9 => Self::VP9,
             CodecName::AV1 => Self::AV1,
-            CodecName::H264HW(_) | CodecName::H264GPU => Self::H264,
+            CodecName::H264HW(_) | CodecName::H264GPU | CodecName::H264SW => Self::H264,
             CodecName::H265HW(_) | CodecName::H265GPU => Self::H265,
         }
     }
@@ -481,3 +677,59 @@ pub trait GoogleImage {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dirty_rect_crop() {
+        let rect = |x, y, w, h| DirtyRect { x, y, w, h };
+        let area = rect(20, 20, 100, 100);
+        assert_eq!(rect(10, 10, 20, 20).crop(&area), Some(rect(0, 0, 10, 10)));
+        assert_eq!(rect(30, 40, 10, 10).crop(&area), Some(rect(10, 20, 10, 10)));
+        assert_eq!(
+            rect(110, 110, 50, 50).crop(&area),
+            Some(rect(90, 90, 10, 10))
+        );
+        assert_eq!(rect(0, 0, 20, 20).crop(&area), None);
+        assert_eq!(rect(120, 20, 10, 10).crop(&area), None);
+    }
+
+    #[test]
+    fn test_dirty_region() {
+        let rect = |x, y| DirtyRect { x, y, w: 10, h: 10 };
+        let mut region = DirtyRegion::default();
+        assert!(region.is_empty());
+        region.add(Some(&[]));
+        assert!(region.is_empty());
+
+        // A dropped frame is encoded again with the next one.
+        region.add(Some(&[rect(0, 0)]));
+        region.add(Some(&[rect(0, 0), rect(20, 0)]));
+        assert_eq!(region.rects(), Some(&[rect(0, 0), rect(20, 0)][..]));
+        region.clear();
+        assert!(region.is_empty());
+
+        region.add(Some(&[rect(0, 0)]));
+        region.add(None);
+        region.add(Some(&[rect(20, 0)]));
+        assert_eq!(region.rects(), None);
+        assert!(!region.is_empty());
+        region.clear();
+
+        let many: Vec<_> = (0..=MAX_DIRTY_RECTS).map(|i| rect(i * 10, i)).collect();
+        region.add(Some(&many));
+        assert_eq!(
+            region.rects(),
+            Some(
+                &[DirtyRect {
+                    x: 0,
+                    y: 0,
+                    w: MAX_DIRTY_RECTS * 10 + 10,
+                    h: MAX_DIRTY_RECTS + 10,
+                }][..]
+            )
+        );
+    }
+}
@@ -0,0 +1,255 @@
+// Software H.264 with Cisco's OpenH264, for machines without a hardware codec.
+
+use crate::{
+    codec::{base_bitrate, EncoderApi, EncoderCfg, Quality},
+    hw, EncodeInput, EncodeYuvFormat, ImageRgb, Pixfmt,
+};
+use hbb_common::{
+    anyhow::{anyhow, Context},
+    bail,
+    bytes::Bytes,
+    log,
+    message_proto::{EncodedVideoFrame, EncodedVideoFrames, VideoFrame},
+    ResultType,
+};
+use openh264::{
+    decoder::Decoder,
+    encoder::{Encoder, EncoderConfig, FrameType, RateControlMode},
+    formats::YUVSource,
+};
+use openh264_sys2::{SBitrateInfo, ENCODER_OPTION_BITRATE, SPATIAL_LAYER_ALL};
+
+const MAX_FRAME_RATE: f32 = 30.0;
+
+#[derive(Debug, Clone)]
+pub struct OpenH264EncoderConfig {
+    pub width: usize,
+    pub height: usize,
+    pub quality: Quality,
+    pub keyframe_interval: Option<usize>,
+}
+
+pub struct OpenH264Encoder {
+    encoder: Encoder,
+    width: usize,
+    height: usize,
+    bitrate: u32, // kbs
+    keyframe_interval: Option<usize>,
+    frame_count: usize,
+    yuvfmt: EncodeYuvFormat,
+}
+
+impl EncoderApi for OpenH264Encoder {
+    fn new(cfg: EncoderCfg, _i444: bool) -> ResultType<Self>
+    where
+        Self: Sized,
+    {
+        match cfg {
+            EncoderCfg::OpenH264(config) => {
+                let bitrate = Self::bitrate_of(config.width, config.height, config.quality);
+                let encoder_config = EncoderConfig::new(config.width as _, config.height as _)
+                    .set_bitrate_bps(bitrate * 1000)
+                    .max_frame_rate(MAX_FRAME_RATE)
+                    .rate_control_mode(RateControlMode::Bitrate)
+                    // Idle screens produce no frames anyway, skipping busy ones only adds latency.
+                    .enable_skip_frame(false);
+                let encoder = Encoder::with_config(encoder_config)
+                    .map_err(|e| anyhow!("Failed to create openh264 encoder: {e:?}"))?;
+                Ok(OpenH264Encoder {
+                    encoder,
+                    width: config.width,
+                    height: config.height,
+                    bitrate,
+                    keyframe_interval: config.keyframe_interval,
+                    frame_count: 0,
+                    yuvfmt: Self::get_yuvfmt(config.width, config.height),
+                })
+            }
+            _ => Err(anyhow!("encoder type mismatch")),
+        }
+    }
+
+    fn encode_to_message(&mut self, input: EncodeInput, ms: i64) -> ResultType<VideoFrame> {
+        if let Some(interval) = self.keyframe_interval {
+            if interval > 0 && self.frame_count % interval == 0 && self.frame_count > 0 {
+                self.encoder.force_intra_frame();
+            }
+        }
+        self.frame_count += 1;
+        let source = I420Source {
+            fmt: &self.yuvfmt,
+            data: input.yuv()?,
+        };
+        let bitstream = self
+            .encoder
+            .encode(&source)
+            .map_err(|e| anyhow!("{e:?}"))
+            .with_context(|| "Failed to encode")?;
+        let key = match bitstream.frame_type() {
+            FrameType::IDR | FrameType::I => true,
+            FrameType::P | FrameType::IPMixed => false,
+            _ => return Err(anyhow!("no valid frame")),
+        };
+        let data = bitstream.to_vec();
+        if data.is_empty() {
+            return Err(anyhow!("no valid frame"));
+        }
+        let mut vf = VideoFrame::new();
+        vf.set_h264s(EncodedVideoFrames {
+            frames: vec![EncodedVideoFrame {
+                data: Bytes::from(data),
+                pts: ms,
+                key,
+                ..Default::default()
+            }]
+            .into(),
+            ..Default::default()
+        });
+        Ok(vf)
+    }
+
+    fn yuvfmt(&self) -> EncodeYuvFormat {
+        self.yuvfmt.clone()
+    }
+
+    #[cfg(feature = "gpucodec")]
+    fn input_texture(&self) -> bool {
+        false
+    }
+
+    fn set_quality(&mut self, quality: Quality) -> ResultType<()> {
+        let bitrate = Self::bitrate_of(self.width, self.height, quality);
+        if bitrate > 0 && bitrate != self.bitrate {
+            let mut info = SBitrateInfo {
+                iLayer: SPATIAL_LAYER_ALL,
+                iBitrate: (bitrate * 1000) as _,
+            };
+            let ret = unsafe {
+                self.encoder
+                    .raw_api()
+                    .set_option(ENCODER_OPTION_BITRATE, &mut info as *mut _ as _)
+            };
+            if ret != 0 {
+                bail!("Failed to set openh264 bitrate, error: {ret}");
+            }
+            self.bitrate = bitrate;
+        }
+        Ok(())
+    }
+
+    fn bitrate(&self) -> u32 {
+        self.bitrate
+    }
+
+    fn support_abr(&self) -> bool {
+        true
+    }
+}
+
+impl OpenH264Encoder {
+    fn bitrate_of(width: usize, height: usize, quality: Quality) -> u32 {
+        let b = match quality {
+            Quality::Best => 150,
+            Quality::Balanced => 100,
+            Quality::Low => 50,
+            Quality::Custom(b) => b,
+        };
+        let base_bitrate = base_bitrate(width as _, height as _);
+        let bitrate = base_bitrate * b / 100;
+        if bitrate > 0 {
+            bitrate
+        } else {
+            base_bitrate
+        }
+    }
+
+    fn get_yuvfmt(width: usize, height: usize) -> EncodeYuvFormat {
+        let stride_y = (width + 31) / 32 * 32;
+        let stride_uv = stride_y / 2;
+        let u = stride_y * height;
+        EncodeYuvFormat {
+            pixfmt: Pixfmt::I420,
+            w: width,
+            h: height,
+            stride: vec![stride_y, stride_uv, stride_uv],
+            u,
+            v: u + stride_uv * ((height + 1) / 2),
+        }
+    }
+}
+
+struct I420Source<'a> {
+    fmt: &'a EncodeYuvFormat,
+    data: &'a [u8],
+}
+
+impl YUVSource for I420Source<'_> {
+    fn width(&self) -> i32 {
+        self.fmt.w as _
+    }
+
+    fn height(&self) -> i32 {
+        self.fmt.h as _
+    }
+
+    fn y(&self) -> &[u8] {
+        &self.data[..self.fmt.u]
+    }
+
+    fn u(&self) -> &[u8] {
+        &self.data[self.fmt.u..self.fmt.v]
+    }
+
+    fn v(&self) -> &[u8] {
+        &self.data[self.fmt.v..]
+    }
+
+    fn y_stride(&self) -> i32 {
+        self.fmt.stride[0] as _
+    }
+
+    fn u_stride(&self) -> i32 {
+        self.fmt.stride[1] as _
+    }
+
+    fn v_stride(&self) -> i32 {
+        self.fmt.stride[2] as _
+    }
+}
+
+pub struct OpenH264Decoder {
+    decoder: Decoder,
+}
+
+impl OpenH264Decoder {
+    pub fn new() -> ResultType<Self> {
+        log::info!("try create openh264 decoder");
+        let decoder = Decoder::new().map_err(|e| anyhow!("{e:?}"))?;
+        Ok(OpenH264Decoder { decoder })
+    }
+
+    // rgb [in/out] fmt and stride must be set in ImageRgb
+    pub fn decode(&mut self, data: &[u8], rgb: &mut ImageRgb) -> ResultType<bool> {
+        let yuv = match self.decoder.decode(data).map_err(|e| anyhow!("{e:?}"))? {
+            Some(yuv) => yuv,
+            None => return Ok(false),
+        };
+        let (width, height) = yuv.dimension_rgb();
+        let (stride_y, stride_u, stride_v) = yuv.strides_yuv();
+        rgb.w = width;
+        rgb.h = height;
+        hw::hw_i420_to(
+            rgb.fmt(),
+            width,
+            height,
+            yuv.y_with_stride(),
+            yuv.u_with_stride(),
+            yuv.v_with_stride(),
+            stride_y,
+            stride_u,
+            stride_v,
+            &mut rgb.raw,
+        )?;
+        Ok(true)
+    }
+}
@@ -5,12 +5,17 @@ use hbb_common::{
     bail, chrono,
     config::Config,
     log,
-    message_proto::{message, video_frame, EncodedVideoFrame, Message},
+    message_proto::{
+        message, misc, video_frame, AudioFormat, AudioFrame, EncodedVideoFrame, Message, VideoFrame,
+    },
     ResultType,
 };
 #[cfg(feature = "hwcodec")]
 use hwcodec::mux::{MuxContext, Muxer};
+#[cfg(all(feature = "openh264", not(feature = "hwcodec")))]
+use mp4::{AvcConfig, MediaConfig, Mp4Config, Mp4Sample, Mp4Writer, TrackConfig, TrackType};
 use std::{
+    collections::VecDeque,
     fs::{File, OpenOptions},
     io,
     ops::{Deref, DerefMut},
@@ -18,9 +23,11 @@ use std::{
     sync::mpsc::Sender,
     time::Instant,
 };
-use webm::mux::{self, Segment, Track, VideoTrack, Writer};
+use webm::mux::{self, AudioTrack, Segment, Track, VideoTrack, Writer};
 
 const MIN_SECS: u64 = 1;
+// How long the frames of one track wait for the other one, to be written in pts order.
+const INTERLEAVE_MS: i64 = 500;
 
 #[derive(Debug, Clone)]
 pub struct RecorderContext {
@@ -32,6 +39,8 @@ pub struct RecorderContext {
     pub height: usize,
     pub format: CodecFormat,
     pub tx: Option<Sender<RecordState>>,
+    // of the audio written next to an mp4 file
+    pub audio_tx: Option<Sender<RecordState>>,
 }
 
 impl RecorderContext {
@@ -73,6 +82,14 @@ pub trait RecorderApi {
     where
         Self: Sized;
     fn write_video(&mut self, frame: &EncodedVideoFrame) -> bool;
+    /// Add an opus track, which is only possible before the first frame.
+    fn add_audio_track(&mut self, _sample_rate: u32, _channels: u16) -> bool {
+        false
+    }
+    /// `pts` is in ms on the clock of the video.
+    fn write_audio(&mut self, _data: &[u8], _pts: i64) -> bool {
+        false
+    }
 }
 
 #[derive(Debug)]
@@ -83,10 +100,19 @@ pub enum RecordState {
     RemoveFile,
 }
 
+/// The audio of the session is muxed into the WebM files. The mp4 muxers take no opus track:
+/// the mp4 crate only writes the sample entries it has a config for, and the ffmpeg muxer of
+/// hwcodec only has a video stream, so the audio of an mp4 is written next to it in
+/// `<name>.audio.webm`, with the pts of the video.
 pub struct Recorder {
     pub inner: Box<dyn RecorderApi>,
     ctx: RecorderContext,
     pts: Option<i64>,
+    audio_format: Option<AudioFormat>,
+    // of the mp4 files
+    audio_file: Option<AudioRecorder>,
+    // capture time - pts of the last video frame, to place the audio frames
+    clock_offset: Option<i64>,
 }
 
 impl Deref for Recorder {
@@ -111,12 +137,27 @@ impl Recorder {
                 inner: Box::new(WebmRecorder::new(ctx.clone())?),
                 ctx,
                 pts: None,
+                audio_format: None,
+                audio_file: None,
+                clock_offset: None,
             },
             #[cfg(feature = "hwcodec")]
             _ => Recorder {
                 inner: Box::new(HwRecorder::new(ctx.clone())?),
                 ctx,
                 pts: None,
+                audio_format: None,
+                audio_file: None,
+                clock_offset: None,
+            },
+            #[cfg(all(feature = "openh264", not(feature = "hwcodec")))]
+            CodecFormat::H264 => Recorder {
+                inner: Box::new(Mp4Recorder::new(ctx.clone())?),
+                ctx,
+                pts: None,
+                audio_format: None,
+                audio_file: None,
+                clock_offset: None,
             },
             #[cfg(not(feature = "hwcodec"))]
             _ => bail!("unsupported codec type"),
@@ -133,20 +174,113 @@ impl Recorder {
             }
             #[cfg(feature = "hwcodec")]
             _ => Box::new(HwRecorder::new(ctx.clone())?),
+            #[cfg(all(feature = "openh264", not(feature = "hwcodec")))]
+            CodecFormat::H264 => Box::new(Mp4Recorder::new(ctx.clone())?),
             #[cfg(not(feature = "hwcodec"))]
             _ => bail!("unsupported codec type"),
         };
         self.ctx = ctx;
         self.pts = None;
+        self.clock_offset = None;
+        self.audio_file = None;
+        self.add_audio_track();
         self.send_state(RecordState::NewFile(self.ctx.filename.clone()));
         Ok(())
     }
 
+    fn add_audio_track(&mut self) {
+        let Some(format) = &self.audio_format else {
+            return;
+        };
+        if self
+            .inner
+            .add_audio_track(format.sample_rate, format.channels as _)
+        {
+            return;
+        }
+        if self.is_webm() {
+            log::info!("No audio track in {}", self.ctx.filename);
+            return;
+        }
+        let filename = PathBuf::from(&self.ctx.filename)
+            .with_extension("audio.webm")
+            .to_string_lossy()
+            .to_string();
+        match AudioRecorder::new(
+            filename,
+            format.sample_rate,
+            format.channels as _,
+            self.ctx.audio_tx.clone(),
+        ) {
+            Ok(audio) => self.audio_file = Some(audio),
+            Err(e) => log::error!("Failed to record the audio of {}: {e}", self.ctx.filename),
+        }
+    }
+
+    fn is_webm(&self) -> bool {
+        matches!(
+            self.ctx.format,
+            CodecFormat::VP8 | CodecFormat::VP9 | CodecFormat::AV1
+        )
+    }
+
+    pub fn set_audio_format(&mut self, format: &AudioFormat) {
+        if self.audio_format.as_ref() == Some(format) {
+            return;
+        }
+        self.audio_format = Some(format.clone());
+        if self.pts.is_some() && self.is_webm() {
+            log::info!("audio format changed, change record filename");
+            if let Err(e) = self.change(self.ctx.clone()) {
+                log::error!("Failed to change record file: {e}");
+            }
+        } else {
+            self.add_audio_track();
+        }
+    }
+
     pub fn write_message(&mut self, msg: &Message) {
-        if let Some(message::Union::VideoFrame(vf)) = &msg.union {
-            if let Some(frame) = &vf.union {
-                self.write_frame(frame).ok();
+        match &msg.union {
+            Some(message::Union::VideoFrame(vf)) => {
+                self.write_video_frame(vf).ok();
+            }
+            Some(message::Union::AudioFrame(af)) => self.write_audio_frame(af),
+            Some(message::Union::Misc(m)) => {
+                if let Some(misc::Union::AudioFormat(f)) = &m.union {
+                    self.set_audio_format(f);
+                }
             }
+            _ => {}
+        }
+    }
+
+    pub fn write_video_frame(&mut self, vf: &VideoFrame) -> ResultType<()> {
+        let Some(frame) = &vf.union else {
+            bail!("empty video frame");
+        };
+        self.write_frame(frame)?;
+        if vf.capture_time > 0 {
+            self.clock_offset = self.pts.map(|pts| vf.capture_time - pts);
+        }
+        Ok(())
+    }
+
+    /// Frames of old peers without capture time are not written, they cannot be placed.
+    pub fn write_audio_frame(&mut self, frame: &AudioFrame) {
+        let Some(offset) = self.clock_offset else {
+            return;
+        };
+        if frame.capture_time <= 0 {
+            return;
+        }
+        let pts = frame.capture_time - offset;
+        if pts < 0 {
+            return;
+        }
+        if let Some(audio) = self.audio_file.as_mut() {
+            audio.write_audio_at(&frame.data, pts);
+        } else if self.inner.write_audio(&frame.data, pts) {
+            self.send_state(RecordState::NewFrame);
         }
     }
 
@@ -188,7 +322,7 @@ impl Recorder {
                     self.write_video(f);
                 }
             }
-            #[cfg(feature = "hwcodec")]
+            #[cfg(any(feature = "hwcodec", feature = "openh264"))]
             video_frame::Union::H264s(h264s) => {
                 if self.ctx.format != CodecFormat::H264 {
                     self.change(RecorderContext {
@@ -238,11 +372,18 @@ impl Recorder {
 
 struct WebmRecorder {
     vt: VideoTrack,
+    at: Option<AudioTrack>,
     webm: Option<Segment<Writer<File>>>,
     ctx: RecorderContext,
     key: bool,
     written: bool,
     start: Instant,
+    // With audio, frames are queued to be written in pts order, the tracks arrive with
+    // different latencies and libwebm rejects a frame older than its cluster.
+    video_queue: VecDeque<(i64, bool, Vec<u8>)>,
+    audio_queue: VecDeque<(i64, Vec<u8>)>,
+    newest_pts: i64,
+    last_pts: i64,
 }
 
 impl RecorderApi for WebmRecorder {
@@ -282,11 +423,16 @@ impl RecorderApi for WebmRecorder {
         }
         Ok(WebmRecorder {
             vt,
+            at: None,
             webm: Some(webm),
             ctx,
             key: false,
             written: false,
             start: Instant::now(),
+            video_queue: Default::default(),
+            audio_queue: Default::default(),
+            newest_pts: 0,
+            last_pts: 0,
         })
     }
 
@@ -294,22 +440,92 @@ impl RecorderApi for WebmRecorder {
         if frame.key {
             self.key = true;
         }
-        if self.key {
+        if !self.key {
+            return false;
+        }
+        if self.at.is_none() {
             let ok = self
                 .vt
                 .add_frame(&frame.data, frame.pts as u64 * 1_000_000, frame.key);
             if ok {
                 self.written = true;
             }
-            ok
-        } else {
-            false
+            return ok;
+        }
+        self.newest_pts = self.newest_pts.max(frame.pts);
+        self.video_queue
+            .push_back((frame.pts, frame.key, frame.data.to_vec()));
+        self.flush(false);
+        true
+    }
+
+    fn add_audio_track(&mut self, sample_rate: u32, channels: u16) -> bool {
+        if self.written || self.at.is_some() {
+            return false;
+        }
+        let Some(webm) = self.webm.as_mut() else {
+            return false;
+        };
+        let at = webm.add_audio_track(
+            sample_rate as _,
+            channels as _,
+            None,
+            mux::AudioCodecId::Opus,
+        );
+        if !webm.set_codec_private(at.track_number(), &opus_head(sample_rate, channels)) {
+            log::error!("Failed to set opus codec private");
+            return false;
+        }
+        self.at = Some(at);
+        true
+    }
+
+    fn write_audio(&mut self, data: &[u8], pts: i64) -> bool {
+        // Starts with the video, which starts with a key frame.
+        if self.at.is_none() || !self.key {
+            return false;
+        }
+        self.newest_pts = self.newest_pts.max(pts);
+        self.audio_queue.push_back((pts, data.to_vec()));
+        self.flush(false);
+        true
+    }
+}
+
+impl WebmRecorder {
+    // Writes the queued frames in pts order, waiting at most INTERLEAVE_MS for the other track
+    // unless `all`.
+    fn flush(&mut self, all: bool) {
+        loop {
+            let ready = |pts: i64| all || self.newest_pts - pts > INTERLEAVE_MS;
+            let video = match (self.video_queue.front(), self.audio_queue.front()) {
+                (Some(v), Some(a)) => v.0 <= a.0,
+                (Some(v), None) if ready(v.0) => true,
+                (None, Some(a)) if ready(a.0) => false,
+                _ => break,
+            };
+            if video {
+                if let Some((pts, key, data)) = self.video_queue.pop_front() {
+                    let pts = pts.max(self.last_pts);
+                    self.last_pts = pts;
+                    if self.vt.add_frame(&data, pts as u64 * 1_000_000, key) {
+                        self.written = true;
+                    }
+                }
+            } else if let Some((pts, data)) = self.audio_queue.pop_front() {
+                let pts = pts.max(self.last_pts);
+                self.last_pts = pts;
+                if let Some(at) = self.at.as_mut() {
+                    at.add_frame(&data, pts as u64 * 1_000_000, true);
+                }
+            }
         }
     }
 }
 
 impl Drop for WebmRecorder {
     fn drop(&mut self) {
+        self.flush(true);
         let _ = std::mem::replace(&mut self.webm, None).map_or(false, |webm| webm.finalize(None));
         let mut state = RecordState::WriteTail;
         if !self.written || self.start.elapsed().as_secs() < MIN_SECS {
@@ -320,6 +536,107 @@ impl Drop for WebmRecorder {
     }
 }
 
+/// Records the opus frames of a session into a webm file with a single audio track.
+pub struct AudioRecorder {
+    at: AudioTrack,
+    webm: Option<Segment<Writer<File>>>,
+    filename: String,
+    tx: Option<Sender<RecordState>>,
+    written: bool,
+    start: Instant,
+    // in ms, of `write_audio_at`
+    last_pts: i64,
+}
+
+unsafe impl Send for AudioRecorder {}
+
+impl AudioRecorder {
+    pub fn new(
+        filename: String,
+        sample_rate: u32,
+        channels: u16,
+        tx: Option<Sender<RecordState>>,
+    ) -> ResultType<Self> {
+        let out = File::create(&filename)?;
+        let mut webm = match mux::Segment::new(mux::Writer::new(out)) {
+            Some(v) => v,
+            None => bail!("Failed to create webm mux"),
+        };
+        let at = webm.add_audio_track(
+            sample_rate as _,
+            channels as _,
+            None,
+            mux::AudioCodecId::Opus,
+        );
+        if !webm.set_codec_private(at.track_number(), &opus_head(sample_rate, channels)) {
+            bail!("Failed to set codec private");
+        }
+        if let Some(tx) = tx.as_ref() {
+            tx.send(RecordState::NewFile(filename.clone())).ok();
+        }
+        Ok(AudioRecorder {
+            at,
+            webm: Some(webm),
+            filename,
+            tx,
+            written: false,
+            start: Instant::now(),
+            last_pts: 0,
+        })
+    }
+
+    /// Write a frame at the time since the recorder was created.
+    pub fn write_audio(&mut self, data: &[u8]) -> bool {
+        self.add_frame(data, self.start.elapsed().as_nanos() as u64)
+    }
+
+    /// Write a frame at `pts` in ms, on the clock of another track.
+    pub fn write_audio_at(&mut self, data: &[u8], pts: i64) -> bool {
+        let pts = pts.max(self.last_pts);
+        self.last_pts = pts;
+        self.add_frame(data, pts as u64 * 1_000_000)
+    }
+
+    fn add_frame(&mut self, data: &[u8], ns: u64) -> bool {
+        let ok = self.at.add_frame(data, ns, true);
+        if ok {
+            self.written = true;
+            if let Some(tx) = self.tx.as_ref() {
+                tx.send(RecordState::NewFrame).ok();
+            }
+        }
+        ok
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+}
+
+impl Drop for AudioRecorder {
+    fn drop(&mut self) {
+        let _ = std::mem::replace(&mut self.webm, None).map_or(false, |webm| webm.finalize(None));
+        let mut state = RecordState::WriteTail;
+        if !self.written {
+            std::fs::remove_file(&self.filename).ok();
+            state = RecordState::RemoveFile;
+        }
+        self.tx.as_ref().map(|tx| tx.send(state));
+    }
+}
+
+// https://datatracker.ietf.org/doc/html/rfc7845#section-5.1
+fn opus_head(sample_rate: u32, channels: u16) -> Vec<u8> {
+    let mut opus_head = b"OpusHead".to_vec();
+    opus_head.push(1); // version
+    opus_head.push(channels as u8);
+    opus_head.extend_from_slice(&0u16.to_le_bytes()); // pre-skip
+    opus_head.extend_from_slice(&sample_rate.to_le_bytes());
+    opus_head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    opus_head.push(0); // channel mapping family
+    opus_head
+}
+
 #[cfg(feature = "hwcodec")]
 struct HwRecorder {
     muxer: Muxer,
@@ -377,3 +694,250 @@ impl Drop for HwRecorder {
         self.ctx.tx.as_ref().map(|tx| tx.send(state));
     }
 }
+
+// Without hwcodec there is no ffmpeg to mux, the software h264 stream is written with the mp4 crate.
+// The track is added on the first key frame, which carries the SPS and PPS.
+#[cfg(all(feature = "openh264", not(feature = "hwcodec")))]
+const MP4_LAST_SAMPLE_DURATION: u32 = 1000 / 30;
+
+#[cfg(all(feature = "openh264", not(feature = "hwcodec")))]
+struct Mp4Recorder {
+    writer: Mp4Writer<File>,
+    track: Option<u32>,
+    // A sample is written when the next one arrives, its duration is the pts difference.
+    pending: Option<(i64, bool, Vec<u8>)>,
+    first_pts: i64,
+    ctx: RecorderContext,
+    written: bool,
+    start: Instant,
+}
+
+#[cfg(all(feature = "openh264", not(feature = "hwcodec")))]
+impl RecorderApi for Mp4Recorder {
+    fn new(ctx: RecorderContext) -> ResultType<Self> {
+        let out = File::create(&ctx.filename)?;
+        let config = Mp4Config {
+            major_brand: "isom".parse()?,
+            minor_version: 512,
+            compatible_brands: vec![
+                "isom".parse()?,
+                "iso2".parse()?,
+                "avc1".parse()?,
+                "mp41".parse()?,
+            ],
+            timescale: 1000,
+        };
+        let writer = Mp4Writer::write_start(out, &config)?;
+        Ok(Mp4Recorder {
+            writer,
+            track: None,
+            pending: None,
+            first_pts: 0,
+            ctx,
+            written: false,
+            start: Instant::now(),
+        })
+    }
+
+    fn write_video(&mut self, frame: &EncodedVideoFrame) -> bool {
+        let nals = annexb_nals(&frame.data);
+        if self.track.is_none() {
+            if !frame.key {
+                return false;
+            }
+            let find = |t: u8| nals.iter().find(|n| n[0] & 0x1f == t).map(|n| n.to_vec());
+            let (sps, pps) = match (find(7), find(8)) {
+                (Some(sps), Some(pps)) => (sps, pps),
+                _ => return false,
+            };
+            let track = TrackConfig {
+                track_type: TrackType::Video,
+                timescale: 1000,
+                language: "und".to_owned(),
+                media_conf: MediaConfig::AvcConfig(AvcConfig {
+                    width: self.ctx.width as _,
+                    height: self.ctx.height as _,
+                    seq_param_set: sps,
+                    pic_param_set: pps,
+                }),
+            };
+            if let Err(e) = self.writer.add_track(&track) {
+                log::error!("Failed to add mp4 track: {e}");
+                return false;
+            }
+            self.track = Some(1);
+            self.first_pts = frame.pts;
+        }
+        // mp4 samples are length prefixed, the parameter sets are in the track.
+        let mut data = Vec::with_capacity(frame.data.len());
+        for nal in nals.iter().filter(|n| n[0] & 0x1f != 7 && n[0] & 0x1f != 8) {
+            data.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+            data.extend_from_slice(nal);
+        }
+        let pts = frame.pts - self.first_pts;
+        let ok = match self.pending.take() {
+            Some((last, key, bytes)) => {
+                self.write_sample(last, (pts - last).max(1) as _, key, bytes)
+            }
+            None => true,
+        };
+        self.pending = Some((pts, frame.key, data));
+        ok
+    }
+}
+
+#[cfg(all(feature = "openh264", not(feature = "hwcodec")))]
+impl Mp4Recorder {
+    fn write_sample(&mut self, pts: i64, duration: u32, key: bool, bytes: Vec<u8>) -> bool {
+        let track = match self.track {
+            Some(track) => track,
+            None => return false,
+        };
+        let sample = Mp4Sample {
+            start_time: pts as _,
+            duration,
+            rendering_offset: 0,
+            is_sync: key,
+            bytes: bytes.into(),
+        };
+        let ok = self.writer.write_sample(track, &sample).is_ok();
+        if ok {
+            self.written = true;
+        }
+        ok
+    }
+}
+
+#[cfg(all(feature = "openh264", not(feature = "hwcodec")))]
+impl Drop for Mp4Recorder {
+    fn drop(&mut self) {
+        if let Some((pts, key, bytes)) = self.pending.take() {
+            self.write_sample(pts, MP4_LAST_SAMPLE_DURATION, key, bytes);
+        }
+        self.writer.write_end().ok();
+        let mut state = RecordState::WriteTail;
+        if !self.written || self.start.elapsed().as_secs() < MIN_SECS {
+            std::fs::remove_file(&self.ctx.filename).ok();
+            state = RecordState::RemoveFile;
+        }
+        self.ctx.tx.as_ref().map(|tx| tx.send(state));
+    }
+}
+
+// Splits an Annex B stream at its start codes.
+#[cfg(all(feature = "openh264", not(feature = "hwcodec")))]
+fn annexb_nals(data: &[u8]) -> Vec<&[u8]> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    let mut nals = Vec::new();
+    for (n, start) in starts.iter().enumerate() {
+        let mut end = starts.get(n + 1).map(|s| s - 3).unwrap_or(data.len());
+        // 4 byte start codes have an extra leading zero.
+        while end > *start && data[end - 1] == 0 && n + 1 < starts.len() {
+            end -= 1;
+        }
+        if end > *start {
+            nals.push(&data[*start..end]);
+        }
+    }
+    nals
+}
+
+#[cfg(all(test, feature = "openh264", not(feature = "hwcodec")))]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const SPS: &[u8] = &[0x67, 0x42, 0xc0, 0x1e, 0xd9];
+    const PPS: &[u8] = &[0x68, 0xce, 0x3c, 0x80];
+    const IDR: &[u8] = &[0x65, 0x88, 0x84, 0x21];
+    const P: &[u8] = &[0x41, 0x9a, 0x02];
+
+    fn annexb(nals: &[&[u8]]) -> Vec<u8> {
+        let mut data = vec![];
+        for (i, nal) in nals.iter().enumerate() {
+            // both lengths of start codes
+            if i % 2 == 0 {
+                data.extend_from_slice(&[0, 0, 0, 1]);
+            } else {
+                data.extend_from_slice(&[0, 0, 1]);
+            }
+            data.extend_from_slice(nal);
+        }
+        data
+    }
+
+    #[test]
+    fn test_annexb_nals() {
+        let data = annexb(&[SPS, PPS, IDR]);
+        assert_eq!(annexb_nals(&data), vec![SPS, PPS, IDR]);
+        assert!(annexb_nals(&[0, 0, 0, 1]).is_empty());
+        assert!(annexb_nals(&[1, 2, 3]).is_empty());
+    }
+
+    #[test]
+    fn test_mp4_recorder() {
+        let filename = std::env::temp_dir()
+            .join(format!("scrap_test_{}.mp4", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let ctx = RecorderContext {
+            server: false,
+            id: "test".to_owned(),
+            default_dir: "".to_owned(),
+            filename: filename.clone(),
+            width: 64,
+            height: 48,
+            format: CodecFormat::H264,
+            tx: None,
+            audio_tx: None,
+        };
+        let frame = |data: Vec<u8>, key: bool, pts: i64| EncodedVideoFrame {
+            data: data.into(),
+            key,
+            pts,
+            ..Default::default()
+        };
+        {
+            let mut recorder = Mp4Recorder::new(ctx).unwrap();
+            // no track before a key frame
+            assert!(!recorder.write_video(&frame(annexb(&[P]), false, 90)));
+            assert!(recorder.write_video(&frame(annexb(&[SPS, PPS, IDR]), true, 100)));
+            assert!(recorder.write_video(&frame(annexb(&[P]), false, 140)));
+            assert!(recorder.write_video(&frame(annexb(&[P]), false, 150)));
+            // kept when dropped
+            recorder.start = Instant::now()
+                .checked_sub(Duration::from_secs(MIN_SECS + 1))
+                .unwrap();
+        }
+        let file = File::open(&filename).unwrap();
+        let size = file.metadata().unwrap().len();
+        let mut reader = mp4::Mp4Reader::read_header(io::BufReader::new(file), size).unwrap();
+        assert_eq!(reader.tracks().len(), 1);
+        assert_eq!(reader.sample_count(1).unwrap(), 3);
+        let mut samples = vec![];
+        for id in 1..=3 {
+            let s = reader.read_sample(1, id).unwrap().unwrap();
+            samples.push((s.start_time, s.duration, s.is_sync, s.bytes.to_vec()));
+        }
+        std::fs::remove_file(&filename).ok();
+        let length_prefixed = |nal: &[u8]| [&(nal.len() as u32).to_be_bytes()[..], nal].concat();
+        assert_eq!(
+            samples,
+            vec![
+                // the parameter sets are in the track, not in the samples
+                (0, 40, true, length_prefixed(IDR)),
+                (40, 10, false, length_prefixed(P)),
+                (50, MP4_LAST_SAMPLE_DURATION, false, length_prefixed(P)),
+            ]
+        );
+    }
+}
@@ -7,8 +7,10 @@ use hbb_common::log;
 use hbb_common::message_proto::{Chroma, EncodedVideoFrame, EncodedVideoFrames, VideoFrame};
 use hbb_common::ResultType;
 
-use crate::codec::{base_bitrate, codec_thread_num, EncoderApi, Quality};
-use crate::{EncodeInput, EncodeYuvFormat, GoogleImage, Pixfmt, STRIDE_ALIGN};
+use crate::codec::{
+    base_bitrate, codec_thread_num, EncoderApi, LongTermRefs, Quality, RefAction, RefBuffer,
+};
+use crate::{DirtyRect, EncodeInput, EncodeYuvFormat, GoogleImage, Pixfmt, STRIDE_ALIGN};
 
 use super::vpx::{vp8e_enc_control_id::*, vpx_codec_err_t::*, *};
 use crate::{generate_call_macro, generate_call_ptr_macro, Error, Result};
@@ -41,6 +43,10 @@ pub struct VpxEncoder {
     id: VpxVideoCodecId,
     i444: bool,
     yuvfmt: EncodeYuvFormat,
+    active_map: Vec<u8>,
+    // (rc_min_quantizer, rc_max_quantizer, rc_dropframe_thresh) to restore after lossless frames.
+    lossy_rc: Option<(u32, u32, u32)>,
+    refs: LongTermRefs,
 }
 
 pub struct VpxDecoder {
@@ -177,6 +183,9 @@ impl EncoderApi for VpxEncoder {
                     id: config.codec,
                     i444,
                     yuvfmt: Self::get_yuvfmt(config.width, config.height, i444),
+                    active_map: Vec::new(),
+                    lossy_rc: None,
+                    refs: Default::default(),
                 })
             }
             _ => Err(anyhow!("encoder type mismatch")),
@@ -184,9 +193,11 @@ impl EncoderApi for VpxEncoder {
     }
 
     fn encode_to_message(&mut self, input: EncodeInput, ms: i64) -> ResultType<VideoFrame> {
+        let action = self.refs.next();
+        let flags = Self::reference_flags(action);
         let mut frames = Vec::new();
         for ref frame in self
-            .encode(ms, input.yuv()?, STRIDE_ALIGN)
+            .encode_with_flags(ms, input.yuv()?, STRIDE_ALIGN, flags)
             .with_context(|| "Failed to encode")?
         {
             frames.push(VpxEncoder::create_frame(frame));
@@ -194,6 +205,10 @@ impl EncoderApi for VpxEncoder {
         for ref frame in self.flush().with_context(|| "Failed to flush")? {
             frames.push(VpxEncoder::create_frame(frame));
         }
+        if let Some(frame) = frames.iter_mut().find(|f| f.pts == ms) {
+            frame.recovery = matches!(action, RefAction::Recover(_));
+            self.refs.encoded(action, ms, frame.key);
+        }
 
         // to-do: flush periodically, e.g. 1 second
         if frames.len() > 0 {
@@ -235,10 +250,112 @@ impl EncoderApi for VpxEncoder {
     fn support_abr(&self) -> bool {
         true
     }
+
+    fn set_dirty_rects(&mut self, rects: Option<&[DirtyRect]>) {
+        // Macroblocks outside of the changed areas are skipped, i.e. copied from the
+        // previous frame. Not worth it if most of the frame changed.
+        let cols = (self.width + 15) / 16;
+        let rows = (self.height + 15) / 16;
+        let rects = rects.filter(|rects| {
+            rects.iter().map(|r| r.area()).sum::<usize>() * 2 < self.width * self.height
+        });
+        let enabled = rects.is_some();
+        if !enabled && self.active_map.is_empty() {
+            return;
+        }
+        self.active_map.clear();
+        if let Some(rects) = rects {
+            self.active_map.resize(rows * cols, 0);
+            for r in rects {
+                let (left, right) = ((r.x / 16).min(cols), ((r.x + r.w + 15) / 16).min(cols));
+                for row in (r.y / 16)..((r.y + r.h + 15) / 16).min(rows) {
+                    self.active_map[row * cols + left..row * cols + right].fill(1);
+                }
+            }
+        }
+        let mut map = vpx_active_map_t {
+            active_map: if enabled {
+                self.active_map.as_mut_ptr()
+            } else {
+                ptr::null_mut()
+            },
+            rows: rows as _,
+            cols: cols as _,
+        };
+        let ret = unsafe { vpx_codec_control_(&mut self.ctx, VP8E_SET_ACTIVEMAP as _, &mut map) };
+        if ret != VPX_CODEC_OK {
+            log::debug!("Failed to set active map: {:?}", ret);
+        }
+    }
+
+    fn set_screen_content(&mut self, screen_content: bool) {
+        let (ctrl, value) = match self.id {
+            VpxVideoCodecId::VP8 => (VP8E_SET_SCREEN_CONTENT_MODE, screen_content as c_int),
+            // VP9E_CONTENT_SCREEN : VP9E_CONTENT_DEFAULT
+            VpxVideoCodecId::VP9 => (VP9E_SET_TUNE_CONTENT, screen_content as c_int),
+        };
+        unsafe {
+            let res = vpx_codec_control_(&mut self.ctx, ctrl as _, value);
+            if res != VPX_CODEC_OK {
+                log::error!("failed to set screen content mode: {res:?}");
+            }
+        }
+    }
+
+    fn support_lossless(&self) -> bool {
+        self.id == VpxVideoCodecId::VP9
+    }
+
+    fn set_lossless(&mut self, lossless: bool) -> ResultType<()> {
+        if !self.support_lossless() || lossless == self.lossy_rc.is_some() {
+            return Ok(());
+        }
+        let mut c = unsafe { *self.ctx.config.enc.to_owned() };
+        if lossless {
+            self.lossy_rc = Some((
+                c.rc_min_quantizer,
+                c.rc_max_quantizer,
+                c.rc_dropframe_thresh,
+            ));
+            c.rc_min_quantizer = 0;
+            c.rc_max_quantizer = 0;
+            // A lossless frame is large, it must not be dropped by the rate control.
+            c.rc_dropframe_thresh = 0;
+        } else if let Some((q_min, q_max, drop_thresh)) = self.lossy_rc.take() {
+            c.rc_min_quantizer = q_min;
+            c.rc_max_quantizer = q_max;
+            c.rc_dropframe_thresh = drop_thresh;
+        }
+        call_vpx!(vpx_codec_enc_config_set(&mut self.ctx, &c));
+        call_vpx!(vpx_codec_control_(
+            &mut self.ctx,
+            VP9E_SET_LOSSLESS as _,
+            lossless as c_int
+        ));
+        Ok(())
+    }
+
+    fn support_recovery(&self) -> bool {
+        true
+    }
+
+    fn recover(&mut self, last_decoded_pts: i64) {
+        self.refs.recover(last_decoded_pts);
+    }
 }
 
 impl VpxEncoder {
     pub fn encode(&mut self, pts: i64, data: &[u8], stride_align: usize) -> Result<EncodeFrames> {
+        self.encode_with_flags(pts, data, stride_align, 0)
+    }
+
+    fn encode_with_flags(
+        &mut self,
+        pts: i64,
+        data: &[u8],
+        stride_align: usize,
+        flags: vpx_enc_frame_flags_t,
+    ) -> Result<EncodeFrames> {
         let bpp = if self.i444 { 24 } else { 12 };
         if data.len() < self.width * self.height * bpp / 8 {
             return Err(Error::FailedCall("len not enough".to_string()));
@@ -264,7 +381,7 @@ impl VpxEncoder {
             &image,
             pts as _,
             1, // Duration
-            0, // Flags
+            flags,
             VPX_DL_REALTIME as _,
         ));
 
@@ -274,6 +391,29 @@ impl VpxEncoder {
         })
     }
 
+    // The last frame buffer is updated by every frame, the others only when asked to.
+    fn reference_flags(action: RefAction) -> vpx_enc_frame_flags_t {
+        let flags = match action {
+            RefAction::Normal(None) => VP8_EFLAG_NO_UPD_GF | VP8_EFLAG_NO_UPD_ARF,
+            RefAction::Normal(Some(RefBuffer::Golden)) => VP8_EFLAG_NO_UPD_ARF,
+            RefAction::Normal(Some(RefBuffer::Altref)) => VP8_EFLAG_NO_UPD_GF,
+            RefAction::Recover(RefBuffer::Golden) => {
+                VP8_EFLAG_NO_REF_LAST
+                    | VP8_EFLAG_NO_REF_ARF
+                    | VP8_EFLAG_NO_UPD_GF
+                    | VP8_EFLAG_NO_UPD_ARF
+            }
+            RefAction::Recover(RefBuffer::Altref) => {
+                VP8_EFLAG_NO_REF_LAST
+                    | VP8_EFLAG_NO_REF_GF
+                    | VP8_EFLAG_NO_UPD_GF
+                    | VP8_EFLAG_NO_UPD_ARF
+            }
+            RefAction::Key => VPX_EFLAG_FORCE_KF,
+        };
+        flags as _
+    }
+
     /// Notify the encoder to return any pending packets
     pub fn flush(&mut self) -> Result<EncodeFrames> {
         call_vpx!(vpx_codec_encode(
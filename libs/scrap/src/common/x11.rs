@@ -1,4 +1,6 @@
-use crate::{common::TraitCapturer, x11, Frame, Pixfmt, TraitPixelBuffer};
+use crate::{
+    common::TraitCapturer, x11, DirtyRect, Frame, Pixfmt, TraitPixelBuffer, WindowInfo, WindowState,
+};
 use std::{io, time::Duration};
 
 pub struct Capturer(x11::Capturer);
@@ -23,12 +25,24 @@ impl TraitCapturer for Capturer {
     fn frame<'a>(&'a mut self, _timeout: Duration) -> io::Result<Frame<'a>> {
         let width = self.width();
         let height = self.height();
-        Ok(Frame::PixelBuffer(PixelBuffer::new(
-            self.0.frame()?,
-            Pixfmt::BGRA,
-            width,
-            height,
-        )))
+        let (data, dirty_rects) = self.0.frame()?;
+        Ok(Frame::PixelBuffer(
+            PixelBuffer::new(data, Pixfmt::BGRA, width, height).with_dirty_rects(dirty_rects),
+        ))
+    }
+
+    fn window_state(&self) -> Option<WindowState> {
+        self.0.window_state().map(|(rect, active)| WindowState {
+            x: rect.x as _,
+            y: rect.y as _,
+            width: rect.w as _,
+            height: rect.h as _,
+            active,
+        })
+    }
+
+    fn invalidate(&mut self) {
+        self.0.invalidate();
     }
 }
 
@@ -38,6 +52,7 @@ pub struct PixelBuffer<'a> {
     width: usize,
     height: usize,
     stride: Vec<usize>,
+    dirty_rects: Option<&'a [DirtyRect]>,
 }
 
 impl<'a> PixelBuffer<'a> {
@@ -51,8 +66,14 @@ impl<'a> PixelBuffer<'a> {
             width,
             height,
             stride,
+            dirty_rects: None,
         }
     }
+
+    pub fn with_dirty_rects(mut self, dirty_rects: Option<&'a [DirtyRect]>) -> Self {
+        self.dirty_rects = dirty_rects;
+        self
+    }
 }
 
 impl<'a> TraitPixelBuffer for PixelBuffer<'a> {
@@ -75,6 +96,10 @@ impl<'a> TraitPixelBuffer for PixelBuffer<'a> {
     fn pixfmt(&self) -> crate::Pixfmt {
         self.pixfmt
     }
+
+    fn dirty_rects(&self) -> Option<&[DirtyRect]> {
+        self.dirty_rects
+    }
 }
 
 pub struct Display(x11::Display);
@@ -107,6 +132,18 @@ impl Display {
         Ok(x11::Server::displays(server).map(Display).collect())
     }
 
+    /// Capture a top-level window, `id` is one of `windows()`.
+    pub fn window(id: u64) -> io::Result<Display> {
+        let server = match x11::Server::default() {
+            Ok(server) => server,
+            Err(_) => return Err(io::ErrorKind::ConnectionRefused.into()),
+        };
+        match x11::Display::window(server, id as _) {
+            Some(display) => Ok(Display(display)),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
     pub fn width(&self) -> usize {
         self.0.rect().w as usize
     }
@@ -136,3 +173,22 @@ impl Display {
         self.0.server().get_shm_status()
     }
 }
+
+pub fn windows() -> io::Result<Vec<WindowInfo>> {
+    let server = match x11::Server::default() {
+        Ok(server) => server,
+        Err(_) => return Err(io::ErrorKind::ConnectionRefused.into()),
+    };
+    Ok(x11::windows(&server)
+        .drain(..)
+        .map(|w| WindowInfo {
+            id: w.id as _,
+            title: w.title,
+            app: w.app,
+            x: w.rect.x as _,
+            y: w.rect.y as _,
+            width: w.rect.w as _,
+            height: w.rect.h as _,
+        })
+        .collect())
+}
@@ -2,7 +2,10 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::os::unix::io::AsRawFd;
 use std::process::Command;
-use std::sync::{atomic::AtomicBool, Arc, Mutex};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
 use std::time::Duration;
 use tracing::{debug, trace, warn};
 
@@ -30,6 +33,34 @@ lazy_static! {
     pub static ref RDP_RESPONSE: Mutex<Option<RdpResponse>> = Mutex::new(None);
 }
 
+// https://flatpak.github.io/xdg-desktop-portal/portal-docs.html#gdbus-property-org-freedesktop-portal-ScreenCast.AvailableSourceTypes
+const SOURCE_TYPE_MONITOR: u32 = 1;
+const SOURCE_TYPE_WINDOW: u32 = 2;
+
+static WINDOW_SOURCE: AtomicBool = AtomicBool::new(false);
+
+/// Ask for a window instead of monitors in the screencast dialog.
+/// The current session is closed, the next capturer starts a new one.
+pub fn set_window_source(window: bool) {
+    WINDOW_SOURCE.store(window, Ordering::SeqCst);
+    if let Ok(mut lock) = RDP_RESPONSE.lock() {
+        // The portal closes the session with the connection.
+        lock.take();
+    }
+}
+
+pub fn is_window_source() -> bool {
+    WINDOW_SOURCE.load(Ordering::SeqCst)
+}
+
+fn source_types() -> u32 {
+    if is_window_source() {
+        SOURCE_TYPE_WINDOW
+    } else {
+        SOURCE_TYPE_MONITOR
+    }
+}
+
 pub struct RdpResponse {
     pub conn: Arc<SyncConnection>,
     pub streams: Vec<PwStreamInfo>,
@@ -593,7 +624,8 @@ fn on_create_session_response(
             if let Ok(version) = screencast_portal::version(&portal) {
                 if version >= 4 {
                     let restore_token = config::LocalConfig::get_option(RESTORE_TOKEN_CONF_KEY);
-                    if !restore_token.is_empty() {
+                    // The token restores the monitors picked last time.
+                    if !restore_token.is_empty() && !is_window_source() {
                         args.insert(RESTORE_TOKEN.to_string(), Variant(Box::new(restore_token)));
                     }
                     // persist_mode may be configured by the user.
@@ -606,7 +638,7 @@ fn on_create_session_response(
             );
             // https://flatpak.github.io/xdg-desktop-portal/portal-docs.html#gdbus-method-org-freedesktop-portal-ScreenCast.SelectSources
             args.insert("multiple".into(), Variant(Box::new(true)));
-            args.insert("types".into(), Variant(Box::new(1u32))); //| 2u32)));
+            args.insert("types".into(), Variant(Box::new(source_types())));
 
             let path = portal.select_sources(ses.clone(), args)?;
             handle_response(
@@ -654,7 +686,7 @@ fn on_select_devices_response(
         );
         // https://flatpak.github.io/xdg-desktop-portal/portal-docs.html#gdbus-method-org-freedesktop-portal-ScreenCast.SelectSources
         args.insert("multiple".into(), Variant(Box::new(true)));
-        args.insert("types".into(), Variant(Box::new(1u32))); //| 2u32)));
+        args.insert("types".into(), Variant(Box::new(source_types())));
 
         let session = session.clone();
         let path = portal.select_sources(session.clone(), args)?;
@@ -716,7 +748,8 @@ fn on_start_response(
         let portal = get_portal(c);
         if is_server_running() {
             if let Ok(version) = screencast_portal::version(&portal) {
-                if version >= 4 {
+                // Keep the token of the monitors.
+                if version >= 4 && !is_window_source() {
                     if let Some(restore_token) = r.results.get(RESTORE_TOKEN) {
                         if let Some(restore_token) = restore_token.as_str() {
                             config::LocalConfig::set_option(
@@ -1,6 +1,7 @@
 use super::ffi::*;
-use super::Display;
-use hbb_common::libc;
+use super::{default_root, Display, Rect, Tracker};
+use crate::{DirtyRect, MAX_DIRTY_RECTS};
+use hbb_common::{libc, log};
 use std::{io, ptr, slice};
 
 pub struct Capturer {
@@ -11,6 +12,127 @@ pub struct Capturer {
 
     size: usize,
     saved_raw_data: Vec<u8>, // for faster compare and copy
+    damage: Option<Damage>,
+    dirty_rects: Vec<DirtyRect>,
+    window: Option<WindowCapture>,
+}
+
+// Capturing one window instead of a monitor, the display rect follows the window.
+struct WindowCapture {
+    tracker: Tracker,
+    screen: Rect,
+    active: bool,
+    // A blank frame is sent while the window is not viewable.
+    hidden: bool,
+    data: Vec<u8>,
+}
+
+// XDamage reports the changed areas of the root window, so an idle screen is neither grabbed
+// nor compared. Without the extension, frames are compared as before.
+struct Damage {
+    id: xcb_damage_damage_t,
+    first_event: u8,
+    // The next frame is a full one, e.g. the first frame.
+    full: bool,
+}
+
+impl Damage {
+    unsafe fn new(display: &Display) -> Option<Damage> {
+        let c = display.server().raw();
+        let ext = xcb_get_extension_data(c, ptr::addr_of_mut!(xcb_damage_id));
+        if ext.is_null() || (*ext).present == 0 {
+            log::info!("XDamage is not available");
+            return None;
+        }
+        let first_event = (*ext).first_event;
+        // The version has to be negotiated before any other damage request.
+        let reply =
+            xcb_damage_query_version_reply(c, xcb_damage_query_version(c, 1, 1), ptr::null_mut());
+        if reply.is_null() {
+            log::info!("Failed to query XDamage version");
+            return None;
+        }
+        libc::free(reply as *mut _);
+        let id = xcb_generate_id(c);
+        let err = xcb_request_check(
+            c,
+            xcb_damage_create_checked(
+                c,
+                id,
+                display.root(),
+                XCB_DAMAGE_REPORT_LEVEL_DELTA_RECTANGLES,
+            ),
+        );
+        if !err.is_null() {
+            log::info!("Failed to create XDamage, error {}", (*err).error_code);
+            libc::free(err as *mut _);
+            return None;
+        }
+        Some(Damage {
+            id,
+            first_event,
+            full: true,
+        })
+    }
+
+    // Collect the rectangles reported since the last call, clipped to `rect` and relative to it.
+    unsafe fn poll(&mut self, display: &Display, rects: &mut Vec<DirtyRect>) {
+        let c = display.server().raw();
+        let rect = display.rect();
+        let (x0, y0) = (rect.x as i32, rect.y as i32);
+        let (x1, y1) = (x0 + rect.w as i32, y0 + rect.h as i32);
+        loop {
+            let event = xcb_poll_for_event(c);
+            if event.is_null() {
+                break;
+            }
+            if (*event).response_type & 0x7f == self.first_event + XCB_DAMAGE_NOTIFY {
+                let notify = &*(event as *const xcb_damage_notify_event_t);
+                if notify.damage == self.id {
+                    let a = notify.area;
+                    let l = (a.x as i32).max(x0);
+                    let t = (a.y as i32).max(y0);
+                    let r = (a.x as i32 + a.width as i32).min(x1);
+                    let b = (a.y as i32 + a.height as i32).min(y1);
+                    if l < r && t < b {
+                        rects.push(DirtyRect {
+                            x: (l - x0) as _,
+                            y: (t - y0) as _,
+                            w: (r - l) as _,
+                            h: (b - t) as _,
+                        });
+                    }
+                }
+            }
+            libc::free(event as *mut _);
+        }
+        if self.full {
+            self.full = false;
+            rects.clear();
+            rects.push(DirtyRect {
+                x: 0,
+                y: 0,
+                w: rect.w as _,
+                h: rect.h as _,
+            });
+        } else if rects.len() > MAX_DIRTY_RECTS {
+            let l = rects.iter().map(|r| r.x).min().unwrap_or_default();
+            let t = rects.iter().map(|r| r.y).min().unwrap_or_default();
+            let r = rects.iter().map(|r| r.x + r.w).max().unwrap_or_default();
+            let b = rects.iter().map(|r| r.y + r.h).max().unwrap_or_default();
+            rects.clear();
+            rects.push(DirtyRect {
+                x: l,
+                y: t,
+                w: r - l,
+                h: b - t,
+            });
+        }
+        // Reset the damage, changes after this point are reported with the next call. The
+        // areas of the frames the encoder drops are kept by the caller, see `DirtyRegion`.
+        xcb_damage_subtract(c, self.id, XCB_NONE, XCB_NONE);
+        xcb_flush(c);
+    }
 }
 
 impl Capturer {
@@ -57,6 +179,15 @@ impl Capturer {
             );
         }
 
+        let damage = unsafe { Damage::new(&display) };
+        let window = display.window_id().map(|window| WindowCapture {
+            tracker: Tracker::new(display.server(), window),
+            screen: screen_rect(&display),
+            active: false,
+            hidden: false,
+            data: Vec::new(),
+        });
+
         let c = Capturer {
             display,
             shmid,
@@ -64,6 +195,9 @@ impl Capturer {
             buffer,
             size,
             saved_raw_data: Vec::new(),
+            damage,
+            dirty_rects: Vec::new(),
+            window,
         };
         Ok(c)
     }
@@ -72,8 +206,25 @@ impl Capturer {
         &self.display
     }
 
-    fn get_image(&self) {
-        let rect = self.display.rect();
+    /// The next frame is grabbed and returned whole, even if nothing changed.
+    pub fn invalidate(&mut self) {
+        if let Some(damage) = self.damage.as_mut() {
+            damage.full = true;
+        }
+        self.saved_raw_data.clear();
+        if let Some(window) = self.window.as_mut() {
+            window.hidden = false;
+        }
+    }
+
+    /// The position of the captured window and whether it has the focus.
+    pub fn window_state(&self) -> Option<(Rect, bool)> {
+        self.window
+            .as_ref()
+            .map(|w| (self.display.rect(), w.active))
+    }
+
+    fn get_image(&self, rect: Rect) {
         unsafe {
             let request = xcb_shm_get_image_unchecked(
                 self.display.server().raw(),
@@ -93,17 +244,182 @@ impl Capturer {
         }
     }
 
-    pub fn frame<'b>(&'b mut self) -> std::io::Result<&'b [u8]> {
-        self.get_image();
+    /// The frame and the areas changed since the previous frame, `None` if not tracked.
+    pub fn frame<'b>(&'b mut self) -> std::io::Result<(&'b [u8], Option<&'b [DirtyRect]>)> {
+        if self.window.is_some() {
+            return self.window_frame();
+        }
+        if let Some(damage) = self.damage.as_mut() {
+            self.dirty_rects.clear();
+            unsafe { damage.poll(&self.display, &mut self.dirty_rects) };
+            if self.dirty_rects.is_empty() {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+            self.get_image(self.display.rect());
+            let result = unsafe { slice::from_raw_parts(self.buffer, self.size) };
+            return Ok((result, Some(&self.dirty_rects)));
+        }
+        self.get_image(self.display.rect());
         let result = unsafe { slice::from_raw_parts(self.buffer, self.size) };
         crate::would_block_if_equal(&mut self.saved_raw_data, result)?;
-        Ok(result)
+        Ok((result, None))
+    }
+
+    fn window_frame<'b>(&'b mut self) -> std::io::Result<(&'b [u8], Option<&'b [DirtyRect]>)> {
+        let c = self.display.server().raw();
+        let root = self.display.root();
+        let (rect, screen, viewable) = match self.window.as_mut() {
+            Some(window) => {
+                window.active = window.tracker.is_active(c, root);
+                (
+                    window.tracker.rect(c, root),
+                    window.screen,
+                    window.tracker.is_viewable(c),
+                )
+            }
+            None => return Err(std::io::ErrorKind::NotFound.into()),
+        };
+        let rect = match rect {
+            Some(rect) => rect,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "The shared window is closed",
+                ))
+            }
+        };
+        let old = self.display.rect();
+        if (rect.w, rect.h) != (old.w, old.h) {
+            // The encoder has a fixed size, the capturer is created again.
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "The shared window is resized",
+            ));
+        }
+        if (rect.x, rect.y) != (old.x, old.y) {
+            self.display.set_rect(rect);
+            if let Some(damage) = self.damage.as_mut() {
+                damage.full = true;
+            }
+        }
+        if let Some(window) = self.window.as_mut() {
+            if !viewable {
+                window.active = false;
+                if window.hidden {
+                    return Err(std::io::ErrorKind::WouldBlock.into());
+                }
+                // Only once, then paused until the window is shown again in a full frame.
+                window.hidden = true;
+                window.data.clear();
+                window.data.resize(self.size, 0);
+                if let Some(damage) = self.damage.as_mut() {
+                    damage.full = true;
+                }
+                self.saved_raw_data.clear();
+                self.dirty_rects.clear();
+                self.dirty_rects.push(DirtyRect {
+                    x: 0,
+                    y: 0,
+                    w: rect.w as _,
+                    h: rect.h as _,
+                });
+                return Ok((&window.data, Some(&self.dirty_rects)));
+            }
+            window.hidden = false;
+        }
+        if let Some(damage) = self.damage.as_mut() {
+            self.dirty_rects.clear();
+            unsafe { damage.poll(&self.display, &mut self.dirty_rects) };
+            if self.dirty_rects.is_empty() {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+        }
+
+        // Only the part on the screen can be grabbed, the rest is black.
+        let visible = intersect(rect, screen);
+        if let Some(visible) = visible {
+            self.get_image(visible);
+        }
+        let src = unsafe { slice::from_raw_parts(self.buffer, self.size) };
+        let window = match self.window.as_mut() {
+            Some(window) => window,
+            None => return Err(std::io::ErrorKind::NotFound.into()),
+        };
+        let (x, y, w) = (rect.x as i32, rect.y as i32, rect.w as usize);
+        let offset = |r: &Rect, i: usize| {
+            ((r.y as i32 - y) as usize + i) * w * 4 + (r.x as i32 - x) as usize * 4
+        };
+        window.data.clear();
+        window.data.resize(self.size, 0);
+        if let Some(visible) = visible {
+            let row = visible.w as usize * 4;
+            for i in 0..visible.h as usize {
+                let dst = offset(&visible, i);
+                window.data[dst..dst + row].copy_from_slice(&src[i * row..(i + 1) * row]);
+            }
+        }
+        // Black out the windows above, they are not shared.
+        for other in window.tracker.occlusions(c, root) {
+            if let Some(o) = intersect(rect, other) {
+                let row = o.w as usize * 4;
+                for i in 0..o.h as usize {
+                    let dst = offset(&o, i);
+                    window.data[dst..dst + row].fill(0);
+                }
+            }
+        }
+
+        if self.damage.is_some() {
+            return Ok((&window.data, Some(&self.dirty_rects)));
+        }
+        crate::would_block_if_equal(&mut self.saved_raw_data, &window.data)?;
+        Ok((&window.data, None))
+    }
+}
+
+fn screen_rect(display: &Display) -> Rect {
+    let mut rect = Rect {
+        x: 0,
+        y: 0,
+        w: 0,
+        h: 0,
+    };
+    unsafe {
+        let c = display.server().raw();
+        let root = default_root(display.server()).unwrap_or(display.root());
+        let reply = xcb_get_geometry_reply(c, xcb_get_geometry(c, root), ptr::null_mut());
+        if !reply.is_null() {
+            rect.w = (*reply).width;
+            rect.h = (*reply).height;
+            libc::free(reply as *mut _);
+        }
+    }
+    rect
+}
+
+fn intersect(a: Rect, b: Rect) -> Option<Rect> {
+    let l = (a.x as i32).max(b.x as i32);
+    let t = (a.y as i32).max(b.y as i32);
+    let r = (a.x as i32 + a.w as i32).min(b.x as i32 + b.w as i32);
+    let btm = (a.y as i32 + a.h as i32).min(b.y as i32 + b.h as i32);
+    if l < r && t < btm {
+        Some(Rect {
+            x: l as _,
+            y: t as _,
+            w: (r - l) as _,
+            h: (btm - t) as _,
+        })
+    } else {
+        None
     }
 }
 
 impl Drop for Capturer {
     fn drop(&mut self) {
         unsafe {
+            if let Some(damage) = self.damage.take() {
+                xcb_damage_destroy(self.display.server().raw(), damage.id);
+            }
             // Detach segment from XCB.
             xcb_shm_detach(self.display.server().raw(), self.xcbid);
             // Detach segment from our space.
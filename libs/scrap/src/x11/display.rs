@@ -10,6 +10,7 @@ pub struct Display {
     rect: Rect,
     root: xcb_window_t,
     name: String,
+    window: Option<xcb_window_t>,
 }
 
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
@@ -34,9 +35,19 @@ impl Display {
             rect,
             root,
             name,
+            window: None,
         }
     }
 
+    pub(super) fn with_window(mut self, window: xcb_window_t) -> Display {
+        self.window = Some(window);
+        self
+    }
+
+    pub(super) fn set_rect(&mut self, rect: Rect) {
+        self.rect = rect;
+    }
+
     pub fn server(&self) -> &Rc<Server> {
         &self.server
     }
@@ -59,4 +70,9 @@ impl Display {
     pub fn name(&self) -> String {
         self.name.clone()
     }
+
+    /// The window followed by the capturer, see `Display::window`.
+    pub fn window_id(&self) -> Option<xcb_window_t> {
+        self.window
+    }
 }
@@ -0,0 +1,378 @@
+use std::ptr;
+use std::rc::Rc;
+use std::slice;
+
+use hbb_common::libc;
+
+use super::ffi::*;
+use super::{Display, Rect, Server};
+
+/// A top-level window of the default screen, as listed by the window manager.
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub id: xcb_window_t,
+    pub title: String,
+    pub app: String,
+    pub rect: Rect,
+}
+
+/// The atoms used to track windows, interned once per connection.
+#[derive(Debug, Clone, Copy)]
+pub struct Atoms {
+    client_list: xcb_atom_t,
+    active_window: xcb_atom_t,
+    wm_name: xcb_atom_t,
+    wm_pid: xcb_atom_t,
+    utf8_string: xcb_atom_t,
+}
+
+impl Atoms {
+    pub fn new(server: &Server) -> Atoms {
+        let c = server.raw();
+        Atoms {
+            client_list: intern_atom(c, "_NET_CLIENT_LIST_STACKING"),
+            active_window: intern_atom(c, "_NET_ACTIVE_WINDOW"),
+            wm_name: intern_atom(c, "_NET_WM_NAME"),
+            wm_pid: intern_atom(c, "_NET_WM_PID"),
+            utf8_string: intern_atom(c, "UTF8_STRING"),
+        }
+    }
+}
+
+/// The root window of the default screen.
+pub fn default_root(server: &Server) -> Option<xcb_window_t> {
+    unsafe {
+        let mut iter = xcb_setup_roots_iterator(server.setup());
+        for _ in 0..server.screenp() {
+            if iter.rem == 0 {
+                return None;
+            }
+            xcb_screen_next(&mut iter);
+        }
+        if iter.rem == 0 || iter.data.is_null() {
+            None
+        } else {
+            Some((*iter.data).root)
+        }
+    }
+}
+
+/// Visible windows with a title, the top-most first.
+pub fn windows(server: &Server) -> Vec<Window> {
+    let c = server.raw();
+    let root = match default_root(server) {
+        Some(root) => root,
+        None => return vec![],
+    };
+    let atoms = Atoms::new(server);
+    let mut ids = get_windows(c, root, atoms.client_list);
+    ids.reverse();
+    ids.into_iter()
+        .filter(|id| is_viewable(c, *id))
+        .filter_map(|id| {
+            let rect = window_rect(c, root, id)?;
+            let title = window_title(c, &atoms, id);
+            if title.is_empty() || rect.w == 0 || rect.h == 0 {
+                return None;
+            }
+            Some(Window {
+                id,
+                title,
+                app: window_class(c, id),
+                rect,
+            })
+        })
+        .collect()
+}
+
+impl Display {
+    /// A display covering `window`, the capturer follows the window while it moves.
+    pub fn window(server: Rc<Server>, window: xcb_window_t) -> Option<Display> {
+        let c = server.raw();
+        let root = default_root(&server)?;
+        if !is_viewable(c, window) {
+            return None;
+        }
+        let rect = window_rect(c, root, window)?;
+        let atoms = Atoms::new(&server);
+        let name = window_title(c, &atoms, window);
+        let display = unsafe { Display::new(server, false, rect, root, name) };
+        Some(display.with_window(window))
+    }
+}
+
+/// Follows one window: its position, the windows covering it and whether it has the focus.
+pub(super) struct Tracker {
+    window: xcb_window_t,
+    pid: Option<u32>,
+    atoms: Atoms,
+    // The active window and whether it belongs to the same process.
+    active: (xcb_window_t, bool),
+}
+
+impl Tracker {
+    pub(super) fn new(server: &Server, window: xcb_window_t) -> Tracker {
+        let atoms = Atoms::new(server);
+        Tracker {
+            window,
+            pid: window_pid(server.raw(), &atoms, window),
+            atoms,
+            active: (XCB_NONE, false),
+        }
+    }
+
+    pub(super) fn rect(&self, c: *mut xcb_connection_t, root: xcb_window_t) -> Option<Rect> {
+        window_rect(c, root, self.window)
+    }
+
+    /// False if the window is minimized or unmapped, e.g. on another workspace, its area of
+    /// the root shows other windows then.
+    pub(super) fn is_viewable(&self, c: *mut xcb_connection_t) -> bool {
+        is_viewable(c, self.window)
+    }
+
+    /// Whether the active window is the tracked one or another window of its process,
+    /// e.g. a dialog.
+    pub(super) fn is_active(&mut self, c: *mut xcb_connection_t, root: xcb_window_t) -> bool {
+        let active = get_windows(c, root, self.atoms.active_window)
+            .first()
+            .cloned()
+            .unwrap_or(XCB_NONE);
+        if active != self.active.0 {
+            let same = active == self.window
+                || (active != XCB_NONE
+                    && self.pid.is_some()
+                    && window_pid(c, &self.atoms, active) == self.pid);
+            self.active = (active, same);
+        }
+        self.active.1
+    }
+
+    /// Rectangles of the windows stacked above the tracked one, in root coordinates.
+    /// Its dialogs and menus, which are transient for it, are not included.
+    pub(super) fn occlusions(&self, c: *mut xcb_connection_t, root: xcb_window_t) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        let top = match top_level(c, root, self.window) {
+            Some(top) => top,
+            None => return rects,
+        };
+        let children = query_tree(c, root).map(|(_, c)| c).unwrap_or_default();
+        let above = match children.iter().position(|w| *w == top) {
+            Some(pos) => &children[pos + 1..],
+            None => return rects,
+        };
+        unsafe {
+            // Send all requests first, so that the replies arrive in one round trip.
+            let cookies: Vec<_> = above
+                .iter()
+                .map(|w| {
+                    (
+                        *w,
+                        xcb_get_window_attributes(c, *w),
+                        xcb_get_geometry(c, *w),
+                    )
+                })
+                .collect();
+            for (w, attr, geometry) in cookies {
+                let attr = xcb_get_window_attributes_reply(c, attr, ptr::null_mut());
+                let geometry = xcb_get_geometry_reply(c, geometry, ptr::null_mut());
+                if !attr.is_null() && !geometry.is_null() {
+                    let g = &*geometry;
+                    if (*attr).map_state == XCB_MAP_STATE_VIEWABLE && g.width > 0 && g.height > 0 {
+                        rects.push((
+                            w,
+                            Rect {
+                                x: g.x,
+                                y: g.y,
+                                w: g.width + 2 * g.border_width,
+                                h: g.height + 2 * g.border_width,
+                            },
+                        ));
+                    }
+                }
+                libc::free(attr as *mut _);
+                libc::free(geometry as *mut _);
+            }
+        }
+        rects
+            .into_iter()
+            .filter(|(w, _)| top_level_transient_for(c, *w) != Some(self.window))
+            .map(|(_, r)| r)
+            .collect()
+    }
+}
+
+fn intern_atom(c: *mut xcb_connection_t, name: &str) -> xcb_atom_t {
+    unsafe {
+        let reply = xcb_intern_atom_reply(
+            c,
+            xcb_intern_atom(c, 1, name.len() as _, name.as_ptr()),
+            ptr::null_mut(),
+        );
+        if reply.is_null() {
+            return XCB_NONE;
+        }
+        let atom = (*reply).atom;
+        libc::free(reply as *mut _);
+        atom
+    }
+}
+
+fn get_property(
+    c: *mut xcb_connection_t,
+    window: xcb_window_t,
+    property: xcb_atom_t,
+    type_: xcb_atom_t,
+) -> Option<Vec<u8>> {
+    if property == XCB_NONE {
+        return None;
+    }
+    unsafe {
+        let reply = xcb_get_property_reply(
+            c,
+            xcb_get_property(c, 0, window, property, type_, 0, 1024),
+            ptr::null_mut(),
+        );
+        if reply.is_null() {
+            return None;
+        }
+        let len = xcb_get_property_value_length(reply);
+        let value = if (*reply).type_ == XCB_NONE || len <= 0 {
+            None
+        } else {
+            Some(slice::from_raw_parts(xcb_get_property_value(reply), len as _).to_vec())
+        };
+        libc::free(reply as *mut _);
+        value
+    }
+}
+
+fn get_windows(
+    c: *mut xcb_connection_t,
+    window: xcb_window_t,
+    property: xcb_atom_t,
+) -> Vec<xcb_window_t> {
+    get_property(c, window, property, XCB_ATOM_WINDOW)
+        .map(|v| {
+            v.chunks_exact(4)
+                .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn window_title(c: *mut xcb_connection_t, atoms: &Atoms, window: xcb_window_t) -> String {
+    get_property(c, window, atoms.wm_name, atoms.utf8_string)
+        .or_else(|| get_property(c, window, XCB_ATOM_WM_NAME, XCB_ATOM_STRING))
+        .map(|v| String::from_utf8_lossy(&v).trim().to_owned())
+        .unwrap_or_default()
+}
+
+// WM_CLASS is "instance\0class\0".
+fn window_class(c: *mut xcb_connection_t, window: xcb_window_t) -> String {
+    get_property(c, window, XCB_ATOM_WM_CLASS, XCB_ATOM_STRING)
+        .and_then(|v| {
+            v.split(|b| *b == 0)
+                .filter(|s| !s.is_empty())
+                .last()
+                .map(|s| String::from_utf8_lossy(s).to_string())
+        })
+        .unwrap_or_default()
+}
+
+fn window_pid(c: *mut xcb_connection_t, atoms: &Atoms, window: xcb_window_t) -> Option<u32> {
+    get_property(c, window, atoms.wm_pid, XCB_ATOM_CARDINAL)
+        .filter(|v| v.len() >= 4)
+        .map(|v| u32::from_ne_bytes([v[0], v[1], v[2], v[3]]))
+}
+
+fn transient_for(c: *mut xcb_connection_t, window: xcb_window_t) -> Option<xcb_window_t> {
+    get_windows(c, window, XCB_ATOM_WM_TRANSIENT_FOR)
+        .first()
+        .cloned()
+}
+
+// Window managers reparent clients into frames, the property is set on the client.
+fn top_level_transient_for(c: *mut xcb_connection_t, window: xcb_window_t) -> Option<xcb_window_t> {
+    transient_for(c, window).or_else(|| {
+        query_tree(c, window)?
+            .1
+            .iter()
+            .find_map(|child| transient_for(c, *child))
+    })
+}
+
+fn is_viewable(c: *mut xcb_connection_t, window: xcb_window_t) -> bool {
+    unsafe {
+        let reply = xcb_get_window_attributes_reply(
+            c,
+            xcb_get_window_attributes(c, window),
+            ptr::null_mut(),
+        );
+        if reply.is_null() {
+            return false;
+        }
+        let viewable = (*reply).map_state == XCB_MAP_STATE_VIEWABLE;
+        libc::free(reply as *mut _);
+        viewable
+    }
+}
+
+// The window area without the frame of the window manager, in root coordinates.
+fn window_rect(c: *mut xcb_connection_t, root: xcb_window_t, window: xcb_window_t) -> Option<Rect> {
+    unsafe {
+        let geometry = xcb_get_geometry(c, window);
+        let translate = xcb_translate_coordinates(c, window, root, 0, 0);
+        let geometry = xcb_get_geometry_reply(c, geometry, ptr::null_mut());
+        let translate = xcb_translate_coordinates_reply(c, translate, ptr::null_mut());
+        let rect = if geometry.is_null() || translate.is_null() {
+            None
+        } else {
+            Some(Rect {
+                x: (*translate).dst_x,
+                y: (*translate).dst_y,
+                w: (*geometry).width,
+                h: (*geometry).height,
+            })
+        };
+        libc::free(geometry as *mut _);
+        libc::free(translate as *mut _);
+        rect
+    }
+}
+
+fn query_tree(
+    c: *mut xcb_connection_t,
+    window: xcb_window_t,
+) -> Option<(xcb_window_t, Vec<xcb_window_t>)> {
+    unsafe {
+        let reply = xcb_query_tree_reply(c, xcb_query_tree(c, window), ptr::null_mut());
+        if reply.is_null() {
+            return None;
+        }
+        let children = slice::from_raw_parts(
+            xcb_query_tree_children(reply),
+            xcb_query_tree_children_length(reply).max(0) as _,
+        )
+        .to_vec();
+        let parent = (*reply).parent;
+        libc::free(reply as *mut _);
+        Some((parent, children))
+    }
+}
+
+// The ancestor of `window` which is a child of the root, i.e. the frame of the window manager.
+fn top_level(
+    c: *mut xcb_connection_t,
+    root: xcb_window_t,
+    window: xcb_window_t,
+) -> Option<xcb_window_t> {
+    let mut window = window;
+    loop {
+        let (parent, _) = query_tree(c, window)?;
+        if parent == root || parent == XCB_NONE {
+            return Some(window);
+        }
+        window = parent;
+    }
+}
@@ -67,19 +67,30 @@ use crate::{
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use crate::ui_session_interface::SessionPermissionConfig;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
-use crate::{check_clipboard, CLIPBOARD_INTERVAL};
+use crate::{check_clipboard, wait_clipboard_change, CLIPBOARD_INTERVAL};
 
 pub use super::lang::*;
 
+pub mod clipboard_history;
 pub mod file_trait;
 pub mod helper;
 pub mod io_loop;
+pub mod jitter_buffer;
+pub mod replay;
+pub mod screenshot;
 
 pub const MILLI1: Duration = Duration::from_millis(1);
 pub const SEC30: Duration = Duration::from_secs(30);
 pub const VIDEO_QUEUE_SIZE: usize = 120;
 const MAX_DECODE_FAIL_COUNTER: usize = 10; // Currently, failed decode cause refresh_video, so make it small
 
+// Refresh the video if no frame to go on from comes for this long.
+const VIDEO_RECOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+// How often the decode thread checks the recovery, with or without frames coming in.
+const VIDEO_RECOVERY_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+// Older peers ignore RecoverVideo.
+const RECOVER_VIDEO_VERSION: &str = "1.2.4";
+
 #[cfg(all(target_os = "linux", feature = "linux_headless"))]
 #[cfg(not(any(feature = "flatpak", feature = "appimage")))]
 pub const LOGIN_MSG_DESKTOP_NOT_INITED: &str = "Desktop env is not inited";
@@ -728,6 +739,8 @@ impl Client {
         std::thread::spawn(move || {
             let mut is_sent = false;
             let mut ctx = None;
+            let mut seq = 0;
+            let mut changed = true;
             loop {
                 if !TEXT_CLIPBOARD_STATE.lock().unwrap().running {
                     break;
@@ -736,13 +749,20 @@ impl Client {
                     continue;
                 }
 
-                if let Some(msg) = check_clipboard(&mut ctx, Some(&OLD_CLIPBOARD_TEXT)) {
-                    #[cfg(feature = "flutter")]
-                    crate::flutter::send_text_clipboard_msg(msg);
-                    #[cfg(not(feature = "flutter"))]
-                    if let Some(ctx) = &_ctx {
-                        if ctx.cfg.is_text_clipboard_required() {
-                            let _ = ctx.tx.send(Data::Message(msg));
+                if changed {
+                    for msg in check_clipboard(&mut ctx, Some(&OLD_CLIPBOARD_TEXT)) {
+                        #[cfg(feature = "flutter")]
+                        crate::flutter::send_text_clipboard_msg(msg);
+                        // The notice of a blocked clipboard is for the flutter sessions.
+                        #[cfg(not(feature = "flutter"))]
+                        if msg.has_message_box() {
+                            continue;
+                        }
+                        #[cfg(not(feature = "flutter"))]
+                        if let Some(ctx) = &_ctx {
+                            if ctx.cfg.is_text_clipboard_required() {
+                                let _ = ctx.tx.send(Data::Message(msg));
+                            }
                         }
                     }
                 }
@@ -752,7 +772,8 @@ impl Client {
                     tx.send(()).ok();
                 }
 
-                std::thread::sleep(Duration::from_millis(CLIPBOARD_INTERVAL));
+                changed =
+                    wait_clipboard_change(&mut seq, Duration::from_millis(CLIPBOARD_INTERVAL));
             }
             log::info!("Stop text clipboard loop");
         });
@@ -762,13 +783,23 @@ impl Client {
 
     #[inline]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
-    fn get_current_text_clipboard_msg() -> Option<Message> {
+    fn get_current_clipboard_msgs() -> Vec<Message> {
+        let mut msgs = vec![];
         let txt = &*OLD_CLIPBOARD_TEXT.lock().unwrap();
-        if txt.is_empty() {
-            None
-        } else {
-            Some(crate::create_clipboard_msg(txt.clone()))
+        if !txt.is_empty()
+            && crate::clipboard_filter::check(
+                crate::clipboard_filter::Direction::ClientToHost,
+                ClipboardFormat::Text,
+                txt.as_bytes(),
+            )
+            .is_ok()
+        {
+            msgs.push(crate::create_clipboard_msg(txt.clone()));
         }
+        msgs.extend(crate::rich_clipboard::current_format_list(
+            crate::rich_clipboard::ClipboardSide::Client,
+        ));
+        msgs
     }
 }
 
@@ -782,14 +813,54 @@ impl TextClipboardState {
     }
 }
 
+// Video frames are held at most this long to wait for their audio.
+const MAX_AV_SYNC_DELAY_MS: i64 = 300;
+// No sound is heard if no audio frame was played for this long, video is not held then.
+const AV_SYNC_IDLE: Duration = Duration::from_millis(500);
+
+/// The audio position shared with the video thread, to show the frames when their sound is heard.
+#[derive(Default)]
+pub struct AvSync {
+    // (capture time of the last audio frame played, when it is heard)
+    audio: Mutex<Option<(i64, std::time::Instant)>>,
+}
+
+impl AvSync {
+    fn on_audio(&self, capture_time: i64, latency: Duration) {
+        *self.audio.lock().unwrap() = Some((capture_time, std::time::Instant::now() + latency));
+    }
+
+    /// How long to hold the video frame captured at `capture_time`.
+    pub fn video_delay(&self, capture_time: i64) -> Duration {
+        let Some((audio_time, heard_at)) = *self.audio.lock().unwrap() else {
+            return Duration::ZERO;
+        };
+        let now = std::time::Instant::now();
+        let since_heard = match now.checked_duration_since(heard_at) {
+            Some(elapsed) if elapsed > AV_SYNC_IDLE => return Duration::ZERO,
+            Some(elapsed) => elapsed.as_millis() as i64,
+            None => -(heard_at.duration_since(now).as_millis() as i64),
+        };
+        let ahead = capture_time - (audio_time + since_heard);
+        Duration::from_millis(ahead.clamp(0, MAX_AV_SYNC_DELAY_MS) as _)
+    }
+}
+
 /// Audio handler for the [`Client`].
 #[derive(Default)]
 pub struct AudioHandler {
     audio_decoder: Option<(AudioDecoder, Vec<f32>)>,
+    jitter_buffer: jitter_buffer::JitterBuffer,
+    av_sync: Option<Arc<AvSync>>,
+    // samples per channel of the last frame, the size of a lost one
+    frame_samples: usize,
     #[cfg(target_os = "android")]
     oboe: Option<OboePlayer>,
     #[cfg(target_os = "linux")]
     simple: Option<psimple::Simple>,
+    // the default output if None
+    #[cfg(target_os = "linux")]
+    device: Option<String>,
     #[cfg(not(any(target_os = "android", target_os = "linux")))]
     audio_buffer: AudioBuffer,
     sample_rate: (u32, u32),
@@ -835,7 +906,7 @@ impl AudioHandler {
             None,                   // Use the default server
             &crate::get_app_name(), // Our application’s name
             Direction::Playback,    // We want a playback stream
-            None,                   // Use the default device
+            self.device.as_deref(), // Use the default device if None
             "playback",             // Description of our stream
             &spec,                  // Our sample format
             None,                   // Use default channel map
@@ -906,6 +977,8 @@ impl AudioHandler {
             Ok(d) => {
                 let buffer = vec![0.; f.sample_rate as usize * f.channels as usize];
                 self.audio_decoder = Some((d, buffer));
+                self.jitter_buffer = Default::default();
+                self.frame_samples = f.sample_rate as usize / 100;
                 self.channels = f.channels as _;
                 allow_err!(self.start_audio(f));
             }
@@ -918,6 +991,59 @@ impl AudioHandler {
     /// Handle audio frame and play it.
     #[inline]
     pub fn handle_frame(&mut self, frame: AudioFrame) {
+        self.decode(&frame.data, false);
+        if frame.capture_time > 0 {
+            if let Some(av_sync) = self.av_sync.as_ref() {
+                av_sync.on_audio(frame.capture_time, self.output_latency());
+            }
+        }
+    }
+
+    // The sound written is heard after this.
+    fn output_latency(&self) -> Duration {
+        #[cfg(target_os = "linux")]
+        if let Some(Ok(latency)) = self.simple.as_ref().map(|s| s.get_latency()) {
+            return Duration::from_micros(latency.0);
+        }
+        #[cfg(not(any(target_os = "android", target_os = "linux")))]
+        if self.sample_rate.1 > 0 && self.device_channel > 0 {
+            let samples = self.audio_buffer.0.lock().unwrap().occupied_len();
+            let rate = self.sample_rate.1 as usize * self.device_channel as usize;
+            return Duration::from_secs_f64(samples as f64 / rate as f64);
+        }
+        Duration::ZERO
+    }
+
+    /// Buffer the frame to be played by `play_due`, a frame of an old peer without sequence is
+    /// played at once.
+    pub fn push_frame(&mut self, frame: AudioFrame) {
+        if frame.seq == 0 {
+            self.handle_frame(frame);
+        } else {
+            self.jitter_buffer.push(frame);
+        }
+    }
+
+    /// Play the buffered frames which are due, return how long to wait for the next one.
+    pub fn play_due(&mut self) -> Duration {
+        let frame_duration = if self.frame_samples > 0 && self.sample_rate.0 > 0 {
+            Duration::from_secs_f64(self.frame_samples as f64 / self.sample_rate.0 as f64)
+        } else {
+            Duration::from_millis(10)
+        };
+        let now = std::time::Instant::now();
+        while let Some(playout) = self.jitter_buffer.pop(now, frame_duration) {
+            match playout {
+                jitter_buffer::Playout::Frame(frame) => self.handle_frame(frame),
+                jitter_buffer::Playout::Fec(next) => self.decode(&next.data, true),
+                jitter_buffer::Playout::Plc => self.decode(&[], false),
+            }
+        }
+        self.jitter_buffer.wait(now)
+    }
+
+    // Empty `data` conceals a lost frame, `fec` decodes the lost frame before `data`.
+    fn decode(&mut self, data: &[u8], fec: bool) {
         #[cfg(not(any(target_os = "android", target_os = "linux")))]
         if self.audio_stream.is_none() || !self.ready.lock().unwrap().clone() {
             return;
@@ -931,47 +1057,57 @@ impl AudioHandler {
         if self.oboe.is_none() {
             return;
         }
-        self.audio_decoder.as_mut().map(|(d, buffer)| {
-            if let Ok(n) = d.decode_float(&frame.data, buffer, false) {
-                let channels = self.channels;
-                let n = n * (channels as usize);
-                #[cfg(not(any(target_os = "android", target_os = "linux")))]
-                {
-                    let sample_rate0 = self.sample_rate.0;
-                    let sample_rate = self.sample_rate.1;
-                    let audio_buffer = self.audio_buffer.0.clone();
-                    let mut buffer = buffer[0..n].to_owned();
-                    if sample_rate != sample_rate0 {
-                        buffer = crate::audio_resample(
-                            &buffer[0..n],
-                            sample_rate0,
-                            sample_rate,
-                            channels,
-                        );
-                    }
-                    if self.channels != self.device_channel {
-                        buffer = crate::audio_rechannel(
-                            buffer,
-                            sample_rate,
-                            sample_rate,
-                            self.channels,
-                            self.device_channel,
-                        );
-                    }
-                    audio_buffer.lock().unwrap().push_slice_overwrite(&buffer);
-                }
-                #[cfg(target_os = "android")]
-                {
-                    self.oboe.as_mut().map(|x| x.push(&buffer[0..n]));
-                }
-                #[cfg(target_os = "linux")]
-                {
-                    let data_u8 =
-                        unsafe { std::slice::from_raw_parts::<u8>(buffer.as_ptr() as _, n * 4) };
-                    self.simple.as_mut().map(|x| x.write(data_u8));
-                }
+        let Some((mut d, mut buffer)) = self.audio_decoder.take() else {
+            return;
+        };
+        let channels = self.channels as usize;
+        // Opus takes the size of the lost frame from the output.
+        let len = if data.is_empty() || fec {
+            (self.frame_samples * channels).min(buffer.len())
+        } else {
+            buffer.len()
+        };
+        if let Ok(n) = d.decode_float(data, &mut buffer[..len], fec) {
+            if !data.is_empty() && !fec {
+                self.frame_samples = n;
             }
-        });
+            self.play(&buffer[..n * channels]);
+        }
+        self.audio_decoder = Some((d, buffer));
+    }
+
+    fn play(&mut self, buffer: &[f32]) {
+        let n = buffer.len();
+        #[cfg(not(any(target_os = "android", target_os = "linux")))]
+        {
+            let channels = self.channels;
+            let sample_rate0 = self.sample_rate.0;
+            let sample_rate = self.sample_rate.1;
+            let audio_buffer = self.audio_buffer.0.clone();
+            let mut buffer = buffer[0..n].to_owned();
+            if sample_rate != sample_rate0 {
+                buffer = crate::audio_resample(&buffer[0..n], sample_rate0, sample_rate, channels);
+            }
+            if self.channels != self.device_channel {
+                buffer = crate::audio_rechannel(
+                    buffer,
+                    sample_rate,
+                    sample_rate,
+                    self.channels,
+                    self.device_channel,
+                );
+            }
+            audio_buffer.lock().unwrap().push_slice_overwrite(&buffer);
+        }
+        #[cfg(target_os = "android")]
+        {
+            self.oboe.as_mut().map(|x| x.push(&buffer[0..n]));
+        }
+        #[cfg(target_os = "linux")]
+        {
+            let data_u8 = unsafe { std::slice::from_raw_parts::<u8>(buffer.as_ptr() as _, n * 4) };
+            self.simple.as_mut().map(|x| x.write(data_u8));
+        }
     }
 
     /// Build audio output stream for current device.
@@ -1029,6 +1165,10 @@ pub struct VideoHandler {
     record: bool,
     _display: usize, // useful for debug
     fail_counter: usize,
+    // pts of the last frame decoded correctly.
+    last_pts: Option<i64>,
+    // Since when frames are skipped until a key or recovery frame, and the pts requested.
+    recovering: Option<(std::time::Instant, i64)>,
 }
 
 impl VideoHandler {
@@ -1047,6 +1187,8 @@ impl VideoHandler {
             record: false,
             _display,
             fail_counter: 0,
+            last_pts: None,
+            recovering: None,
         }
     }
 
@@ -1062,6 +1204,12 @@ impl VideoHandler {
         if format != self.decoder.format() {
             self.reset(Some(format));
         }
+        if self.recovering.is_some() {
+            if !Self::is_recovery_point(&vf) {
+                return Ok(false);
+            }
+            self.recovering = None;
+        }
         match &vf.union {
             Some(frame) => {
                 let res = self.decoder.handle_video_frame(
@@ -1073,6 +1221,13 @@ impl VideoHandler {
                 );
                 if res.as_ref().is_ok_and(|x| *x) {
                     self.fail_counter = 0;
+                    // The low layer of a slow connection is sent at a smaller size.
+                    if *pixelbuffer && vf.downscale > 1 {
+                        self.rgb.scale_up(vf.downscale as _);
+                    }
+                    self.last_pts = Self::encoded_frames(&vf)
+                        .and_then(|f| f.iter().map(|f| f.pts).max())
+                        .or(self.last_pts);
                 } else {
                     if self.fail_counter < usize::MAX {
                         self.fail_counter += 1
@@ -1083,7 +1238,7 @@ impl VideoHandler {
                         .lock()
                         .unwrap()
                         .as_mut()
-                        .map(|r| r.write_frame(frame));
+                        .map(|r| r.write_video_frame(&vf));
                 }
                 res
             }
@@ -1100,6 +1255,88 @@ impl VideoHandler {
         let format = format.unwrap_or(self.decoder.format());
         self.decoder = Decoder::new(format, luid);
         self.fail_counter = 0;
+        self.last_pts = None;
+        self.recovering = None;
+    }
+
+    /// Whether the peer of `version` can be asked for a frame to go on from after a failure,
+    /// instead of refreshing the video.
+    pub fn can_recover(&self, version: i64) -> bool {
+        let format = self.decoder.format();
+        version > get_version_number(RECOVER_VIDEO_VERSION)
+            && self.last_pts.is_some()
+            && (format == CodecFormat::VP8
+                || format == CodecFormat::VP9
+                || format == CodecFormat::AV1)
+    }
+
+    /// Give up a recovery that has not got a frame to go on from in time, even if no frame
+    /// comes at all. Returns true if the video must be refreshed.
+    pub fn check_recovery_timeout(&mut self) -> bool {
+        if self
+            .recovering
+            .map_or(false, |(since, _)| since.elapsed() > VIDEO_RECOVERY_TIMEOUT)
+        {
+            self.recovering = None;
+            self.last_pts = None;
+            return true;
+        }
+        false
+    }
+
+    /// Skip frames until the peer sends one to go on from. `lost_pts` is the oldest frame lost
+    /// before decoding. Returns the pts of the last frame decoded correctly, to be sent in
+    /// [`RecoverVideo`], or `None` if it has been sent already.
+    pub fn start_recovery(&mut self, lost_pts: Option<i64>) -> Option<i64> {
+        let mut last = self.last_pts?;
+        if let Some(lost) = lost_pts {
+            last = last.min(lost - 1);
+        }
+        match self.recovering.as_mut() {
+            Some((_, requested)) if *requested == last => None,
+            Some((_, requested)) => {
+                *requested = last;
+                Some(last)
+            }
+            None => {
+                self.recovering = Some((std::time::Instant::now(), last));
+                Some(last)
+            }
+        }
+    }
+
+    pub(crate) fn encoded_frames(vf: &VideoFrame) -> Option<&[EncodedVideoFrame]> {
+        match &vf.union {
+            Some(video_frame::Union::Vp8s(f))
+            | Some(video_frame::Union::Vp9s(f))
+            | Some(video_frame::Union::Av1s(f)) => Some(&f.frames),
+            _ => None,
+        }
+    }
+
+    fn is_recovery_point(vf: &VideoFrame) -> bool {
+        Self::encoded_frames(vf).map_or(true, |f| f.iter().any(|f| f.key || f.recovery))
+    }
+
+    /// Mux the audio of the session into the record.
+    pub fn set_audio_format(&mut self, format: &AudioFormat) {
+        if self.record {
+            self.recorder
+                .lock()
+                .unwrap()
+                .as_mut()
+                .map(|r| r.set_audio_format(format));
+        }
+    }
+
+    pub fn write_audio(&mut self, frame: &AudioFrame) {
+        if self.record {
+            self.recorder
+                .lock()
+                .unwrap()
+                .as_mut()
+                .map(|r| r.write_audio_frame(frame));
+        }
     }
 
     /// Start or stop screen record.
@@ -1115,6 +1352,7 @@ impl VideoHandler {
                 height: h as _,
                 format: scrap::CodecFormat::VP9,
                 tx: None,
+                audio_tx: None,
             })
             .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))));
         } else {
@@ -1151,6 +1389,7 @@ pub struct LoginConfigHandler {
     pub mark_unsupported: Vec<CodecFormat>,
     pub selected_windows_session_id: Option<u32>,
     pub peer_info: Option<PeerInfo>,
+    pub screenshot: Option<i32>, // display to capture, -1 for all
 }
 
 impl Deref for LoginConfigHandler {
@@ -1445,6 +1684,14 @@ impl LoginConfigHandler {
             option.block_input = BoolOption::Yes.into();
         } else if name == "unblock-input" {
             option.block_input = BoolOption::No.into();
+        } else if name == "screen-content" {
+            let on = config.options.get(&name).map_or(true, |v| v.is_empty());
+            if on {
+                config.options.insert(name.clone(), "Y".to_owned());
+            } else {
+                config.options.remove(&name);
+            }
+            option.screen_content = (if on { BoolOption::Yes } else { BoolOption::No }).into();
         } else if name == "show-quality-monitor" {
             config.show_quality_monitor.v = !config.show_quality_monitor.v;
         } else if name == "allow_swap_key" {
@@ -1579,6 +1826,14 @@ impl LoginConfigHandler {
             msg.disable_clipboard = BoolOption::Yes.into();
             n += 1;
         }
+        if self.get_toggle_option("screen-content") {
+            msg.screen_content = BoolOption::Yes.into();
+            n += 1;
+        }
+        if let Some(q) = Self::get_audio_quality_enum(&self.get_option("audio-quality")) {
+            msg.audio_quality = q.into();
+            n += 1;
+        }
         msg.supported_decoding =
             hbb_common::protobuf::MessageField::some(Decoder::supported_decodings(
                 Some(&self.id),
@@ -1639,6 +1894,16 @@ impl LoginConfigHandler {
         }
     }
 
+    /// Parse the audio quality option, `None` for the default.
+    fn get_audio_quality_enum(q: &str) -> Option<AudioQuality> {
+        match q {
+            "low" => Some(AudioQuality::AudioQualityLow),
+            "balanced" => Some(AudioQuality::AudioQualityBalanced),
+            "best" => Some(AudioQuality::AudioQualityBest),
+            _ => None,
+        }
+    }
+
     /// Get the status of a toggle option.
     ///
     /// # Arguments
@@ -1738,6 +2003,25 @@ impl LoginConfigHandler {
         res
     }
 
+    /// Save the given audio quality to the config.
+    /// Return a [`Message`] that contains audio quality, or `None` if the audio quality is not valid.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The audio quality, "low", "balanced" or "best".
+    pub fn save_audio_quality(&mut self, value: String) -> Option<Message> {
+        let q = Self::get_audio_quality_enum(&value)?;
+        self.set_option("audio-quality".to_owned(), value);
+        let mut misc = Misc::new();
+        misc.set_option(OptionMessage {
+            audio_quality: q.into(),
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        Some(msg_out)
+    }
+
     /// Create a [`Message`] for saving custom fps.
     ///
     /// # Arguments
@@ -1956,7 +2240,14 @@ impl LoginConfigHandler {
                 port: self.port_forward.1,
                 ..Default::default()
             }),
-            _ => {}
+            _ => {
+                if let Some(display) = self.screenshot {
+                    lr.set_screenshot(Screenshot {
+                        display,
+                        ..Default::default()
+                    });
+                }
+            }
         }
 
         let mut msg_out = Message::new();
@@ -1994,6 +2285,8 @@ impl LoginConfigHandler {
 pub enum MediaData {
     VideoQueue(usize),
     VideoFrame(Box<VideoFrame>),
+    // Frames of the display were dropped from the queue, with the pts of the oldest one.
+    VideoDropped(usize, i64),
     AudioFrame(Box<AudioFrame>),
     AudioFormat(AudioFormat),
     Reset(usize),
@@ -2039,16 +2332,52 @@ where
     let chroma = Arc::new(RwLock::new(None));
     let chroma_cloned = chroma.clone();
     let mut last_chroma = None;
+    let av_sync = Arc::new(AvSync::default());
+    let audio_sender = audio_thread(None, Some(av_sync.clone()));
 
     std::thread::spawn(move || {
         #[cfg(windows)]
         sync_cpu_usage();
         let mut handler_controller_map = Vec::new();
+        // for the records
+        let mut audio_format = None;
+        // display -> (when to show the frame decoded last, pixelbuffer), held for its audio
+        let mut held: HashMap<usize, (std::time::Instant, bool)> = HashMap::new();
         // let mut count = Vec::new();
         // let mut duration = std::time::Duration::ZERO;
         // let mut skip_beginning = Vec::new();
         loop {
-            if let Ok(data) = video_receiver.recv() {
+            let now = std::time::Instant::now();
+            let wait = held
+                .values()
+                .map(|(due, _)| due.saturating_duration_since(now))
+                .fold(VIDEO_RECOVERY_CHECK_INTERVAL, Duration::min);
+            let data = video_receiver.recv_timeout(wait);
+            let now = std::time::Instant::now();
+            held.retain(|display, (due, pixelbuffer)| {
+                if *due > now {
+                    return true;
+                }
+                if let Some(h) = handler_controller_map.get_mut(*display) {
+                    video_callback(
+                        *display,
+                        &mut h.handler.rgb,
+                        h.handler.texture,
+                        *pixelbuffer,
+                    );
+                }
+                false
+            });
+            for (display, h) in handler_controller_map.iter_mut().enumerate() {
+                if h.handler.check_recovery_timeout() {
+                    log::warn!("video recovery of display {} timed out", display);
+                    session.refresh_video(display as _);
+                }
+            }
+            if let Err(mpsc::RecvTimeoutError::Timeout) = data {
+                continue;
+            }
+            if let Ok(data) = data {
                 match data {
                     MediaData::VideoFrame(_) | MediaData::VideoQueue(_) => {
                         let vf = match data {
@@ -2072,6 +2401,7 @@ where
                             }
                         };
                         let display = vf.display as usize;
+                        let capture_time = vf.capture_time;
                         let start = std::time::Instant::now();
                         let format = CodecFormat::from(&vf);
                         if handler_controller_map.len() <= display {
@@ -2085,6 +2415,15 @@ where
                             }
                         }
                         if let Some(handler_controller) = handler_controller_map.get_mut(display) {
+                            // Shown late rather than overwritten by the next one.
+                            if let Some((_, pixelbuffer)) = held.remove(&display) {
+                                video_callback(
+                                    display,
+                                    &mut handler_controller.handler.rgb,
+                                    handler_controller.handler.texture,
+                                    pixelbuffer,
+                                );
+                            }
                             let mut pixelbuffer = true;
                             let mut tmp_chroma = None;
                             match handler_controller.handler.handle_frame(
@@ -2093,12 +2432,31 @@ where
                                 &mut tmp_chroma,
                             ) {
                                 Ok(true) => {
-                                    video_callback(
-                                        display,
-                                        &mut handler_controller.handler.rgb,
-                                        handler_controller.handler.texture,
-                                        pixelbuffer,
-                                    );
+                                    let delay = if capture_time > 0
+                                        && session
+                                            .lc
+                                            .read()
+                                            .unwrap()
+                                            .get_toggle_option("sync-audio-video")
+                                    {
+                                        av_sync.video_delay(capture_time)
+                                    } else {
+                                        Duration::ZERO
+                                    };
+                                    if delay.is_zero() {
+                                        video_callback(
+                                            display,
+                                            &mut handler_controller.handler.rgb,
+                                            handler_controller.handler.texture,
+                                            pixelbuffer,
+                                        );
+                                    } else {
+                                        // Shown by the loop, which keeps receiving meanwhile.
+                                        held.insert(
+                                            display,
+                                            (std::time::Instant::now() + delay, pixelbuffer),
+                                        );
+                                    }
 
                                     // chroma
                                     if tmp_chroma.is_some() && last_chroma != tmp_chroma {
@@ -2142,7 +2500,14 @@ where
                                     //
                                     // to-do: fix the error
                                     log::error!("handle video frame error, {}", e);
-                                    session.refresh_video(display as _);
+                                    let handler = &mut handler_controller.handler;
+                                    if handler.can_recover(session.lc.read().unwrap().version) {
+                                        if let Some(pts) = handler.start_recovery(None) {
+                                            session.recover_video(display as _, pts);
+                                        }
+                                    } else {
+                                        session.refresh_video(display as _);
+                                    }
                                 }
                                 _ => {}
                             }
@@ -2170,7 +2535,18 @@ where
                             ));
                         }
                     }
+                    MediaData::VideoDropped(display, pts) => {
+                        if let Some(handler_controller) = handler_controller_map.get_mut(display) {
+                            let handler = &mut handler_controller.handler;
+                            if handler.can_recover(session.lc.read().unwrap().version) {
+                                if let Some(pts) = handler.start_recovery(Some(pts)) {
+                                    session.recover_video(display as _, pts);
+                                }
+                            }
+                        }
+                    }
                     MediaData::Reset(display) => {
+                        held.remove(&display);
                         if let Some(handler_controler) = handler_controller_map.get_mut(display) {
                             handler_controler.handler.reset(None);
                         }
@@ -2190,6 +2566,22 @@ where
                                 handler_controler.handler.record_screen(start, w, h, id);
                             }
                         }
+                        if let Some(format) = audio_format.as_ref() {
+                            for h in handler_controller_map.iter_mut() {
+                                h.handler.set_audio_format(format);
+                            }
+                        }
+                    }
+                    MediaData::AudioFormat(format) => {
+                        for h in handler_controller_map.iter_mut() {
+                            h.handler.set_audio_format(&format);
+                        }
+                        audio_format = Some(format);
+                    }
+                    MediaData::AudioFrame(frame) => {
+                        for h in handler_controller_map.iter_mut() {
+                            h.handler.write_audio(&frame);
+                        }
                     }
                     _ => {}
                 }
@@ -2199,7 +2591,6 @@ where
         }
         log::info!("Video decoder loop exits");
     });
-    let audio_sender = start_audio_thread();
     return (
         video_sender,
         audio_sender,
@@ -2212,24 +2603,38 @@ where
 /// Start an audio thread
 /// Return a audio [`MediaSender`]
 pub fn start_audio_thread() -> MediaSender {
+    start_audio_thread_to(None)
+}
+
+/// Like `start_audio_thread`, but plays to `device` instead of the default output on Linux.
+pub fn start_audio_thread_to(device: Option<String>) -> MediaSender {
+    audio_thread(device, None)
+}
+
+fn audio_thread(_device: Option<String>, av_sync: Option<Arc<AvSync>>) -> MediaSender {
     let (audio_sender, audio_receiver) = mpsc::channel::<MediaData>();
     std::thread::spawn(move || {
         let mut audio_handler = AudioHandler::default();
+        audio_handler.av_sync = av_sync;
+        #[cfg(target_os = "linux")]
+        {
+            audio_handler.device = _device;
+        }
+        let mut timeout = Duration::from_secs(1);
         loop {
-            if let Ok(data) = audio_receiver.recv() {
-                match data {
-                    MediaData::AudioFrame(af) => {
-                        audio_handler.handle_frame(*af);
-                    }
-                    MediaData::AudioFormat(f) => {
-                        log::debug!("recved audio format, sample rate={}", f.sample_rate);
-                        audio_handler.handle_format(f);
-                    }
-                    _ => {}
+            match audio_receiver.recv_timeout(timeout) {
+                Ok(MediaData::AudioFrame(af)) => {
+                    audio_handler.push_frame(*af);
                 }
-            } else {
-                break;
+                Ok(MediaData::AudioFormat(f)) => {
+                    log::debug!("recved audio format, sample rate={}", f.sample_rate);
+                    audio_handler.handle_format(f);
+                }
+                Ok(_) => {}
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
             }
+            timeout = audio_handler.play_due();
         }
         log::info!("Audio decoder loop exits");
     });
@@ -0,0 +1,157 @@
+// The clipboard texts sent to and received from the peer during a session, newest first, so that
+// an entry overwritten by a later copy can be pushed to the peer again.
+//
+// The history lives with the session and is dropped when it ends, unless the peer option
+// `keep-clipboard-history` is set: then it is saved to a file per peer on every change, and the
+// next session to the peer starts with it.
+
+use hbb_common::{config::Config, get_time, log};
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::VecDeque, path::PathBuf};
+
+pub const OPTION_KEEP_CLIPBOARD_HISTORY: &str = "keep-clipboard-history";
+const MAX_ENTRIES: usize = 50;
+// of all the entries, the oldest are dropped beyond
+const MAX_TOTAL_SIZE: usize = 10_000_000;
+const DIR: &str = "clipboard_history";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub text: String,
+    /// Sent to the peer, received from it otherwise.
+    pub sent: bool,
+    /// In ms since the epoch.
+    pub time: i64,
+}
+
+#[derive(Default)]
+pub struct ClipboardHistory {
+    loaded: bool,
+    entries: VecDeque<Entry>,
+}
+
+impl ClipboardHistory {
+    /// Add `text` as the newest entry, moving it up if it is already in the history.
+    pub fn push(&mut self, id: &str, keep: bool, text: String, sent: bool) {
+        if text.is_empty() {
+            return;
+        }
+        self.load(id, keep);
+        if self
+            .entries
+            .front()
+            .map(|e| e.text == text)
+            .unwrap_or(false)
+        {
+            return;
+        }
+        self.entries.retain(|e| e.text != text);
+        self.entries.push_front(Entry {
+            text,
+            sent,
+            time: get_time(),
+        });
+        let mut size = 0;
+        let mut len = 0;
+        for e in self.entries.iter() {
+            size += e.text.len();
+            if len >= MAX_ENTRIES || (len > 0 && size > MAX_TOTAL_SIZE) {
+                break;
+            }
+            len += 1;
+        }
+        self.entries.truncate(len);
+        if keep {
+            self.save(id);
+        }
+    }
+
+    pub fn entries(&mut self, id: &str, keep: bool) -> &VecDeque<Entry> {
+        self.load(id, keep);
+        &self.entries
+    }
+
+    /// Save the history to keep it, remove the saved one otherwise.
+    pub fn set_keep(&mut self, id: &str, keep: bool) {
+        if keep {
+            self.load(id, keep);
+            self.save(id);
+        } else if let Err(err) = std::fs::remove_file(path(id)) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                log::error!("Failed to remove the clipboard history of {}: {}", id, err);
+            }
+        }
+    }
+
+    fn load(&mut self, id: &str, keep: bool) {
+        if self.loaded {
+            return;
+        }
+        self.loaded = true;
+        if !keep {
+            return;
+        }
+        let Ok(data) = std::fs::read(path(id)) else {
+            return;
+        };
+        match serde_json::from_slice::<VecDeque<Entry>>(&data) {
+            Ok(mut entries) => {
+                // The newer ones of this session first.
+                entries.retain(|e| !self.entries.iter().any(|e2| e2.text == e.text));
+                self.entries.extend(entries);
+                self.entries.truncate(MAX_ENTRIES);
+            }
+            Err(err) => log::error!("Failed to load the clipboard history of {}: {}", id, err),
+        }
+    }
+
+    fn save(&self, id: &str) {
+        let path = path(id);
+        let res = serde_json::to_vec(&self.entries)
+            .map_err(std::io::Error::from)
+            .and_then(|data| {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                std::fs::write(&path, data)
+            });
+        if let Err(err) = res {
+            log::error!("Failed to save the clipboard history of {}: {}", id, err);
+        }
+    }
+}
+
+fn path(id: &str) -> PathBuf {
+    // The id may have the server or a port.
+    let name: String = id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    Config::path(DIR).join(format!("{}.json", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push() {
+        let mut history = ClipboardHistory::default();
+        for text in ["a", "b", "a", ""] {
+            history.push("test", false, text.to_owned(), true);
+        }
+        let texts: Vec<_> = history.entries.iter().map(|e| e.text.as_str()).collect();
+        assert_eq!(texts, vec!["a", "b"]);
+        for i in 0..MAX_ENTRIES {
+            history.push("test", false, i.to_string(), false);
+        }
+        assert_eq!(history.entries.len(), MAX_ENTRIES);
+        assert_eq!(history.entries[0].text, (MAX_ENTRIES - 1).to_string());
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     num::NonZeroI64,
     sync::{
         atomic::{AtomicUsize, Ordering},
@@ -38,11 +38,14 @@ use hbb_common::{tokio::sync::Mutex as TokioMutex, ResultType};
 use scrap::CodecFormat;
 
 use crate::client::{
-    new_voice_call_request, Client, MediaData, MediaSender, QualityStatus, MILLI1, SEC30,
+    new_voice_call_request, replay, Client, MediaData, MediaSender, QualityStatus, VideoHandler,
+    MILLI1, SEC30,
 };
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use crate::common::{self, update_clipboard};
 use crate::common::{get_default_sound_input, set_sound_input};
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+use crate::rich_clipboard::{self, ClipboardSide};
 use crate::ui_session_interface::{InvokeUiSession, Session};
 #[cfg(not(any(target_os = "ios")))]
 use crate::{audio_service, ConnInner, CLIENT_SERVER};
@@ -74,6 +77,11 @@ pub struct Remote<T: InvokeUiSession> {
     fps_control_map: HashMap<usize, FpsControl>,
     decode_fps_map: Arc<RwLock<HashMap<usize, usize>>>,
     chroma: Arc<RwLock<Option<Chroma>>>,
+    replay: Option<replay::Writer>,
+    // Windows or regions shared by the peer instead of displays, (window, region, width, height).
+    peer_windows: HashMap<i32, (u64, bool, i32, i32)>,
+    // Displays being recorded, which also take the audio.
+    recording_displays: HashSet<usize>,
 }
 
 impl<T: InvokeUiSession> Remote<T> {
@@ -113,6 +121,9 @@ impl<T: InvokeUiSession> Remote<T> {
             fps_control_map: Default::default(),
             decode_fps_map: decode_fps,
             chroma,
+            replay: None,
+            peer_windows: Default::default(),
+            recording_displays: Default::default(),
         }
     }
 
@@ -145,6 +156,12 @@ impl<T: InvokeUiSession> Remote<T> {
                 if conn_type == ConnType::DEFAULT_CONN {
                     self.handler
                         .set_fingerprint(crate::common::pk_to_fingerprint(pk.unwrap_or_default()));
+                    if replay::is_auto_record() && !self.handler.is_port_forward() {
+                        match replay::Writer::for_session(&self.handler.get_id()) {
+                            Ok(w) => self.replay = Some(w),
+                            Err(err) => log::error!("Failed to start session replay: {}", err),
+                        }
+                    }
                 }
 
                 // just build for now
@@ -336,6 +353,13 @@ impl<T: InvokeUiSession> Remote<T> {
                         if let Err(e) = ContextSend::make_sure_enabled() {
                             log::error!("failed to restart clipboard context: {}", e);
                         };
+                        if let Err(b) = crate::clipboard_filter::check_file(
+                            crate::clipboard_filter::Direction::ClientToHost,
+                            &clip,
+                        ) {
+                            self.clipboard_blocked(b);
+                            return;
+                        }
                         log::debug!("Send system clipboard message to remote");
                         let msg = crate::clipboard_file::clip_2_msg(clip);
                         allow_err!(peer.send(&msg).await);
@@ -477,6 +501,17 @@ impl<T: InvokeUiSession> Remote<T> {
                 self.check_clipboard_file_context();
             }
             Data::Message(msg) => {
+                self.write_replay(&msg, None, true);
+                if let Some(message::Union::Clipboard(cb)) = &msg.union {
+                    let content = if cb.compress {
+                        hbb_common::compress::decompress(&cb.content)
+                    } else {
+                        cb.content.to_vec()
+                    };
+                    if let Ok(text) = String::from_utf8(content) {
+                        self.handler.add_clipboard_history(text, true);
+                    }
+                }
                 allow_err!(peer.send(&msg).await);
             }
             Data::SendFiles((id, path, to, file_num, include_hidden, is_remote)) => {
@@ -798,6 +833,11 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
             }
             Data::RecordScreen(start, display, w, h, id) => {
+                if start {
+                    self.recording_displays.insert(display);
+                } else {
+                    self.recording_displays.remove(&display);
+                }
                 let _ = self
                     .video_sender
                     .send(MediaData::RecordScreen(start, display, w, h, id));
@@ -827,7 +867,13 @@ impl<T: InvokeUiSession> Remote<T> {
                 self.elevation_requested = true;
             }
             Data::NewVoiceCall => {
-                let msg = new_voice_call_request(true);
+                let mut msg = new_voice_call_request(true);
+                msg.mut_voice_call_request().virtual_microphone = self
+                    .handler
+                    .lc
+                    .read()
+                    .unwrap()
+                    .get_toggle_option("voice-call-virtual-microphone");
                 // Save the voice call request timestamp for the further validation.
                 self.voice_call_request_timestamp = Some(
                     NonZeroI64::new(msg.voice_call_request().req_timestamp)
@@ -1040,8 +1086,18 @@ impl<T: InvokeUiSession> Remote<T> {
         }
     }
 
+    fn write_replay(&mut self, msg: &Message, raw: Option<&[u8]>, outgoing: bool) {
+        if let Some(w) = self.replay.as_mut() {
+            if let Err(err) = w.write(msg, raw, outgoing) {
+                log::error!("Failed to write session replay, stop it: {}", err);
+                self.replay = None;
+            }
+        }
+    }
+
     async fn handle_msg_from_peer(&mut self, data: &[u8], peer: &mut Stream) -> bool {
         if let Ok(msg_in) = Message::parse_from_bytes(&data) {
+            self.write_replay(&msg_in, Some(data), false);
             match msg_in.union {
                 Some(message::Union::VideoFrame(vf)) => {
                     if !self.first_frame {
@@ -1077,7 +1133,16 @@ impl<T: InvokeUiSession> Remote<T> {
                             .ok();
                     } else {
                         if let Some(video_queue) = video_queue_write.get_mut(&display) {
-                            video_queue.force_push(vf);
+                            if let Some(dropped) = video_queue.force_push(vf) {
+                                if let Some(pts) = VideoHandler::encoded_frames(&dropped)
+                                    .and_then(|f| f.first())
+                                    .map(|f| f.pts)
+                                {
+                                    self.video_sender
+                                        .send(MediaData::VideoDropped(display, pts))
+                                        .ok();
+                                }
+                            }
                         }
                         self.video_sender.send(MediaData::VideoQueue(display)).ok();
                     }
@@ -1115,16 +1180,21 @@ impl<T: InvokeUiSession> Remote<T> {
                             }
 
                             #[cfg(not(any(target_os = "android", target_os = "ios")))]
-                            if let Some(msg_out) = Client::get_current_text_clipboard_msg() {
-                                let sender = self.sender.clone();
-                                let permission_config = self.handler.get_permission_config();
-                                tokio::spawn(async move {
-                                    // due to clipboard service interval time
-                                    sleep(common::CLIPBOARD_INTERVAL as f32 / 1_000.).await;
-                                    if permission_config.is_text_clipboard_required() {
-                                        sender.send(Data::Message(msg_out)).ok();
-                                    }
-                                });
+                            {
+                                let msgs = Client::get_current_clipboard_msgs();
+                                if !msgs.is_empty() {
+                                    let sender = self.sender.clone();
+                                    let permission_config = self.handler.get_permission_config();
+                                    tokio::spawn(async move {
+                                        // due to clipboard service interval time
+                                        sleep(common::CLIPBOARD_INTERVAL as f32 / 1_000.).await;
+                                        if permission_config.is_text_clipboard_required() {
+                                            for msg_out in msgs {
+                                                sender.send(Data::Message(msg_out)).ok();
+                                            }
+                                        }
+                                    });
+                                }
                             }
 
                             // on connection established client
@@ -1156,7 +1226,10 @@ impl<T: InvokeUiSession> Remote<T> {
                 Some(message::Union::Clipboard(cb)) => {
                     if !self.handler.lc.read().unwrap().disable_clipboard.v {
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
-                        update_clipboard(cb, Some(&crate::client::get_old_clipboard_text()));
+                        match update_clipboard(cb, Some(&crate::client::get_old_clipboard_text())) {
+                            Some(b) => self.clipboard_blocked(b),
+                            None => self.add_received_clipboard_history(),
+                        }
                         #[cfg(any(target_os = "android", target_os = "ios"))]
                         {
                             let content = if cb.compress {
@@ -1165,11 +1238,50 @@ impl<T: InvokeUiSession> Remote<T> {
                                 cb.content.into()
                             };
                             if let Ok(content) = String::from_utf8(content) {
+                                self.handler.add_clipboard_history(content.clone(), false);
                                 self.handler.clipboard(content);
                             }
                         }
                     }
                 }
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                Some(message::Union::ClipboardFormatList(list)) => {
+                    if !self.handler.lc.read().unwrap().disable_clipboard.v {
+                        if let Some(msg) =
+                            rich_clipboard::handle_format_list(ClipboardSide::Client, list)
+                        {
+                            allow_err!(peer.send(&msg).await);
+                        }
+                    }
+                }
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                Some(message::Union::ClipboardRequest(request)) => {
+                    if !self.handler.lc.read().unwrap().disable_clipboard.v {
+                        if let Some(msg) =
+                            rich_clipboard::handle_request(ClipboardSide::Client, request)
+                        {
+                            allow_err!(peer.send(&msg).await);
+                        }
+                    }
+                }
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                Some(message::Union::MultiClipboards(multi)) => {
+                    if !self.handler.lc.read().unwrap().disable_clipboard.v {
+                        let blocked = tokio::task::spawn_blocking(move || {
+                            rich_clipboard::update(
+                                ClipboardSide::Client,
+                                multi,
+                                crate::client::get_old_clipboard_text(),
+                            )
+                        })
+                        .await;
+                        match blocked {
+                            Ok(Some(b)) => self.clipboard_blocked(b),
+                            Ok(None) => self.add_received_clipboard_history(),
+                            Err(err) => log::error!("Failed to update the clipboard: {}", err),
+                        }
+                    }
+                }
                 #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
                 Some(message::Union::Cliprdr(clip)) => {
                     self.handle_cliprdr_msg(clip);
@@ -1320,6 +1432,9 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 Some(message::Union::Misc(misc)) => match misc.union {
                     Some(misc::Union::AudioFormat(f)) => {
+                        self.video_sender
+                            .send(MediaData::AudioFormat(f.clone()))
+                            .ok();
                         self.audio_sender.send(MediaData::AudioFormat(f)).ok();
                     }
                     Some(misc::Union::ChatMessage(c)) => {
@@ -1368,9 +1483,20 @@ impl<T: InvokeUiSession> Remote<T> {
                     }
                     Some(misc::Union::SwitchDisplay(s)) => {
                         self.handler.handle_peer_switch_display(&s);
-                        self.video_sender
-                            .send(MediaData::Reset(s.display as _))
-                            .ok();
+                        // The shared window or region is only moved, the video stream goes on.
+                        let area = (s.window, s.region, s.width, s.height);
+                        let partial = s.window != 0 || s.region;
+                        let moved = partial && self.peer_windows.get(&s.display) == Some(&area);
+                        if partial {
+                            self.peer_windows.insert(s.display, area);
+                        } else {
+                            self.peer_windows.remove(&s.display);
+                        }
+                        if !moved {
+                            self.video_sender
+                                .send(MediaData::Reset(s.display as _))
+                                .ok();
+                        }
                         if s.width > 0 && s.height > 0 {
                             self.handler.set_display(
                                 s.x,
@@ -1504,6 +1630,15 @@ impl<T: InvokeUiSession> Remote<T> {
                         log::info!("update supported encoding:{:?}", e);
                         self.handler.lc.write().unwrap().supported_encoding = e;
                     }
+                    Some(misc::Union::ServerRecordStatus(on)) => {
+                        self.handler.update_server_record_status(on);
+                    }
+                    Some(misc::Union::WindowList(list)) => {
+                        self.handler.update_window_list(&list);
+                    }
+                    Some(misc::Union::AudioApplications(list)) => {
+                        self.handler.update_audio_applications(&list);
+                    }
 
                     _ => {}
                 },
@@ -1512,6 +1647,11 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 Some(message::Union::AudioFrame(frame)) => {
                     if !self.handler.lc.read().unwrap().disable_audio.v {
+                        if !self.recording_displays.is_empty() {
+                            self.video_sender
+                                .send(MediaData::AudioFrame(Box::new(frame.clone())))
+                                .ok();
+                        }
                         self.audio_sender
                             .send(MediaData::AudioFrame(Box::new(frame)))
                             .ok();
@@ -1748,6 +1888,23 @@ impl<T: InvokeUiSession> Remote<T> {
         true
     }
 
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn clipboard_blocked(&self, blocked: crate::clipboard_filter::Blocked) {
+        blocked.log();
+        self.handler
+            .msgbox("nook-nocancel-hasclose", "Clipboard", blocked.tip(), "");
+    }
+
+    // The text written last is the one received.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn add_received_clipboard_history(&self) {
+        let text = crate::client::get_old_clipboard_text()
+            .lock()
+            .unwrap()
+            .clone();
+        self.handler.add_clipboard_history(text, false);
+    }
+
     fn check_clipboard_file_context(&self) {
         #[cfg(any(
             target_os = "windows",
@@ -1779,6 +1936,13 @@ impl<T: InvokeUiSession> Remote<T> {
             log::warn!("failed to decode cliprdr msg from server peer");
             return;
         };
+        if let Err(b) = crate::clipboard_filter::check_file(
+            crate::clipboard_filter::Direction::HostToClient,
+            &clip,
+        ) {
+            self.clipboard_blocked(b);
+            return;
+        }
 
         let is_stopping_allowed = clip.is_stopping_allowed_from_peer();
         let file_transfer_enabled = self.handler.lc.read().unwrap().enable_file_transfer.v;
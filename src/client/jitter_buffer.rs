@@ -0,0 +1,190 @@
+// Jitter buffer of the audio frames from the peer, driven by the clock of the audio thread.
+//
+// Frames are ordered by `AudioFrame::seq` and held until `depth` of them are buffered, then one
+// is played per frame duration. A frame missing when it is due is concealed, with the FEC data
+// of the next frame if it is already here, with the PLC of opus otherwise. A frame arriving after
+// it was concealed grows the depth, which shrinks again while the network is steady.
+//
+// The peer counts the silence it does not send, so a long gap in `seq` is a pause or another
+// source, and the buffer starts over instead of concealing it.
+
+use hbb_common::message_proto::AudioFrame;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+const MIN_DEPTH: usize = 2;
+const INITIAL_DEPTH: usize = 4;
+const MAX_DEPTH: usize = 30;
+// Frames concealed in a row before waiting for the peer again, opus fades out by then.
+const MAX_CONCEAL: u32 = 5;
+const MAX_GAP: u32 = 50;
+// Frames played without a late one before the depth is decreased, 10s of 10ms frames.
+const STEADY_FRAMES: u32 = 1000;
+const IDLE_WAIT: Duration = Duration::from_secs(1);
+
+pub enum Playout {
+    Frame(AudioFrame),
+    /// The frame before this one is lost, decode it from the FEC data of this one.
+    Fec(AudioFrame),
+    /// The frame is lost, conceal it.
+    Plc,
+}
+
+pub struct JitterBuffer {
+    frames: BTreeMap<u32, AudioFrame>,
+    next_seq: u32,
+    // None while buffering
+    play_at: Option<Instant>,
+    depth: usize,
+    concealed: u32,
+    steady: u32,
+}
+
+impl Default for JitterBuffer {
+    fn default() -> Self {
+        Self {
+            frames: Default::default(),
+            next_seq: 0,
+            play_at: None,
+            depth: INITIAL_DEPTH,
+            concealed: 0,
+            steady: 0,
+        }
+    }
+}
+
+impl JitterBuffer {
+    pub fn push(&mut self, frame: AudioFrame) {
+        let seq = frame.seq;
+        if seq < self.next_seq {
+            if self.next_seq - seq <= MAX_GAP {
+                // Concealed already, the network is slower than the depth.
+                self.depth = (self.depth + 1).min(MAX_DEPTH);
+                self.steady = 0;
+                return;
+            }
+            self.restart();
+        } else if self.play_at.is_some() && seq - self.next_seq > MAX_GAP {
+            self.restart();
+        }
+        self.frames.insert(seq, frame);
+    }
+
+    /// The next frame to play if it is due at `now`.
+    pub fn pop(&mut self, now: Instant, frame_duration: Duration) -> Option<Playout> {
+        let play_at = match self.play_at {
+            Some(play_at) => play_at,
+            None => {
+                if self.frames.len() < self.depth {
+                    return None;
+                }
+                self.next_seq = *self.frames.keys().next()?;
+                now
+            }
+        };
+        if now < play_at {
+            return None;
+        }
+        if self.frames.is_empty() && self.concealed >= MAX_CONCEAL {
+            self.play_at = None;
+            self.concealed = 0;
+            return None;
+        }
+        // Catch up after a burst instead of keeping its latency.
+        while self.frames.len() > self.depth * 2 + MIN_DEPTH {
+            if let Some((seq, _)) = self.frames.pop_first() {
+                self.next_seq = self.next_seq.max(seq + 1);
+            }
+        }
+        // Restart the clock if the thread was held up.
+        self.play_at = Some(if now > play_at + frame_duration * MAX_DEPTH as u32 {
+            now + frame_duration
+        } else {
+            play_at + frame_duration
+        });
+        self.steady += 1;
+        if self.steady >= STEADY_FRAMES {
+            self.steady = 0;
+            self.depth = (self.depth - 1).max(MIN_DEPTH);
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        if let Some(frame) = self.frames.remove(&seq) {
+            self.concealed = 0;
+            return Some(Playout::Frame(frame));
+        }
+        self.concealed += 1;
+        match self.frames.get(&(seq + 1)) {
+            Some(next) => Some(Playout::Fec(next.clone())),
+            None => Some(Playout::Plc),
+        }
+    }
+
+    /// How long to wait for the next frame to be due.
+    pub fn wait(&self, now: Instant) -> Duration {
+        match self.play_at {
+            Some(play_at) => play_at.saturating_duration_since(now),
+            None => IDLE_WAIT,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.frames.clear();
+        self.next_seq = 0;
+        self.play_at = None;
+        self.concealed = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(seq: u32) -> AudioFrame {
+        AudioFrame {
+            seq,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_reorder_and_conceal() {
+        let d = Duration::from_millis(10);
+        let mut jb = JitterBuffer::default();
+        for seq in [2, 1, 3, 5, 6] {
+            jb.push(frame(seq));
+        }
+        let now = Instant::now();
+        let mut played = vec![];
+        for i in 0..6 {
+            while let Some(p) = jb.pop(now + d * i, d) {
+                played.push(match p {
+                    Playout::Frame(f) => f.seq as i32,
+                    Playout::Fec(f) => -(f.seq as i32),
+                    Playout::Plc => 0,
+                });
+            }
+        }
+        assert_eq!(played, vec![1, 2, 3, -5, 5, 6]);
+    }
+
+    #[test]
+    fn test_late_frame_grows_depth() {
+        let d = Duration::from_millis(10);
+        let mut jb = JitterBuffer::default();
+        for seq in 1..=INITIAL_DEPTH as u32 {
+            jb.push(frame(seq));
+        }
+        let now = Instant::now();
+        for i in 0..=INITIAL_DEPTH as u32 {
+            while jb.pop(now + d * i, d).is_some() {}
+        }
+        jb.push(frame(INITIAL_DEPTH as u32 + 1));
+        assert_eq!(jb.depth, INITIAL_DEPTH + 1);
+        // a pause of the peer
+        jb.push(frame(1000));
+        assert_eq!(jb.frames.len(), 1);
+    }
+}
@@ -0,0 +1,873 @@
+// Protocol level session recording, the messages of a session are stored as they are
+// sent on the wire instead of re-encoded pixels.
+//
+// File layout (".rdr"):
+//   MAGIC | version: u8 | header length: varint | header: json
+//   records, each: delta ms: varint | kind: u8 | [display: varint, video only] | length: varint | message
+//
+// The kind byte lets the reader index a file without parsing the messages. The high bit
+// of the kind marks messages sent by the local side (input, chat, clipboard).
+//
+// The text typed and copied by the local side is not recorded, only which kind of key it was
+// and the clipboard format, unless `record-outgoing-text` is set.
+
+use super::{AudioHandler, VideoHandler};
+use hbb_common::{bail, config::Config, log, message_proto::*, protobuf::Message as _, ResultType};
+use scrap::{CodecFormat, ImageFormat, ImageRgb};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+pub const OPTION_AUTO_RECORD_REPLAY: &str = "allow-auto-record-replay";
+pub const OPTION_RECORD_OUTGOING_TEXT: &str = "record-outgoing-text";
+pub const EXTENSION: &str = "rdr";
+const MAGIC: &[u8; 4] = b"RDRP";
+const FORMAT_VERSION: u8 = 1;
+const OUTGOING: u8 = 0x80;
+// Longest wait before the control channel is checked again.
+const MAX_SLEEP: Duration = Duration::from_millis(100);
+
+pub const KIND_OTHER: u8 = 0;
+pub const KIND_VIDEO_KEY: u8 = 1;
+pub const KIND_VIDEO: u8 = 2;
+pub const KIND_AUDIO: u8 = 3;
+pub const KIND_AUDIO_FORMAT: u8 = 4;
+pub const KIND_CURSOR: u8 = 5;
+pub const KIND_CLIPBOARD: u8 = 6;
+pub const KIND_CHAT: u8 = 7;
+pub const KIND_INPUT: u8 = 8;
+pub const KIND_MISC: u8 = 9;
+
+#[inline]
+pub fn is_auto_record() -> bool {
+    !Config::get_option(OPTION_AUTO_RECORD_REPLAY).is_empty()
+}
+
+fn kind_of(msg: &Message) -> (u8, Option<usize>) {
+    match &msg.union {
+        Some(message::Union::VideoFrame(vf)) => {
+            let key = match &vf.union {
+                Some(video_frame::Union::Vp8s(f)) => f.frames.iter().any(|f| f.key),
+                Some(video_frame::Union::Vp9s(f)) => f.frames.iter().any(|f| f.key),
+                Some(video_frame::Union::Av1s(f)) => f.frames.iter().any(|f| f.key),
+                Some(video_frame::Union::H264s(f)) => f.frames.iter().any(|f| f.key),
+                Some(video_frame::Union::H265s(f)) => f.frames.iter().any(|f| f.key),
+                _ => false,
+            };
+            let kind = if key { KIND_VIDEO_KEY } else { KIND_VIDEO };
+            (kind, Some(vf.display as usize))
+        }
+        Some(message::Union::AudioFrame(_)) => (KIND_AUDIO, None),
+        Some(message::Union::CursorData(_))
+        | Some(message::Union::CursorId(_))
+        | Some(message::Union::CursorPosition(_)) => (KIND_CURSOR, None),
+        Some(message::Union::Clipboard(_)) | Some(message::Union::MultiClipboards(_)) => {
+            (KIND_CLIPBOARD, None)
+        }
+        Some(message::Union::MouseEvent(_))
+        | Some(message::Union::KeyEvent(_))
+        | Some(message::Union::PointerDeviceEvent(_)) => (KIND_INPUT, None),
+        Some(message::Union::Misc(m)) => match &m.union {
+            Some(misc::Union::AudioFormat(_)) => (KIND_AUDIO_FORMAT, None),
+            Some(misc::Union::ChatMessage(_)) => (KIND_CHAT, None),
+            _ => (KIND_MISC, None),
+        },
+        _ => (KIND_OTHER, None),
+    }
+}
+
+// The copy of `msg` to record if it holds text, None if it can be recorded as it is.
+fn strip_text(msg: &Message) -> Option<Message> {
+    let mut msg = match &msg.union {
+        Some(message::Union::KeyEvent(ke))
+            if !matches!(ke.union, None | Some(key_event::Union::ControlKey(_))) =>
+        {
+            msg.clone()
+        }
+        Some(message::Union::Clipboard(_)) | Some(message::Union::MultiClipboards(_)) => {
+            msg.clone()
+        }
+        _ => return None,
+    };
+    let clear = |cb: &mut Clipboard| {
+        cb.compress = false;
+        cb.content = Default::default();
+    };
+    match &mut msg.union {
+        Some(message::Union::KeyEvent(ke)) => ke.union = None,
+        Some(message::Union::Clipboard(cb)) => clear(cb),
+        Some(message::Union::MultiClipboards(mc)) => mc.clipboards.iter_mut().for_each(clear),
+        _ => {}
+    }
+    Some(msg)
+}
+
+fn write_varint(w: &mut impl Write, mut v: u64) -> std::io::Result<()> {
+    let mut buf = [0u8; 10];
+    let mut n = 0;
+    loop {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            buf[n] = b;
+            n += 1;
+            break;
+        }
+        buf[n] = b | 0x80;
+        n += 1;
+    }
+    w.write_all(&buf[..n])
+}
+
+// Returns None at the end of the file.
+fn read_varint(r: &mut impl Read) -> ResultType<Option<u64>> {
+    let mut v = 0u64;
+    let mut b = [0u8; 1];
+    for i in 0..10 {
+        if r.read(&mut b)? == 0 {
+            if i == 0 {
+                return Ok(None);
+            }
+            bail!("Truncated record");
+        }
+        v |= ((b[0] & 0x7f) as u64) << (7 * i);
+        if b[0] & 0x80 == 0 {
+            return Ok(Some(v));
+        }
+    }
+    bail!("Invalid varint")
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Header {
+    pub peer_id: String,
+    // unix time in ms
+    pub start: i64,
+    pub version: String,
+}
+
+pub struct Writer {
+    out: BufWriter<File>,
+    path: PathBuf,
+    start: Instant,
+    last_ms: u64,
+    record_text: bool,
+}
+
+impl Writer {
+    pub fn new(path: PathBuf, peer_id: &str) -> ResultType<Self> {
+        let mut out = BufWriter::new(File::create(&path)?);
+        let header = serde_json::to_vec(&Header {
+            peer_id: peer_id.to_owned(),
+            start: hbb_common::get_time(),
+            version: crate::VERSION.to_owned(),
+        })?;
+        out.write_all(MAGIC)?;
+        out.write_all(&[FORMAT_VERSION])?;
+        write_varint(&mut out, header.len() as _)?;
+        out.write_all(&header)?;
+        log::info!("session replay will save to {}", path.display());
+        Ok(Self {
+            out,
+            path,
+            start: Instant::now(),
+            last_ms: 0,
+            record_text: !Config::get_option(OPTION_RECORD_OUTGOING_TEXT).is_empty(),
+        })
+    }
+
+    /// Writer for a new session with `peer_id`, in the video save directory.
+    pub fn for_session(peer_id: &str) -> ResultType<Self> {
+        let mut dir = Config::get_option("video-save-directory");
+        if dir.is_empty() {
+            dir = crate::ui_interface::default_video_save_directory();
+        }
+        std::fs::create_dir_all(&dir)?;
+        let name = format!(
+            "c{}{}.{}",
+            peer_id,
+            chrono::Local::now().format("_%Y%m%d%H%M%S%3f"),
+            EXTENSION
+        );
+        Self::new(PathBuf::from(dir).join(name), peer_id)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `raw` is the encoded `msg` if the caller has it already.
+    pub fn write(&mut self, msg: &Message, raw: Option<&[u8]>, outgoing: bool) -> ResultType<()> {
+        let stripped = if outgoing && !self.record_text {
+            strip_text(msg)
+        } else {
+            None
+        };
+        let encoded;
+        let raw = match (raw, &stripped) {
+            (_, Some(stripped)) => {
+                encoded = stripped.write_to_bytes()?;
+                &encoded
+            }
+            (Some(raw), None) => raw,
+            (None, None) => {
+                encoded = msg.write_to_bytes()?;
+                &encoded
+            }
+        };
+        let (kind, display) = kind_of(msg);
+        let ms = (self.start.elapsed().as_millis() as u64).max(self.last_ms);
+        write_varint(&mut self.out, ms - self.last_ms)?;
+        self.last_ms = ms;
+        self.out
+            .write_all(&[if outgoing { kind | OUTGOING } else { kind }])?;
+        if let Some(display) = display {
+            write_varint(&mut self.out, display as _)?;
+        }
+        write_varint(&mut self.out, raw.len() as _)?;
+        self.out.write_all(raw)?;
+        Ok(())
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        self.out.flush().ok();
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    pub ms: u64,
+    pub kind: u8,
+    pub outgoing: bool,
+    pub display: Option<usize>,
+    offset: u64,
+    len: u32,
+}
+
+pub struct Reader {
+    file: BufReader<File>,
+    pub header: Header,
+    pub entries: Vec<Entry>,
+}
+
+impl Reader {
+    pub fn open(path: &Path) -> ResultType<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 5];
+        file.read_exact(&mut magic)?;
+        if &magic[..4] != MAGIC {
+            bail!("Not a session replay file");
+        }
+        if magic[4] > FORMAT_VERSION {
+            bail!("Unsupported replay version {}", magic[4]);
+        }
+        let Some(len) = read_varint(&mut file)? else {
+            bail!("Truncated header");
+        };
+        let mut header = vec![0u8; len as usize];
+        file.read_exact(&mut header)?;
+        let header: Header = serde_json::from_slice(&header)?;
+
+        // Index the records without parsing them, a session that was not closed properly
+        // ends with a partial record which is dropped.
+        let mut entries = Vec::new();
+        let mut ms = 0u64;
+        loop {
+            let entry = (|| -> ResultType<Option<Entry>> {
+                let Some(delta) = read_varint(&mut file)? else {
+                    return Ok(None);
+                };
+                let mut kind = [0u8; 1];
+                file.read_exact(&mut kind)?;
+                let outgoing = kind[0] & OUTGOING != 0;
+                let kind = kind[0] & !OUTGOING;
+                let display = if kind == KIND_VIDEO || kind == KIND_VIDEO_KEY {
+                    read_varint(&mut file)?.map(|d| d as usize)
+                } else {
+                    None
+                };
+                let Some(len) = read_varint(&mut file)? else {
+                    bail!("Truncated record");
+                };
+                let offset = file.stream_position()?;
+                file.seek_relative(len as _)?;
+                Ok(Some(Entry {
+                    ms: ms + delta,
+                    kind,
+                    outgoing,
+                    display,
+                    offset,
+                    len: len as _,
+                }))
+            })();
+            match entry {
+                Ok(Some(entry)) => {
+                    ms = entry.ms;
+                    entries.push(entry);
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    log::warn!("Stop reading replay at record {}: {}", entries.len(), err);
+                    break;
+                }
+            }
+        }
+        // The last record may claim more bytes than the file has.
+        let size = file.get_ref().metadata()?.len();
+        while entries
+            .last()
+            .map_or(false, |e| e.offset + e.len as u64 > size)
+        {
+            entries.pop();
+        }
+        Ok(Self {
+            file,
+            header,
+            entries,
+        })
+    }
+
+    pub fn duration(&self) -> u64 {
+        self.entries.last().map(|e| e.ms).unwrap_or_default()
+    }
+
+    pub fn read(&mut self, entry: &Entry) -> ResultType<Message> {
+        self.file.seek(SeekFrom::Start(entry.offset))?;
+        let mut buf = vec![0u8; entry.len as usize];
+        self.file.read_exact(&mut buf)?;
+        Ok(Message::parse_from_bytes(&buf)?)
+    }
+
+    /// Index of the entry to start decoding at to show the session at `ms`: the earliest of the
+    /// last key frames of all displays before `ms`, so that every display can be decoded.
+    pub fn seek_index(&self, ms: u64) -> usize {
+        let mut last_keys: HashMap<usize, usize> = HashMap::new();
+        let mut first_after = self.entries.len();
+        for (i, e) in self.entries.iter().enumerate() {
+            if e.ms > ms {
+                first_after = i;
+                break;
+            }
+            if e.kind == KIND_VIDEO_KEY {
+                last_keys.insert(e.display.unwrap_or_default(), i);
+            }
+        }
+        last_keys.values().min().cloned().unwrap_or(first_after)
+    }
+
+    /// Chat, clipboard, input and other events for review, video, audio and cursor
+    /// updates are left out. Mouse moves are left out, clicks are kept.
+    pub fn events(&mut self) -> Vec<Event> {
+        let entries: Vec<Entry> = self
+            .entries
+            .iter()
+            .filter(|e| {
+                [KIND_CLIPBOARD, KIND_CHAT, KIND_INPUT, KIND_MISC, KIND_OTHER].contains(&e.kind)
+            })
+            .cloned()
+            .collect();
+        let mut events = Vec::new();
+        for e in entries.iter() {
+            let msg = match self.read(e) {
+                Ok(msg) => msg,
+                Err(err) => {
+                    log::warn!("Failed to read replay record at {} ms: {}", e.ms, err);
+                    continue;
+                }
+            };
+            if let Some((name, data)) = describe(&msg) {
+                events.push(Event {
+                    ms: e.ms,
+                    event: name.to_owned(),
+                    outgoing: e.outgoing,
+                    data,
+                });
+            }
+        }
+        events
+    }
+
+    /// Write the event index as json lines.
+    pub fn export_index(&mut self, path: &Path) -> ResultType<usize> {
+        let events = self.events();
+        let mut out = BufWriter::new(File::create(path)?);
+        for e in events.iter() {
+            writeln!(out, "{}", serde_json::to_string(e)?)?;
+        }
+        out.flush()?;
+        Ok(events.len())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub ms: u64,
+    pub event: String,
+    pub outgoing: bool,
+    pub data: Value,
+}
+
+fn describe(msg: &Message) -> Option<(&'static str, Value)> {
+    match &msg.union {
+        Some(message::Union::Clipboard(cb)) => Some((
+            "clipboard",
+            json!({ "compress": cb.compress, "len": cb.content.len() }),
+        )),
+        Some(message::Union::MouseEvent(me)) => {
+            // Button presses only, moves carry no button bits.
+            let typ = me.mask & 0x7;
+            if typ == crate::common::input::MOUSE_TYPE_DOWN {
+                Some((
+                    "mouse_down",
+                    json!({ "x": me.x, "y": me.y, "buttons": me.mask >> 3 }),
+                ))
+            } else {
+                None
+            }
+        }
+        Some(message::Union::KeyEvent(ke)) => {
+            if !(ke.down || ke.press) {
+                return None;
+            }
+            let key = match &ke.union {
+                Some(key_event::Union::ControlKey(ck)) => json!(format!("{:?}", ck.enum_value())),
+                Some(key_event::Union::Chr(chr)) => json!(chr),
+                Some(key_event::Union::Unicode(u)) => json!(u),
+                Some(key_event::Union::Seq(seq)) => json!(seq),
+                _ => Value::Null,
+            };
+            Some(("key", json!({ "key": key })))
+        }
+        Some(message::Union::FileAction(_)) => Some(("file_action", json!({}))),
+        Some(message::Union::FileResponse(_)) => Some(("file_response", json!({}))),
+        Some(message::Union::Misc(m)) => match &m.union {
+            Some(misc::Union::ChatMessage(c)) => Some(("chat", json!({ "text": c.text }))),
+            Some(misc::Union::SwitchDisplay(sd)) => {
+                Some(("switch_display", json!({ "display": sd.display })))
+            }
+            Some(misc::Union::PermissionInfo(p)) => Some((
+                "permission",
+                json!({ "permission": p.permission.value(), "enabled": p.enabled }),
+            )),
+            Some(misc::Union::Option(_)) => Some(("option", json!({}))),
+            Some(misc::Union::CloseReason(r)) => Some(("close", json!({ "reason": r }))),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub enum Control {
+    Pause,
+    Resume,
+    Seek(u64),
+    Speed(f32),
+    Stop,
+}
+
+/// Plays a replay file through the same decoders as a live session.
+pub struct Player {
+    reader: Reader,
+    video: HashMap<usize, VideoHandler>,
+    audio: AudioHandler,
+    audio_format: Option<AudioFormat>,
+    speed: f32,
+}
+
+impl Player {
+    pub fn new(path: &Path) -> ResultType<Self> {
+        Ok(Self {
+            reader: Reader::open(path)?,
+            video: Default::default(),
+            audio: Default::default(),
+            audio_format: None,
+            speed: 1.,
+        })
+    }
+
+    pub fn reader(&mut self) -> &mut Reader {
+        &mut self.reader
+    }
+
+    /// Play from `from` ms until the end or `Control::Stop`, `on_frame` is called with the
+    /// display, time and image of every decoded frame and `on_event` with every event as in
+    /// `Reader::events`.
+    pub fn play(
+        &mut self,
+        from: u64,
+        control: Receiver<Control>,
+        mut on_frame: impl FnMut(usize, u64, &ImageRgb),
+        mut on_event: impl FnMut(&Event),
+    ) -> ResultType<()> {
+        let mut target = from;
+        let mut i = self.reader.seek_index(target);
+        // Playback clock: wall time `base` corresponds to session time `base_ms`.
+        let mut base = Instant::now();
+        let mut base_ms = target;
+        let mut paused = false;
+        while i < self.reader.entries.len() {
+            let entry = self.reader.entries[i];
+            let fast_forward = entry.ms < target;
+            if !fast_forward {
+                let due = Duration::from_millis(
+                    ((entry.ms.saturating_sub(base_ms)) as f32 / self.speed) as u64,
+                );
+                let elapsed = base.elapsed();
+                if paused || due > elapsed {
+                    let wait = if paused {
+                        MAX_SLEEP
+                    } else {
+                        (due - elapsed).min(MAX_SLEEP)
+                    };
+                    match control.recv_timeout(wait) {
+                        Ok(Control::Pause) => paused = true,
+                        Ok(Control::Resume) => {
+                            paused = false;
+                            base = Instant::now();
+                            base_ms = entry.ms;
+                        }
+                        Ok(Control::Seek(ms)) => {
+                            target = ms.min(self.reader.duration());
+                            i = self.reader.seek_index(target);
+                            base = Instant::now();
+                            base_ms = target;
+                            self.video.values_mut().for_each(|v| v.reset(None));
+                        }
+                        Ok(Control::Speed(speed)) => {
+                            if speed > 0. {
+                                // keep the current position
+                                base_ms += (base.elapsed().as_millis() as f32 * self.speed) as u64;
+                                base = Instant::now();
+                                self.speed = speed;
+                            }
+                        }
+                        Ok(Control::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                            return Ok(());
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                    }
+                    continue;
+                }
+            }
+            i += 1;
+            let msg = match self.reader.read(&entry) {
+                Ok(msg) => msg,
+                Err(err) => {
+                    log::warn!("Failed to read replay record at {} ms: {}", entry.ms, err);
+                    continue;
+                }
+            };
+            match msg.union {
+                Some(message::Union::VideoFrame(vf)) => {
+                    let display = vf.display as usize;
+                    let format = CodecFormat::from(&vf);
+                    let handler = self
+                        .video
+                        .entry(display)
+                        .or_insert_with(|| VideoHandler::new(format, display));
+                    let mut pixelbuffer = true;
+                    let mut chroma = None;
+                    match handler.handle_frame(vf, &mut pixelbuffer, &mut chroma) {
+                        Ok(true) if !fast_forward => on_frame(display, entry.ms, &handler.rgb),
+                        Ok(_) => {}
+                        Err(err) => log::debug!("Failed to decode replay frame: {}", err),
+                    }
+                }
+                Some(message::Union::AudioFrame(af)) => {
+                    // Audio is only played at normal speed.
+                    if !fast_forward && (self.speed - 1.).abs() < f32::EPSILON {
+                        if let Some(format) = self.audio_format.take() {
+                            self.audio.handle_format(format);
+                        }
+                        self.audio.handle_frame(af);
+                    }
+                }
+                Some(message::Union::Misc(ref m)) => {
+                    if let Some(misc::Union::AudioFormat(f)) = &m.union {
+                        // Started with the next frame, the device may be busy while fast-forwarding.
+                        self.audio_format = Some(f.clone());
+                    } else if !fast_forward {
+                        if let Some((name, data)) = describe(&msg) {
+                            on_event(&Event {
+                                ms: entry.ms,
+                                event: name.to_owned(),
+                                outgoing: entry.outgoing,
+                                data,
+                            });
+                        }
+                    }
+                }
+                _ => {
+                    if !fast_forward {
+                        if let Some((name, data)) = describe(&msg) {
+                            on_event(&Event {
+                                ms: entry.ms,
+                                event: name.to_owned(),
+                                outgoing: entry.outgoing,
+                                data,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn format_ms(ms: u64) -> String {
+    let secs = ms / 1000;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        ms % 1000
+    )
+}
+
+/// PNG of a decoded frame.
+pub fn encode_png(rgb: &ImageRgb) -> ResultType<Vec<u8>> {
+    if rgb.w == 0 || rgb.h == 0 || rgb.raw.len() < rgb.w * rgb.h * 4 {
+        bail!("Invalid frame");
+    }
+    // Rows are aligned to the stride of the image.
+    let row = rgb.raw.len() / rgb.h;
+    let mut rgba = Vec::with_capacity(rgb.w * rgb.h * 4);
+    for y in 0..rgb.h {
+        let line = &rgb.raw[y * row..y * row + rgb.w * 4];
+        match rgb.fmt() {
+            // libyuv names, ABGR is R, G, B, A in memory and ARGB is B, G, R, A.
+            ImageFormat::ABGR => rgba.extend_from_slice(line),
+            ImageFormat::ARGB => {
+                for p in line.chunks_exact(4) {
+                    rgba.extend_from_slice(&[p[2], p[1], p[0], 255]);
+                }
+            }
+            ImageFormat::Raw => bail!("Unsupported image format"),
+        }
+    }
+    let mut png = Vec::new();
+    repng::encode(&mut png, rgb.w as _, rgb.h as _, &rgba)?;
+    Ok(png)
+}
+
+// "90", "1:30" or "0:01:30" to ms
+fn parse_time(s: &str) -> Option<u64> {
+    let mut secs = 0f64;
+    for part in s.split(':') {
+        secs = secs * 60. + part.trim().parse::<f64>().ok()?;
+    }
+    Some((secs * 1000.) as u64)
+}
+
+/// `--play <file> [--from <time>] [--speed <x>] [--frames <dir> [--fps <n>]]
+///  [--export-index <file>]`
+///
+/// Plays the session, printing its events. With `--frames`, the frames shown are rendered
+/// to `<dir>/<display>_<ms>.png`, at most `--fps` per display and second of the session,
+/// 1 by default. While playing, commands are read from stdin:
+/// `p` pause, `r` resume, `g <time>` seek, `s <x>` speed, `q` quit.
+pub fn play_cli(args: &[String]) {
+    let Some(path) = args.first() else {
+        println!("Usage: --play <file> [--from <time>] [--speed <x>] [--frames <dir> [--fps <n>]]");
+        println!("       --play <file> --export-index <file>");
+        return;
+    };
+    let get_arg = |name: &str| {
+        args.iter()
+            .position(|x| x == name)
+            .and_then(|i| args.get(i + 1))
+            .cloned()
+    };
+    let mut player = match Player::new(Path::new(path)) {
+        Ok(p) => p,
+        Err(err) => {
+            println!("Failed to open {}: {}", path, err);
+            return;
+        }
+    };
+    let reader = player.reader();
+    println!(
+        "Session with {}, {} records, {}",
+        reader.header.peer_id,
+        reader.entries.len(),
+        format_ms(reader.duration())
+    );
+    if let Some(out) = get_arg("--export-index") {
+        match reader.export_index(Path::new(&out)) {
+            Ok(n) => println!("{} events written to {}", n, out),
+            Err(err) => println!("Failed to export index: {}", err),
+        }
+        return;
+    }
+    let from = get_arg("--from").and_then(|t| parse_time(&t)).unwrap_or(0);
+    let frames_dir = get_arg("--frames").map(PathBuf::from);
+    if let Some(dir) = frames_dir.as_ref() {
+        if let Err(err) = std::fs::create_dir_all(dir) {
+            println!("Failed to create {}: {}", dir.display(), err);
+            return;
+        }
+    }
+    let interval = get_arg("--fps")
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|fps| *fps > 0.)
+        .map_or(1000, |fps| (1000. / fps) as u64);
+    let (tx, rx) = std::sync::mpsc::channel();
+    if let Some(speed) = get_arg("--speed").and_then(|s| s.parse::<f32>().ok()) {
+        tx.send(Control::Speed(speed)).ok();
+    }
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else { break };
+            let mut it = line.split_whitespace();
+            let control = match (it.next(), it.next()) {
+                (Some("p"), _) => Control::Pause,
+                (Some("r"), _) => Control::Resume,
+                (Some("q"), _) => Control::Stop,
+                (Some("g"), Some(t)) => match parse_time(t) {
+                    Some(ms) => Control::Seek(ms),
+                    None => continue,
+                },
+                (Some("s"), Some(x)) => match x.parse() {
+                    Ok(x) => Control::Speed(x),
+                    Err(_) => continue,
+                },
+                _ => continue,
+            };
+            if tx.send(control).is_err() {
+                break;
+            }
+        }
+    });
+    let mut frames = 0usize;
+    let mut rendered = 0usize;
+    let mut last_rendered: HashMap<usize, u64> = HashMap::new();
+    let mut render = |display: usize, ms: u64, rgb: &ImageRgb| {
+        frames += 1;
+        let Some(dir) = frames_dir.as_ref() else {
+            return;
+        };
+        if last_rendered
+            .get(&display)
+            .map_or(false, |last| ms < last + interval && ms >= *last)
+        {
+            return;
+        }
+        last_rendered.insert(display, ms);
+        let file = dir.join(format!("{}_{:09}.png", display, ms));
+        match encode_png(rgb).and_then(|png| Ok(std::fs::write(&file, png)?)) {
+            Ok(()) => rendered += 1,
+            Err(err) => log::error!("Failed to render {}: {}", file.display(), err),
+        }
+    };
+    let res = player.play(from, rx, &mut render, |e| {
+        println!(
+            "{} {}{} {}",
+            format_ms(e.ms),
+            if e.outgoing { "> " } else { "< " },
+            e.event,
+            e.data
+        )
+    });
+    if let Err(err) = res {
+        println!("Replay failed: {}", err);
+    }
+    println!("{} frames shown", frames);
+    if let Some(dir) = frames_dir {
+        println!("{} frames rendered to {}", rendered, dir.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video(display: i32, key: bool) -> Message {
+        let mut vf = VideoFrame::new();
+        vf.display = display;
+        vf.set_vp9s(EncodedVideoFrames {
+            frames: vec![EncodedVideoFrame {
+                key,
+                ..Default::default()
+            }]
+            .into(),
+            ..Default::default()
+        });
+        let mut msg = Message::new();
+        msg.set_video_frame(vf);
+        msg
+    }
+
+    #[test]
+    fn test_write_read_seek() {
+        let path = std::env::temp_dir().join(format!("replay_test_{}.rdr", std::process::id()));
+        let mut w = Writer::new(path.clone(), "123").unwrap();
+        w.write(&video(0, true), None, false).unwrap();
+        w.write(&video(1, true), None, false).unwrap();
+        w.write(&video(0, false), None, false).unwrap();
+        let mut misc = Misc::new();
+        misc.set_chat_message(ChatMessage {
+            text: "hi".to_owned(),
+            ..Default::default()
+        });
+        let mut msg = Message::new();
+        msg.set_misc(misc);
+        w.write(&msg, None, true).unwrap();
+        w.write(&video(0, true), None, false).unwrap();
+        // the text typed and copied is not recorded
+        w.record_text = false;
+        let mut ke = KeyEvent::new();
+        ke.set_unicode('x' as _);
+        let mut msg = Message::new();
+        msg.set_key_event(ke);
+        w.write(&msg, None, true).unwrap();
+        let mut msg = Message::new();
+        msg.set_clipboard(Clipboard {
+            content: b"secret".to_vec().into(),
+            ..Default::default()
+        });
+        w.write(&msg, None, true).unwrap();
+        drop(w);
+
+        let mut r = Reader::open(&path).unwrap();
+        assert_eq!(r.header.peer_id, "123");
+        assert_eq!(r.entries.len(), 7);
+        let (key, clipboard) = (r.entries[5], r.entries[6]);
+        assert_eq!(r.read(&key).unwrap().key_event().union, None);
+        assert!(r.read(&clipboard).unwrap().clipboard().content.is_empty());
+        // display 1 has its key frame at 1, so decoding must start there
+        assert_eq!(r.seek_index(r.duration()), 1);
+        let events = r.events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event, "chat");
+        assert!(events[0].outgoing);
+        assert_eq!(events[1].event, "clipboard");
+        assert_eq!(parse_time("1:30"), Some(90_000));
+
+        // rows padded to the stride are cut
+        let mut rgb = ImageRgb::new(ImageFormat::ARGB, 64);
+        rgb.w = 2;
+        rgb.h = 2;
+        rgb.raw = vec![0u8; 64 * 2];
+        assert!(encode_png(&rgb).is_ok());
+        rgb.raw.truncate(8);
+        assert!(encode_png(&rgb).is_err());
+
+        // a partial record at the end is dropped
+        let mut content = std::fs::read(&path).unwrap();
+        content.truncate(content.len() - 1);
+        std::fs::write(&path, content).unwrap();
+        assert_eq!(Reader::open(&path).unwrap().entries.len(), 6);
+        std::fs::remove_file(&path).ok();
+    }
+}
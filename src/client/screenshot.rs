@@ -0,0 +1,253 @@
+// One shot screenshot of a remote peer, used by `--screenshot` and the ui bridges.
+//
+// The login request carries a `Screenshot` instead of a normal session, so the peer does not
+// start any service: it captures the requested displays once, replies with one PNG per display
+// and the connection is closed after the response marked `last`.
+
+use super::{
+    handle_hash, handle_login_error, handle_login_from_ui, handle_test_delay, Client, Data,
+    Interface, LoginConfigHandler,
+};
+use hbb_common::{
+    bail,
+    config::{LocalConfig, READ_TIMEOUT},
+    futures::StreamExt,
+    log,
+    message_proto::*,
+    protobuf::Message as _,
+    rendezvous_proto::ConnType,
+    tokio, ResultType, Stream,
+};
+use std::{
+    io::BufRead,
+    sync::{Arc, Mutex, RwLock},
+};
+
+/// Upper bound of the whole exchange, the peer may take a while to capture all its displays
+/// but a stalled login or relay must not hang the caller forever.
+const SCREENSHOT_TIMEOUT: u64 = 60_000;
+const PASSWORD_ENV: &str = "RUSTDESK_PASSWORD";
+
+#[derive(Clone, Default)]
+struct Handler {
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    error: Arc<Mutex<Option<String>>>,
+}
+
+impl Interface for Handler {
+    fn send(&self, _data: Data) {}
+
+    fn msgbox(&self, msgtype: &str, title: &str, text: &str, _link: &str) {
+        log::info!("{}: {}: {}", msgtype, title, text);
+        if msgtype.contains("error") || msgtype.contains("password") || msgtype.contains("2fa") {
+            let err = if text.is_empty() { title } else { text };
+            *self.error.lock().unwrap() = Some(err.to_owned());
+        }
+    }
+
+    fn handle_login_error(&self, err: &str) -> bool {
+        handle_login_error(self.lc.clone(), err, self);
+        false
+    }
+
+    fn handle_peer_info(&self, pi: PeerInfo) {
+        self.lc.write().unwrap().handle_peer_info(&pi);
+    }
+
+    fn set_multiple_windows_session(&self, _sessions: Vec<WindowsSession>) {}
+
+    async fn handle_hash(&self, pass: &str, hash: Hash, peer: &mut Stream) {
+        handle_hash(self.lc.clone(), pass, hash, self, peer).await;
+    }
+
+    async fn handle_login_from_ui(
+        &self,
+        os_username: String,
+        os_password: String,
+        password: String,
+        remember: bool,
+        peer: &mut Stream,
+    ) {
+        handle_login_from_ui(
+            self.lc.clone(),
+            os_username,
+            os_password,
+            password,
+            remember,
+            peer,
+        )
+        .await;
+    }
+
+    async fn handle_test_delay(&self, t: TestDelay, peer: &mut Stream) {
+        handle_test_delay(t, peer).await;
+    }
+
+    fn get_lch(&self) -> Arc<RwLock<LoginConfigHandler>> {
+        self.lc.clone()
+    }
+}
+
+impl Handler {
+    fn check_error(&self) -> ResultType<()> {
+        if let Some(err) = self.error.lock().unwrap().take() {
+            bail!(err);
+        }
+        Ok(())
+    }
+}
+
+/// Capture `display` of the peer, -1 for all displays.
+///
+/// Returns the PNG data of every captured display with its index.
+pub async fn take(id: &str, password: &str, display: i32) -> ResultType<Vec<(i32, Vec<u8>)>> {
+    match hbb_common::timeout(SCREENSHOT_TIMEOUT, take_(id, password, display)).await {
+        Ok(res) => res,
+        Err(_) => bail!("Timeout"),
+    }
+}
+
+async fn take_(id: &str, password: &str, display: i32) -> ResultType<Vec<(i32, Vec<u8>)>> {
+    let handler = Handler::default();
+    {
+        let mut lc = handler.lc.write().unwrap();
+        lc.initialize(id.to_owned(), ConnType::DEFAULT_CONN, None, false, None);
+        lc.screenshot = Some(display);
+    }
+    let key = crate::common::get_key(false).await;
+    let token = LocalConfig::get_option("access_token");
+    let id = handler.get_id();
+    let (mut stream, _, _) =
+        Client::start(&id, &key, &token, ConnType::DEFAULT_CONN, handler.clone()).await?;
+    let mut shots = Vec::new();
+    loop {
+        let bytes = match hbb_common::timeout(READ_TIMEOUT, stream.next()).await {
+            Ok(Some(Ok(bytes))) => bytes,
+            Ok(Some(Err(err))) => bail!(err),
+            Ok(None) => bail!("Connection closed by the peer"),
+            Err(_) => bail!("Timeout"),
+        };
+        let Ok(msg_in) = Message::parse_from_bytes(&bytes) else {
+            continue;
+        };
+        match msg_in.union {
+            Some(message::Union::Hash(hash)) => {
+                handler.handle_hash(password, hash, &mut stream).await;
+            }
+            Some(message::Union::LoginResponse(lr)) => match lr.union {
+                Some(login_response::Union::Error(err)) => {
+                    handler.handle_login_error(&err);
+                    bail!(err);
+                }
+                // Peers with screenshot support never send the peer info of a normal session,
+                // older ones ignore the request and would start streaming the screen.
+                Some(login_response::Union::PeerInfo(pi)) => {
+                    log::info!("Screenshot not supported by peer version {}", pi.version);
+                    bail!("peer does not support screenshots");
+                }
+                _ => {}
+            },
+            Some(message::Union::TestDelay(t)) => {
+                handler.handle_test_delay(t, &mut stream).await;
+            }
+            Some(message::Union::ScreenshotResponse(res)) => {
+                if res.error.is_empty() {
+                    shots.push((res.display, res.data.into()));
+                } else {
+                    log::error!("Failed to capture display {}: {}", res.display, res.error);
+                }
+                if res.last {
+                    break;
+                }
+            }
+            _ => {}
+        }
+        handler.check_error()?;
+    }
+    if shots.is_empty() {
+        bail!("No screenshot received");
+    }
+    Ok(shots)
+}
+
+/// Save the screenshots to `path`, the display index is appended to the file name when
+/// there are several of them.
+pub fn save(shots: &[(i32, Vec<u8>)], path: &str) -> ResultType<Vec<String>> {
+    let path = std::path::Path::new(path);
+    let mut saved = Vec::new();
+    for (display, data) in shots {
+        let file = if shots.len() > 1 {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let ext = path.extension().unwrap_or("png".as_ref()).to_string_lossy();
+            path.with_file_name(format!("{}_{}.{}", stem, display, ext))
+        } else {
+            path.to_path_buf()
+        };
+        std::fs::write(&file, data)?;
+        saved.push(file.to_string_lossy().to_string());
+    }
+    Ok(saved)
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn take_sync(id: &str, password: &str, display: i32) -> ResultType<Vec<(i32, Vec<u8>)>> {
+    take(id, password, display).await
+}
+
+/// Returns false on failure, so the caller can exit with a non-zero status.
+///
+/// The password is never taken from the command line, where other users could read it:
+/// it comes from `RUSTDESK_PASSWORD` or, with `--password-stdin`, from the first line of stdin.
+pub fn screenshot_cli(args: &[String]) -> bool {
+    let Some(id) = args.first().filter(|x| !x.starts_with('-')) else {
+        println!(
+            "Usage: --screenshot <peer-id> [-o <file>] [--display <n>|all] [--password-stdin]"
+        );
+        println!(
+            "The password is read from stdin or the {} variable",
+            PASSWORD_ENV
+        );
+        return false;
+    };
+    let get_arg = |name: &str| {
+        args.iter()
+            .position(|x| x == name)
+            .and_then(|i| args.get(i + 1))
+            .cloned()
+    };
+    let out = get_arg("-o").unwrap_or(format!("{}.png", id));
+    let display = match get_arg("--display").as_deref() {
+        None => 0,
+        Some("all") => -1,
+        Some(d) => match d.parse::<i32>() {
+            Ok(d) if d >= 0 => d,
+            _ => {
+                println!("Invalid display: {}", d);
+                return false;
+            }
+        },
+    };
+    let password = if args.iter().any(|x| x == "--password-stdin") {
+        let mut line = String::new();
+        if let Err(err) = std::io::stdin().lock().read_line(&mut line) {
+            println!("Failed to read the password: {}", err);
+            return false;
+        }
+        line.trim_end_matches(&['\r', '\n'][..]).to_owned()
+    } else {
+        std::env::var(PASSWORD_ENV).unwrap_or_default()
+    };
+    let res = take_sync(id, &password, display).and_then(|shots| save(&shots, &out));
+    match res {
+        Ok(files) => {
+            for f in files {
+                println!("{}", f);
+            }
+            true
+        }
+        Err(err) => {
+            println!("Failed to take screenshot of {}: {}", id, err);
+            false
+        }
+    }
+}
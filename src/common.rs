@@ -118,6 +118,11 @@ impl ClipboardContext {
     }
 }
 
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+use crate::{
+    clipboard_filter,
+    rich_clipboard::{self, ClipboardSide},
+};
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use hbb_common::compress::decompress;
 use hbb_common::{
@@ -202,7 +207,7 @@ lazy_static::lazy_static! {
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 lazy_static::lazy_static! {
-    static ref ARBOARD_MTX: Arc<Mutex<()>> = Arc::new(Mutex::new(()));
+    pub(crate) static ref ARBOARD_MTX: Arc<Mutex<()>> = Arc::new(Mutex::new(()));
 }
 
 pub struct SimpleCallOnReturn {
@@ -282,32 +287,75 @@ pub fn create_clipboard_msg(content: String) -> Message {
     msg
 }
 
+/// The text, and the list of the other formats if any, to send if the clipboard changed.
+/// The content blocked by `clipboard_filter` is left out, audited on the host, and a notice for
+/// the user of the client is added instead.
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub fn check_clipboard(
     ctx: &mut Option<ClipboardContext>,
     old: Option<&Arc<Mutex<String>>>,
-) -> Option<Message> {
+) -> Vec<Message> {
+    let mut msgs = vec![];
     if ctx.is_none() {
         *ctx = ClipboardContext::new().ok();
     }
-    let ctx2 = ctx.as_mut()?;
+    let Some(ctx2) = ctx.as_mut() else {
+        return msgs;
+    };
     let side = if old.is_none() { "host" } else { "client" };
+    let rich_side = if old.is_none() {
+        ClipboardSide::Host
+    } else {
+        ClipboardSide::Client
+    };
     let old = if let Some(old) = old { old } else { &CONTENT };
     let content = {
         let _lock = ARBOARD_MTX.lock().unwrap();
         ctx2.get_text()
     };
-    if let Ok(content) = content {
-        if content.len() < 2_000_000 && !content.is_empty() {
-            let changed = content != *old.lock().unwrap();
-            if changed {
-                log::info!("{} update found on {}", CLIPBOARD_NAME, side);
-                *old.lock().unwrap() = content.clone();
-                return Some(create_clipboard_msg(content));
+    let content = content.unwrap_or_default();
+    let mut blocked = None;
+    if !content.is_empty() {
+        let changed = content != *old.lock().unwrap();
+        if changed {
+            log::info!("{} update found on {}", CLIPBOARD_NAME, side);
+            *old.lock().unwrap() = content.clone();
+            match clipboard_filter::check(
+                rich_side.sending(),
+                ClipboardFormat::Text,
+                content.as_bytes(),
+            ) {
+                Ok(()) => msgs.push(create_clipboard_msg(content.clone())),
+                Err(b) => {
+                    match rich_side {
+                        ClipboardSide::Host => b.audit(0, ""),
+                        ClipboardSide::Client => b.log(),
+                    }
+                    blocked = Some(b);
+                }
             }
         }
     }
-    None
+    let (msg, rich_blocked) = rich_clipboard::check(rich_side, &content);
+    msgs.extend(msg);
+    if let Some(b) = blocked.or(rich_blocked) {
+        msgs.push(b.notice());
+    }
+    msgs
+}
+
+/// Wait at most `timeout` for a change of the clipboard, true if it may have changed since `seq`.
+/// The clipboard is polled with `timeout` where the changes are not notified.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn wait_clipboard_change(seq: &mut u64, timeout: Duration) -> bool {
+    #[cfg(target_os = "linux")]
+    return crate::platform::linux_clipboard_watch::wait(seq, timeout);
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = seq;
+        std::thread::sleep(timeout);
+        true
+    }
 }
 
 /// Set sound input device.
@@ -358,17 +406,29 @@ pub fn get_default_sound_input() -> Option<String> {
     None
 }
 
+/// Write the text of the peer to the clipboard, unless `clipboard_filter` blocks it.
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
-pub fn update_clipboard(clipboard: Clipboard, old: Option<&Arc<Mutex<String>>>) {
+pub fn update_clipboard(
+    clipboard: Clipboard,
+    old: Option<&Arc<Mutex<String>>>,
+) -> Option<clipboard_filter::Blocked> {
     let content = if clipboard.compress {
         decompress(&clipboard.content)
     } else {
         clipboard.content.into()
     };
+    let direction = if old.is_none() {
+        clipboard_filter::Direction::ClientToHost
+    } else {
+        clipboard_filter::Direction::HostToClient
+    };
+    if let Err(b) = clipboard_filter::check(direction, ClipboardFormat::Text, &content) {
+        return Some(b);
+    }
     if let Ok(content) = String::from_utf8(content) {
         if content.is_empty() {
             // ctx.set_text may crash if content is empty
-            return;
+            return None;
         }
         match ClipboardContext::new() {
             Ok(mut ctx) => {
@@ -384,6 +444,7 @@ pub fn update_clipboard(clipboard: Clipboard, old: Option<&Arc<Mutex<String>>>)
             }
         }
     }
+    None
 }
 
 #[cfg(feature = "use_rubato")]
@@ -1066,7 +1127,7 @@ pub fn get_audit_server(api: String, custom: String, typ: String) -> String {
     format!("{}/api/audit/{}", url, typ)
 }
 
-pub async fn post_request(url: String, body: String, header: &str) -> ResultType<String> {
+fn new_post_request(url: String, body: String, header: &str) -> reqwest::RequestBuilder {
     let mut req = reqwest::Client::new().post(url);
     if !header.is_empty() {
         let tmp: Vec<&str> = header.split(": ").collect();
@@ -1076,12 +1137,27 @@ pub async fn post_request(url: String, body: String, header: &str) -> ResultType
     }
     req = req.header("Content-Type", "application/json");
     let to = std::time::Duration::from_secs(12);
-    Ok(req.body(body).timeout(to).send().await?.text().await?)
+    req.body(body).timeout(to)
 }
 
+pub async fn post_request(url: String, body: String, header: &str) -> ResultType<String> {
+    Ok(new_post_request(url, body, header)
+        .send()
+        .await?
+        .text()
+        .await?)
+}
+
+/// A response other than 2xx is an error, with its body.
 #[tokio::main(flavor = "current_thread")]
 pub async fn post_request_sync(url: String, body: String, header: &str) -> ResultType<String> {
-    post_request(url, body, header).await
+    let res = new_post_request(url, body, header).send().await?;
+    let status = res.status();
+    let text = res.text().await?;
+    if !status.is_success() {
+        bail!("{}: {}", status, text);
+    }
+    Ok(text)
 }
 
 #[inline]
@@ -1180,6 +1256,33 @@ pub fn decode64<T: AsRef<[u8]>>(input: T) -> Result<Vec<u8>, base64::DecodeError
     base64::decode(input)
 }
 
+/// Load the secret `name` from the config directory, it is created with `gen` on first use and
+/// readable by its owner only. For keys that must not sit next to the files they protect.
+pub fn load_or_create_secret(name: &str, gen: impl FnOnce() -> Vec<u8>) -> ResultType<Vec<u8>> {
+    let path = Config::path(name);
+    match std::fs::read_to_string(&path) {
+        Ok(s) => match decode64(s.trim()) {
+            Ok(secret) => Ok(secret),
+            Err(err) => bail!("Invalid secret {}: {}", path.display(), err),
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let secret = gen();
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            let mut file = options.open(&path)?;
+            std::io::Write::write_all(&mut file, encode64(&secret).as_bytes())?;
+            log::info!("Created {}", path.display());
+            Ok(secret)
+        }
+        Err(err) => bail!("Failed to read {}: {}", path.display(), err),
+    }
+}
+
 pub async fn get_key(sync: bool) -> String {
     #[cfg(windows)]
     if let Ok(lic) = crate::platform::windows::get_license_from_exe_name() {
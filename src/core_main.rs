@@ -431,6 +431,48 @@ pub fn core_main() -> Option<Vec<String>> {
                 println!("Installation and administrative privileges required!");
             }
             return None;
+        } else if args[0] == "--rekey" {
+            if args.len() == 2 && args[1] == "legacy" {
+                hbb_common::secret_store::disable();
+            }
+            match config::Config2::rekey_secrets() {
+                Ok(n) => println!(
+                    "Secrets of {} peers stored with {}",
+                    n,
+                    hbb_common::secret_store::backend_name()
+                ),
+                Err(err) => {
+                    println!("Failed to store the secrets: {}", err);
+                    std::process::exit(1);
+                }
+            }
+            return None;
+        } else if args[0] == "--play" {
+            crate::client::replay::play_cli(&args[1..]);
+            return None;
+        } else if args[0] == "--screenshot" {
+            if !crate::client::screenshot::screenshot_cli(&args[1..]) {
+                std::process::exit(1);
+            }
+            return None;
+        } else if args[0] == "--verify-audit" {
+            // As the user of the service, the key and the state are in its config directory.
+            let dir = if args.len() == 2 {
+                std::path::PathBuf::from(&args[1])
+            } else {
+                crate::server::audit_log::dir()
+            };
+            match crate::server::audit_log::verify(&dir) {
+                Ok(res) => println!(
+                    "OK, {} records in {} files, starting at seq {}",
+                    res.records, res.files, res.first_seq
+                ),
+                Err(err) => {
+                    println!("Audit log verification failed: {}", err);
+                    std::process::exit(1);
+                }
+            }
+            return None;
         } else if args[0] == "--check-hwcodec-config" {
             #[cfg(feature = "hwcodec")]
             scrap::hwcodec::check_available_hwcodec();
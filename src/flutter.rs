@@ -929,6 +929,8 @@ impl InvokeUiSession for FlutterHandler {
                     .to_string(),
                 ),
                 ("resolutions", &resolutions),
+                ("window", &display.window.to_string()),
+                ("region", &(display.region as i32).to_string()),
                 (
                     "original_width",
                     &display.original_resolution.width.to_string(),
@@ -967,6 +969,58 @@ impl InvokeUiSession for FlutterHandler {
         );
     }
 
+    fn update_server_record_status(&self, on: bool) {
+        self.push_event(
+            "update_server_record_status",
+            &[("on", on.to_string().as_str())],
+            &[],
+        );
+    }
+
+    fn update_window_list(&self, list: &WindowList) {
+        let mut windows = Vec::new();
+        for w in list.windows.iter() {
+            let mut h: HashMap<&str, String> = Default::default();
+            h.insert("id", w.id.to_string());
+            h.insert("title", w.title.clone());
+            h.insert("app", w.app.clone());
+            h.insert("width", w.width.to_string());
+            h.insert("height", w.height.to_string());
+            windows.push(h);
+        }
+        self.push_event(
+            "window_list",
+            &[
+                ("display", &list.display.to_string()),
+                (
+                    "windows",
+                    &serde_json::ser::to_string(&windows).unwrap_or("".to_owned()),
+                ),
+                ("pick_on_peer", &list.pick_on_peer.to_string()),
+            ],
+            &[],
+        );
+    }
+
+    fn update_audio_applications(&self, list: &AudioApplications) {
+        let mut applications = Vec::new();
+        for a in list.applications.iter() {
+            let mut h: HashMap<&str, String> = Default::default();
+            h.insert("id", a.id.clone());
+            h.insert("name", a.name.clone());
+            h.insert("pid", a.pid.to_string());
+            applications.push(h);
+        }
+        self.push_event(
+            "audio_applications",
+            &[(
+                "applications",
+                &serde_json::ser::to_string(&applications).unwrap_or("".to_owned()),
+            )],
+            &[],
+        );
+    }
+
     fn on_voice_call_started(&self) {
         self.push_event::<&str>("on_voice_call_started", &[], &[]);
     }
@@ -1157,6 +1211,11 @@ pub fn update_text_clipboard_required() {
 pub fn send_text_clipboard_msg(msg: Message) {
     for s in sessions::get_sessions() {
         if s.is_text_clipboard_required() {
+            // The clipboard was blocked by the policy, tell the user instead.
+            if let Some(message::Union::MessageBox(mb)) = &msg.union {
+                s.msgbox(&mb.msgtype, &mb.title, &mb.text, &mb.link);
+                continue;
+            }
             s.send(Data::Message(msg.clone()));
         }
     }
@@ -336,6 +336,12 @@ pub fn session_set_image_quality(session_id: SessionID, value: String) {
     }
 }
 
+pub fn session_set_audio_quality(session_id: SessionID, value: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.save_audio_quality(value);
+    }
+}
+
 pub fn session_get_keyboard_mode(session_id: SessionID) -> Option<String> {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         Some(session.get_keyboard_mode())
@@ -758,7 +764,9 @@ pub fn main_get_error() -> String {
 pub fn main_show_option(_key: String) -> SyncReturn<bool> {
     #[cfg(all(target_os = "linux", feature = "linux_headless"))]
     #[cfg(not(any(feature = "flatpak", feature = "appimage")))]
-    if _key.eq(config::CONFIG_OPTION_ALLOW_LINUX_HEADLESS) {
+    if _key.eq(config::CONFIG_OPTION_ALLOW_LINUX_HEADLESS)
+        || _key.eq(config::CONFIG_OPTION_ALLOW_LINUX_HEADLESS_VIRTUAL_DISPLAY)
+    {
         return SyncReturn(true);
     }
     SyncReturn(false)
@@ -876,6 +884,10 @@ pub fn main_post_request(url: String, body: String, header: String) {
     post_request(url, body, header)
 }
 
+pub fn main_take_screenshot(id: String, password: String, display: i32, path: String) {
+    take_screenshot(id, password, display, path)
+}
+
 pub fn main_get_local_option(key: String) -> SyncReturn<String> {
     SyncReturn(get_local_option(key))
 }
@@ -1436,6 +1448,20 @@ pub fn session_send_note(session_id: SessionID, note: String) {
     }
 }
 
+pub fn session_get_clipboard_history(session_id: SessionID) -> String {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.get_clipboard_history()
+    } else {
+        "[]".to_owned()
+    }
+}
+
+pub fn session_push_clipboard_history(session_id: SessionID, index: usize) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.push_clipboard_history(index);
+    }
+}
+
 pub fn session_alternative_codecs(session_id: SessionID) -> String {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         let (vp8, av1, h264, h265) = session.alternative_codecs();
@@ -1462,6 +1488,44 @@ pub fn session_toggle_virtual_display(session_id: SessionID, index: i32, on: boo
     }
 }
 
+pub fn session_get_window_list(session_id: SessionID, display: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.get_window_list(display);
+    }
+}
+
+pub fn session_get_audio_applications(session_id: SessionID) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.get_audio_applications();
+    }
+}
+
+pub fn session_set_audio_source(session_id: SessionID, application: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_audio_source(application);
+    }
+}
+
+pub fn session_switch_window(session_id: SessionID, display: i32, window: u64) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.switch_window(display, window);
+    }
+}
+
+pub fn session_capture_region(
+    session_id: SessionID,
+    display: i32,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    follow_cursor: bool,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.capture_region(display, x, y, width, height, follow_cursor);
+    }
+}
+
 pub fn main_set_home_dir(_home: String) {
     #[cfg(any(target_os = "android", target_os = "ios"))]
     {
@@ -20,7 +20,20 @@ lazy_static::lazy_static! {
 }
 
 pub fn is_enable() -> bool {
-    ENABLE.lock().unwrap().clone()
+    if ENABLE.lock().unwrap().clone() {
+        return true;
+    }
+    // Enforced recordings go to the api server whenever there is a private one.
+    #[cfg(not(target_os = "ios"))]
+    if crate::server::session_recording::is_enforced() {
+        return !crate::get_audit_server(
+            Config::get_option("api-server"),
+            Config::get_option("custom-rendezvous-server"),
+            "record".to_owned(),
+        )
+        .is_empty();
+    }
+    false
 }
 
 pub fn run(rx: Receiver<RecordState>) {
@@ -513,82 +513,217 @@ pub async fn connect(ms_timeout: u64, postfix: &str) -> ResultType<ConnectionTmp
 }
 
 #[cfg(target_os = "linux")]
-#[tokio::main(flavor = "current_thread")]
+#[tokio::main]
 pub async fn start_pa() {
-    use crate::audio_service::AUDIO_DATA_SIZE_U8;
-
     match new_listener("_pa").await {
-        Ok(mut incoming) => {
-            loop {
-                if let Some(result) = incoming.next().await {
-                    match result {
-                        Ok(stream) => {
-                            let mut stream = Connection::new(stream);
-                            let mut device: String = "".to_owned();
-                            if let Some(Ok(Some(Data::Config((_, Some(x)))))) =
-                                stream.next_timeout2(1000).await
-                            {
-                                device = x;
-                            }
-                            if !device.is_empty() {
-                                device = crate::platform::linux::get_pa_source_name(&device);
-                            }
-                            if device.is_empty() {
-                                device = crate::platform::linux::get_pa_monitor();
-                            }
-                            if device.is_empty() {
-                                continue;
-                            }
-                            let spec = pulse::sample::Spec {
-                                format: pulse::sample::Format::F32le,
-                                channels: 2,
-                                rate: crate::platform::PA_SAMPLE_RATE,
-                            };
-                            log::info!("pa monitor: {:?}", device);
-                            // systemctl --user status pulseaudio.service
-                            let mut buf: Vec<u8> = vec![0; AUDIO_DATA_SIZE_U8];
-                            match psimple::Simple::new(
-                                None,                             // Use the default server
-                                &crate::get_app_name(),           // Our application’s name
-                                pulse::stream::Direction::Record, // We want a record stream
-                                Some(&device),                    // Use the default device
-                                "record",                         // Description of our stream
-                                &spec,                            // Our sample format
-                                None,                             // Use default channel map
-                                None, // Use default buffering attributes
-                            ) {
-                                Ok(s) => loop {
-                                    if let Ok(_) = s.read(&mut buf) {
-                                        let out =
-                                            if buf.iter().filter(|x| **x != 0).next().is_none() {
-                                                vec![]
-                                            } else {
-                                                buf.clone()
-                                            };
-                                        if let Err(err) = stream.send_raw(out.into()).await {
-                                            log::error!("Failed to send audio data:{}", err);
-                                            break;
-                                        }
-                                    }
-                                },
-                                Err(err) => {
-                                    log::error!("Could not create simple pulse: {}", err);
-                                }
-                            }
-                        }
-                        Err(err) => {
-                            log::error!("Couldn't get pa client: {:?}", err);
-                        }
+        Ok(mut incoming) => loop {
+            if let Some(result) = incoming.next().await {
+                match result {
+                    Ok(stream) => {
+                        // The audio of a session is streamed until it ends, e.g. the applications
+                        // are listed meanwhile.
+                        tokio::spawn(handle_pa(Connection::new(stream)));
+                    }
+                    Err(err) => {
+                        log::error!("Couldn't get pa client: {:?}", err);
                     }
                 }
             }
-        }
+        },
         Err(err) => {
             log::error!("Failed to start pa ipc server: {}", err);
         }
     }
 }
 
+#[cfg(target_os = "linux")]
+async fn handle_pa(mut stream: Connection) {
+    use crate::audio_service::AUDIO_DATA_SIZE_U8;
+
+    let mut device: String = "".to_owned();
+    let mut name = "".to_owned();
+    if let Some(Ok(Some(Data::Config((n, x))))) = stream.next_timeout2(1000).await {
+        name = n;
+        device = x.unwrap_or_default();
+    }
+    match name.as_str() {
+        "audio-applications" => {
+            let apps = get_audio_applications();
+            allow_err!(
+                stream
+                    .send(&Data::Config((name, serde_json::to_string(&apps).ok())))
+                    .await
+            );
+            return;
+        }
+        "audio-application" => {
+            let res = match device.strip_prefix(PA_SINK_INPUT_PREFIX) {
+                Some(index) => record_pa_application(index, &mut stream).await,
+                #[cfg(feature = "pipewire-audio")]
+                None => record_pipewire(&device, true, &mut stream).await,
+                #[cfg(not(feature = "pipewire-audio"))]
+                None => Err(hbb_common::anyhow::anyhow!("Not a pulseaudio sink input")),
+            };
+            if let Err(err) = res {
+                log::error!("Failed to record application {}: {}", device, err);
+            }
+            return;
+        }
+        _ => {}
+    }
+    if !device.is_empty() {
+        device = crate::platform::linux::get_pa_source_name(&device);
+    }
+    #[cfg(feature = "pipewire-audio")]
+    if crate::platform::linux_pipewire::is_running() {
+        match record_pipewire(&device, false, &mut stream).await {
+            Ok(_) => return,
+            Err(err) => {
+                log::error!(
+                    "Failed to record with pipewire, fall back to pulseaudio: {}",
+                    err
+                );
+            }
+        }
+    }
+    if device.is_empty() {
+        device = crate::platform::linux::get_pa_monitor();
+    }
+    if device.is_empty() {
+        return;
+    }
+    let spec = pulse::sample::Spec {
+        format: pulse::sample::Format::F32le,
+        channels: 2,
+        rate: crate::platform::PA_SAMPLE_RATE,
+    };
+    log::info!("pa monitor: {:?}", device);
+    // systemctl --user status pulseaudio.service
+    let mut buf: Vec<u8> = vec![0; AUDIO_DATA_SIZE_U8];
+    match psimple::Simple::new(
+        None,                             // Use the default server
+        &crate::get_app_name(),           // Our application’s name
+        pulse::stream::Direction::Record, // We want a record stream
+        Some(&device),                    // Use the default device
+        "record",                         // Description of our stream
+        &spec,                            // Our sample format
+        None,                             // Use default channel map
+        None,                             // Use default buffering attributes
+    ) {
+        Ok(s) => loop {
+            // blocks for 10ms, not to hold up the other connections
+            if let Ok(_) = tokio::task::block_in_place(|| s.read(&mut buf)) {
+                let out = if buf.iter().filter(|x| **x != 0).next().is_none() {
+                    vec![]
+                } else {
+                    buf.clone()
+                };
+                if let Err(err) = stream.send_raw(out.into()).await {
+                    log::error!("Failed to send audio data:{}", err);
+                    break;
+                }
+            }
+        },
+        Err(err) => {
+            log::error!("Could not create simple pulse: {}", err);
+        }
+    }
+}
+
+// The id of an application recorded by pulseaudio, the PipeWire object serial otherwise.
+#[cfg(target_os = "linux")]
+const PA_SINK_INPUT_PREFIX: &str = "pa:";
+
+// The PipeWire streams if they can be recorded natively, else the pulseaudio sink inputs.
+#[cfg(target_os = "linux")]
+fn get_audio_applications() -> Vec<(String, String, u32)> {
+    let apps = crate::platform::linux::get_pa_applications();
+    #[cfg(feature = "pipewire-audio")]
+    if crate::platform::linux_pipewire::is_running() {
+        return apps
+            .into_iter()
+            .filter(|(serial, ..)| !serial.is_empty())
+            .map(|(serial, name, pid, _)| (serial, name, pid))
+            .collect();
+    }
+    apps.into_iter()
+        .map(|(_, name, pid, index)| (format!("{}{}", PA_SINK_INPUT_PREFIX, index), name, pid))
+        .collect()
+}
+
+// The simple API can not record a single sink input, parec can. It exits when the sink input
+// is gone.
+#[cfg(target_os = "linux")]
+async fn record_pa_application(index: &str, stream: &mut Connection) -> ResultType<()> {
+    use crate::audio_service::AUDIO_DATA_SIZE_U8;
+    use hbb_common::tokio::io::AsyncReadExt;
+
+    let mut child = tokio::process::Command::new("parec")
+        .arg(format!("--monitor-stream={}", index))
+        .args(["--raw", "--format=float32le", "--channels=2"])
+        .arg(format!("--rate={}", crate::platform::PA_SAMPLE_RATE))
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+    let Some(mut stdout) = child.stdout.take() else {
+        bail!("No output of parec");
+    };
+    let mut buf = vec![0u8; AUDIO_DATA_SIZE_U8];
+    while stdout.read_exact(&mut buf).await.is_ok() {
+        let out = if buf.iter().all(|x| *x == 0) {
+            vec![]
+        } else {
+            buf.clone()
+        };
+        if let Err(err) = stream.send_raw(out.into()).await {
+            log::error!("Failed to send audio data:{}", err);
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Returns an error only if the capture can not be started, then the pulseaudio path is used.
+// The default sink is recorded instead if `device` is gone, unless it is an application.
+#[cfg(all(target_os = "linux", feature = "pipewire-audio"))]
+async fn record_pipewire(device: &str, app: bool, stream: &mut Connection) -> ResultType<()> {
+    use crate::platform::linux_pipewire::Capture;
+
+    let mut capture = Capture::new(device).await?;
+    loop {
+        match capture.next().await {
+            Some(buf) => {
+                let out = if buf.iter().all(|x| *x == 0) {
+                    vec![]
+                } else {
+                    buf
+                };
+                if let Err(err) = stream.send_raw(out.into()).await {
+                    log::error!("Failed to send audio data:{}", err);
+                    return Ok(());
+                }
+            }
+            None => {
+                if app {
+                    return Ok(());
+                }
+                // The stream failed, e.g. the target device is removed, try the default sink
+                // until the client is gone.
+                drop(capture);
+                hbb_common::sleep(0.3).await;
+                capture = match Capture::new("").await {
+                    Ok(c) => c,
+                    Err(err) => {
+                        log::error!("Failed to restart pipewire record: {}", err);
+                        return Ok(());
+                    }
+                };
+            }
+        }
+    }
+}
+
 #[inline]
 #[cfg(not(windows))]
 fn get_pid_file(postfix: &str) -> String {
@@ -588,5 +588,36 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("Please select the session you want to connect to", "请选择您要连接的会话"),
         ("powered_by_me", "由 RustDesk 提供支持"),
         ("outgoing_only_desk_tip", "当前版本的软件是定制版本。\n您可以连接至其他设备，但是其他设备无法连接至您的设备。"),
+        ("Remote side is recording this session", ""),
+        ("Failed to start session recording", ""),
+        ("Automatically record outgoing sessions for replay", ""),
+        ("Share a window", ""),
+        ("Share the whole display", ""),
+        ("No windows to share", ""),
+        ("share_window_pick_on_peer_tip", ""),
+        ("Sharp text (screen content)", ""),
+        ("virtual_display_not_support_linux_tip", ""),
+        ("Audio source", ""),
+        ("Whole system audio", ""),
+        ("No applications playing audio", ""),
+        ("Use my voice as the remote microphone", ""),
+        ("Audio quality", ""),
+        ("Best", ""),
+        ("Low bandwidth", ""),
+        ("Sync video with audio", ""),
+        ("Clipboard", ""),
+        ("clipboard_blocked_direction_tip", ""),
+        ("clipboard_blocked_size_tip", ""),
+        ("clipboard_blocked_rule_tip", ""),
+        ("Clipboard history", ""),
+        ("Keep clipboard history", ""),
+        ("Send to the remote clipboard", ""),
+        ("window_shared_by_other_tip", ""),
+        ("region_set_by_other_tip", ""),
+        ("Take screenshot", ""),
+        ("Enable screenshot", ""),
+        ("virtual_microphone_failed_tip", ""),
+        ("Record the typed text and clipboard for replay", ""),
+        ("Allow virtual displays in linux headless", ""),
     ].iter().cloned().collect();
 }
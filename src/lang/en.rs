@@ -215,5 +215,13 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("wrong-2fa-code", "Can't verify the code. Check that code and local time settings are correct"),
         ("enter-2fa-title", "Two-factor authentication"),
         ("powered_by_me", "Powered by RustDesk"),
+        ("share_window_pick_on_peer_tip", "Select the window to share in the screen sharing dialog on the remote side."),
+        ("virtual_display_not_support_linux_tip", "Virtual displays require an X11 session with RandR 1.5 or later."),
+        ("clipboard_blocked_direction_tip", "The clipboard was not synced: the security policy does not allow it in this direction."),
+        ("clipboard_blocked_size_tip", "The clipboard was not synced: it is larger than the security policy allows."),
+        ("clipboard_blocked_rule_tip", "The clipboard was not synced: it contains sensitive data blocked by the security policy, such as a card number, an API key or a private key."),
+        ("window_shared_by_other_tip", "A window of this display is shared by another session, only that session can switch it."),
+        ("region_set_by_other_tip", "The captured region of this display is set by another session, only that session can change it."),
+        ("virtual_microphone_failed_tip", "The virtual microphone could not be created on the remote side, your voice is not played there."),
     ].iter().cloned().collect();
 }
@@ -214,5 +214,37 @@ lazy_static::lazy_static! {
             ("enable-2fa-desc", "אנא הגדר כעת את האפליקציה שלך לאימות. תוכל להשתמש באפליקציית אימות כגון Authy, Microsoft או Google Authenticator בטלפון או במחשב שלך.\n\nסרוק את קוד ה-QR עם האפליקציה שלך והזן את הקוד שהאפליקציה מציגה כדי להפעיל את אימות הדו-שלבי."),
             ("wrong-2fa-code", "לא ניתן לאמת את הקוד. בדוק שהקוד והגדרות הזמן המקומיות נכונות"),
             ("enter-2fa-title", "אימות דו-שלבי"),
+            ("Remote side is recording this session", ""),
+            ("Failed to start session recording", ""),
+            ("Automatically record outgoing sessions for replay", ""),
+            ("Share a window", ""),
+            ("Share the whole display", ""),
+            ("No windows to share", ""),
+            ("share_window_pick_on_peer_tip", ""),
+            ("Sharp text (screen content)", ""),
+            ("virtual_display_not_support_linux_tip", ""),
+            ("Audio source", ""),
+            ("Whole system audio", ""),
+            ("No applications playing audio", ""),
+            ("Use my voice as the remote microphone", ""),
+            ("Audio quality", ""),
+            ("Best", ""),
+            ("Low bandwidth", ""),
+            ("Sync video with audio", ""),
+            ("Clipboard", ""),
+            ("clipboard_blocked_direction_tip", ""),
+            ("clipboard_blocked_size_tip", ""),
+            ("clipboard_blocked_rule_tip", ""),
+            ("Clipboard history", ""),
+            ("Keep clipboard history", ""),
+            ("Send to the remote clipboard", ""),
+            ("window_shared_by_other_tip", ""),
+            ("region_set_by_other_tip", ""),
+            ("Take screenshot", ""),
+            ("Enable screenshot", ""),
+            ("virtual_microphone_failed_tip", ""),
+            ("Voice call", ""),
+            ("Record the typed text and clipboard for replay", ""),
+            ("Allow virtual displays in linux headless", ""),
         ].iter().cloned().collect();
     }
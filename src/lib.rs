@@ -61,6 +61,10 @@ mod hbbs_http;
 
 #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
 pub mod clipboard_file;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod rich_clipboard;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod clipboard_filter;
 
 pub mod privacy_mode;
 
@@ -275,7 +275,7 @@ fn stop_rustdesk_servers() {
 #[inline]
 fn stop_subprocess() {
     let _ = run_cmds(&format!(
-        r##"ps -ef | grep '/etc/rustdesk/xorg.conf' | grep -v grep | awk '{{printf("kill -9 %d\n", $2)}}' | bash"##,
+        r##"ps -ef | grep -E '/etc/rustdesk/(virtual/)?xorg.conf' | grep -v grep | awk '{{printf("kill -9 %d\n", $2)}}' | bash"##,
     ));
     let _ = run_cmds(&format!(
         r##"ps -ef | grep -E 'rustdesk +--cm-no-ui' | grep -v grep | awk '{{printf("kill -9 %d\n", $2)}}' | bash"##,
@@ -641,6 +641,44 @@ pub fn get_pa_sources() -> Vec<(String, String)> {
     out
 }
 
+// (PipeWire object serial, application name, pid, sink input index) of the playback streams,
+// the serial is only set by pipewire-pulse.
+pub fn get_pa_applications() -> Vec<(String, String, u32, u32)> {
+    use pulsectl::controllers::*;
+    let mut out = Vec::new();
+    match SinkController::create() {
+        Ok(mut handler) => {
+            if let Ok(apps) = handler.list_applications() {
+                for app in apps {
+                    let (serial, name, pid) = parse_pa_application(&app.proplist, app.name);
+                    out.push((serial, name, pid, app.index));
+                }
+            }
+        }
+        Err(err) => {
+            log::error!("Failed to get_pa_applications: {:?}", err);
+        }
+    }
+    out
+}
+
+// The sink input name is the media name, the application name is preferred.
+fn parse_pa_application(
+    proplist: &pulse::proplist::Proplist,
+    name: Option<String>,
+) -> (String, String, u32) {
+    let serial = proplist.get_str("object.serial").unwrap_or_default();
+    let name = proplist
+        .get_str("application.name")
+        .or(name)
+        .unwrap_or_default();
+    let pid = proplist
+        .get_str("application.process.id")
+        .and_then(|p| p.parse().ok())
+        .unwrap_or_default();
+    (serial, name, pid)
+}
+
 pub fn get_default_pa_source() -> Option<(String, String)> {
     use pulsectl::controllers::*;
     match SourceController::create() {
@@ -824,6 +862,9 @@ fn get_xrandr_conn_pat(name: &str) -> String {
 }
 
 pub fn resolutions(name: &str) -> Vec<Resolution> {
+    if let Some(v) = super::linux_virtual_display::resolutions(name) {
+        return v;
+    }
     let resolutions_pat = r"(?P<resolutions>(\s*\d+x\d+\s+\d+.*\n)+)";
     let connected_pat = get_xrandr_conn_pat(name);
     let mut v = vec![];
@@ -1159,7 +1200,7 @@ mod desktop {
 
         fn set_is_subprocess(&mut self) {
             self.is_rustdesk_subprocess = false;
-            let cmd = "ps -ef | grep 'rustdesk/xorg.conf' | grep -v grep | wc -l";
+            let cmd = "ps -ef | grep -E 'rustdesk/(virtual/)?xorg.conf' | grep -v grep | wc -l";
             if let Ok(res) = run_cmds(cmd) {
                 if res.trim() != "0" {
                     self.is_rustdesk_subprocess = true;
@@ -1407,3 +1448,25 @@ pub fn is_selinux_enforcing() -> bool {
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pa_application() {
+        let mut proplist = pulse::proplist::Proplist::new().unwrap();
+        let app = parse_pa_application(&proplist, Some("Playback".to_owned()));
+        assert_eq!(app, ("".to_owned(), "Playback".to_owned(), 0));
+
+        proplist.set_str("object.serial", "42").unwrap();
+        proplist.set_str("application.name", "Firefox").unwrap();
+        proplist.set_str("application.process.id", "1234").unwrap();
+        let app = parse_pa_application(&proplist, Some("Playback".to_owned()));
+        assert_eq!(app, ("42".to_owned(), "Firefox".to_owned(), 1234));
+
+        proplist.set_str("application.process.id", "x").unwrap();
+        let app = parse_pa_application(&proplist, None);
+        assert_eq!(app, ("42".to_owned(), "Firefox".to_owned(), 0));
+    }
+}
@@ -0,0 +1,254 @@
+// Notifications of the clipboard changes, so that the clipboard is read only when it changed.
+//
+// X11 sends XFixesSelectionNotify when the owner of the CLIPBOARD selection changes, which is on
+// every copy. On Wayland `wl-paste --watch` runs a command on every copy, with the data-control
+// protocol of wlroots, KDE and recent GNOME. The watch runs in one thread for the host and the
+// client, and if it fails or is not started yet, the callers fall back to polling. It starts
+// again after a failure, or on X11 when the display changes.
+
+use hbb_common::{
+    bail,
+    libc::{self, c_char, c_int, c_long, c_ulong, c_void},
+    log, ResultType,
+};
+use std::{
+    io::{BufRead, BufReader},
+    os::unix::process::CommandExt,
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        Condvar, Mutex, Once,
+    },
+    time::{Duration, Instant},
+};
+
+const XFIXES_SELECTION_NOTIFY: c_int = 0;
+const XFIXES_SET_SELECTION_OWNER_NOTIFY_MASK: c_ulong = 1 << 0;
+const XFIXES_SELECTION_WINDOW_DESTROY_NOTIFY_MASK: c_ulong = 1 << 1;
+const XFIXES_SELECTION_CLIENT_CLOSE_NOTIFY_MASK: c_ulong = 1 << 2;
+// How often the display of X11 is checked for a change.
+const DISPLAY_CHECK_INTERVAL: c_int = 1000;
+// The wait before the watch starts again after a failure, doubled up to the max while it fails.
+const MIN_RETRY: Duration = Duration::from_secs(3);
+const MAX_RETRY: Duration = Duration::from_secs(300);
+
+type XIOErrorHandler = Option<unsafe extern "C" fn(d: *mut c_void) -> c_int>;
+
+#[link(name = "X11")]
+extern "C" {
+    fn XOpenDisplay(display_name: *const c_char) -> *mut c_void;
+    fn XCloseDisplay(d: *mut c_void) -> c_int;
+    fn XDefaultRootWindow(d: *mut c_void) -> c_ulong;
+    fn XInternAtom(d: *mut c_void, atom_name: *const c_char, only_if_exists: c_int) -> c_ulong;
+    fn XNextEvent(d: *mut c_void, event: *mut XEvent) -> c_int;
+    fn XPending(d: *mut c_void) -> c_int;
+    fn XConnectionNumber(d: *mut c_void) -> c_int;
+    fn XSetIOErrorHandler(handler: XIOErrorHandler) -> XIOErrorHandler;
+}
+
+#[link(name = "Xfixes")]
+extern "C" {
+    fn XFixesQueryExtension(d: *mut c_void, event: *mut c_int, error: *mut c_int) -> c_int;
+    fn XFixesSelectSelectionInput(
+        d: *mut c_void,
+        window: c_ulong,
+        selection: c_ulong,
+        event_mask: c_ulong,
+    );
+}
+
+// The union of /usr/include/X11/Xlib.h, only its type is read.
+#[repr(C)]
+struct XEvent {
+    type_: c_int,
+    pad: [c_long; 24],
+}
+
+#[derive(Default)]
+struct State {
+    started: bool,
+    watching: bool,
+    seq: u64,
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: (Mutex<State>, Condvar) = Default::default();
+    static ref PREVIOUS_IO_ERROR_HANDLER: Mutex<XIOErrorHandler> = Mutex::new(None);
+}
+
+// The display of the watch, its IO errors must not exit the process.
+static WATCHED_DISPLAY: AtomicPtr<c_void> = AtomicPtr::new(std::ptr::null_mut());
+
+/// Wait at most `timeout` for a change of the clipboard, true if it may have changed since `seq`.
+/// It is always true after `timeout` if the changes are not watched.
+pub fn wait(seq: &mut u64, timeout: Duration) -> bool {
+    start();
+    let (lock, cvar) = &*STATE;
+    let state = lock.lock().unwrap();
+    if !state.watching {
+        drop(state);
+        std::thread::sleep(timeout);
+        return true;
+    }
+    let (state, _) = cvar
+        .wait_timeout_while(state, timeout, |s| s.watching && s.seq == *seq)
+        .unwrap();
+    let changed = !state.watching || state.seq != *seq;
+    *seq = state.seq;
+    changed
+}
+
+/// The count of the changes seen, None if the changes are not watched.
+pub fn sequence() -> Option<u64> {
+    start();
+    let state = STATE.0.lock().unwrap();
+    state.watching.then_some(state.seq)
+}
+
+fn start() {
+    let mut state = STATE.0.lock().unwrap();
+    if state.started {
+        return;
+    }
+    state.started = true;
+    drop(state);
+    spawn_watch(Duration::ZERO);
+}
+
+fn spawn_watch(delay: Duration) {
+    std::thread::spawn(move || {
+        std::thread::sleep(delay);
+        let begin = Instant::now();
+        let res = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            watch_wayland()
+        } else {
+            watch_x11()
+        };
+        set_watching(false);
+        match res {
+            // The display changed.
+            Ok(()) => spawn_watch(Duration::ZERO),
+            Err(err) => {
+                log::warn!("Clipboard changes are polled: {}", err);
+                // Soon again if it worked for a while.
+                let retry = if begin.elapsed() > MAX_RETRY {
+                    MIN_RETRY
+                } else {
+                    (delay * 2).clamp(MIN_RETRY, MAX_RETRY)
+                };
+                spawn_watch(retry);
+            }
+        }
+    });
+}
+
+fn set_watching(watching: bool) {
+    let (lock, cvar) = &*STATE;
+    lock.lock().unwrap().watching = watching;
+    cvar.notify_all();
+}
+
+fn changed() {
+    let (lock, cvar) = &*STATE;
+    let mut state = lock.lock().unwrap();
+    state.seq = state.seq.wrapping_add(1);
+    cvar.notify_all();
+}
+
+// The default handler exits the process when the X server is gone, e.g. the session ended.
+unsafe extern "C" fn io_error_handler(d: *mut c_void) -> c_int {
+    if !d.is_null() && d == WATCHED_DISPLAY.load(Ordering::SeqCst) {
+        WATCHED_DISPLAY.store(std::ptr::null_mut(), Ordering::SeqCst);
+        log::warn!("Lost the connection to the X server, clipboard changes are polled");
+        set_watching(false);
+        spawn_watch(MIN_RETRY);
+        // Xlib exits the process if the handler returns, this thread stops here instead. Its
+        // display is broken and never used again.
+        loop {
+            std::thread::park();
+        }
+    }
+    let previous = *PREVIOUS_IO_ERROR_HANDLER.lock().unwrap();
+    match previous {
+        Some(handler) => handler(d),
+        None => 0,
+    }
+}
+
+fn watch_x11() -> ResultType<()> {
+    static INSTALL_HANDLER: Once = Once::new();
+    INSTALL_HANDLER.call_once(|| unsafe {
+        *PREVIOUS_IO_ERROR_HANDLER.lock().unwrap() = XSetIOErrorHandler(Some(io_error_handler));
+    });
+    let display = std::env::var_os("DISPLAY");
+    unsafe {
+        let d = XOpenDisplay(std::ptr::null());
+        if d.is_null() {
+            bail!("Failed to open display {:?}", display);
+        }
+        let (mut event_base, mut error_base) = (0, 0);
+        if XFixesQueryExtension(d, &mut event_base, &mut error_base) == 0 {
+            XCloseDisplay(d);
+            bail!("No XFixes extension");
+        }
+        let clipboard = XInternAtom(d, "CLIPBOARD\0".as_ptr() as _, 0);
+        XFixesSelectSelectionInput(
+            d,
+            XDefaultRootWindow(d),
+            clipboard,
+            XFIXES_SET_SELECTION_OWNER_NOTIFY_MASK
+                | XFIXES_SELECTION_WINDOW_DESTROY_NOTIFY_MASK
+                | XFIXES_SELECTION_CLIENT_CLOSE_NOTIFY_MASK,
+        );
+        WATCHED_DISPLAY.store(d, Ordering::SeqCst);
+        set_watching(true);
+        let mut event: XEvent = std::mem::zeroed();
+        let mut fd = libc::pollfd {
+            fd: XConnectionNumber(d),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        loop {
+            while XPending(d) > 0 {
+                XNextEvent(d, &mut event);
+                if event.type_ == event_base + XFIXES_SELECTION_NOTIFY {
+                    changed();
+                }
+            }
+            if libc::poll(&mut fd, 1, DISPLAY_CHECK_INTERVAL) == 0
+                && std::env::var_os("DISPLAY") != display
+            {
+                log::info!("Display changed, watch the clipboard of the new one");
+                XCloseDisplay(d);
+                WATCHED_DISPLAY.store(std::ptr::null_mut(), Ordering::SeqCst);
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn watch_wayland() -> ResultType<()> {
+    // `echo` prints a line on every copy.
+    let mut child = unsafe {
+        Command::new("wl-paste")
+            .args(["--watch", "echo"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .pre_exec(|| {
+                libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM);
+                Ok(())
+            })
+            .spawn()?
+    };
+    let Some(stdout) = child.stdout.take() else {
+        bail!("No output of wl-paste");
+    };
+    set_watching(true);
+    for line in BufReader::new(stdout).lines() {
+        if line.is_err() {
+            break;
+        }
+        changed();
+    }
+    bail!("wl-paste exited: {:?}", child.wait());
+}
@@ -4,7 +4,13 @@ use crate::client::{
     LOGIN_MSG_DESKTOP_SESSION_NOT_READY, LOGIN_MSG_DESKTOP_XORG_NOT_FOUND,
     LOGIN_MSG_DESKTOP_XSESSION_FAILED,
 };
-use hbb_common::{allow_err, bail, log, rand::prelude::*, tokio::time};
+use hbb_common::{
+    allow_err, bail,
+    config::{Config, CONFIG_OPTION_ALLOW_LINUX_HEADLESS_VIRTUAL_DISPLAY},
+    log,
+    rand::prelude::*,
+    tokio::time,
+};
 use pam;
 use std::{
     collections::HashMap,
@@ -20,6 +26,10 @@ use std::{
 };
 use users::{get_user_by_name, os::unix::UserExt, User};
 
+// Both are matched by `stop_subprocess` and `set_is_subprocess` in linux.rs.
+const XORG_CONF: &str = "/etc/rustdesk/xorg.conf";
+const XORG_CONF_VIRTUAL_DISPLAY: &str = "/etc/rustdesk/virtual/xorg.conf";
+
 lazy_static::lazy_static! {
     static ref DESKTOP_RUNNING: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
     static ref DESKTOP_MANAGER: Arc<Mutex<Option<DesktopManager>>> = Arc::new(Mutex::new(None));
@@ -658,6 +668,27 @@ impl DesktopManager {
         "Xorg"
     }
 
+    // The shipped config, or a copy with room for virtual displays if they are allowed.
+    fn xorg_conf() -> &'static str {
+        if Config::get_option(CONFIG_OPTION_ALLOW_LINUX_HEADLESS_VIRTUAL_DISPLAY) != "Y" {
+            return XORG_CONF;
+        }
+        let res = std::fs::read_to_string(XORG_CONF).and_then(|conf| {
+            std::fs::create_dir_all(Path::new(XORG_CONF_VIRTUAL_DISPLAY).parent().unwrap())?;
+            std::fs::write(
+                XORG_CONF_VIRTUAL_DISPLAY,
+                super::linux_virtual_display::headless_xorg_conf(&conf),
+            )
+        });
+        match res {
+            Ok(()) => XORG_CONF_VIRTUAL_DISPLAY,
+            Err(e) => {
+                log::error!("Failed to write {}, {}", XORG_CONF_VIRTUAL_DISPLAY, e);
+                XORG_CONF
+            }
+        }
+    }
+
     fn start_x_server(
         xauth: &str,
         display: &str,
@@ -667,6 +698,7 @@ impl DesktopManager {
     ) -> ResultType<Child> {
         let xorg = Self::get_xorg();
         log::info!("Use xorg: {}", &xorg);
+        let conf = Self::xorg_conf();
         match Command::new(xorg)
             .envs(envs)
             .uid(uid)
@@ -682,7 +714,7 @@ impl DesktopManager {
                 //"-logfile",
                 //"/tmp/RustDesk_xorg.log",
                 "-config",
-                "/etc/rustdesk/xorg.conf",
+                conf,
                 "-auth",
                 xauth,
                 display,
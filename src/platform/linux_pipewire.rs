@@ -0,0 +1,222 @@
+// Native PipeWire audio capture, used by the `_pa` ipc server instead of the PulseAudio
+// simple api when the PipeWire daemon is running.
+//
+// Without a target, the stream captures the monitor of the default sink
+// (`stream.capture.sink`), the session manager moves it to the new default sink when the
+// device is hot-swapped. A target is a node name from `audio-input`, a `.monitor` source
+// of pipewire-pulse is turned into its sink.
+//
+// To test without a sound card:
+// pactl load-module module-null-sink sink_name=rustdesk_null
+// pactl set-default-sink rustdesk_null
+// paplay --device=rustdesk_null /usr/share/sounds/alsa/Front_Center.wav
+
+use crate::audio_service::AUDIO_DATA_SIZE_U8;
+use hbb_common::{anyhow::anyhow, bail, log, tokio::sync::mpsc, ResultType};
+use pipewire as pw;
+use pw::{properties::properties, spa};
+use std::{cell::RefCell, rc::Rc, thread::JoinHandle, time::Duration};
+
+const START_TIMEOUT: Duration = Duration::from_secs(3);
+
+struct UserData {
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+pub struct Capture {
+    rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    quit: Option<pw::channel::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Capture {
+    /// Start capturing 48000Hz stereo f32, `target` is a node name, empty for the default sink.
+    ///
+    /// Returns once the stream is streaming or paused, i.e. linked to its target, or fails.
+    pub async fn new(target: &str) -> ResultType<Self> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let target = target.to_owned();
+        let thread = std::thread::spawn(move || {
+            let (quit_tx, quit_rx) = pw::channel::channel::<()>();
+            let failed_tx = started_tx.clone();
+            let res = run(&target, tx, quit_rx, move |res| {
+                started_tx.send(res.map(|_| quit_tx)).ok();
+            });
+            if let Err(err) = res {
+                // Only received if failed before streaming.
+                failed_tx.send(Err(err)).ok();
+            }
+        });
+        let started =
+            hbb_common::tokio::task::spawn_blocking(move || started_rx.recv_timeout(START_TIMEOUT))
+                .await?;
+        match started {
+            Ok(Ok(quit)) => Ok(Self {
+                rx,
+                quit: Some(quit),
+                thread: Some(thread),
+            }),
+            Ok(Err(err)) => {
+                thread.join().ok();
+                Err(err)
+            }
+            Err(_) => bail!("Timeout to start pipewire capture"),
+        }
+    }
+
+    /// One frame of `AUDIO_DATA_SIZE_U8` bytes, None if the stream is gone.
+    pub async fn next(&mut self) -> Option<Vec<u8>> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        if let Some(quit) = self.quit.take() {
+            quit.send(()).ok();
+        }
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+fn run(
+    target: &str,
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+    quit: pw::channel::Receiver<()>,
+    started: impl FnOnce(ResultType<()>) + 'static,
+) -> ResultType<()> {
+    pw::init();
+    let mainloop = pw::main_loop::MainLoop::new(None)?;
+    let context = pw::context::Context::new(&mainloop)?;
+    // Fails if the daemon is not running.
+    let core = context.connect(None)?;
+
+    let mut props = properties! {
+        *pw::keys::MEDIA_TYPE => "Audio",
+        *pw::keys::MEDIA_CATEGORY => "Capture",
+        *pw::keys::MEDIA_ROLE => "Screen",
+        *pw::keys::APP_NAME => crate::get_app_name(),
+        *pw::keys::NODE_NAME => format!("{}-record", crate::get_app_name()),
+        // 10ms, the frame size of the opus encoder
+        *pw::keys::NODE_LATENCY => format!("480/{}", crate::platform::PA_SAMPLE_RATE),
+    };
+    match target.strip_suffix(".monitor") {
+        Some(sink) => {
+            props.insert(*pw::keys::STREAM_CAPTURE_SINK, "true");
+            props.insert(*pw::keys::TARGET_OBJECT, sink);
+        }
+        None if target.is_empty() => {
+            props.insert(*pw::keys::STREAM_CAPTURE_SINK, "true");
+        }
+        None => {
+            props.insert(*pw::keys::TARGET_OBJECT, target);
+        }
+    }
+    let stream = pw::stream::Stream::new(&core, "record", props)?;
+    // Called on the first state after connecting, so that the caller can fall back to
+    // pulseaudio if the target can not be linked.
+    let started: Rc<RefCell<Option<Box<dyn FnOnce(ResultType<()>)>>>> =
+        Rc::new(RefCell::new(Some(Box::new(started))));
+
+    let _quit = quit.attach(mainloop.loop_(), {
+        let mainloop = mainloop.clone();
+        move |_| mainloop.quit()
+    });
+    let _listener = stream
+        .add_local_listener_with_user_data(UserData {
+            tx,
+            pending: Vec::with_capacity(AUDIO_DATA_SIZE_U8 * 2),
+        })
+        .state_changed({
+            let mainloop = mainloop.clone();
+            let started = started.clone();
+            move |_, _, old, new| {
+                log::info!("pipewire record stream: {:?} -> {:?}", old, new);
+                match new {
+                    pw::stream::StreamState::Streaming | pw::stream::StreamState::Paused => {
+                        if let Some(started) = started.borrow_mut().take() {
+                            started(Ok(()));
+                        }
+                    }
+                    pw::stream::StreamState::Error(err) => {
+                        log::error!("pipewire record stream error: {}", err);
+                        if let Some(started) = started.borrow_mut().take() {
+                            started(Err(anyhow!("pipewire record stream error: {}", err)));
+                        }
+                        mainloop.quit();
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .process({
+            let mainloop = mainloop.clone();
+            move |stream, data| {
+                let Some(mut buffer) = stream.dequeue_buffer() else {
+                    return;
+                };
+                let datas = buffer.datas_mut();
+                let Some(d) = datas.first_mut() else {
+                    return;
+                };
+                let size = d.chunk().size() as usize;
+                let Some(bytes) = d.data() else {
+                    return;
+                };
+                data.pending
+                    .extend_from_slice(&bytes[..size.min(bytes.len())]);
+                while data.pending.len() >= AUDIO_DATA_SIZE_U8 {
+                    let frame = data.pending.drain(..AUDIO_DATA_SIZE_U8).collect();
+                    if data.tx.send(frame).is_err() {
+                        // The ipc client is gone.
+                        mainloop.quit();
+                        return;
+                    }
+                }
+            }
+        })
+        .register()?;
+
+    let mut info = spa::param::audio::AudioInfoRaw::new();
+    info.set_format(spa::param::audio::AudioFormat::F32LE);
+    info.set_rate(crate::platform::PA_SAMPLE_RATE);
+    info.set_channels(2);
+    let format = spa::pod::Value::Object(spa::pod::Object {
+        type_: spa::utils::SpaTypes::ObjectParamFormat.as_raw(),
+        id: spa::param::ParamType::EnumFormat.as_raw(),
+        properties: info.into(),
+    });
+    let values =
+        spa::pod::serialize::PodSerializer::serialize(std::io::Cursor::new(vec![]), &format)
+            .map_err(|e| anyhow!("Failed to serialize the audio format: {:?}", e))?
+            .0
+            .into_inner();
+    let Some(pod) = spa::pod::Pod::from_bytes(&values) else {
+        bail!("Invalid audio format pod");
+    };
+    stream.connect(
+        spa::utils::Direction::Input,
+        None,
+        pw::stream::StreamFlags::AUTOCONNECT | pw::stream::StreamFlags::MAP_BUFFERS,
+        &mut [pod],
+    )?;
+    log::info!("pipewire record stream connected, target: {:?}", target);
+    mainloop.run();
+    if started.borrow_mut().take().is_some() {
+        bail!("pipewire record stream stopped before streaming");
+    }
+    Ok(())
+}
+
+/// Whether the PipeWire daemon of the current user is running.
+pub fn is_running() -> bool {
+    let Ok(dir) = std::env::var("XDG_RUNTIME_DIR") else {
+        return false;
+    };
+    let remote = std::env::var("PIPEWIRE_REMOTE").unwrap_or("pipewire-0".to_owned());
+    std::path::Path::new(&dir).join(remote).exists()
+}
@@ -0,0 +1,417 @@
+// Virtual displays on X11, for headless hosts (the dummy driver Xorg started by
+// linux_desktop_manager, or Xvfb) and for hosts with fewer monitors than the peer wants.
+//
+// A virtual display is a RandR 1.5 monitor (`xrandr --setmonitor`) placed to the right of the
+// real screen, the frame buffer is grown to make room for it. scrap enumerates RandR monitors,
+// so it is captured like a real one. The frame buffer cannot grow beyond the maximum screen
+// size reported by the server, which is the `Virtual` size for the dummy driver and the
+// `-screen` size for Xvfb. res/xorg.conf has no room beyond its modes, the headless Xorg is
+// started with a copy that has (see `headless_xorg_conf`) only if
+// `allow-linux-headless-virtual-display` is set, the bigger frame buffer costs memory.
+//
+// Xvfb starts with a frame buffer of its maximum size, so it has room for virtual displays
+// only if the desktop is made smaller first, e.g.
+// `Xvfb :99 -screen 0 7680x2160x24` and `xrandr --fb 1920x1080`.
+
+use super::linux::is_x11;
+use hbb_common::{bail, lazy_static, log, message_proto::Resolution, regex::Regex, ResultType};
+use std::{
+    collections::BTreeMap,
+    process::Command,
+    sync::{Arc, Mutex},
+};
+
+const MONITOR_NAME_PREFIX: &str = "RustDesk-Virtual-";
+// Same index range as the Windows IDD displays, 0 is reserved for headless.
+const VIRTUAL_DISPLAY_START_FOR_PEER: u32 = 1;
+const VIRTUAL_DISPLAY_MAX_COUNT: u32 = 5;
+const DEFAULT_RESOLUTION: (u32, u32) = (1920, 1080);
+// The maximum frame buffer of the headless Xorg with virtual displays.
+const HEADLESS_VIRTUAL_SIZE: (u32, u32) = (8192, 4096);
+const COMMON_RESOLUTIONS: [(u32, u32); 8] = [
+    (3840, 2160),
+    (2560, 1440),
+    (1920, 1200),
+    (1920, 1080),
+    (1680, 1050),
+    (1600, 900),
+    (1366, 768),
+    (1280, 720),
+];
+
+lazy_static::lazy_static! {
+    static ref VIRTUAL_DISPLAYS: Arc<Mutex<VirtualDisplays>> = Default::default();
+}
+
+#[derive(Default)]
+struct VirtualDisplays {
+    // index -> (width, height)
+    monitors: BTreeMap<u32, (u32, u32)>,
+    // frame buffer size before the first virtual display was plugged in
+    base: Option<(u32, u32)>,
+}
+
+impl VirtualDisplays {
+    // Removes all virtual monitors and adds the current ones again from left to right,
+    // simpler than moving the ones on the right of a removed or resized monitor.
+    fn layout(&mut self, old: &[u32]) -> ResultType<()> {
+        let (base_w, base_h) = match self.base {
+            Some(base) => base,
+            None => {
+                let base = base_size()?;
+                self.base = Some(base);
+                base
+            }
+        };
+        for idx in old {
+            if let Err(e) = xrandr(&["--delmonitor", &monitor_name(*idx)]) {
+                log::error!("Failed to remove virtual monitor {}: {}", idx, e);
+            }
+        }
+        let (width, height) = layout_size((base_w, base_h), &self.monitors);
+        let (_, (max_w, max_h)) = screen_size()?;
+        if width > max_w || height > max_h {
+            if is_xvfb() {
+                bail!(
+                    "Screen size {}x{} exceeds the maximum {}x{} of Xvfb, start it with a larger -screen and a smaller desktop",
+                    width,
+                    height,
+                    max_w,
+                    max_h
+                );
+            }
+            bail!(
+                "Screen size {}x{} exceeds the maximum {}x{} of the X server, set {} for the headless one",
+                width,
+                height,
+                max_w,
+                max_h,
+                hbb_common::config::CONFIG_OPTION_ALLOW_LINUX_HEADLESS_VIRTUAL_DISPLAY
+            );
+        }
+        xrandr(&["--fb", &format!("{}x{}", width, height)])?;
+        for (idx, geometry) in layout_geometries(base_w, &self.monitors) {
+            xrandr(&["--setmonitor", &monitor_name(idx), &geometry, "none"])?;
+        }
+        if self.monitors.is_empty() {
+            self.base = None;
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, monitors: BTreeMap<u32, (u32, u32)>) -> ResultType<()> {
+        let old: Vec<u32> = self.monitors.keys().cloned().collect();
+        let prev = std::mem::replace(&mut self.monitors, monitors);
+        if let Err(e) = self.layout(&old) {
+            // Restore the previous layout as much as possible.
+            let failed: Vec<u32> = self.monitors.keys().cloned().collect();
+            self.monitors = prev;
+            if let Err(e) = self.layout(&failed) {
+                log::error!("Failed to restore virtual monitors: {}", e);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+// The frame buffer for the monitors on the right of the desktop of `base` size.
+fn layout_size(base: (u32, u32), monitors: &BTreeMap<u32, (u32, u32)>) -> (u32, u32) {
+    let width = base.0 + monitors.values().map(|(w, _)| w).sum::<u32>();
+    let height = monitors.values().map(|(_, h)| *h).fold(base.1, u32::max);
+    (width, height)
+}
+
+// (index, `--setmonitor` geometry) from left to right.
+fn layout_geometries(base_w: u32, monitors: &BTreeMap<u32, (u32, u32)>) -> Vec<(u32, String)> {
+    let mut x = base_w;
+    monitors
+        .iter()
+        .map(|(idx, (w, h))| {
+            // Physical size is set to 0, it is only used for dpi.
+            let geometry = format!("{}/0x{}/0+{}+0", w, h, x);
+            x += w;
+            (*idx, geometry)
+        })
+        .collect()
+}
+
+#[inline]
+fn monitor_name(idx: u32) -> String {
+    format!("{}{}", MONITOR_NAME_PREFIX, idx)
+}
+
+fn xrandr(args: &[&str]) -> ResultType<String> {
+    let output = Command::new("xrandr").args(args).output()?;
+    if !output.status.success() {
+        bail!(
+            "xrandr {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+// ((current width, current height), (maximum width, maximum height))
+fn screen_size() -> ResultType<((u32, u32), (u32, u32))> {
+    match parse_screen_size(&xrandr(&["--current"])?) {
+        Some(size) => Ok(size),
+        None => bail!("Failed to get the screen size from xrandr"),
+    }
+}
+
+// Screen 0: minimum 320 x 200, current 1920 x 1080, maximum 16384 x 16384
+fn parse_screen_size(output: &str) -> Option<((u32, u32), (u32, u32))> {
+    let re = Regex::new(r"current (\d+) x (\d+), maximum (\d+) x (\d+)").ok()?;
+    let caps = re.captures(output)?;
+    let get = |i: usize| caps[i].parse::<u32>().unwrap_or_default();
+    Some(((get(1), get(2)), (get(3), get(4))))
+}
+
+// The area of the monitors which are not virtual, the frame buffer may be larger, e.g. Xvfb.
+fn base_size() -> ResultType<(u32, u32)> {
+    let output = xrandr(&["--listmonitors"])?;
+    match parse_monitors_size(&output) {
+        Some(size) => Ok(size),
+        None => Ok(screen_size()?.0),
+    }
+}
+
+//  0: +*default 1920/508x1080/286+0+0  default
+fn parse_monitors_size(output: &str) -> Option<(u32, u32)> {
+    let re = Regex::new(r"^\s*\d+: [+*]*(\S+) (\d+)/\d+x(\d+)/\d+\+(\d+)\+(\d+)").ok()?;
+    let mut size = None;
+    for caps in output.lines().filter_map(|l| re.captures(l)) {
+        if caps[1].starts_with(MONITOR_NAME_PREFIX) {
+            continue;
+        }
+        let get = |i: usize| caps[i].parse::<u32>().unwrap_or_default();
+        let (w, h) = size.unwrap_or_default();
+        size = Some((w.max(get(4) + get(2)), h.max(get(5) + get(3))));
+    }
+    size
+}
+
+// Monitors of RandR 1.5 are required.
+fn randr_version_supported() -> bool {
+    let Ok(output) = xrandr(&["--version"]) else {
+        return false;
+    };
+    match parse_randr_version(&output) {
+        Some((major, minor)) => major > 1 || (major == 1 && minor >= 5),
+        None => false,
+    }
+}
+
+// Server reports RandR version 1.6
+fn parse_randr_version(output: &str) -> Option<(u32, u32)> {
+    let re = Regex::new(r"Server reports RandR version (\d+)\.(\d+)").ok()?;
+    let caps = re.captures(output)?;
+    Some((caps[1].parse().ok()?, caps[2].parse().ok()?))
+}
+
+// Whether the X server of `DISPLAY` is Xvfb, which can not grow its frame buffer.
+fn is_xvfb() -> bool {
+    let Ok(display) = std::env::var("DISPLAY") else {
+        return false;
+    };
+    // :99 or :99.0
+    let display = display.split('.').next().unwrap_or_default().to_owned();
+    let Ok(dir) = std::fs::read_dir("/proc") else {
+        return false;
+    };
+    dir.flatten().any(|entry| {
+        let path = entry.path();
+        let is_xvfb =
+            std::fs::read_to_string(path.join("comm")).map_or(false, |comm| comm.trim() == "Xvfb");
+        is_xvfb
+            && std::fs::read(path.join("cmdline")).map_or(false, |cmdline| {
+                cmdline
+                    .split(|b| *b == 0)
+                    .any(|arg| arg == display.as_bytes())
+            })
+    })
+}
+
+pub fn is_virtual_display_supported() -> bool {
+    is_x11() && randr_version_supported()
+}
+
+pub fn get_virtual_displays() -> Vec<u32> {
+    VIRTUAL_DISPLAYS
+        .lock()
+        .unwrap()
+        .monitors
+        .keys()
+        .cloned()
+        .collect()
+}
+
+/// Plug in the virtual display `idx`, with the first of `modes` or 1920x1080.
+pub fn plug_in_index_modes(idx: u32, modes: Vec<Resolution>) -> ResultType<()> {
+    if !(VIRTUAL_DISPLAY_START_FOR_PEER..VIRTUAL_DISPLAY_MAX_COUNT).contains(&idx) {
+        bail!("Invalid virtual display index {}", idx);
+    }
+    let mut lock = VIRTUAL_DISPLAYS.lock().unwrap();
+    if lock.monitors.contains_key(&idx) {
+        return Ok(());
+    }
+    let size = modes
+        .first()
+        .filter(|r| r.width > 0 && r.height > 0)
+        .map(|r| (r.width as u32, r.height as u32))
+        .unwrap_or(DEFAULT_RESOLUTION);
+    let mut monitors = lock.monitors.clone();
+    monitors.insert(idx, size);
+    lock.apply(monitors)?;
+    log::info!("Virtual display {} plugged in, {}x{}", idx, size.0, size.1);
+    Ok(())
+}
+
+pub fn plug_out_peer_request(indices: &[u32]) -> ResultType<()> {
+    let mut lock = VIRTUAL_DISPLAYS.lock().unwrap();
+    let mut monitors = lock.monitors.clone();
+    monitors.retain(|idx, _| !indices.contains(idx));
+    if monitors.len() != lock.monitors.len() {
+        lock.apply(monitors)?;
+    }
+    Ok(())
+}
+
+pub fn reset_all() -> ResultType<()> {
+    plug_out_peer_request(&get_virtual_displays())
+}
+
+fn index_of(name: &str) -> Option<u32> {
+    name.strip_prefix(MONITOR_NAME_PREFIX)?.parse().ok()
+}
+
+pub fn is_virtual_display(name: &str) -> bool {
+    match index_of(name) {
+        Some(idx) => VIRTUAL_DISPLAYS.lock().unwrap().monitors.contains_key(&idx),
+        None => false,
+    }
+}
+
+/// Resolutions offered to the peer for a virtual display, any other one can be set as well.
+pub fn resolutions(name: &str) -> Option<Vec<Resolution>> {
+    if !is_virtual_display(name) {
+        return None;
+    }
+    Some(
+        COMMON_RESOLUTIONS
+            .iter()
+            .map(|(w, h)| Resolution {
+                width: *w as _,
+                height: *h as _,
+                ..Default::default()
+            })
+            .collect(),
+    )
+}
+
+pub fn change_resolution_if_is_virtual_display(name: &str, w: u32, h: u32) -> Option<bool> {
+    let idx = index_of(name)?;
+    let mut lock = VIRTUAL_DISPLAYS.lock().unwrap();
+    if !lock.monitors.contains_key(&idx) {
+        return None;
+    }
+    let mut monitors = lock.monitors.clone();
+    monitors.insert(idx, (w, h));
+    match lock.apply(monitors) {
+        Ok(_) => Some(true),
+        Err(e) => {
+            log::error!(
+                "Failed to change virtual display {} to {}x{}: {}",
+                idx,
+                w,
+                h,
+                e
+            );
+            Some(false)
+        }
+    }
+}
+
+/// `conf` (res/xorg.conf) with a frame buffer that can grow to the right for virtual displays,
+/// and the video memory for it.
+pub fn headless_xorg_conf(conf: &str) -> String {
+    let (w, h) = HEADLESS_VIRTUAL_SIZE;
+    let mut out = String::new();
+    for line in conf.lines() {
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+        if trimmed.starts_with("VideoRam ") {
+            out += &format!("{}VideoRam {}\n", indent, w * h * 4 / 1024);
+            continue;
+        }
+        if trimmed.starts_with("Virtual ") {
+            continue;
+        }
+        out += line;
+        out += "\n";
+        if trimmed.starts_with("Modes ") {
+            out += &format!("{}Virtual {} {}\n", indent, w, h);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headless_xorg_conf() {
+        let conf = include_str!("../../res/xorg.conf");
+        assert!(!conf.contains("Virtual "));
+        let conf = headless_xorg_conf(conf);
+        assert!(conf.contains("    VideoRam 131072\n"));
+        assert!(conf.contains("        Virtual 8192 4096\n"));
+        assert!(!conf.contains("VideoRam 8100"));
+        // generated again from its own output
+        assert_eq!(headless_xorg_conf(&conf), conf);
+    }
+
+    #[test]
+    fn test_parse_xrandr() {
+        let output = "Screen 0: minimum 320 x 200, current 1920 x 1080, maximum 8192 x 4096\n\
+            default connected primary 1920x1080+0+0 0mm x 0mm\n";
+        assert_eq!(
+            parse_screen_size(output),
+            Some(((1920, 1080), (8192, 4096)))
+        );
+        assert_eq!(parse_screen_size("xrandr: bad"), None);
+
+        assert_eq!(
+            parse_randr_version(
+                "xrandr program version       1.5.1\nServer reports RandR version 1.6\n"
+            ),
+            Some((1, 6))
+        );
+        assert_eq!(parse_randr_version("Can't open display"), None);
+
+        let output = "Monitors: 3\n \
+            0: +*default 1920/508x1080/286+0+0  default\n \
+            1: +HDMI-1 1280/340x1024/270+1920+0  HDMI-1\n \
+            2: RustDesk-Virtual-1 1920/0x1080/0+3200+0  none\n";
+        assert_eq!(parse_monitors_size(output), Some((3200, 1080)));
+        assert_eq!(parse_monitors_size("Monitors: 0\n"), None);
+    }
+
+    #[test]
+    fn test_layout() {
+        let monitors: BTreeMap<u32, (u32, u32)> =
+            [(2, (1280, 720)), (1, (2560, 1440))].into_iter().collect();
+        assert_eq!(layout_size((1920, 1080), &monitors), (5760, 1440));
+        assert_eq!(
+            layout_geometries(1920, &monitors),
+            vec![
+                (1, "2560/0x1440/0+1920+0".to_owned()),
+                (2, "1280/0x720/0+4480+0".to_owned())
+            ]
+        );
+        assert_eq!(layout_size((1920, 1080), &BTreeMap::new()), (1920, 1080));
+    }
+}
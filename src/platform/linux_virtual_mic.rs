@@ -0,0 +1,130 @@
+// A virtual microphone fed with the voice of the peer during a voice call, so that apps on
+// this side, e.g. conferencing or dictation, can use it.
+//
+// The voice is played to a null sink, whose monitor is remapped as a source, because apps
+// usually do not list monitors as microphones. Both are pactl modules, which works with
+// PulseAudio and pipewire-pulse, and they are unloaded when the voice call or the session ends.
+// The modules left by a server which was killed are unloaded when the server starts.
+
+use hbb_common::{allow_err, bail, log, ResultType};
+use std::process::Command;
+
+pub struct VirtualMicrophone {
+    sink: String,
+    modules: Vec<String>,
+}
+
+impl VirtualMicrophone {
+    pub fn new(conn_id: i32) -> ResultType<Self> {
+        let app = app_name();
+        let sink = format!("{}{}", sink_prefix(), conn_id);
+        let mut mic = Self {
+            sink: sink.clone(),
+            modules: Vec::new(),
+        };
+        mic.load(&[
+            "module-null-sink",
+            &format!("sink_name={}", sink),
+            &format!("sink_properties=device.description={}-Voice-Call", app),
+        ])?;
+        mic.load(&[
+            "module-remap-source",
+            &format!("master={}.monitor", sink),
+            &format!("source_name={}_mic", sink),
+            &format!("source_properties=device.description={}-Microphone", app),
+        ])?;
+        log::info!("Virtual microphone {}_mic created", sink);
+        Ok(mic)
+    }
+
+    /// The sink to play the voice of the peer to.
+    pub fn sink(&self) -> &str {
+        &self.sink
+    }
+
+    fn load(&mut self, args: &[&str]) -> ResultType<()> {
+        let output = Command::new("pactl")
+            .arg("load-module")
+            .args(args)
+            .output()?;
+        if !output.status.success() {
+            bail!(
+                "pactl load-module {:?} failed: {}",
+                args,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        self.modules
+            .push(String::from_utf8_lossy(&output.stdout).trim().to_owned());
+        Ok(())
+    }
+}
+
+// Module arguments are separated by spaces.
+fn app_name() -> String {
+    crate::get_app_name().replace(' ', "-")
+}
+
+fn sink_prefix() -> String {
+    format!("{}_voice_call_", app_name().to_lowercase())
+}
+
+/// Unload the modules of the virtual microphones of a previous run, which could not remove
+/// them, e.g. killed or crashed.
+pub fn unload_leaked() {
+    let output = match Command::new("pactl")
+        .args(["list", "short", "modules"])
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        res => {
+            log::debug!("Failed to list the modules: {:?}", res);
+            return;
+        }
+    };
+    let prefix = sink_prefix();
+    let (sink, master) = (
+        format!("sink_name={}", prefix),
+        format!("master={}", prefix),
+    );
+    // index, name, arguments, separated by tabs
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // The remapped sources first, they use the sinks.
+    let mut leaked: Vec<(bool, &str)> = stdout
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let index = fields.next()?;
+            let args = fields.nth(1)?;
+            if args.contains(&master) {
+                Some((false, index))
+            } else if args.contains(&sink) {
+                Some((true, index))
+            } else {
+                None
+            }
+        })
+        .collect();
+    leaked.sort_by_key(|(is_sink, _)| *is_sink);
+    for (_, module) in leaked {
+        log::info!("Unload the leaked module {}", module);
+        allow_err!(Command::new("pactl")
+            .args(["unload-module", module])
+            .status());
+    }
+}
+
+impl Drop for VirtualMicrophone {
+    fn drop(&mut self) {
+        for module in self.modules.iter().rev() {
+            match Command::new("pactl")
+                .args(["unload-module", module])
+                .status()
+            {
+                Ok(status) if status.success() => {}
+                res => log::error!("Failed to unload module {}: {:?}", module, res),
+            }
+        }
+        log::info!("Virtual microphone {}_mic removed", self.sink);
+    }
+}
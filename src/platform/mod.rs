@@ -21,6 +21,18 @@ pub mod linux;
 #[cfg(not(any(feature = "flatpak", feature = "appimage")))]
 pub mod linux_desktop_manager;
 
+#[cfg(target_os = "linux")]
+pub mod linux_virtual_display;
+
+#[cfg(all(target_os = "linux", feature = "pipewire-audio"))]
+pub mod linux_pipewire;
+
+#[cfg(target_os = "linux")]
+pub mod linux_virtual_mic;
+
+#[cfg(target_os = "linux")]
+pub mod linux_clipboard_watch;
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use hbb_common::{message_proto::CursorData, ResultType};
 use std::sync::{Arc, Mutex};
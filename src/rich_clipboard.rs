@@ -0,0 +1,771 @@
+// Clipboard formats other than text: HTML, RTF and images as PNG.
+//
+// They can be large, so only the list of the formats with their sizes is sent when the clipboard
+// changes, and the peer fetches the payloads it takes with `ClipboardRequest`. The text is still
+// sent alone with `Clipboard` too, for the peers without this.
+//
+// arboard writes all of them, but it only reads text and RGBA images, so HTML, RTF and PNG are
+// read natively: with the clipboard API on Windows and macOS, with wl-paste or xclip on Linux.
+// The formats arboard cannot write together are added natively after it on Windows and macOS.
+// The clipboard of Linux has one owner, arboard, so it takes the image if there is one, or the
+// text formats.
+//
+// Reading all the formats is slow, so it is only done if the change count of the clipboard
+// moved: the sequence number on Windows, `changeCount` on macOS, and on Linux the changes
+// watched, or the list of the targets if they are not.
+//
+// Every format is checked against `clipboard_filter` when it is offered and when it is received.
+
+use crate::{
+    clipboard_filter::{self, Blocked, Direction},
+    common::ARBOARD_MTX,
+};
+use hbb_common::{
+    compress::{compress, decompress},
+    config::Config,
+    log,
+    message_proto::*,
+    protobuf::EnumOrUnknown,
+    ResultType,
+};
+use std::{
+    borrow::Cow,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+// in MB
+const DEFAULT_MAX_SIZE: usize = 2;
+const DEFAULT_MAX_IMAGE_SIZE: usize = 20;
+const NATIVE_FORMATS: [ClipboardFormat; 3] = [
+    ClipboardFormat::Html,
+    ClipboardFormat::Rtf,
+    ClipboardFormat::ImagePng,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClipboardSide {
+    Host,
+    Client,
+}
+
+impl ClipboardSide {
+    pub fn sending(&self) -> Direction {
+        match self {
+            ClipboardSide::Host => Direction::HostToClient,
+            ClipboardSide::Client => Direction::ClientToHost,
+        }
+    }
+
+    pub fn receiving(&self) -> Direction {
+        match self {
+            ClipboardSide::Host => Direction::ClientToHost,
+            ClipboardSide::Client => Direction::HostToClient,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Offer {
+    id: i32,
+    // of all the formats read or written last, to tell a change
+    hash: u64,
+    // of the text and the change count of the clipboard when it was read or written last
+    signal: Option<u64>,
+    // uncompressed
+    clipboards: Vec<Clipboard>,
+}
+
+lazy_static::lazy_static! {
+    static ref HOST_OFFER: Mutex<Offer> = Default::default();
+    static ref CLIENT_OFFER: Mutex<Offer> = Default::default();
+}
+
+fn offer(side: ClipboardSide) -> &'static Mutex<Offer> {
+    match side {
+        ClipboardSide::Host => &HOST_OFFER,
+        ClipboardSide::Client => &CLIENT_OFFER,
+    }
+}
+
+/// The largest payload of `format` sent or fetched, set with the options `max-clipboard-size`
+/// and `max-clipboard-image-size`, in MB.
+pub fn max_size(format: ClipboardFormat) -> usize {
+    let (key, default) = match format {
+        ClipboardFormat::ImagePng => ("max-clipboard-image-size", DEFAULT_MAX_IMAGE_SIZE),
+        _ => ("max-clipboard-size", DEFAULT_MAX_SIZE),
+    };
+    Config::get_option(key).parse().unwrap_or(default) * 1_000_000
+}
+
+enum Image {
+    Png(Vec<u8>),
+    // width, height, pixels
+    Rgba(usize, usize, Vec<u8>),
+}
+
+struct Formats {
+    hash: u64,
+    native: Vec<(ClipboardFormat, Vec<u8>)>,
+    image: Option<Image>,
+}
+
+fn read(text: &str) -> Formats {
+    let mut native = native::read(&NATIVE_FORMATS);
+    #[allow(unused_mut)]
+    let mut image = native
+        .iter()
+        .position(|(f, _)| *f == ClipboardFormat::ImagePng)
+        .map(|i| Image::Png(native.remove(i).1));
+    #[cfg(not(target_os = "linux"))]
+    if image.is_none() {
+        let _lock = ARBOARD_MTX.lock().unwrap();
+        if let Ok(data) = arboard::Clipboard::new().and_then(|mut c| c.get_image()) {
+            image = Some(Image::Rgba(
+                data.width,
+                data.height,
+                data.bytes.into_owned(),
+            ));
+        }
+    }
+    Formats {
+        hash: hash(text, &native, image.as_ref()),
+        native,
+        image,
+    }
+}
+
+fn hash(text: &str, native: &[(ClipboardFormat, Vec<u8>)], image: Option<&Image>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    native.hash(&mut hasher);
+    match image {
+        Some(Image::Png(png)) => png.hash(&mut hasher),
+        Some(Image::Rgba(width, height, rgba)) => (width, height, rgba).hash(&mut hasher),
+        None => {}
+    }
+    hasher.finish()
+}
+
+// None if the clipboard has no change count, so that it is always read.
+fn signal(text: &str) -> Option<u64> {
+    let count = native::change_count()?;
+    let mut hasher = DefaultHasher::new();
+    (text, count).hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+fn format_list(offer: &Offer) -> Option<Message> {
+    if offer.clipboards.is_empty() {
+        return None;
+    }
+    let mut msg = Message::new();
+    msg.set_clipboard_format_list(ClipboardFormatList {
+        id: offer.id,
+        formats: offer
+            .clipboards
+            .iter()
+            .map(|c| ClipboardFormatEntry {
+                format: c.format,
+                size: c.content.len() as _,
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    });
+    Some(msg)
+}
+
+/// The format list to send if the clipboard has more than `text`, and it changed since it was
+/// read or written last, with the first format blocked by the policy. The blocks of formats
+/// other than text are logged (and audited on the host) here, the text is checked with
+/// `Clipboard`.
+pub fn check(side: ClipboardSide, text: &str) -> (Option<Message>, Option<Blocked>) {
+    let signal = signal(text);
+    if signal.is_some() && offer(side).lock().unwrap().signal == signal {
+        return (None, None);
+    }
+    let formats = read(text);
+    let mut offer = offer(side).lock().unwrap();
+    offer.signal = signal;
+    if formats.hash == offer.hash {
+        return (None, None);
+    }
+    offer.hash = formats.hash;
+    offer.clipboards.clear();
+    if formats.native.is_empty() && formats.image.is_none() {
+        return (None, None);
+    }
+    let mut clipboards = vec![(ClipboardFormat::Text, text.as_bytes().to_vec())];
+    clipboards.extend(formats.native);
+    match formats.image {
+        Some(Image::Png(png)) => clipboards.push((ClipboardFormat::ImagePng, png)),
+        Some(Image::Rgba(width, height, rgba)) => match encode_png(width, height, &rgba) {
+            Ok(png) => clipboards.push((ClipboardFormat::ImagePng, png)),
+            Err(err) => log::error!("Failed to encode the clipboard image: {}", err),
+        },
+        None => {}
+    }
+    let mut blocked = None;
+    offer.clipboards = clipboards
+        .into_iter()
+        .filter(|(format, data)| {
+            if data.is_empty() {
+                return false;
+            }
+            match clipboard_filter::check(side.sending(), *format, data) {
+                Ok(()) => true,
+                Err(b) => {
+                    if *format != ClipboardFormat::Text {
+                        match side {
+                            ClipboardSide::Host => b.audit(0, ""),
+                            ClipboardSide::Client => b.log(),
+                        }
+                    }
+                    blocked.get_or_insert(b);
+                    false
+                }
+            }
+        })
+        .map(|(format, data)| Clipboard {
+            format: format.into(),
+            content: data.into(),
+            ..Default::default()
+        })
+        .collect();
+    offer.id = offer.id.wrapping_add(1);
+    log::info!(
+        "{} formats update found on {:?}",
+        crate::CLIPBOARD_NAME,
+        side
+    );
+    (format_list(&offer), blocked)
+}
+
+/// The format list of the clipboard read last, for a new peer.
+pub fn current_format_list(side: ClipboardSide) -> Option<Message> {
+    format_list(&offer(side).lock().unwrap())
+}
+
+/// Forget the clipboard read last, so that it is sent again.
+pub fn reset(side: ClipboardSide) {
+    let mut offer = offer(side).lock().unwrap();
+    offer.hash = 0;
+    offer.signal = None;
+}
+
+/// The request for the formats of the peer the clipboard here takes.
+pub fn handle_format_list(side: ClipboardSide, list: ClipboardFormatList) -> Option<Message> {
+    if !clipboard_filter::allows(side.receiving()) {
+        return None;
+    }
+    let formats: Vec<EnumOrUnknown<ClipboardFormat>> = list
+        .formats
+        .iter()
+        .filter_map(|f| {
+            let format = f.format.enum_value().ok()?;
+            (f.size > 0 && f.size as usize <= max_size(format)).then_some(format.into())
+        })
+        .collect();
+    // The text alone comes with `Clipboard`.
+    if formats
+        .iter()
+        .all(|f| f.enum_value() == Ok(ClipboardFormat::Text))
+    {
+        return None;
+    }
+    let mut msg = Message::new();
+    msg.set_clipboard_request(ClipboardRequest {
+        id: list.id,
+        formats,
+        ..Default::default()
+    });
+    Some(msg)
+}
+
+/// The payloads the peer requested, if the clipboard has not changed since it was offered.
+pub fn handle_request(side: ClipboardSide, request: ClipboardRequest) -> Option<Message> {
+    let offer = offer(side).lock().unwrap();
+    if offer.id != request.id {
+        return None;
+    }
+    let clipboards: Vec<_> = offer
+        .clipboards
+        .iter()
+        .filter(|c| request.formats.contains(&c.format))
+        .map(|c| {
+            let compressed = if c.format.enum_value() == Ok(ClipboardFormat::ImagePng) {
+                vec![]
+            } else {
+                compress(&c.content)
+            };
+            let compress = !compressed.is_empty() && compressed.len() < c.content.len();
+            Clipboard {
+                compress,
+                content: if compress {
+                    compressed.into()
+                } else {
+                    c.content.clone()
+                },
+                format: c.format,
+                ..Default::default()
+            }
+        })
+        .collect();
+    if clipboards.is_empty() {
+        return None;
+    }
+    let mut msg = Message::new();
+    msg.set_multi_clipboards(MultiClipboards {
+        id: request.id,
+        clipboards,
+        ..Default::default()
+    });
+    Some(msg)
+}
+
+/// Write the formats of the peer the policy allows to the clipboard, `old` is the text read last
+/// on this side. The first format blocked is returned, to be audited and told.
+///
+/// It waits for the clipboard, call it with `spawn_blocking` in async code.
+pub fn update(
+    side: ClipboardSide,
+    multi: MultiClipboards,
+    old: &Arc<Mutex<String>>,
+) -> Option<Blocked> {
+    let mut blocked = None;
+    let mut text = None;
+    let mut png = None;
+    let mut native = vec![];
+    for c in multi.clipboards {
+        let content = if c.compress {
+            decompress(&c.content)
+        } else {
+            c.content.into()
+        };
+        let Ok(format) = c.format.enum_value() else {
+            continue;
+        };
+        if content.is_empty() {
+            continue;
+        }
+        if let Err(b) = clipboard_filter::check(side.receiving(), format, &content) {
+            blocked.get_or_insert(b);
+            continue;
+        }
+        match format {
+            ClipboardFormat::Text => text = String::from_utf8(content).ok(),
+            ClipboardFormat::ImagePng => png = Some(content),
+            _ => native.push((format, content)),
+        }
+    }
+    if text.is_none() && native.is_empty() && png.is_none() {
+        return blocked;
+    }
+    #[cfg(target_os = "linux")]
+    if png.is_some() {
+        log::debug!("The text formats are not written with the image");
+        text = None;
+        native.clear();
+    }
+    if let Err(err) = write(text.as_deref(), native, png) {
+        log::error!("Failed to update {}: {}", crate::CLIPBOARD_NAME, err);
+        return blocked;
+    }
+    let text = text.unwrap_or_default();
+    let hash = read(&text).hash;
+    let signal = signal(&text);
+    *old.lock().unwrap() = text;
+    let mut offer = offer(side).lock().unwrap();
+    offer.hash = hash;
+    offer.signal = signal;
+    drop(offer);
+    log::debug!("{} formats updated on {:?}", crate::CLIPBOARD_NAME, side);
+    blocked
+}
+
+fn write(
+    text: Option<&str>,
+    mut native: Vec<(ClipboardFormat, Vec<u8>)>,
+    png: Option<Vec<u8>>,
+) -> ResultType<()> {
+    let html = native
+        .iter()
+        .position(|(f, _)| *f == ClipboardFormat::Html)
+        .map(|i| native.remove(i).1);
+    let _lock = ARBOARD_MTX.lock().unwrap();
+    let mut ctx = arboard::Clipboard::new()?;
+    match (html, text) {
+        (Some(html), text) => {
+            ctx.set_html(String::from_utf8_lossy(&html), text.map(Cow::Borrowed))?
+        }
+        (None, Some(text)) => ctx.set_text(text)?,
+        (None, None) => {
+            if let Some(png) = png {
+                let rgba = image::load_from_memory_with_format(&png, image::ImageFormat::Png)?
+                    .into_rgba8();
+                ctx.set_image(arboard::ImageData {
+                    width: rgba.width() as _,
+                    height: rgba.height() as _,
+                    bytes: Cow::Owned(rgba.into_raw()),
+                })?;
+            }
+            return Ok(());
+        }
+    }
+    if let Some(png) = png {
+        native.push((ClipboardFormat::ImagePng, png));
+    }
+    native::add(&native);
+    Ok(())
+}
+
+fn encode_png(width: usize, height: usize, rgba: &[u8]) -> ResultType<Vec<u8>> {
+    use image::ImageEncoder;
+    let mut png = Vec::new();
+    image::codecs::png::PngEncoder::new(&mut png).write_image(
+        rgba,
+        width as _,
+        height as _,
+        image::ColorType::Rgba8,
+    )?;
+    Ok(png)
+}
+
+#[cfg(target_os = "windows")]
+mod native {
+    use super::*;
+    use clipboard_win::{raw, Clipboard};
+    use hbb_common::allow_err;
+
+    const OPEN_ATTEMPTS: usize = 10;
+
+    fn format_id(format: ClipboardFormat) -> Option<u32> {
+        let name = match format {
+            ClipboardFormat::Html => "HTML Format",
+            ClipboardFormat::Rtf => "Rich Text Format",
+            ClipboardFormat::ImagePng => "PNG",
+            ClipboardFormat::Text => return None,
+        };
+        raw::register_format(name).map(|id| id.get())
+    }
+
+    pub fn read(formats: &[ClipboardFormat]) -> Vec<(ClipboardFormat, Vec<u8>)> {
+        let Ok(_clipboard) = Clipboard::new_attempts(OPEN_ATTEMPTS) else {
+            return vec![];
+        };
+        formats
+            .iter()
+            .filter_map(|format| {
+                let id = format_id(*format)?;
+                if !raw::is_format_avail(id) {
+                    return None;
+                }
+                let mut data = Vec::new();
+                raw::get_vec(id, &mut data).ok()?;
+                let data = match format {
+                    ClipboardFormat::Html => html_fragment(&data),
+                    ClipboardFormat::Rtf => {
+                        // null-terminated
+                        let len = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+                        data.truncate(len);
+                        Some(data)
+                    }
+                    _ => Some(data),
+                };
+                data.map(|data| (*format, data))
+            })
+            .collect()
+    }
+
+    pub fn change_count() -> Option<u64> {
+        raw::seq_num().map(|n| n.get() as _)
+    }
+
+    pub fn add(formats: &[(ClipboardFormat, Vec<u8>)]) {
+        if formats.is_empty() {
+            return;
+        }
+        let Ok(_clipboard) = Clipboard::new_attempts(OPEN_ATTEMPTS) else {
+            return;
+        };
+        for (format, data) in formats {
+            if let Some(id) = format_id(*format) {
+                allow_err!(raw::set_without_clear(id, data));
+            }
+        }
+    }
+
+    // "HTML Format" is the fragment after a header with its offsets, the one arboard writes.
+    fn html_fragment(data: &[u8]) -> Option<Vec<u8>> {
+        let header = String::from_utf8_lossy(&data[..data.len().min(512)]);
+        let offset = |key: &str| -> Option<usize> {
+            header
+                .lines()
+                .find_map(|line| line.strip_prefix(key))
+                .and_then(|v| v.trim().parse().ok())
+        };
+        let start = offset("StartFragment:")?;
+        let end = offset("EndFragment:")?.min(data.len());
+        (start < end).then(|| data[start..end].to_vec())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod native {
+    use super::*;
+    use cocoa::{
+        appkit::NSPasteboard,
+        base::{id, nil},
+        foundation::{NSAutoreleasePool, NSData, NSInteger, NSString},
+    };
+    use objc::{msg_send, sel, sel_impl};
+
+    fn uti(format: ClipboardFormat) -> Option<&'static str> {
+        match format {
+            ClipboardFormat::Html => Some("public.html"),
+            ClipboardFormat::Rtf => Some("public.rtf"),
+            ClipboardFormat::ImagePng => Some("public.png"),
+            ClipboardFormat::Text => None,
+        }
+    }
+
+    pub fn read(formats: &[ClipboardFormat]) -> Vec<(ClipboardFormat, Vec<u8>)> {
+        unsafe {
+            let pool = NSAutoreleasePool::new(nil);
+            let pasteboard = NSPasteboard::generalPasteboard(nil);
+            let res: Vec<_> = formats
+                .iter()
+                .filter_map(|format| {
+                    let ty = NSString::alloc(nil).init_str(uti(*format)?).autorelease();
+                    let data: id = pasteboard.dataForType(ty);
+                    if data == nil {
+                        return None;
+                    }
+                    let bytes = std::slice::from_raw_parts(
+                        data.bytes() as *const u8,
+                        data.length() as usize,
+                    );
+                    Some((*format, bytes.to_vec()))
+                })
+                .collect();
+            pool.drain();
+            res
+        }
+    }
+
+    pub fn change_count() -> Option<u64> {
+        unsafe {
+            let pasteboard = NSPasteboard::generalPasteboard(nil);
+            let count: NSInteger = msg_send![pasteboard, changeCount];
+            Some(count as _)
+        }
+    }
+
+    pub fn add(formats: &[(ClipboardFormat, Vec<u8>)]) {
+        unsafe {
+            let pool = NSAutoreleasePool::new(nil);
+            let pasteboard = NSPasteboard::generalPasteboard(nil);
+            for (format, bytes) in formats {
+                if let Some(uti) = uti(*format) {
+                    let ty = NSString::alloc(nil).init_str(uti).autorelease();
+                    let data =
+                        NSData::dataWithBytes_length_(nil, bytes.as_ptr() as _, bytes.len() as _);
+                    pasteboard.setData_forType(data, ty);
+                }
+            }
+            pool.drain();
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod native {
+    use super::*;
+    use std::{
+        process::Command,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    static TOOL_MISSING: AtomicBool = AtomicBool::new(false);
+
+    fn mime(format: ClipboardFormat) -> &'static str {
+        match format {
+            ClipboardFormat::Text => "text/plain;charset=utf-8",
+            ClipboardFormat::Html => "text/html",
+            ClipboardFormat::Rtf => "text/rtf",
+            ClipboardFormat::ImagePng => "image/png",
+        }
+    }
+
+    // `target` of None lists the targets.
+    fn paste(target: Option<&str>) -> Option<Vec<u8>> {
+        if TOOL_MISSING.load(Ordering::Relaxed) {
+            return None;
+        }
+        let mut cmd;
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            cmd = Command::new("wl-paste");
+            match target {
+                Some(target) => cmd.args(["--no-newline", "--type", target]),
+                None => cmd.arg("--list-types"),
+            };
+        } else {
+            cmd = Command::new("xclip");
+            cmd.args(["-selection", "clipboard", "-o", "-t"])
+                .arg(target.unwrap_or("TARGETS"));
+        }
+        match cmd.output() {
+            Ok(output) if output.status.success() => Some(output.stdout),
+            Ok(_) => None,
+            Err(err) => {
+                log::warn!(
+                    "{:?} is required for the clipboard formats other than text: {}",
+                    cmd.get_program(),
+                    err
+                );
+                TOOL_MISSING.store(true, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    // If the changes are not watched, TIMESTAMP of X11 is when the owner took the clipboard.
+    // Wayland has no such target, the targets tell a change of the formats but not of their
+    // data, so a change of the other formats which keeps the text and the targets is missed.
+    pub fn change_count() -> Option<u64> {
+        if let Some(seq) = crate::platform::linux_clipboard_watch::sequence() {
+            return Some(seq);
+        }
+        let targets = paste(None)?;
+        let mut hasher = DefaultHasher::new();
+        targets.hash(&mut hasher);
+        if std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            paste(Some("TIMESTAMP")).hash(&mut hasher);
+        }
+        Some(hasher.finish())
+    }
+
+    pub fn read(formats: &[ClipboardFormat]) -> Vec<(ClipboardFormat, Vec<u8>)> {
+        let Some(targets) = paste(None) else {
+            return vec![];
+        };
+        let targets = String::from_utf8_lossy(&targets);
+        formats
+            .iter()
+            .filter(|format| targets.lines().any(|t| t.trim() == mime(**format)))
+            .filter_map(|format| {
+                paste(Some(mime(*format)))
+                    .filter(|data| !data.is_empty())
+                    .map(|data| (*format, data))
+            })
+            .collect()
+    }
+
+    // A clipboard owner of X11 or Wayland is one process, the formats arboard sets are kept.
+    pub fn add(_formats: &[(ClipboardFormat, Vec<u8>)]) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_list() {
+        let mut offer = Offer::default();
+        assert!(format_list(&offer).is_none());
+        offer.id = 3;
+        offer.clipboards = vec![
+            Clipboard {
+                format: ClipboardFormat::Text.into(),
+                content: b"text".to_vec().into(),
+                ..Default::default()
+            },
+            Clipboard {
+                format: ClipboardFormat::ImagePng.into(),
+                content: vec![0; 10].into(),
+                ..Default::default()
+            },
+        ];
+        let msg = format_list(&offer).unwrap();
+        let list = msg.clipboard_format_list();
+        assert_eq!(list.id, 3);
+        let formats: Vec<_> = list
+            .formats
+            .iter()
+            .map(|f| (f.format.enum_value(), f.size))
+            .collect();
+        assert_eq!(
+            formats,
+            vec![
+                (Ok(ClipboardFormat::Text), 4),
+                (Ok(ClipboardFormat::ImagePng), 10)
+            ]
+        );
+
+        let entry = |format: ClipboardFormat, size: usize| ClipboardFormatEntry {
+            format: format.into(),
+            size: size as _,
+            ..Default::default()
+        };
+        let request = |formats: Vec<ClipboardFormatEntry>| {
+            handle_format_list(
+                ClipboardSide::Client,
+                ClipboardFormatList {
+                    id: 3,
+                    formats,
+                    ..Default::default()
+                },
+            )
+            .map(|msg| msg.clipboard_request().formats.clone())
+        };
+        assert_eq!(request(vec![entry(ClipboardFormat::Text, 4)]), None);
+        assert_eq!(
+            request(vec![
+                entry(ClipboardFormat::Text, 4),
+                entry(ClipboardFormat::Html, 0),
+                entry(ClipboardFormat::Rtf, max_size(ClipboardFormat::Rtf) + 1),
+            ]),
+            None
+        );
+        assert_eq!(
+            request(vec![
+                entry(ClipboardFormat::Text, 4),
+                entry(ClipboardFormat::Html, 10),
+                entry(
+                    ClipboardFormat::ImagePng,
+                    max_size(ClipboardFormat::Html) + 1
+                ),
+            ]),
+            Some(vec![
+                ClipboardFormat::Text.into(),
+                ClipboardFormat::Html.into(),
+                ClipboardFormat::ImagePng.into()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_hash() {
+        let html = vec![(ClipboardFormat::Html, b"<b>a</b>".to_vec())];
+        let png = Image::Png(vec![1, 2, 3]);
+        let base = hash("a", &html, Some(&png));
+        assert_eq!(base, hash("a", &html, Some(&Image::Png(vec![1, 2, 3]))));
+        assert_ne!(base, hash("b", &html, Some(&png)));
+        assert_ne!(
+            base,
+            hash(
+                "a",
+                &[(ClipboardFormat::Rtf, b"<b>a</b>".to_vec())],
+                Some(&png)
+            )
+        );
+        assert_ne!(base, hash("a", &html, Some(&Image::Png(vec![1, 2, 4]))));
+        assert_ne!(base, hash("a", &html, None));
+        assert_ne!(
+            hash("", &[], Some(&Image::Rgba(1, 2, vec![0; 8]))),
+            hash("", &[], Some(&Image::Rgba(2, 1, vec![0; 8])))
+        );
+    }
+}
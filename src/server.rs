@@ -31,6 +31,7 @@ use service::{EmptyExtraFieldService, GenericService, Service, Subscriber};
 use crate::ipc::Data;
 
 pub mod audio_service;
+pub mod audit_log;
 cfg_if::cfg_if! {
 if #[cfg(not(any(target_os = "android", target_os = "ios")))] {
 mod clipboard_service;
@@ -56,9 +57,11 @@ pub const NAME_POS: &'static str = "";
 
 mod connection;
 pub mod display_service;
+pub mod event_sink;
 #[cfg(windows)]
 pub mod portable_service;
 mod service;
+pub mod session_recording;
 mod video_qos;
 pub mod video_service;
 
@@ -79,6 +82,18 @@ lazy_static::lazy_static! {
     // Now we use this [`CLIENT_SERVER`] to do following operations:
     // - record local audio, and send to remote
     pub static ref CLIENT_SERVER: ServerPtr = new();
+    static ref MEDIA_CLOCK: std::time::Instant = std::time::Instant::now();
+}
+
+/// The clock of the capture times of the audio and video frames, shared by all the services so
+/// that the peer can play them in sync.
+pub fn media_clock() -> std::time::Instant {
+    *MEDIA_CLOCK
+}
+
+/// Milliseconds on [`media_clock`].
+pub fn media_time() -> i64 {
+    MEDIA_CLOCK.elapsed().as_millis() as _
 }
 
 pub struct Server {
@@ -465,6 +480,8 @@ pub async fn start_server(is_server: bool) {
         });
         input_service::fix_key_down_timeout_loop();
         #[cfg(target_os = "linux")]
+        std::thread::spawn(crate::platform::linux_virtual_mic::unload_leaked);
+        #[cfg(target_os = "linux")]
         if crate::platform::current_is_wayland() {
             allow_err!(input_service::setup_uinput(0, 1920, 0, 1080).await);
         }
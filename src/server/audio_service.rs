@@ -6,21 +6,43 @@
 // https://docs.microsoft.com/en-us/windows/win32/api/audioclient/nn-audioclient-iaudioclient
 // https://github.com/ExistentialAudio/BlackHole
 
+// With the pipewire-audio feature, the _pa ipc server records from PipeWire natively if its
+// daemon is running, see platform/linux_pipewire.rs, pulseaudio is the fallback.
+// The capture stays behind the _pa ipc: the server runs as root from the service, while the
+// PipeWire and pulseaudio daemons belong to the user session, which only the connection
+// manager process (where start_pa runs) can reach.
+// A single application is recorded natively too, without it through `parec --monitor-stream`
+// on its pulseaudio sink input.
+
 // if pactl not work, please run
 // sudo apt-get --purge --reinstall install pulseaudio
 // https://askubuntu.com/questions/403416/how-to-listen-live-sounds-from-input-from-external-sound-card
 // https://wiki.debian.org/audio-loopback
 // https://github.com/krruzic/pulsectl
 
+use super::video_qos::AudioParams;
 use super::*;
 #[cfg(not(any(target_os = "linux", target_os = "android")))]
 use hbb_common::anyhow::anyhow;
-use magnum_opus::{Application::*, Channels::*, Encoder};
-use std::sync::atomic::{AtomicBool, Ordering};
+use magnum_opus::{Application::*, Bitrate, Channels::*, Encoder};
+use std::{
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    time::Instant,
+};
 
 pub const NAME: &'static str = "audio";
 pub const AUDIO_DATA_SIZE_U8: usize = 960 * 4; // 10ms in 48000 stereo
+const ADAPT_INTERVAL: Duration = Duration::from_secs(1);
 static RESTARTING: AtomicBool = AtomicBool::new(false);
+// `AudioFrame::seq` of the mix
+static SEQ: AtomicU32 = AtomicU32::new(1);
+static SHORT_SILENCE_GATE: AtomicBool = AtomicBool::new(false);
+
+#[cfg(target_os = "linux")]
+lazy_static::lazy_static! {
+    // conn id -> PipeWire serial of the application the session listens to, the mix if absent
+    static ref SOURCES: Arc<Mutex<HashMap<i32, String>>> = Default::default();
+}
 
 #[cfg(not(any(target_os = "linux", target_os = "android")))]
 pub fn new() -> GenericService {
@@ -44,6 +66,65 @@ pub fn restart() {
     RESTARTING.store(true, Ordering::SeqCst);
 }
 
+/// Send the audio of `application` to the session instead of the whole mix, empty for the mix.
+#[cfg(target_os = "linux")]
+pub fn set_source(conn_id: i32, application: &str) {
+    let mut lock = SOURCES.lock().unwrap();
+    if application.is_empty() {
+        lock.remove(&conn_id);
+    } else {
+        lock.insert(conn_id, application.to_owned());
+    }
+}
+
+/// The applications playing audio, which can be selected by `set_source`.
+#[cfg(target_os = "linux")]
+pub async fn get_applications() -> ResultType<Vec<AudioApplication>> {
+    let mut stream = crate::ipc::connect(1000, "_pa").await?;
+    stream
+        .send(&Data::Config(("audio-applications".to_owned(), None)))
+        .await?;
+    let mut applications = Vec::new();
+    if let Some(Data::Config((_, Some(apps)))) = stream.next_timeout(1000).await? {
+        let apps: Vec<(String, String, u32)> = serde_json::from_str(&apps)?;
+        for (id, name, pid) in apps {
+            applications.push(AudioApplication {
+                id,
+                name,
+                pid,
+                ..Default::default()
+            });
+        }
+    }
+    Ok(applications)
+}
+
+// Follows `VideoQoS::audio_params` with an encoder.
+#[derive(Default)]
+struct Adaptive {
+    params: Option<AudioParams>,
+    checked: Option<Instant>,
+}
+
+impl Adaptive {
+    fn update(&mut self, encoder: &mut Encoder) {
+        if self.checked.map_or(false, |t| t.elapsed() < ADAPT_INTERVAL) {
+            return;
+        }
+        self.checked = Some(Instant::now());
+        let params = video_service::VIDEO_QOS.lock().unwrap().audio_params();
+        if self.params == Some(params) {
+            return;
+        }
+        log::info!("Audio encoder params: {:?}", params);
+        allow_err!(encoder.set_bitrate(Bitrate::Bits(params.bitrate as _)));
+        allow_err!(encoder.set_inband_fec(params.loss_percent > 0));
+        allow_err!(encoder.set_packet_loss_perc(params.loss_percent));
+        SHORT_SILENCE_GATE.store(params.short_silence_gate, Ordering::Relaxed);
+        self.params = Some(params);
+    }
+}
+
 #[cfg(any(target_os = "linux", target_os = "android"))]
 mod pa_impl {
     use super::*;
@@ -57,6 +138,7 @@ mod pa_impl {
             AUDIO_ZERO_COUNT = 0;
         }
         let mut encoder = Encoder::new(crate::platform::PA_SAMPLE_RATE, Stereo, LowDelay)?;
+        let mut adaptive = Adaptive::default();
         #[cfg(target_os = "linux")]
         allow_err!(
             stream
@@ -68,11 +150,17 @@ mod pa_impl {
         );
         #[cfg(target_os = "linux")]
         let zero_audio_frame: Vec<f32> = vec![0.; AUDIO_DATA_SIZE_U8 / 4];
+        #[cfg(target_os = "linux")]
+        let mut applications = HashMap::new();
+        super::video_service::record_audio(&create_format_msg(crate::platform::PA_SAMPLE_RATE, 2));
         while sp.ok() && !RESTARTING.load(Ordering::SeqCst) {
             sp.snapshot(|sps| {
                 sps.send(create_format_msg(crate::platform::PA_SAMPLE_RATE, 2));
                 Ok(())
             })?;
+            adaptive.update(&mut encoder);
+            #[cfg(target_os = "linux")]
+            update_applications(&sp, &mut applications);
             #[cfg(target_os = "linux")]
             if let Ok(data) = stream.next_raw().await {
                 if data.len() == 0 {
@@ -97,8 +185,90 @@ mod pa_impl {
                 hbb_common::sleep(0.1).await;
             }
         }
+        #[cfg(target_os = "linux")]
+        for (_, task) in applications {
+            task.abort();
+        }
         Ok(())
     }
+
+    // One recording task per selected application, besides the mix.
+    #[cfg(target_os = "linux")]
+    fn update_applications(
+        sp: &GenericService,
+        tasks: &mut HashMap<String, tokio::task::JoinHandle<()>>,
+    ) {
+        let selected: std::collections::HashSet<String> =
+            SOURCES.lock().unwrap().values().cloned().collect();
+        tasks.retain(|app, task| {
+            let keep = selected.contains(app) && !task.is_finished();
+            if !keep {
+                task.abort();
+            }
+            keep
+        });
+        for app in selected {
+            if !tasks.contains_key(&app) {
+                log::info!("Start recording audio application {}", app);
+                let task = tokio::spawn(record_application(sp.clone(), app.clone()));
+                tasks.insert(app, task);
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn record_application(sp: GenericService, app: String) {
+        if let Err(err) = record_application_(&sp, &app).await {
+            log::error!("Failed to record audio application {}: {}", app, err);
+        }
+        // The application is gone, back to the mix.
+        SOURCES.lock().unwrap().retain(|_, a| *a != app);
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn record_application_(sp: &GenericService, app: &str) -> ResultType<()> {
+        let mut stream = crate::ipc::connect(1000, "_pa").await?;
+        stream
+            .send(&Data::Config((
+                "audio-application".to_owned(),
+                Some(app.to_owned()),
+            )))
+            .await?;
+        let mut encoder = Encoder::new(crate::platform::PA_SAMPLE_RATE, Stereo, LowDelay)?;
+        let mut adaptive = Adaptive::default();
+        let mut seq: u32 = 0;
+        loop {
+            let data = stream.next_raw().await?;
+            seq += 1;
+            // silence is not sent
+            if data.len() != AUDIO_DATA_SIZE_U8 {
+                continue;
+            }
+            adaptive.update(&mut encoder);
+            let data =
+                unsafe { std::slice::from_raw_parts::<f32>(data.as_ptr() as _, data.len() / 4) };
+            let conns: std::collections::HashSet<i32> = SOURCES
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, a)| *a == app)
+                .map(|(id, _)| *id)
+                .collect();
+            if conns.is_empty() {
+                return Ok(());
+            }
+            if let Ok(data) = encoder.encode_vec_float(data, data.len() * 6) {
+                let mut msg_out = Message::new();
+                msg_out.set_audio_frame(AudioFrame {
+                    data: data.into(),
+                    seq,
+                    capture_time: super::media_time(),
+                    ..Default::default()
+                });
+                sp.send_filtered(msg_out, |id| conns.contains(&id));
+            }
+        }
+    }
 }
 
 #[cfg(not(any(target_os = "linux", target_os = "android")))]
@@ -251,10 +421,9 @@ mod cpal_impl {
             f => bail!("unsupported audio format: {:?}", f),
         };
         stream.play()?;
-        Ok((
-            Box::new(stream),
-            Arc::new(create_format_msg(sample_rate, ch as _)),
-        ))
+        let format = create_format_msg(sample_rate, ch as _);
+        super::video_service::record_audio(&format);
+        Ok((Box::new(stream), Arc::new(format)))
     }
 
     fn build_input_stream<T>(
@@ -278,6 +447,7 @@ mod cpal_impl {
         }
         let device_channel = config.channels();
         let mut encoder = Encoder::new(sample_rate, encode_channel, LowDelay)?;
+        let mut adaptive = Adaptive::default();
         // https://www.opus-codec.org/docs/html_api/group__opusencoder.html#gace941e4ef26ed844879fde342ffbe546
         // https://chromium.googlesource.com/chromium/deps/opus/+/1.1.1/include/opus.h
         let frame_size = sample_rate as usize / 100; // 10 ms
@@ -296,6 +466,7 @@ mod cpal_impl {
                 let buffer: Vec<f32> = data.iter().map(|s| T::to_sample(*s)).collect();
                 let mut lock = INPUT_BUFFER.lock().unwrap();
                 lock.extend(buffer);
+                adaptive.update(&mut encoder);
                 while lock.len() >= rechannel_len {
                     let frame: Vec<f32> = lock.drain(0..rechannel_len).collect();
                     send(
@@ -333,20 +504,29 @@ fn create_format_msg(sample_rate: u32, channels: u16) -> Message {
 // every audio data length is set to 480
 // MAX_AUDIO_ZERO_COUNT=800 is similar as Gate Attack Time 3~5s(Linux) || 6~8s(Windows)
 const MAX_AUDIO_ZERO_COUNT: u16 = 800;
+// 200ms on a slow network, see `AudioParams::short_silence_gate`
+const SHORT_AUDIO_ZERO_COUNT: u16 = 20;
 static mut AUDIO_ZERO_COUNT: u16 = 0;
 
 fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
+    let max_zero_count = if SHORT_SILENCE_GATE.load(Ordering::Relaxed) {
+        SHORT_AUDIO_ZERO_COUNT
+    } else {
+        MAX_AUDIO_ZERO_COUNT
+    };
     if data.iter().filter(|x| **x != 0.).next().is_some() {
         unsafe {
             AUDIO_ZERO_COUNT = 0;
         }
     } else {
         unsafe {
-            if AUDIO_ZERO_COUNT > MAX_AUDIO_ZERO_COUNT {
-                if AUDIO_ZERO_COUNT == MAX_AUDIO_ZERO_COUNT + 1 {
+            if AUDIO_ZERO_COUNT > max_zero_count {
+                if AUDIO_ZERO_COUNT == max_zero_count + 1 {
                     log::debug!("Audio Zero Gate Attack");
                     AUDIO_ZERO_COUNT += 1;
                 }
+                // Counted so that the peer does not take the silence for a loss.
+                SEQ.fetch_add(1, Ordering::Relaxed);
                 return;
             }
             AUDIO_ZERO_COUNT += 1;
@@ -369,8 +549,11 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
                         let mut msg_out = Message::new();
                         msg_out.set_audio_frame(AudioFrame {
                             data: data.into(),
+                            seq: SEQ.fetch_add(1, Ordering::Relaxed),
+                            capture_time: super::media_time(),
                             ..Default::default()
                         });
+                        video_service::record_audio(&msg_out);
                         sp.send(msg_out);
                     }
                     Err(_) => {}
@@ -388,10 +571,28 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
             let mut msg_out = Message::new();
             msg_out.set_audio_frame(AudioFrame {
                 data: data.into(),
+                seq: SEQ.fetch_add(1, Ordering::Relaxed),
+                capture_time: super::media_time(),
                 ..Default::default()
             });
-            sp.send(msg_out);
+            video_service::record_audio(&msg_out);
+            send_mix(sp, msg_out);
         }
         Err(_) => {}
     }
 }
+
+#[cfg(not(target_os = "android"))]
+fn send_mix(sp: &GenericService, msg: Message) {
+    // The sessions listening to an application get its audio from `record_application`.
+    #[cfg(target_os = "linux")]
+    {
+        let apps: std::collections::HashSet<i32> =
+            SOURCES.lock().unwrap().keys().cloned().collect();
+        if !apps.is_empty() {
+            sp.send_filtered(msg, |id| !apps.contains(&id));
+            return;
+        }
+    }
+    sp.send(msg);
+}
@@ -0,0 +1,567 @@
+// Local, append-only audit log, off unless `enable-audit-log` is "Y".
+//
+// Every record is one JSON line under `Config::log_path()/audit`. Records are chained with an
+// HMAC: `hash = hmac-sha256(key, prev || record without hash)`, so editing, inserting or
+// removing a line breaks the chain from that point on, which `--verify-audit` reports.
+// The key is kept in the config directory (`audit.key`), not next to the log, so the chain can
+// not be rebuilt by someone who can only write the logs.
+//
+// `audit.state` next to the key holds the first and the next sequence number and the last hash,
+// which catches removed files at the head and truncated records at the tail.
+//
+// The chain continues across rotated files, rotated files older than the retention are removed.
+// Records are written by a dedicated thread, `append` never blocks on the disk.
+
+use hbb_common::{
+    bail,
+    config::Config,
+    get_time, log,
+    sodiumoxide::crypto::auth::hmacsha256::{self, Key},
+    ResultType,
+};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+const DIR: &str = "audit";
+const CURRENT: &str = "audit.log";
+const KEY_FILE: &str = "audit.key";
+const STATE_FILE: &str = "audit.state";
+const ROTATED_PREFIX: &str = "audit-";
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+const DEFAULT_MAX_SIZE_MB: u64 = 10;
+const DEFAULT_RETENTION_DAYS: u64 = 90;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+pub const OPTION_ENABLE_AUDIT_LOG: &str = "enable-audit-log";
+pub const OPTION_AUDIT_LOG_MAX_SIZE: &str = "audit-log-max-size";
+pub const OPTION_AUDIT_LOG_RETENTION_DAYS: &str = "audit-log-retention-days";
+
+pub const EVENT_CONN_OPEN: &str = "conn_open";
+pub const EVENT_CONN_AUTHORIZE: &str = "conn_authorize";
+pub const EVENT_CONN_CLOSE: &str = "conn_close";
+pub const EVENT_PERMISSION: &str = "permission";
+pub const EVENT_FILE_SEND: &str = "file_send";
+pub const EVENT_FILE_RECEIVE: &str = "file_receive";
+pub const EVENT_FILE_REMOVE: &str = "file_remove";
+pub const EVENT_CLIPBOARD: &str = "clipboard";
+pub const EVENT_CLIPBOARD_BLOCKED: &str = "clipboard_blocked";
+pub const EVENT_CHAT: &str = "chat";
+pub const EVENT_ALARM: &str = "alarm";
+pub const EVENT_SCREENSHOT: &str = "screenshot";
+
+lazy_static::lazy_static! {
+    static ref WRITER: Mutex<Option<Sender<Entry>>> = Default::default();
+}
+
+struct Entry {
+    event: String,
+    conn_id: i32,
+    peer_id: String,
+    data: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    seq: u64,
+    ts: i64,
+    event: String,
+    conn_id: i32,
+    peer_id: String,
+    data: Value,
+    prev: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    hash: String,
+}
+
+impl Record {
+    fn digest(&self, key: &Key) -> String {
+        let mut unhashed = self.clone();
+        unhashed.hash.clear();
+        let mut state = hmacsha256::State::init(&key.0);
+        state.update(self.prev.as_bytes());
+        state.update(
+            serde_json::to_string(&unhashed)
+                .unwrap_or_default()
+                .as_bytes(),
+        );
+        hex::encode(state.finalize())
+    }
+}
+
+// Where the chain is expected to start and end, kept with the key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct State {
+    // seq of the oldest record kept
+    head: u64,
+    // seq of the next record
+    next: u64,
+    // hash of the last record
+    hash: String,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            head: 0,
+            next: 0,
+            hash: GENESIS.to_owned(),
+        }
+    }
+}
+
+impl State {
+    fn load(path: &Path) -> ResultType<Option<Self>> {
+        match fs::read_to_string(path) {
+            Ok(s) => Ok(Some(serde_json::from_str(&s)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn store(&self, path: &Path) -> ResultType<()> {
+        // Written aside and renamed, a crash must not leave a half written state.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+fn key() -> ResultType<Key> {
+    let key = crate::common::load_or_create_secret(KEY_FILE, || hmacsha256::gen_key().0.to_vec())?;
+    match Key::from_slice(&key) {
+        Some(key) => Ok(key),
+        None => bail!("Invalid audit log key"),
+    }
+}
+
+#[inline]
+fn state_path() -> PathBuf {
+    Config::path(STATE_FILE)
+}
+
+struct Writer {
+    dir: PathBuf,
+    key: Key,
+    state_path: PathBuf,
+    state: State,
+    file: File,
+    size: u64,
+    last_prune: Option<Instant>,
+}
+
+impl Writer {
+    fn open(dir: PathBuf, key: Key, state_path: PathBuf) -> ResultType<Self> {
+        fs::create_dir_all(&dir)?;
+        let current = dir.join(CURRENT);
+        // Continue the chain from the newest record, which may be in the last rotated file.
+        let mut tail = last_record(&current)?;
+        if tail.is_none() {
+            if let Some(rotated) = rotated_files(&dir)?.last() {
+                tail = last_record(rotated)?;
+            }
+        }
+        let state = match State::load(&state_path)? {
+            Some(state) => {
+                // Keep the chain of the state, `verify` reports the records that are gone.
+                if tail.as_ref().map(|r| (r.seq + 1, &r.hash)) != Some((state.next, &state.hash))
+                    && state.next > 0
+                {
+                    log::error!(
+                        "Audit log does not end at seq {}, records were removed",
+                        state.next
+                    );
+                }
+                state
+            }
+            None => tail
+                .map(|r| State {
+                    head: 0,
+                    next: r.seq + 1,
+                    hash: r.hash,
+                })
+                .unwrap_or_default(),
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&current)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            dir,
+            key,
+            state_path,
+            state,
+            file,
+            size,
+            last_prune: None,
+        })
+    }
+
+    fn append(&mut self, event: &str, conn_id: i32, peer_id: &str, data: Value) -> ResultType<()> {
+        self.rotate_if_needed()?;
+        let mut record = Record {
+            seq: self.state.next,
+            ts: get_time(),
+            event: event.to_owned(),
+            conn_id,
+            peer_id: peer_id.to_owned(),
+            data,
+            prev: self.state.hash.clone(),
+            hash: "".to_owned(),
+        };
+        record.hash = record.digest(&self.key);
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.flush()?;
+        self.size += line.len() as u64;
+        self.state.next += 1;
+        self.state.hash = record.hash;
+        self.state.store(&self.state_path)
+    }
+
+    fn prune(&mut self) -> ResultType<()> {
+        if !prune(&self.dir) {
+            return Ok(());
+        }
+        // The oldest record left is the new head.
+        let mut files = rotated_files(&self.dir)?;
+        files.push(self.dir.join(CURRENT));
+        self.state.head = self.state.next;
+        for path in files.iter() {
+            if let Some(record) = first_record(path)? {
+                self.state.head = record.seq;
+                break;
+            }
+        }
+        self.state.store(&self.state_path)
+    }
+
+    fn rotate_if_needed(&mut self) -> ResultType<()> {
+        let max_size = Config::get_option(OPTION_AUDIT_LOG_MAX_SIZE)
+            .parse::<u64>()
+            .unwrap_or(DEFAULT_MAX_SIZE_MB)
+            .max(1)
+            * 1024
+            * 1024;
+        if self.size >= max_size {
+            let name = format!(
+                "{}{}.log",
+                ROTATED_PREFIX,
+                chrono::Local::now().format("%Y%m%d%H%M%S%3f")
+            );
+            let current = self.dir.join(CURRENT);
+            fs::rename(&current, self.dir.join(name))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&current)?;
+            self.size = 0;
+            self.last_prune = None;
+        }
+        if self
+            .last_prune
+            .map_or(true, |t| t.elapsed() > PRUNE_INTERVAL)
+        {
+            self.last_prune = Some(Instant::now());
+            self.prune()?;
+        }
+        Ok(())
+    }
+}
+
+// Returns true if any file was removed.
+fn prune(dir: &Path) -> bool {
+    let days = Config::get_option(OPTION_AUDIT_LOG_RETENTION_DAYS)
+        .parse::<u64>()
+        .unwrap_or(DEFAULT_RETENTION_DAYS);
+    if days == 0 {
+        return false;
+    }
+    let mut removed = false;
+    let retention = Duration::from_secs(days * 24 * 3600);
+    for path in rotated_files(dir).unwrap_or_default() {
+        let expired = fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.elapsed().ok())
+            .map_or(false, |elapsed| elapsed > retention);
+        if expired {
+            log::info!("Remove expired audit log {}", path.display());
+            removed |= fs::remove_file(&path).is_ok();
+        }
+    }
+    removed
+}
+
+#[inline]
+pub fn dir() -> PathBuf {
+    Config::log_path().join(DIR)
+}
+
+#[inline]
+pub fn is_enabled() -> bool {
+    Config::get_option(OPTION_ENABLE_AUDIT_LOG) == "Y"
+}
+
+pub fn append(event: &str, conn_id: i32, peer_id: &str, data: Value) {
+    super::event_sink::publish(event, conn_id, peer_id, &data);
+    if !is_enabled() {
+        return;
+    }
+    let entry = Entry {
+        event: event.to_owned(),
+        conn_id,
+        peer_id: peer_id.to_owned(),
+        data,
+    };
+    let mut lock = WRITER.lock().unwrap();
+    let tx = lock.get_or_insert_with(|| {
+        let (tx, rx) = channel();
+        std::thread::spawn(move || write_loop(rx));
+        tx
+    });
+    if tx.send(entry).is_err() {
+        log::error!("Audit log writer is gone");
+        lock.take();
+    }
+}
+
+fn write_loop(rx: Receiver<Entry>) {
+    let mut writer: Option<Writer> = None;
+    while let Ok(entry) = rx.recv() {
+        if writer.is_none() {
+            match key().and_then(|key| Writer::open(dir(), key, state_path())) {
+                Ok(w) => writer = Some(w),
+                Err(err) => {
+                    log::error!("Failed to open audit log: {}", err);
+                    continue;
+                }
+            }
+        }
+        if let Some(w) = writer.as_mut() {
+            if let Err(err) = w.append(&entry.event, entry.conn_id, &entry.peer_id, entry.data) {
+                log::error!("Failed to write audit log: {}", err);
+                // Reopen on next write, the file may have been moved away.
+                writer = None;
+            }
+        }
+    }
+}
+
+// Rotated files sorted from the oldest to the newest.
+fn rotated_files(dir: &Path) -> ResultType<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.file_name().and_then(|n| n.to_str()).map_or(false, |n| {
+                n.starts_with(ROTATED_PREFIX) && n.ends_with(".log")
+            })
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+fn first_record(path: &Path) -> ResultType<Option<Record>> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            return Ok(Some(serde_json::from_str(&line)?));
+        }
+    }
+    Ok(None)
+}
+
+fn last_record(path: &Path) -> ResultType<Option<Record>> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let mut last = None;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            last = Some(line);
+        }
+    }
+    match last {
+        Some(line) => Ok(Some(serde_json::from_str(&line)?)),
+        None => Ok(None),
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct VerifyResult {
+    pub files: usize,
+    pub records: u64,
+    // Sequence number of the oldest record kept, non-zero if older files are pruned.
+    pub first_seq: u64,
+}
+
+/// Check the hash chain of all audit log files in `dir`, against the key and the state of
+/// this machine. Both are in the config directory of the user running this, so it must be the
+/// user of the service which writes the log (root on Linux and macOS, SYSTEM on Windows),
+/// not the one logged in.
+pub fn verify(dir: &Path) -> ResultType<VerifyResult> {
+    let Some(state) = State::load(&state_path())? else {
+        bail!(
+            "No audit log state in {}, run it as the user of the service",
+            state_path().display()
+        );
+    };
+    verify_with(dir, &key()?, &state)
+}
+
+fn verify_with(dir: &Path, key: &Key, state: &State) -> ResultType<VerifyResult> {
+    let mut files = rotated_files(dir)?;
+    let current = dir.join(CURRENT);
+    if current.exists() {
+        files.push(current);
+    }
+    let mut res = VerifyResult::default();
+    let mut prev: Option<(u64, String)> = None;
+    for path in files.iter() {
+        res.files += 1;
+        let reader = BufReader::new(File::open(path)?);
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let at = format!("{}:{}", path.display(), n + 1);
+            let record: Record = match serde_json::from_str(&line) {
+                Ok(r) => r,
+                Err(err) => bail!("{}: malformed record: {}", at, err),
+            };
+            match &prev {
+                Some((seq, hash)) => {
+                    if record.seq != seq + 1 {
+                        bail!("{}: expected seq {}, found {}", at, seq + 1, record.seq);
+                    }
+                    if &record.prev != hash {
+                        bail!(
+                            "{}: chain broken, previous record was changed or removed",
+                            at
+                        );
+                    }
+                }
+                None => {
+                    if record.seq != state.head {
+                        bail!(
+                            "{}: log starts at seq {}, records from seq {} were removed",
+                            at,
+                            record.seq,
+                            state.head
+                        );
+                    }
+                    if record.seq == 0 && record.prev != GENESIS {
+                        bail!("{}: first record does not start the chain", at);
+                    }
+                    res.first_seq = record.seq;
+                }
+            }
+            if record.digest(key) != record.hash {
+                bail!("{}: record was modified", at);
+            }
+            res.records += 1;
+            prev = Some((record.seq, record.hash));
+        }
+    }
+    match prev {
+        Some((seq, hash)) => {
+            if seq + 1 != state.next {
+                bail!(
+                    "log ends at seq {}, but the next record is seq {}, records were removed",
+                    seq,
+                    state.next
+                );
+            }
+            if hash != state.hash {
+                bail!("last record was replaced");
+            }
+        }
+        None => {
+            if state.next != state.head {
+                bail!(
+                    "log is empty, but the next record is seq {}, records were removed",
+                    state.next
+                );
+            }
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_chain() {
+        let dir = std::env::temp_dir().join(format!("audit_test_{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        let log_dir = dir.join("log");
+        let state_path = dir.join(STATE_FILE);
+        let key = hmacsha256::gen_key();
+        let mut w = Writer::open(log_dir.clone(), key.clone(), state_path.clone()).unwrap();
+        for i in 0..3 {
+            w.append(EVENT_CHAT, i, "123", json!({ "len": i })).unwrap();
+        }
+        drop(w);
+        // reopening continues the chain
+        let mut w = Writer::open(log_dir.clone(), key.clone(), state_path.clone()).unwrap();
+        w.append(EVENT_CONN_CLOSE, 3, "123", json!({})).unwrap();
+        drop(w);
+        let state = State::load(&state_path).unwrap().unwrap();
+        assert_eq!((state.head, state.next), (0, 4));
+        assert_eq!(
+            verify_with(&log_dir, &key, &state).unwrap(),
+            VerifyResult {
+                files: 1,
+                records: 4,
+                first_seq: 0
+            }
+        );
+        // a chain rebuilt without the key does not verify
+        assert!(verify_with(&log_dir, &hmacsha256::gen_key(), &state).is_err());
+
+        let current = log_dir.join(CURRENT);
+        let content = fs::read_to_string(&current).unwrap();
+        fs::write(&current, content.replacen("\"len\":1", "\"len\":2", 1)).unwrap();
+        assert!(verify_with(&log_dir, &key, &state).is_err());
+
+        let mut lines: Vec<&str> = content.lines().collect();
+        lines.remove(1);
+        fs::write(&current, lines.join("\n")).unwrap();
+        assert!(verify_with(&log_dir, &key, &state).is_err());
+
+        // removed records at the tail or at the head
+        let lines: Vec<&str> = content.lines().collect();
+        fs::write(&current, lines[..3].join("\n")).unwrap();
+        assert!(verify_with(&log_dir, &key, &state).is_err());
+        fs::write(&current, lines[1..].join("\n")).unwrap();
+        assert!(verify_with(&log_dir, &key, &state).is_err());
+        fs::write(&current, "").unwrap();
+        assert!(verify_with(&log_dir, &key, &state).is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
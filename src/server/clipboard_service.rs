@@ -1,7 +1,12 @@
+use super::service::Reset;
 use super::*;
 pub use crate::common::{
-    check_clipboard, ClipboardContext, CLIPBOARD_INTERVAL as INTERVAL, CLIPBOARD_NAME as NAME,
-    CONTENT,
+    check_clipboard, wait_clipboard_change, ClipboardContext, CLIPBOARD_INTERVAL as INTERVAL,
+    CLIPBOARD_NAME as NAME, CONTENT,
+};
+use crate::{
+    clipboard_filter::{self, Direction},
+    rich_clipboard::{self, ClipboardSide},
 };
 
 struct State {
@@ -21,29 +26,51 @@ impl Default for State {
     }
 }
 
-impl super::service::Reset for State {
+impl Reset for State {
     fn reset(&mut self) {
         *CONTENT.lock().unwrap() = Default::default();
+        rich_clipboard::reset(ClipboardSide::Host);
     }
 }
 
 pub fn new() -> GenericService {
     let svc = EmptyExtraFieldService::new(NAME.to_owned(), true);
-    GenericService::repeat::<State, _, _>(&svc.clone(), INTERVAL, run);
+    GenericService::run(&svc.clone(), run);
     svc.sp
 }
 
-fn run(sp: EmptyExtraFieldService, state: &mut State) -> ResultType<()> {
-    if let Some(msg) = check_clipboard(&mut state.ctx, None) {
-        sp.send(msg);
-    }
-    sp.snapshot(|sps| {
-        let txt = crate::CONTENT.lock().unwrap().clone();
-        if !txt.is_empty() {
-            let msg_out = crate::create_clipboard_msg(txt);
-            sps.send_shared(Arc::new(msg_out));
+// The clipboard is checked when it is subscribed, then on its changes, which are pushed at once.
+fn run(sp: EmptyExtraFieldService) -> ResultType<()> {
+    let mut state = State::default();
+    let mut seq = 0;
+    let mut changed = true;
+    while sp.ok() {
+        if changed {
+            for msg in check_clipboard(&mut state.ctx, None) {
+                sp.send(msg);
+            }
         }
-        Ok(())
-    })?;
+        sp.snapshot(|sps| {
+            let txt = crate::CONTENT.lock().unwrap().clone();
+            // Blocked already when it was read.
+            if !txt.is_empty()
+                && clipboard_filter::check(
+                    Direction::HostToClient,
+                    ClipboardFormat::Text,
+                    txt.as_bytes(),
+                )
+                .is_ok()
+            {
+                let msg_out = crate::create_clipboard_msg(txt);
+                sps.send_shared(Arc::new(msg_out));
+            }
+            if let Some(msg_out) = rich_clipboard::current_format_list(ClipboardSide::Host) {
+                sps.send_shared(Arc::new(msg_out));
+            }
+            Ok(())
+        })?;
+        changed = wait_clipboard_change(&mut seq, Duration::from_millis(INTERVAL));
+    }
+    state.reset();
     Ok(())
 }
@@ -1,3 +1,4 @@
+use super::{audit_log, session_recording};
 use super::{input_service::*, *};
 #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
 use crate::clipboard_file::*;
@@ -16,7 +17,8 @@ use crate::platform::WallPaperRemover;
 use crate::portable_service::client as portable_client;
 use crate::{
     client::{
-        new_voice_call_request, new_voice_call_response, start_audio_thread, MediaData, MediaSender,
+        new_voice_call_request, new_voice_call_response, start_audio_thread_to, MediaData,
+        MediaSender,
     },
     common::{get_default_sound_input, set_sound_input},
     display_service, ipc, privacy_mode, video_service, VERSION,
@@ -60,6 +62,8 @@ use std::{
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use system_shutdown;
 
+#[cfg(target_os = "linux")]
+use crate::platform::linux_virtual_display as virtual_display_manager;
 #[cfg(all(windows, feature = "virtual_display_driver"))]
 use crate::virtual_display_manager;
 #[cfg(not(any(target_os = "ios")))]
@@ -166,6 +170,7 @@ pub enum AuthConnType {
     Remote,
     FileTransfer,
     PortForward,
+    Screenshot,
 }
 
 pub struct Connection {
@@ -180,6 +185,7 @@ pub struct Connection {
     file_transfer: Option<(String, bool)>,
     port_forward_socket: Option<Framed<TcpStream, BytesCodec>>,
     port_forward_address: String,
+    screenshot: Option<i32>,
     tx_to_cm: mpsc::UnboundedSender<ipc::Data>,
     authorized: bool,
     require_2fa: Option<totp_rs::TOTP>,
@@ -221,6 +227,9 @@ pub struct Connection {
     portable: PortableState,
     from_switch: bool,
     voice_call_request_timestamp: Option<NonZeroI64>,
+    voice_call_virtual_microphone: bool,
+    #[cfg(target_os = "linux")]
+    virtual_microphone: Option<crate::platform::linux_virtual_mic::VirtualMicrophone>,
     audio_input_device_before_voice_call: Option<String>,
     options_in_login: Option<OptionMessage>,
     #[cfg(not(any(target_os = "ios")))]
@@ -241,6 +250,7 @@ pub struct Connection {
     delayed_read_dir: Option<(String, bool)>,
     #[cfg(target_os = "macos")]
     retina: Retina,
+    session_recorder: Option<session_recording::SessionRecorder>,
 }
 
 impl ConnInner {
@@ -334,6 +344,7 @@ impl Connection {
             file_transfer: None,
             port_forward_socket: None,
             port_forward_address: "".to_owned(),
+            screenshot: None,
             tx_to_cm,
             authorized: false,
             keyboard: Connection::permission("enable-keyboard"),
@@ -367,6 +378,9 @@ impl Connection {
             from_switch: false,
             audio_sender: None,
             voice_call_request_timestamp: None,
+            voice_call_virtual_microphone: false,
+            #[cfg(target_os = "linux")]
+            virtual_microphone: None,
             audio_input_device_before_voice_call: None,
             options_in_login: None,
             #[cfg(not(any(target_os = "ios")))]
@@ -392,6 +406,7 @@ impl Connection {
             delayed_read_dir: None,
             #[cfg(target_os = "macos")]
             retina: Retina::default(),
+            session_recorder: None,
         };
         let addr = hbb_common::try_into_v4(addr);
         if !conn.on_open(addr).await {
@@ -448,7 +463,7 @@ impl Connection {
                         ipc::Data::Authorize => {
                             conn.require_2fa.take();
                             conn.send_logon_response().await;
-                            if conn.port_forward_socket.is_some() {
+                            if conn.port_forward_socket.is_some() || conn.screenshot.is_some() {
                                 break;
                             }
                         }
@@ -467,6 +482,7 @@ impl Connection {
                             }
                         }
                         ipc::Data::ChatMessage{text} => {
+                            conn.audit(audit_log::EVENT_CHAT, json!({"direction": "host_to_client", "len": text.len()}));
                             let mut misc = Misc::new();
                             misc.set_chat_message(ChatMessage {
                                 text,
@@ -479,6 +495,7 @@ impl Connection {
                         }
                         ipc::Data::SwitchPermission{name, enabled} => {
                             log::info!("Change permission {} -> {}", name, enabled);
+                            conn.audit(audit_log::EVENT_PERMISSION, json!({"name": name, "enabled": enabled}));
                             if &name == "keyboard" {
                                 conn.keyboard = enabled;
                                 conn.send_permission(Permission::Keyboard, enabled).await;
@@ -522,7 +539,15 @@ impl Connection {
                         }
                         #[cfg(any(target_os="windows", target_os="linux", target_os = "macos"))]
                         ipc::Data::ClipboardFile(clip) => {
-                            allow_err!(conn.stream.send(&clip_2_msg(clip)).await);
+                            if let Err(b) = crate::clipboard_filter::check_file(
+                                crate::clipboard_filter::Direction::HostToClient,
+                                &clip,
+                            ) {
+                                b.audit(conn.inner.id, &conn.lr.my_id);
+                                conn.send(b.notice()).await;
+                            } else {
+                                allow_err!(conn.stream.send(&clip_2_msg(clip)).await);
+                            }
                         }
                         ipc::Data::PrivacyModeState((_, state, impl_key)) => {
                             let msg_out = match state {
@@ -658,9 +683,25 @@ impl Connection {
                                     conn.on_close("stop service", false).await;
                                     break;
                                 }
+                                Some(misc::Union::AudioFormat(format)) => {
+                                    if let Some(recorder) = conn.session_recorder.as_mut() {
+                                        recorder.on_audio_format(format);
+                                    }
+                                }
                                 _ => {},
                             }
                         }
+                        Some(message::Union::AudioFrame(frame)) => {
+                            if let Some(recorder) = conn.session_recorder.as_mut() {
+                                recorder.on_audio_frame(&frame.data);
+                            }
+                        }
+                        Some(message::Union::Clipboard(cb)) => {
+                            conn.audit(audit_log::EVENT_CLIPBOARD, json!({"direction": "host_to_client", "len": cb.content.len()}));
+                        }
+                        Some(message::Union::MultiClipboards(multi)) => {
+                            conn.audit(audit_log::EVENT_CLIPBOARD, json!({"direction": "host_to_client", "len": multi.clipboards.iter().map(|c| c.content.len()).sum::<usize>()}));
+                        }
                         Some(message::Union::PeerInfo(_pi)) => {
                             conn.refresh_video_display(None);
                             #[cfg(target_os = "macos")]
@@ -690,9 +731,17 @@ impl Connection {
                             break;
                         }
                     }
-                    conn.file_remove_log_control.on_timer().drain(..).map(|x| conn.send_to_cm(x)).count();
+                    for x in conn.file_remove_log_control.on_timer() {
+                        if let ipc::Data::FileTransferLog((_, log)) = &x {
+                            conn.audit(audit_log::EVENT_FILE_REMOVE, serde_json::from_str(log).unwrap_or_default());
+                        }
+                        conn.send_to_cm(x);
+                    }
                     #[cfg(feature = "gpucodec")]
                     conn.update_supported_encoding();
+                    if let Some(recorder) = conn.session_recorder.as_mut() {
+                        recorder.flush();
+                    }
                 }
                 _ = test_delay_timer.tick() => {
                     if last_recv_time.elapsed() >= SEC30 {
@@ -737,9 +786,21 @@ impl Connection {
         conn.post_conn_audit(json!({
             "action": "close",
         }));
+        conn.audit(audit_log::EVENT_CONN_CLOSE, json!({}));
         if let Some(s) = conn.server.upgrade() {
             let mut s = s.write().unwrap();
             s.remove_connection(&conn.inner);
+            #[cfg(target_os = "linux")]
+            super::audio_service::set_source(conn.inner.id(), "");
+            // The displays are shared again as a whole.
+            for (display, option) in video_service::release_conn_options(conn.inner.id()) {
+                s.set_video_service_opt(Some(display), option, "");
+                s.set_video_service_opt(
+                    Some(display),
+                    video_service::OPTION_REFRESH,
+                    super::service::SERVICE_OPTION_VALUE_TRUE,
+                );
+            }
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
             try_stop_record_cursor_pos();
         }
@@ -965,6 +1026,7 @@ impl Connection {
             "ip": addr.ip(),
             "action": "new",
         }));
+        self.audit(audit_log::EVENT_CONN_OPEN, json!({ "ip": addr.ip() }));
         true
     }
 
@@ -996,6 +1058,11 @@ impl Connection {
         });
     }
 
+    #[inline]
+    fn audit(&self, event: &str, data: Value) {
+        audit_log::append(event, self.inner.id, &self.lr.my_id, data);
+    }
+
     fn post_file_audit(
         &self,
         r#type: FileAuditType,
@@ -1003,6 +1070,17 @@ impl Connection {
         files: Vec<(String, i64)>,
         info: Value,
     ) {
+        self.audit(
+            match r#type {
+                FileAuditType::RemoteSend => audit_log::EVENT_FILE_SEND,
+                FileAuditType::RemoteReceive => audit_log::EVENT_FILE_RECEIVE,
+            },
+            json!({
+                "path": path,
+                "num": files.len(),
+                "files": files.iter().take(10).collect::<Vec<_>>(),
+            }),
+        );
         if self.server_audit_file.is_empty() {
             return;
         }
@@ -1032,6 +1110,13 @@ impl Connection {
     }
 
     pub fn post_alarm_audit(typ: AlarmAuditType, info: Value) {
+        let typ = typ as i8;
+        audit_log::append(
+            audit_log::EVENT_ALARM,
+            0,
+            "",
+            json!({ "typ": typ, "info": info }),
+        );
         let url = crate::get_audit_server(
             Config::get_option("api-server"),
             Config::get_option("custom-rendezvous-server"),
@@ -1043,7 +1128,7 @@ impl Connection {
         let mut v = Value::default();
         v["id"] = json!(Config::get_id());
         v["uuid"] = json!(crate::encode64(hbb_common::get_uuid()));
-        v["typ"] = json!(typ as i8);
+        v["typ"] = json!(typ);
         v["info"] = serde_json::Value::String(info.to_string());
         tokio::spawn(async move {
             allow_err!(Self::post_audit_async(url, v).await);
@@ -1063,11 +1148,53 @@ impl Connection {
             self.send_login_error(crate::client::REQUIRE_2FA).await;
             return;
         }
+        let is_remote = self.file_transfer.is_none()
+            && self.port_forward_socket.is_none()
+            && self.screenshot.is_none();
+        let conn_type_name = if self.file_transfer.is_some() {
+            "file_transfer"
+        } else if self.port_forward_socket.is_some() {
+            "port_forward"
+        } else if self.screenshot.is_some() {
+            "screenshot"
+        } else {
+            "remote"
+        };
+        // Every kind of connection, none may go unrecorded.
+        if session_recording::is_enforced() {
+            match session_recording::SessionRecorder::new(
+                self.inner.id(),
+                &self.lr.my_id,
+                &self.lr.my_name,
+                conn_type_name,
+            ) {
+                Ok(mut recorder) => {
+                    if self.port_forward_socket.is_some() {
+                        recorder.on_port_forward(&self.port_forward_address);
+                    }
+                    self.session_recorder = Some(recorder);
+                }
+                Err(err) => {
+                    log::error!("Failed to start session recording: {}", err);
+                    self.send_login_error("Failed to start session recording")
+                        .await;
+                    return;
+                }
+            }
+        }
         self.authorized = true;
+        if is_remote {
+            video_service::VIDEO_QOS.lock().unwrap().user_downscale(
+                self.inner.id(),
+                get_version_number(&self.lr.version) > get_version_number("1.2.4"),
+            );
+        }
         let (conn_type, auth_conn_type) = if self.file_transfer.is_some() {
             (1, AuthConnType::FileTransfer)
         } else if self.port_forward_socket.is_some() {
             (2, AuthConnType::PortForward)
+        } else if self.screenshot.is_some() {
+            (3, AuthConnType::Screenshot)
         } else {
             (0, AuthConnType::Remote)
         };
@@ -1078,6 +1205,14 @@ impl Connection {
         self.post_conn_audit(
             json!({"peer": ((&self.lr.my_id, &self.lr.my_name)), "type": conn_type}),
         );
+        self.audit(
+            audit_log::EVENT_CONN_AUTHORIZE,
+            json!({ "name": self.lr.my_name, "type": conn_type }),
+        );
+        if let Some(display) = self.screenshot {
+            self.handle_screenshot(display).await;
+            return;
+        }
         #[allow(unused_mut)]
         let mut username = crate::platform::get_active_username();
         let mut res = LoginResponse::new();
@@ -1117,6 +1252,10 @@ impl Connection {
                     platform_additions.insert("headless".into(), json!(true));
                 }
             }
+            let virtual_displays = virtual_display_manager::get_virtual_displays();
+            if !virtual_displays.is_empty() {
+                platform_additions.insert("virtual_displays".into(), json!(&virtual_displays));
+            }
         }
         #[cfg(target_os = "windows")]
         {
@@ -1271,6 +1410,13 @@ impl Connection {
         let mut msg_out = Message::new();
         msg_out.set_login_response(res);
         self.send(msg_out).await;
+        if self.session_recorder.is_some() {
+            let mut misc = Misc::new();
+            misc.set_server_record_status(true);
+            let mut msg_out = Message::new();
+            msg_out.set_misc(misc);
+            self.send(msg_out).await;
+        }
         if let Some(o) = self.options_in_login.take() {
             self.update_options(&o).await;
         }
@@ -1292,8 +1438,60 @@ impl Connection {
         }
     }
 
+    async fn handle_screenshot(&mut self, display: i32) {
+        let displays: Vec<usize> = if display < 0 {
+            match display_service::try_get_displays() {
+                Ok(displays) => (0..displays.len()).collect(),
+                Err(e) => {
+                    log::error!("Failed to get displays for screenshot: {}", e);
+                    vec![]
+                }
+            }
+        } else {
+            vec![display as usize]
+        };
+        if displays.is_empty() {
+            let mut msg_out = Message::new();
+            msg_out.set_screenshot_response(ScreenshotResponse {
+                display,
+                error: "No displays".to_owned(),
+                last: true,
+                ..Default::default()
+            });
+            self.send(msg_out).await;
+            return;
+        }
+        let count = displays.len();
+        for (i, idx) in displays.into_iter().enumerate() {
+            let mut res = ScreenshotResponse {
+                display: idx as _,
+                last: i + 1 == count,
+                ..Default::default()
+            };
+            match tokio::task::spawn_blocking(move || video_service::screenshot(idx)).await {
+                Ok(Ok(data)) => {
+                    if let Some(recorder) = self.session_recorder.as_mut() {
+                        recorder.on_screenshot(idx, &data);
+                    }
+                    res.data = data.into();
+                }
+                Ok(Err(e)) => res.error = e.to_string(),
+                Err(e) => res.error = e.to_string(),
+            }
+            self.audit(
+                audit_log::EVENT_SCREENSHOT,
+                json!({ "display": idx, "success": res.error.is_empty() }),
+            );
+            let mut msg_out = Message::new();
+            msg_out.set_screenshot_response(res);
+            self.send(msg_out).await;
+        }
+    }
+
     fn try_sub_services(&mut self) {
-        let is_remote = self.file_transfer.is_none() && self.port_forward_socket.is_none();
+        let is_remote = self.file_transfer.is_none()
+            && self.port_forward_socket.is_none()
+            && self.screenshot.is_none();
         if is_remote && !self.services_subed {
             self.services_subed = true;
             if let Some(s) = self.server.upgrade() {
@@ -1442,12 +1640,21 @@ impl Connection {
     #[inline]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     fn input_mouse(&self, msg: MouseEvent, conn_id: i32) {
+        // Releasing a button is always passed, it may have been pressed in the shared window.
+        if msg.mask & 0x7 != crate::common::input::MOUSE_TYPE_UP
+            && !video_service::is_input_allowed(self.display_idx, Some((msg.x, msg.y)))
+        {
+            return;
+        }
         self.tx_input.send(MessageInput::Mouse((msg, conn_id))).ok();
     }
 
     #[inline]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     fn input_pointer(&self, msg: PointerDeviceEvent, conn_id: i32) {
+        if !video_service::is_input_allowed(self.display_idx, None) {
+            return;
+        }
         self.tx_input
             .send(MessageInput::Pointer((msg, conn_id)))
             .ok();
@@ -1458,6 +1665,9 @@ impl Connection {
     fn input_key(&self, msg: KeyEvent, press: bool) {
         // to-do: if is the legacy mode, and the key is function key "LockScreen".
         // Switch to the primary display.
+        if (msg.down || msg.press) && !video_service::is_input_allowed(self.display_idx, None) {
+            return;
+        }
         self.tx_input.send(MessageInput::Key((msg, press))).ok();
     }
 
@@ -1643,6 +1853,19 @@ impl Connection {
                         }
                     }
                 }
+                Some(login_request::Union::Screenshot(s)) => {
+                    if !Connection::permission("enable-screenshot")
+                        && Config::get_option("access-mode") != "view"
+                    {
+                        self.send_login_error("No permission of screenshot").await;
+                        sleep(1.).await;
+                        return false;
+                    }
+                    if !self.check_privacy_mode_on().await {
+                        return false;
+                    }
+                    self.screenshot = Some(s.display);
+                }
                 _ => {
                     if !self.check_privacy_mode_on().await {
                         return false;
@@ -1833,9 +2056,12 @@ impl Connection {
                 }
             }
         } else if self.authorized {
-            if self.port_forward_socket.is_some() {
+            if self.port_forward_socket.is_some() || self.screenshot.is_some() {
                 return true;
             }
+            if let Some(recorder) = self.session_recorder.as_mut() {
+                recorder.on_message(&msg);
+            }
             match msg.union {
                 #[allow(unused_mut)]
                 Some(message::Union::MouseEvent(mut me)) => {
@@ -2003,7 +2229,63 @@ impl Connection {
                 {
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     if self.clipboard {
-                        update_clipboard(_cb, None);
+                        self.audit(
+                            audit_log::EVENT_CLIPBOARD,
+                            json!({ "direction": "client_to_host", "len": _cb.content.len() }),
+                        );
+                        if let Some(b) = update_clipboard(_cb, None) {
+                            b.audit(self.inner.id, &self.lr.my_id);
+                            self.send(b.notice()).await;
+                        }
+                    }
+                }
+                Some(message::Union::ClipboardFormatList(_list)) =>
+                {
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    if self.clipboard {
+                        if let Some(msg) = crate::rich_clipboard::handle_format_list(
+                            crate::rich_clipboard::ClipboardSide::Host,
+                            _list,
+                        ) {
+                            self.send(msg).await;
+                        }
+                    }
+                }
+                Some(message::Union::ClipboardRequest(_request)) =>
+                {
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    if self.clipboard {
+                        if let Some(msg) = crate::rich_clipboard::handle_request(
+                            crate::rich_clipboard::ClipboardSide::Host,
+                            _request,
+                        ) {
+                            self.send(msg).await;
+                        }
+                    }
+                }
+                Some(message::Union::MultiClipboards(_multi)) =>
+                {
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    if self.clipboard {
+                        self.audit(
+                            audit_log::EVENT_CLIPBOARD,
+                            json!({
+                                "direction": "client_to_host",
+                                "len": _multi.clipboards.iter().map(|c| c.content.len()).sum::<usize>(),
+                            }),
+                        );
+                        let blocked = tokio::task::spawn_blocking(move || {
+                            crate::rich_clipboard::update(
+                                crate::rich_clipboard::ClipboardSide::Host,
+                                _multi,
+                                &crate::CONTENT,
+                            )
+                        })
+                        .await;
+                        if let Ok(Some(b)) = blocked {
+                            b.audit(self.inner.id, &self.lr.my_id);
+                            self.send(b.notice()).await;
+                        }
                     }
                 }
                 Some(message::Union::Cliprdr(_clip)) =>
@@ -2011,7 +2293,15 @@ impl Connection {
                     #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
                     if let Some(clip) = msg_2_clip(_clip) {
                         log::debug!("got clipfile from client peer");
-                        self.send_to_cm(ipc::Data::ClipboardFile(clip))
+                        if let Err(b) = crate::clipboard_filter::check_file(
+                            crate::clipboard_filter::Direction::ClientToHost,
+                            &clip,
+                        ) {
+                            b.audit(self.inner.id, &self.lr.my_id);
+                            self.send(b.notice()).await;
+                        } else {
+                            self.send_to_cm(ipc::Data::ClipboardFile(clip))
+                        }
                     }
                 }
                 Some(message::Union::FileAction(fa)) => {
@@ -2202,7 +2492,25 @@ impl Connection {
                         let set = displays.set.iter().map(|d| *d as usize).collect::<Vec<_>>();
                         self.capture_displays(&add, &sub, &set).await;
                     }
-                    #[cfg(all(windows, feature = "virtual_display_driver"))]
+                    Some(misc::Union::GetWindowList(display)) => {
+                        self.send_window_list(display).await;
+                    }
+                    Some(misc::Union::SwitchWindow(s)) => {
+                        self.switch_window(s).await;
+                    }
+                    Some(misc::Union::CaptureRegion(r)) => {
+                        self.capture_region(r).await;
+                    }
+                    Some(misc::Union::GetAudioApplications(_)) => {
+                        self.send_audio_applications().await;
+                    }
+                    Some(misc::Union::AudioSource(s)) => {
+                        self.set_audio_source(s);
+                    }
+                    #[cfg(any(
+                        all(windows, feature = "virtual_display_driver"),
+                        target_os = "linux"
+                    ))]
                     Some(misc::Union::ToggleVirtualDisplay(t)) => {
                         self.toggle_virtual_display(t).await;
                     }
@@ -2210,6 +2518,10 @@ impl Connection {
                         self.toggle_privacy_mode(t).await;
                     }
                     Some(misc::Union::ChatMessage(c)) => {
+                        self.audit(
+                            audit_log::EVENT_CHAT,
+                            json!({ "direction": "client_to_host", "len": c.text.len() }),
+                        );
                         self.send_to_cm(ipc::Data::ChatMessage { text: c.text });
                         self.chat_unanswered = true;
                         self.update_auto_disconnect_timer();
@@ -2229,6 +2541,15 @@ impl Connection {
                         self.refresh_video_display(Some(display as usize));
                         self.update_auto_disconnect_timer();
                     }
+                    Some(misc::Union::RecoverVideo(r)) => {
+                        self.server.upgrade().map(|s| {
+                            s.read().unwrap().set_video_service_opt(
+                                Some(r.display as usize),
+                                video_service::OPTION_RECOVER,
+                                &r.last_decoded_pts.to_string(),
+                            );
+                        });
+                    }
                     Some(misc::Union::VideoReceived(_)) => {
                         video_service::notify_video_frame_fetched(
                             self.inner.id,
@@ -2272,10 +2593,24 @@ impl Connection {
                         _ => {}
                     },
                     Some(misc::Union::AudioFormat(format)) => {
-                        if !self.disable_audio {
+                        // The peer asked for a microphone which could not be created, its voice
+                        // is not for the speakers.
+                        #[cfg(target_os = "linux")]
+                        let no_microphone =
+                            self.voice_call_virtual_microphone && self.virtual_microphone.is_none();
+                        #[cfg(not(target_os = "linux"))]
+                        let no_microphone = false;
+                        if !self.disable_audio && !no_microphone {
                             // Drop the audio sender previously.
                             drop(std::mem::replace(&mut self.audio_sender, None));
-                            self.audio_sender = Some(start_audio_thread());
+                            #[cfg(target_os = "linux")]
+                            let device = self
+                                .virtual_microphone
+                                .as_ref()
+                                .map(|m| m.sink().to_owned());
+                            #[cfg(not(target_os = "linux"))]
+                            let device = None;
+                            self.audio_sender = Some(start_audio_thread_to(device));
                             self.audio_sender
                                 .as_ref()
                                 .map(|a| allow_err!(a.send(MediaData::AudioFormat(format))));
@@ -2358,6 +2693,7 @@ impl Connection {
                             NonZeroI64::new(request.req_timestamp)
                                 .unwrap_or(NonZeroI64::new(get_time()).unwrap()),
                         );
+                        self.voice_call_virtual_microphone = request.virtual_microphone;
                         // Notify the connection manager.
                         self.send_to_cm(Data::VoiceCallIncoming);
                     } else {
@@ -2568,7 +2904,170 @@ impl Connection {
         }
     }
 
-    #[cfg(all(windows, feature = "virtual_display_driver"))]
+    async fn send_window_list(&mut self, display: i32) {
+        #[allow(unused_mut)]
+        let mut list = WindowList {
+            display,
+            ..Default::default()
+        };
+        #[cfg(target_os = "linux")]
+        if is_x11() {
+            match scrap::windows() {
+                Ok(windows) => {
+                    list.windows = windows
+                        .into_iter()
+                        .map(|w| WindowInfo {
+                            id: w.id,
+                            title: w.title,
+                            app: w.app,
+                            x: w.x,
+                            y: w.y,
+                            width: w.width as _,
+                            height: w.height as _,
+                            ..Default::default()
+                        })
+                        .collect();
+                }
+                Err(err) => log::error!("Failed to get windows: {}", err),
+            }
+        } else {
+            list.pick_on_peer = true;
+        }
+        let mut misc = Misc::new();
+        misc.set_window_list(list);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+    }
+
+    async fn send_audio_applications(&mut self) {
+        #[allow(unused_mut)]
+        let mut list = AudioApplications::new();
+        #[cfg(target_os = "linux")]
+        if self.audio_enabled() {
+            match super::audio_service::get_applications().await {
+                Ok(applications) => list.applications = applications,
+                Err(err) => log::error!("Failed to get audio applications: {}", err),
+            }
+        }
+        let mut misc = Misc::new();
+        misc.set_audio_applications(list);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+    }
+
+    // Only for this session, the other ones keep their own source.
+    fn set_audio_source(&mut self, s: AudioSource) {
+        log::info!("Audio source of #{}: {:?}", self.inner.id(), s.application);
+        #[cfg(target_os = "linux")]
+        super::audio_service::set_source(self.inner.id(), &s.application);
+        #[cfg(not(target_os = "linux"))]
+        if !s.application.is_empty() {
+            log::error!("Audio of an application is not supported");
+        }
+    }
+
+    // Share one window instead of the display, for all peers watching the display. Only the
+    // connection which shared it can switch it again, until it is closed.
+    async fn switch_window(&mut self, s: SwitchWindow) {
+        log::info!("Switch display {} to window {}", s.display, s.window);
+        if !video_service::set_conn_option(
+            s.display as usize,
+            video_service::OPTION_WINDOW,
+            self.inner.id(),
+            if s.window == 0 { "" } else { "1" },
+        ) {
+            log::info!(
+                "The window of display {} is shared by another connection",
+                s.display
+            );
+            let mut msg_out = Message::new();
+            msg_out.set_message_box(MessageBox {
+                msgtype: "nook-nocancel-hasclose".to_owned(),
+                title: "Prompt".to_owned(),
+                text: "window_shared_by_other_tip".to_owned(),
+                link: "".to_owned(),
+                ..Default::default()
+            });
+            self.send(msg_out).await;
+            return;
+        }
+        #[cfg(target_os = "linux")]
+        if !is_x11() {
+            // The window is picked in the screencast dialog on this side.
+            scrap::wayland::pipewire::set_window_source(s.window != 0);
+        }
+        let window = if s.window == 0 {
+            "".to_owned()
+        } else {
+            s.window.to_string()
+        };
+        if let Some(server) = self.server.upgrade() {
+            let lock = server.read().unwrap();
+            let display = Some(s.display as usize);
+            lock.set_video_service_opt(display, video_service::OPTION_WINDOW, &window);
+            lock.set_video_service_opt(
+                display,
+                video_service::OPTION_REFRESH,
+                super::service::SERVICE_OPTION_VALUE_TRUE,
+            );
+        }
+    }
+
+    // Like `switch_window`, the region is the same for all peers watching the display.
+    async fn capture_region(&mut self, r: CaptureRegion) {
+        log::info!(
+            "Capture region {}x{}+{}+{} of display {}, follow cursor: {}",
+            r.width,
+            r.height,
+            r.x,
+            r.y,
+            r.display,
+            r.follow_cursor
+        );
+        let region = if r.width <= 0 || r.height <= 0 {
+            "".to_owned()
+        } else {
+            format!(
+                "{},{},{},{},{}",
+                r.x, r.y, r.width, r.height, r.follow_cursor as i32
+            )
+        };
+        if !video_service::set_conn_option(
+            r.display as usize,
+            video_service::OPTION_REGION,
+            self.inner.id(),
+            &region,
+        ) {
+            log::info!(
+                "The region of display {} is set by another connection",
+                r.display
+            );
+            let mut msg_out = Message::new();
+            msg_out.set_message_box(MessageBox {
+                msgtype: "nook-nocancel-hasclose".to_owned(),
+                title: "Prompt".to_owned(),
+                text: "region_set_by_other_tip".to_owned(),
+                link: "".to_owned(),
+                ..Default::default()
+            });
+            self.send(msg_out).await;
+            return;
+        }
+        if let Some(server) = self.server.upgrade() {
+            let lock = server.read().unwrap();
+            let display = Some(r.display as usize);
+            lock.set_video_service_opt(display, video_service::OPTION_REGION, &region);
+            lock.set_video_service_opt(
+                display,
+                video_service::OPTION_REFRESH,
+                super::service::SERVICE_OPTION_VALUE_TRUE,
+            );
+        }
+    }
+
+    #[cfg(any(all(windows, feature = "virtual_display_driver"), target_os = "linux"))]
     async fn toggle_virtual_display(&mut self, t: ToggleVirtualDisplay) {
         let make_msg = |text: String| {
             let mut msg_out = Message::new();
@@ -2585,8 +3084,11 @@ impl Connection {
 
         if t.on {
             if !virtual_display_manager::is_virtual_display_supported() {
-                self.send(make_msg("idd_not_support_under_win10_2004_tip".to_string()))
-                    .await;
+                #[cfg(windows)]
+                let tip = "idd_not_support_under_win10_2004_tip";
+                #[cfg(target_os = "linux")]
+                let tip = "virtual_display_not_support_linux_tip";
+                self.send(make_msg(tip.to_string())).await;
             } else {
                 if let Err(e) =
                     virtual_display_manager::plug_in_index_modes(t.display as _, Vec::new())
@@ -2630,7 +3132,10 @@ impl Connection {
             if let Ok(displays) = display_service::try_get_displays() {
                 if let Some(display) = displays.get(self.display_idx) {
                     let name = display.name();
-                    #[cfg(all(windows, feature = "virtual_display_driver"))]
+                    #[cfg(any(
+                        all(windows, feature = "virtual_display_driver"),
+                        target_os = "linux"
+                    ))]
                     if let Some(_ok) =
                         virtual_display_manager::change_resolution_if_is_virtual_display(
                             &name,
@@ -2674,6 +3179,26 @@ impl Connection {
                 if let Some(device) = default_sound_device {
                     set_sound_input(device);
                 }
+                #[cfg(target_os = "linux")]
+                if self.voice_call_virtual_microphone {
+                    use crate::platform::linux_virtual_mic::VirtualMicrophone;
+                    match VirtualMicrophone::new(self.inner.id()) {
+                        Ok(mic) => self.virtual_microphone = Some(mic),
+                        Err(err) => {
+                            log::error!("Failed to create the virtual microphone: {}", err);
+                            // The voice is not played on the speakers instead, see `AudioFormat`.
+                            let mut msg_out = Message::new();
+                            msg_out.set_message_box(MessageBox {
+                                msgtype: "nook-nocancel-hasclose".to_owned(),
+                                title: "Voice call".to_owned(),
+                                text: "virtual_microphone_failed_tip".to_owned(),
+                                link: "".to_owned(),
+                                ..Default::default()
+                            });
+                            self.send(msg_out).await;
+                        }
+                    }
+                }
                 self.send_to_cm(Data::StartVoiceCall);
             } else {
                 self.send_to_cm(Data::CloseVoiceCall("".to_owned()));
@@ -2691,6 +3216,13 @@ impl Connection {
         {
             set_sound_input(sound_input);
         }
+        #[cfg(target_os = "linux")]
+        if self.virtual_microphone.is_some() {
+            // Stop playing to the sink before it is removed.
+            self.audio_sender.take();
+            self.virtual_microphone.take();
+        }
+        self.voice_call_virtual_microphone = false;
         // Notify the connection manager that the voice call has been closed.
         self.send_to_cm(Data::CloseVoiceCall("".to_owned()));
     }
@@ -2724,6 +3256,22 @@ impl Connection {
         if let Some(q) = o.supported_decoding.clone().take() {
             scrap::codec::Encoder::update(scrap::codec::EncodingUpdate::Update(self.inner.id(), q));
         }
+        if let Ok(q) = o.screen_content.enum_value() {
+            if q != BoolOption::NotSet {
+                video_service::VIDEO_QOS
+                    .lock()
+                    .unwrap()
+                    .user_screen_content(self.inner.id(), q == BoolOption::Yes);
+            }
+        }
+        if let Ok(q) = o.audio_quality.enum_value() {
+            if q != AudioQuality::AudioQualityNotSet {
+                video_service::VIDEO_QOS
+                    .lock()
+                    .unwrap()
+                    .user_audio_quality(self.inner.id(), q);
+            }
+        }
         if let Ok(q) = o.lock_after_session_end.enum_value() {
             if q != BoolOption::NotSet {
                 self.lock_after_session_end = q == BoolOption::Yes;
@@ -2977,6 +3525,7 @@ impl Connection {
         let data = ipc::Data::Close;
         self.tx_to_cm.send(data).ok();
         self.port_forward_socket.take();
+        self.session_recorder.take();
     }
 
     // The `reason` should be consistent with `check_if_retry` if not empty
@@ -3648,7 +4197,7 @@ mod raii {
                 }
                 #[cfg(not(any(target_os = "android", target_os = "ios")))]
                 display_service::reset_resolutions();
-                #[cfg(all(windows, feature = "virtual_display_driver"))]
+                #[cfg(any(all(windows, feature = "virtual_display_driver"), target_os = "linux"))]
                 let _ = virtual_display_manager::reset_all();
             }
             Self::check_wake_lock();
@@ -168,6 +168,18 @@ fn displays_to_msg(displays: Vec<DisplayInfo>) -> Message {
             pi.platform_additions = serde_json::to_string(&platform_additions).unwrap_or("".into());
         }
     }
+    #[cfg(target_os = "linux")]
+    {
+        let virtual_displays = crate::platform::linux_virtual_display::get_virtual_displays();
+        if !virtual_displays.is_empty() {
+            let mut platform_additions = serde_json::Map::new();
+            platform_additions.insert(
+                "virtual_displays".into(),
+                serde_json::json!(&virtual_displays),
+            );
+            pi.platform_additions = serde_json::to_string(&platform_additions).unwrap_or("".into());
+        }
+    }
 
     // current_display should not be used in server.
     // It is set to 0 for compatibility with old clients.
@@ -226,7 +238,10 @@ pub(super) fn get_original_resolution(
 ) -> MessageField<Resolution> {
     #[cfg(all(windows, feature = "virtual_display_driver"))]
     let is_virtual_display = crate::virtual_display_manager::is_virtual_display(&display_name);
-    #[cfg(not(all(windows, feature = "virtual_display_driver")))]
+    #[cfg(target_os = "linux")]
+    let is_virtual_display =
+        crate::platform::linux_virtual_display::is_virtual_display(&display_name);
+    #[cfg(not(any(all(windows, feature = "virtual_display_driver"), target_os = "linux")))]
     let is_virtual_display = false;
     Some(if is_virtual_display {
         Resolution {
@@ -0,0 +1,636 @@
+// Export of audit and connection events to external collectors (SIEM).
+//
+// Sinks are configured with the `event-sinks` option, a comma separated list of:
+//   syslog+udp://host:514, syslog+tcp://host:601, syslog+unix:///dev/log  (RFC5424)
+//   journald                                                           (systemd-journald native protocol)
+//   otlp+http://host:4318, otlp+https://host:4318                      (OTLP/HTTP json logs)
+//
+// Events are handed over to a dedicated thread, so a slow collector never blocks a connection.
+// The queue is bounded, the events that do not fit are dropped and counted in the log.
+
+use hbb_common::{
+    bail,
+    chrono::{self, TimeZone},
+    config::Config,
+    get_time, log, ResultType,
+};
+use serde_derive::Serialize;
+use serde_json::{json, Value};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::{
+    io::Write,
+    net::{TcpStream, ToSocketAddrs, UdpSocket},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+pub const OPTION_EVENT_SINKS: &str = "event-sinks";
+// Bump only on incompatible changes, collectors can rely on the field names below.
+pub const SCHEMA_VERSION: u32 = 1;
+const APP_NAME_MAX_LEN: usize = 48;
+const SYSLOG_UDP_PORT: u16 = 514;
+// RFC 6587, syslog over TCP
+const SYSLOG_TCP_PORT: u16 = 601;
+const SEND_TIMEOUT: Duration = Duration::from_secs(3);
+const QUEUE_SIZE: usize = 1024;
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+#[cfg(target_os = "linux")]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+lazy_static::lazy_static! {
+    static ref SENDER: Mutex<Option<mpsc::SyncSender<Event>>> = Default::default();
+}
+// Events dropped because the queue was full, since the last one sent.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// The event schema shared by all sinks.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Event {
+    pub schema: u32,
+    /// Unix time in milliseconds.
+    pub time: i64,
+    /// Id of this device.
+    pub host_id: String,
+    /// One of the `audit_log::EVENT_*` names.
+    pub event: String,
+    pub conn_id: i32,
+    /// Id of the controlling peer, empty if not known yet.
+    pub peer_id: String,
+    pub data: Value,
+}
+
+impl Event {
+    pub fn new(event: &str, conn_id: i32, peer_id: &str, data: Value) -> Self {
+        Self {
+            schema: SCHEMA_VERSION,
+            time: get_time(),
+            host_id: Config::get_id(),
+            event: event.to_owned(),
+            conn_id,
+            peer_id: peer_id.to_owned(),
+            data,
+        }
+    }
+
+    fn severity(&self) -> Severity {
+        if self.event == super::audit_log::EVENT_ALARM {
+            Severity::Warning
+        } else {
+            Severity::Notice
+        }
+    }
+
+    fn message(&self) -> String {
+        if self.peer_id.is_empty() {
+            format!("{} conn_id={}", self.event, self.conn_id)
+        } else {
+            format!(
+                "{} conn_id={} peer_id={}",
+                self.event, self.conn_id, self.peer_id
+            )
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Severity {
+    Warning = 4,
+    Notice = 5,
+}
+
+impl Severity {
+    fn otlp(&self) -> (i32, &'static str) {
+        match self {
+            Severity::Warning => (13, "WARN"),
+            Severity::Notice => (10, "INFO2"),
+        }
+    }
+}
+
+pub trait EventSink: Send {
+    fn name(&self) -> String;
+    fn send(&mut self, event: &Event) -> ResultType<()>;
+}
+
+/// Queue `event` for all configured sinks.
+pub fn publish(event: &str, conn_id: i32, peer_id: &str, data: &Value) {
+    if Config::get_option(OPTION_EVENT_SINKS).is_empty() {
+        return;
+    }
+    let mut event = Event::new(event, conn_id, peer_id, data.clone());
+    let mut lock = SENDER.lock().unwrap();
+    if let Some(tx) = lock.as_ref() {
+        match tx.try_send(event) {
+            Ok(()) => return,
+            Err(mpsc::TrySendError::Full(_)) => {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            Err(mpsc::TrySendError::Disconnected(e)) => event = e,
+        }
+    }
+    let (tx, rx) = mpsc::sync_channel(QUEUE_SIZE);
+    std::thread::spawn(move || run(rx));
+    tx.try_send(event).ok();
+    *lock = Some(tx);
+}
+
+fn run(rx: mpsc::Receiver<Event>) {
+    let mut config = "".to_owned();
+    let mut sinks: Vec<Box<dyn EventSink>> = vec![];
+    while let Ok(event) = rx.recv() {
+        let dropped = DROPPED.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            log::warn!("{} events dropped, the event sinks are too slow", dropped);
+        }
+        let option = Config::get_option(OPTION_EVENT_SINKS);
+        if option != config {
+            sinks = parse_sinks(&option);
+            config = option;
+        }
+        for sink in sinks.iter_mut() {
+            if let Err(err) = sink.send(&event) {
+                log::error!("Failed to send event to {}: {}", sink.name(), err);
+            }
+        }
+    }
+}
+
+pub fn parse_sinks(option: &str) -> Vec<Box<dyn EventSink>> {
+    option
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| match new_sink(s) {
+            Ok(sink) => Some(sink),
+            Err(err) => {
+                log::error!("Invalid event sink {}: {}", s, err);
+                None
+            }
+        })
+        .collect()
+}
+
+fn new_sink(s: &str) -> ResultType<Box<dyn EventSink>> {
+    if s == "journald" {
+        #[cfg(target_os = "linux")]
+        return Ok(Box::new(JournaldSink::new(JOURNALD_SOCKET)?));
+        #[cfg(not(target_os = "linux"))]
+        bail!("journald is only available on linux");
+    }
+    let url = url::Url::parse(s)?;
+    let host_port = |default_port: u16| -> ResultType<String> {
+        let Some(host) = url.host_str() else {
+            bail!("host is required");
+        };
+        Ok(format!("{}:{}", host, url.port().unwrap_or(default_port)))
+    };
+    match url.scheme() {
+        "syslog+udp" => {
+            let addr = host_port(SYSLOG_UDP_PORT)?;
+            Ok(Box::new(SyslogSink::new(SyslogTransport::Udp(addr))))
+        }
+        "syslog+tcp" => {
+            let addr = host_port(SYSLOG_TCP_PORT)?;
+            Ok(Box::new(SyslogSink::new(SyslogTransport::Tcp(addr))))
+        }
+        #[cfg(unix)]
+        "syslog+unix" => Ok(Box::new(SyslogSink::new(SyslogTransport::Unix(
+            url.path().to_owned(),
+        )))),
+        "otlp+http" | "otlp+https" => {
+            let endpoint = s.replacen("otlp+", "", 1);
+            Ok(Box::new(OtlpSink::new(&endpoint)))
+        }
+        scheme => bail!("unsupported scheme {}", scheme),
+    }
+}
+
+pub enum SyslogTransport {
+    Udp(String),
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(String),
+}
+
+pub struct SyslogSink {
+    transport: SyslogTransport,
+    udp: Option<UdpSocket>,
+    tcp: Option<TcpStream>,
+    // No connection attempt before `reconnect_at`, the delay doubles on every failure.
+    reconnect_at: Option<Instant>,
+    reconnect_delay: Duration,
+    #[cfg(unix)]
+    unix: Option<UnixDatagram>,
+    hostname: String,
+}
+
+impl SyslogSink {
+    // Facility: security/authorization messages (10)
+    const FACILITY: u8 = 10;
+
+    pub fn new(transport: SyslogTransport) -> Self {
+        Self {
+            transport,
+            udp: None,
+            tcp: None,
+            reconnect_at: None,
+            reconnect_delay: Duration::ZERO,
+            #[cfg(unix)]
+            unix: None,
+            hostname: whoami::hostname().replace(' ', "-"),
+        }
+    }
+
+    fn connect(&mut self, addr: &str) -> ResultType<TcpStream> {
+        if let Some(at) = self.reconnect_at {
+            if Instant::now() < at {
+                bail!("not connected, retry in {:?}", at - Instant::now());
+            }
+        }
+        let mut res = Err(std::io::Error::from(std::io::ErrorKind::AddrNotAvailable));
+        for addr in addr.to_socket_addrs()? {
+            res = TcpStream::connect_timeout(&addr, SEND_TIMEOUT);
+            if res.is_ok() {
+                break;
+            }
+        }
+        match res {
+            Ok(stream) => {
+                stream.set_write_timeout(Some(SEND_TIMEOUT))?;
+                self.reconnect_at = None;
+                self.reconnect_delay = Duration::ZERO;
+                Ok(stream)
+            }
+            Err(err) => {
+                self.reconnect_delay =
+                    (self.reconnect_delay * 2).clamp(MIN_RECONNECT_DELAY, MAX_RECONNECT_DELAY);
+                self.reconnect_at = Some(Instant::now() + self.reconnect_delay);
+                Err(err.into())
+            }
+        }
+    }
+
+    // RFC5424: <PRI>VERSION TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG
+    pub fn format(&self, event: &Event) -> String {
+        let pri = Self::FACILITY * 8 + event.severity() as u8;
+        let time = chrono::Utc
+            .timestamp_millis_opt(event.time)
+            .single()
+            .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+            .unwrap_or("-".to_owned());
+        let mut app_name: String = crate::get_app_name().replace(' ', "-");
+        app_name.truncate(APP_NAME_MAX_LEN);
+        let sd = format!(
+            "[event@{} schema=\"{}\" conn_id=\"{}\" peer_id=\"{}\" host_id=\"{}\"]",
+            // private enterprise number 32473 is reserved for documentation and examples
+            32473,
+            event.schema,
+            event.conn_id,
+            escape_sd(&event.peer_id),
+            escape_sd(&event.host_id),
+        );
+        format!(
+            "<{}>1 {} {} {} {} {} {} {}",
+            pri,
+            time,
+            self.hostname,
+            app_name,
+            std::process::id(),
+            event.event,
+            sd,
+            serde_json::to_string(event).unwrap_or_default()
+        )
+    }
+}
+
+fn escape_sd(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+impl EventSink for SyslogSink {
+    fn name(&self) -> String {
+        match &self.transport {
+            SyslogTransport::Udp(addr) => format!("syslog+udp://{}", addr),
+            SyslogTransport::Tcp(addr) => format!("syslog+tcp://{}", addr),
+            #[cfg(unix)]
+            SyslogTransport::Unix(path) => format!("syslog+unix://{}", path),
+        }
+    }
+
+    fn send(&mut self, event: &Event) -> ResultType<()> {
+        let msg = self.format(event);
+        match &self.transport {
+            SyslogTransport::Udp(addr) => {
+                if self.udp.is_none() {
+                    self.udp = Some(UdpSocket::bind("0.0.0.0:0")?);
+                }
+                if let Some(socket) = self.udp.as_ref() {
+                    socket.send_to(msg.as_bytes(), addr.as_str())?;
+                }
+            }
+            SyslogTransport::Tcp(addr) => {
+                if self.tcp.is_none() {
+                    let addr = addr.clone();
+                    self.tcp = Some(self.connect(&addr)?);
+                }
+                // RFC6587 octet counting
+                let frame = format!("{} {}", msg.len(), msg);
+                if let Some(stream) = self.tcp.as_mut() {
+                    if let Err(err) = stream.write_all(frame.as_bytes()) {
+                        // Reconnect on next event.
+                        self.tcp = None;
+                        return Err(err.into());
+                    }
+                }
+            }
+            #[cfg(unix)]
+            SyslogTransport::Unix(path) => {
+                if self.unix.is_none() {
+                    self.unix = Some(UnixDatagram::unbound()?);
+                }
+                if let Some(socket) = self.unix.as_ref() {
+                    socket.send_to(msg.as_bytes(), path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub struct JournaldSink {
+    path: String,
+    socket: UnixDatagram,
+}
+
+#[cfg(target_os = "linux")]
+impl JournaldSink {
+    pub fn new(path: &str) -> ResultType<Self> {
+        Ok(Self {
+            path: path.to_owned(),
+            socket: UnixDatagram::unbound()?,
+        })
+    }
+
+    // https://systemd.io/JOURNAL_NATIVE_PROTOCOL/
+    pub fn format(event: &Event) -> Vec<u8> {
+        let mut buf = vec![];
+        let mut field = |key: &str, value: &str| {
+            buf.extend_from_slice(key.as_bytes());
+            if value.contains('\n') {
+                buf.push(b'\n');
+                buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            } else {
+                buf.push(b'=');
+            }
+            buf.extend_from_slice(value.as_bytes());
+            buf.push(b'\n');
+        };
+        field("MESSAGE", &event.message());
+        field("PRIORITY", &(event.severity() as u8).to_string());
+        field("SYSLOG_IDENTIFIER", &crate::get_app_name());
+        field("RUSTDESK_SCHEMA", &event.schema.to_string());
+        field("RUSTDESK_EVENT", &event.event);
+        field("RUSTDESK_HOST_ID", &event.host_id);
+        field("RUSTDESK_CONN_ID", &event.conn_id.to_string());
+        field("RUSTDESK_PEER_ID", &event.peer_id);
+        field("RUSTDESK_TIME", &event.time.to_string());
+        field("RUSTDESK_DATA", &event.data.to_string());
+        buf
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl EventSink for JournaldSink {
+    fn name(&self) -> String {
+        "journald".to_owned()
+    }
+
+    fn send(&mut self, event: &Event) -> ResultType<()> {
+        self.socket.send_to(&Self::format(event), &self.path)?;
+        Ok(())
+    }
+}
+
+pub struct OtlpSink {
+    url: String,
+}
+
+impl OtlpSink {
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            url: format!("{}/v1/logs", endpoint.trim_end_matches('/')),
+        }
+    }
+
+    pub fn format(event: &Event) -> Value {
+        let attr = |key: &str, value: Value| json!({ "key": key, "value": value });
+        let (severity_number, severity_text) = event.severity().otlp();
+        json!({
+            "resourceLogs": [{
+                "resource": {
+                    "attributes": [
+                        attr("service.name", json!({ "stringValue": crate::get_app_name() })),
+                        attr("service.instance.id", json!({ "stringValue": event.host_id })),
+                    ]
+                },
+                "scopeLogs": [{
+                    "scope": { "name": "rustdesk.audit", "version": SCHEMA_VERSION.to_string() },
+                    "logRecords": [{
+                        "timeUnixNano": (event.time as u64 * 1_000_000).to_string(),
+                        "severityNumber": severity_number,
+                        "severityText": severity_text,
+                        "body": { "stringValue": event.message() },
+                        "attributes": [
+                            attr("event.name", json!({ "stringValue": event.event })),
+                            attr("rustdesk.conn_id", json!({ "intValue": event.conn_id.to_string() })),
+                            attr("rustdesk.peer_id", json!({ "stringValue": event.peer_id })),
+                            attr("rustdesk.data", json!({ "stringValue": event.data.to_string() })),
+                        ],
+                    }]
+                }]
+            }]
+        })
+    }
+}
+
+impl EventSink for OtlpSink {
+    fn name(&self) -> String {
+        self.url.clone()
+    }
+
+    fn send(&mut self, event: &Event) -> ResultType<()> {
+        crate::post_request_sync(self.url.clone(), Self::format(event).to_string(), "")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Read},
+        net::TcpListener,
+    };
+
+    // Local stand-ins for the collectors, each returns the raw payloads it received.
+    struct Collector;
+
+    impl Collector {
+        fn udp() -> (String, UdpSocket) {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket.set_read_timeout(Some(SEND_TIMEOUT)).unwrap();
+            (socket.local_addr().unwrap().to_string(), socket)
+        }
+
+        fn recv_udp(socket: &UdpSocket) -> String {
+            let mut buf = [0u8; 4096];
+            let (n, _) = socket.recv_from(&mut buf).unwrap();
+            String::from_utf8_lossy(&buf[..n]).to_string()
+        }
+
+        fn tcp() -> (String, TcpListener) {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            (listener.local_addr().unwrap().to_string(), listener)
+        }
+
+        // Answer one request with `status`, the body of the request is returned by the thread.
+        fn http(status: &'static str) -> (String, std::thread::JoinHandle<String>) {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let endpoint = format!("http://{}", listener.local_addr().unwrap());
+            let handle = std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let (mut line, mut len) = (String::new(), 0);
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            len = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0u8; len];
+                reader.read_exact(&mut body).unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+                String::from_utf8_lossy(&body).to_string()
+            });
+            (endpoint, handle)
+        }
+
+        // Read one octet-counted frame.
+        fn recv_tcp(listener: &TcpListener) -> String {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut len = vec![];
+            reader.read_until(b' ', &mut len).unwrap();
+            let len: usize = String::from_utf8_lossy(&len).trim().parse().unwrap();
+            let mut buf = vec![0u8; len];
+            reader.read_exact(&mut buf).unwrap();
+            String::from_utf8_lossy(&buf).to_string()
+        }
+    }
+
+    fn event() -> Event {
+        Event {
+            schema: SCHEMA_VERSION,
+            time: 1_700_000_000_123,
+            host_id: "123456789".to_owned(),
+            event: "conn_open".to_owned(),
+            conn_id: 7,
+            peer_id: "987654321".to_owned(),
+            data: json!({ "ip": "127.0.0.1" }),
+        }
+    }
+
+    #[test]
+    fn test_syslog() {
+        let (addr, socket) = Collector::udp();
+        let mut sink = SyslogSink::new(SyslogTransport::Udp(addr));
+        sink.send(&event()).unwrap();
+        let msg = Collector::recv_udp(&socket);
+        assert!(msg.starts_with("<85>1 2023-11-14T22:13:20.123Z "));
+        assert!(msg.contains(" conn_open [event@32473 schema=\"1\" conn_id=\"7\""));
+        assert!(msg.ends_with(&serde_json::to_string(&event()).unwrap()));
+
+        let (addr, listener) = Collector::tcp();
+        let mut sink = SyslogSink::new(SyslogTransport::Tcp(addr));
+        sink.send(&event()).unwrap();
+        assert_eq!(Collector::recv_tcp(&listener), sink.format(&event()));
+
+        // Not retried before the back off.
+        let (addr, listener) = Collector::tcp();
+        drop(listener);
+        let mut sink = SyslogSink::new(SyslogTransport::Tcp(addr));
+        assert!(sink.send(&event()).is_err());
+        assert_eq!(sink.reconnect_delay, MIN_RECONNECT_DELAY);
+        assert!(sink.send(&event()).is_err());
+        assert_eq!(sink.reconnect_delay, MIN_RECONNECT_DELAY);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_journald() {
+        let path = std::env::temp_dir().join(format!("journal_test_{}", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let collector = UnixDatagram::bind(&path).unwrap();
+        let mut sink = JournaldSink::new(path.to_str().unwrap()).unwrap();
+        sink.send(&event()).unwrap();
+        let mut buf = [0u8; 4096];
+        let n = collector.recv(&mut buf).unwrap();
+        let fields = String::from_utf8_lossy(&buf[..n]).to_string();
+        assert!(fields.contains("PRIORITY=5\n"));
+        assert!(fields.contains("RUSTDESK_EVENT=conn_open\n"));
+        assert!(fields.contains("RUSTDESK_PEER_ID=987654321\n"));
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_otlp() {
+        let v = OtlpSink::format(&event());
+        let record = &v["resourceLogs"][0]["scopeLogs"][0]["logRecords"][0];
+        assert_eq!(record["timeUnixNano"], "1700000000123000000");
+        assert_eq!(record["attributes"][0]["value"]["stringValue"], "conn_open");
+        assert_eq!(
+            OtlpSink::new("http://127.0.0.1:4318/").name(),
+            "http://127.0.0.1:4318/v1/logs"
+        );
+
+        let (endpoint, collector) = Collector::http("200 OK");
+        let mut sink = new_sink(&format!("otlp+{}", endpoint)).unwrap();
+        sink.send(&event()).unwrap();
+        let body: Value = serde_json::from_str(&collector.join().unwrap()).unwrap();
+        assert_eq!(body, OtlpSink::format(&event()));
+        // Refused by the collector.
+        let (endpoint, collector) = Collector::http("500 Internal Server Error");
+        let mut sink = OtlpSink::new(&endpoint);
+        assert!(sink.send(&event()).is_err());
+        collector.join().unwrap();
+    }
+
+    #[test]
+    fn test_parse_sinks() {
+        let sinks = parse_sinks("syslog+udp://127.0.0.1, otlp+http://127.0.0.1:4318, foo://x");
+        assert_eq!(sinks.len(), 2);
+        assert_eq!(sinks[0].name(), "syslog+udp://127.0.0.1:514");
+        let sinks = parse_sinks("syslog+tcp://127.0.0.1");
+        assert_eq!(sinks[0].name(), "syslog+tcp://127.0.0.1:601");
+    }
+}
@@ -241,6 +241,34 @@ impl<T: Subscriber + From<ConnInner>> ServiceTmpl<T> {
         conn_ids
     }
 
+    /// Sends the frame only to the subscribers `filter` accepts.
+    pub fn send_video_frame_filtered(
+        &self,
+        msg: Message,
+        filter: impl Fn(i32) -> bool,
+    ) -> HashSet<i32> {
+        let msg = Arc::new(msg);
+        let mut conn_ids = HashSet::new();
+        let mut lock = self.0.write().unwrap();
+        for s in lock.subscribes.values_mut() {
+            if filter(s.id()) {
+                s.send(msg.clone());
+                conn_ids.insert(s.id());
+            }
+        }
+        conn_ids
+    }
+
+    pub fn send_filtered(&self, msg: Message, filter: impl Fn(i32) -> bool) {
+        let msg = Arc::new(msg);
+        let mut lock = self.0.write().unwrap();
+        for s in lock.subscribes.values_mut() {
+            if filter(s.id()) {
+                s.send(msg.clone());
+            }
+        }
+    }
+
     pub fn send_without(&self, msg: Message, sub: i32) {
         let mut lock = self.0.write().unwrap();
         let msg = Arc::new(msg);
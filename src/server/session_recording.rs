@@ -0,0 +1,420 @@
+// Mandatory recording of incoming sessions on the controlled side.
+//
+// With `enforce-record-incoming` set, every incoming session is recorded: the video service
+// records the screen (see `video_service::get_recorder`), and each connection keeps a
+// `SessionRecorder` for the audio sent to the peer and the timeline of its input events.
+// File transfers and port forwards get a timeline of the file actions and the forwarded
+// address, screenshots are saved next to their timeline. A session is refused if its
+// recording can not be started.
+//
+// Finished files are signed with a key pair used only for this, kept in the config directory
+// (see `sign_key`). `<file>.sig` holds the sha256 of the file and its ed25519 signature, and
+// both are handed to `record_upload` if uploading is enabled.
+//
+// The text typed by the peer is not recorded, only which kind of key it was, unless
+// `record-incoming-text` is set as well.
+
+use crate::hbbs_http::record_upload;
+use hbb_common::{
+    allow_err, bail,
+    config::Config,
+    log,
+    message_proto::{file_action, key_event, message, misc, AudioFormat, Message},
+    sodiumoxide::{base64, crypto::sign},
+    ResultType,
+};
+use scrap::record::{AudioRecorder, RecordState};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::PathBuf,
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+pub const OPTION_ENFORCE_RECORD_INCOMING: &str = "enforce-record-incoming";
+pub const OPTION_RECORD_INCOMING_TEXT: &str = "record-incoming-text";
+const SIGNATURE_EXT: &str = "sig";
+const SIGN_KEY_FILE: &str = "record_sign.key";
+const SIGN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+lazy_static::lazy_static! {
+    static ref SIGNER: Mutex<Option<Sender<SignStream>>> = Default::default();
+    static ref SIGN_KEY: Mutex<Option<sign::SecretKey>> = Default::default();
+}
+
+#[inline]
+pub fn is_enforced() -> bool {
+    !Config::get_option(OPTION_ENFORCE_RECORD_INCOMING).is_empty()
+}
+
+/// Sender for the record states of one file.
+/// When recording is enforced, files are signed once written, before being uploaded.
+pub fn record_state_sender() -> Option<Sender<RecordState>> {
+    let upload = if record_upload::is_enable() {
+        let (tx, rx) = channel();
+        record_upload::run(rx);
+        Some(tx)
+    } else {
+        None
+    };
+    if !is_enforced() {
+        return upload;
+    }
+    let (tx, rx) = channel::<RecordState>();
+    let stream = SignStream {
+        rx,
+        upload,
+        current: None,
+    };
+    let mut lock = SIGNER.lock().unwrap();
+    let signer = lock.get_or_insert_with(|| {
+        let (tx, rx) = channel();
+        std::thread::spawn(move || sign_worker(rx));
+        tx
+    });
+    if let Err(err) = signer.send(stream) {
+        // Only if the worker panicked, start a new one on the next call.
+        log::error!("Recording signer is gone");
+        lock.take();
+        return err.0.upload;
+    }
+    Some(tx)
+}
+
+/// The record states of one file, as seen by the signer.
+struct SignStream {
+    rx: Receiver<RecordState>,
+    upload: Option<Sender<RecordState>>,
+    current: Option<String>,
+}
+
+impl SignStream {
+    fn handle(&mut self, state: RecordState) {
+        let signature = match &state {
+            RecordState::NewFile(file) => {
+                self.current = Some(file.clone());
+                None
+            }
+            RecordState::WriteTail => self.current.take().and_then(|file| match sign_file(&file) {
+                Ok(sig) => Some(sig),
+                Err(err) => {
+                    log::error!("Failed to sign recording {}: {}", file, err);
+                    None
+                }
+            }),
+            RecordState::RemoveFile => {
+                self.current = None;
+                None
+            }
+            RecordState::NewFrame => None,
+        };
+        if let Some(upload) = self.upload.as_ref() {
+            upload.send(state).ok();
+            if let Some(sig) = signature {
+                upload.send(RecordState::NewFile(sig)).ok();
+                upload.send(RecordState::WriteTail).ok();
+            }
+        }
+    }
+}
+
+// One thread for all the recordings of the process, it polls the streams registered by
+// `record_state_sender` and drops them once their recorder is gone.
+fn sign_worker(rx: Receiver<SignStream>) {
+    let mut streams: Vec<SignStream> = Vec::new();
+    loop {
+        let new = if streams.is_empty() {
+            rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            rx.recv_timeout(SIGN_POLL_INTERVAL)
+        };
+        match new {
+            Ok(stream) => streams.push(stream),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                if streams.is_empty() {
+                    break;
+                }
+            }
+        }
+        streams.retain_mut(|stream| loop {
+            match stream.rx.try_recv() {
+                Ok(state) => stream.handle(state),
+                Err(TryRecvError::Empty) => break true,
+                Err(TryRecvError::Disconnected) => break false,
+            }
+        });
+    }
+}
+
+/// Key pair for signing recordings, created on first use. It is not the host key pair, so
+/// that anyone verifying recordings does not need to trust a key used for connections.
+/// The public key is written to every signature.
+fn sign_key() -> ResultType<sign::SecretKey> {
+    let mut lock = SIGN_KEY.lock().unwrap();
+    if let Some(sk) = lock.as_ref() {
+        return Ok(sk.clone());
+    }
+    let sk = crate::common::load_or_create_secret(SIGN_KEY_FILE, || {
+        sign::gen_keypair().1.as_ref().to_vec()
+    })?;
+    let Some(sk) = sign::SecretKey::from_slice(&sk) else {
+        bail!("Invalid recording signing key");
+    };
+    *lock = Some(sk.clone());
+    Ok(sk)
+}
+
+/// Write `<file>.sig`, returns its path.
+pub fn sign_file(file: &str) -> ResultType<String> {
+    let sk = sign_key()?;
+    let pk = sk.public_key();
+
+    let mut reader = BufReader::new(File::open(file)?);
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    let digest = hasher.finalize();
+    let signature = sign::sign_detached(&digest, &sk);
+    let name = PathBuf::from(file)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let sig = json!({
+        "file": name,
+        "id": Config::get_id(),
+        "sha256": hex::encode(digest),
+        "signature": base64::encode(signature, base64::Variant::Original),
+        "pk": base64::encode(pk, base64::Variant::Original),
+    });
+    let path = format!("{}.{}", file, SIGNATURE_EXT);
+    std::fs::write(&path, serde_json::to_string_pretty(&sig)?)?;
+    Ok(path)
+}
+
+fn save_dir() -> ResultType<PathBuf> {
+    let mut dir = Config::get_option("video-save-directory");
+    if dir.is_empty() {
+        dir = crate::ui_interface::default_video_save_directory();
+    }
+    if dir.is_empty() {
+        bail!("No directory to save the recording");
+    }
+    let dir = PathBuf::from(dir);
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Audio and input timeline of one incoming session.
+pub struct SessionRecorder {
+    prefix: String,
+    // Flushed by `flush` on the timer of the connection, not on every event.
+    timeline: BufWriter<File>,
+    timeline_tx: Option<Sender<RecordState>>,
+    audio: Option<AudioRecorder>,
+    start: Instant,
+    record_text: bool,
+}
+
+impl SessionRecorder {
+    /// `conn_type` is "remote", "file_transfer", "port_forward" or "screenshot".
+    pub fn new(conn_id: i32, peer_id: &str, peer_name: &str, conn_type: &str) -> ResultType<Self> {
+        let prefix = save_dir()?
+            .join(format!(
+                "s{}{}_{}",
+                Config::get_id(),
+                chrono::Local::now().format("_%Y%m%d%H%M%S%3f"),
+                peer_id
+            ))
+            .to_string_lossy()
+            .to_string();
+        let timeline_path = format!("{}_input.jsonl", prefix);
+        let timeline = BufWriter::new(File::create(&timeline_path)?);
+        let timeline_tx = record_state_sender();
+        if let Some(tx) = timeline_tx.as_ref() {
+            tx.send(RecordState::NewFile(timeline_path.clone())).ok();
+        }
+        log::info!("session of {} will save to {}", peer_id, prefix);
+        let record_text = !Config::get_option(OPTION_RECORD_INCOMING_TEXT).is_empty();
+        let mut recorder = Self {
+            prefix,
+            timeline,
+            timeline_tx,
+            audio: None,
+            start: Instant::now(),
+            record_text,
+        };
+        recorder.write_line(
+            "start",
+            json!({
+                "conn_id": conn_id,
+                "peer_id": peer_id,
+                "peer_name": peer_name,
+                "type": conn_type,
+                "time": chrono::Local::now().to_rfc3339(),
+                "text": record_text,
+            }),
+        )?;
+        Ok(recorder)
+    }
+
+    fn write_line(&mut self, kind: &str, data: Value) -> ResultType<()> {
+        let line = json!({
+            "ms": self.start.elapsed().as_millis() as u64,
+            "kind": kind,
+            "data": data,
+        });
+        writeln!(self.timeline, "{}", line)?;
+        if let Some(tx) = self.timeline_tx.as_ref() {
+            tx.send(RecordState::NewFrame).ok();
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) {
+        allow_err!(self.timeline.flush());
+    }
+
+    pub fn on_port_forward(&mut self, address: &str) {
+        allow_err!(self.write_line("port_forward", json!({ "address": address })));
+    }
+
+    /// Save the screenshot sent to the peer, signed like the other files.
+    pub fn on_screenshot(&mut self, display: usize, png: &[u8]) {
+        let filename = format!(
+            "{}_screenshot_{}_{}.png",
+            self.prefix,
+            display,
+            self.start.elapsed().as_millis()
+        );
+        if let Err(err) = std::fs::write(&filename, png) {
+            log::error!("Failed to record screenshot: {}", err);
+            return;
+        }
+        if let Some(tx) = record_state_sender() {
+            tx.send(RecordState::NewFile(filename.clone())).ok();
+            tx.send(RecordState::WriteTail).ok();
+        }
+        allow_err!(self.write_line(
+            "screenshot",
+            json!({ "display": display, "file": filename })
+        ));
+    }
+
+    pub fn on_audio_format(&mut self, format: &AudioFormat) {
+        // A new format starts a new file, the frames of the old one can't be decoded with it.
+        self.audio.take();
+        let filename = format!(
+            "{}_audio_{}.webm",
+            self.prefix,
+            self.start.elapsed().as_millis()
+        );
+        match AudioRecorder::new(
+            filename,
+            format.sample_rate,
+            format.channels as _,
+            record_state_sender(),
+        ) {
+            Ok(audio) => {
+                allow_err!(self.write_line("audio", json!({ "file": audio.filename() })));
+                self.audio = Some(audio);
+            }
+            Err(err) => log::error!("Failed to record audio: {}", err),
+        }
+    }
+
+    pub fn on_audio_frame(&mut self, data: &[u8]) {
+        if let Some(audio) = self.audio.as_mut() {
+            audio.write_audio(data);
+        }
+    }
+
+    pub fn on_message(&mut self, msg: &Message) {
+        let (kind, data) = match &msg.union {
+            Some(message::Union::MouseEvent(me)) => {
+                ("mouse", json!({ "x": me.x, "y": me.y, "mask": me.mask }))
+            }
+            Some(message::Union::KeyEvent(ke)) => {
+                let text = self.record_text;
+                let key = match &ke.union {
+                    Some(key_event::Union::ControlKey(ck)) => json!({ "control_key": ck.value() }),
+                    Some(key_event::Union::Chr(chr)) if text => json!({ "chr": chr }),
+                    Some(key_event::Union::Unicode(unicode)) if text => {
+                        json!({ "unicode": unicode })
+                    }
+                    Some(key_event::Union::Seq(seq)) if text => json!({ "seq": seq }),
+                    Some(key_event::Union::Win2winHotkey(hotkey)) if text => {
+                        json!({ "win2win_hotkey": hotkey })
+                    }
+                    // Only the kind of key, the text typed may be a password.
+                    Some(key_event::Union::Chr(_)) => json!({ "type": "chr" }),
+                    Some(key_event::Union::Unicode(_)) => json!({ "type": "unicode" }),
+                    Some(key_event::Union::Seq(_)) => json!({ "type": "seq" }),
+                    Some(key_event::Union::Win2winHotkey(_)) => json!({ "type": "win2win_hotkey" }),
+                    _ => json!({}),
+                };
+                (
+                    "key",
+                    json!({
+                        "down": ke.down,
+                        "press": ke.press,
+                        "mode": ke.mode.value(),
+                        "modifiers": ke.modifiers.iter().map(|m| m.value()).collect::<Vec<_>>(),
+                        "key": key,
+                    }),
+                )
+            }
+            Some(message::Union::PointerDeviceEvent(_)) => ("pointer", json!({})),
+            Some(message::Union::FileAction(fa)) => match &fa.union {
+                Some(file_action::Union::ReadDir(r)) => ("read_dir", json!({ "path": r.path })),
+                Some(file_action::Union::Send(s)) => {
+                    ("file_send", json!({ "id": s.id, "path": s.path }))
+                }
+                Some(file_action::Union::Receive(r)) => (
+                    "file_receive",
+                    json!({ "id": r.id, "path": r.path, "size": r.total_size }),
+                ),
+                Some(file_action::Union::Create(c)) => ("file_create", json!({ "path": c.path })),
+                Some(file_action::Union::RemoveDir(r)) => {
+                    ("file_remove_dir", json!({ "path": r.path }))
+                }
+                Some(file_action::Union::RemoveFile(r)) => {
+                    ("file_remove", json!({ "path": r.path }))
+                }
+                Some(file_action::Union::Cancel(c)) => ("file_cancel", json!({ "id": c.id })),
+                _ => return,
+            },
+            Some(message::Union::Misc(m)) => match &m.union {
+                Some(misc::Union::SwitchDisplay(sd)) => {
+                    ("switch_display", json!({ "display": sd.display }))
+                }
+                _ => return,
+            },
+            _ => return,
+        };
+        allow_err!(self.write_line(kind, data));
+    }
+}
+
+impl Drop for SessionRecorder {
+    fn drop(&mut self) {
+        self.audio.take();
+        allow_err!(self.write_line("end", json!({})));
+        self.timeline.flush().ok();
+        if let Some(tx) = self.timeline_tx.as_ref() {
+            tx.send(RecordState::WriteTail).ok();
+        }
+    }
+}
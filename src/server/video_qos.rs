@@ -1,6 +1,6 @@
 use super::*;
 use scrap::codec::Quality;
-use std::time::Duration;
+use std::{collections::HashSet, time::Duration};
 pub const FPS: u32 = 30;
 pub const MIN_FPS: u32 = 1;
 pub const MAX_FPS: u32 = 120;
@@ -36,6 +36,10 @@ struct UserData {
     delay: Option<Delay>,
     response_delayed: bool,
     record: bool,
+    screen_content: bool,
+    audio_quality: Option<(i64, AudioQuality)>, // (time, quality)
+    // scales up the frames sent smaller, see `VideoFrame::downscale`
+    downscale: bool,
 }
 
 pub struct VideoQoS {
@@ -44,10 +48,34 @@ pub struct VideoQoS {
     users: HashMap<i32, UserData>,
     bitrate_store: u32,
     support_abr: HashMap<usize, bool>,
+    support_simulcast: HashMap<usize, bool>,
+    low_layer: Option<LowLayer>,
+}
+
+/// Viewers on a much slower network than the others get their own layer, encoded separately
+/// at a lower fps and quality, so that they don't drag the others down.
+/// `fps` and `quality` of `VideoQoS` are for the other viewers then.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LowLayer {
+    pub users: HashSet<i32>,
+    pub fps: u32,
+    pub quality: Quality,
+    /// Encoded at half the width and height, if all its viewers scale the frames up.
+    pub downscale: bool,
+}
+
+/// Opus parameters of the audio encoder, which is shared by all the sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioParams {
+    pub bitrate: u32,
+    /// Expected packet loss for the in-band FEC, 0 to disable it.
+    pub loss_percent: i32,
+    /// Stop sending silence after 200ms instead of seconds, it is not the DTX of opus.
+    pub short_silence_gate: bool,
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
-enum DelayState {
+pub(super) enum DelayState {
     Normal = 0,
     LowDelay = 200,
     HighDelay = 500,
@@ -82,6 +110,8 @@ impl Default for VideoQoS {
             users: Default::default(),
             bitrate_store: 0,
             support_abr: Default::default(),
+            support_simulcast: Default::default(),
+            low_layer: None,
         }
     }
 }
@@ -120,49 +150,28 @@ impl VideoQoS {
         self.users.iter().any(|u| u.1.record)
     }
 
+    pub fn screen_content(&self) -> bool {
+        self.users.iter().any(|u| u.1.screen_content)
+    }
+
     pub fn set_support_abr(&mut self, display_idx: usize, support: bool) {
         self.support_abr.insert(display_idx, support);
     }
 
+    pub fn set_support_simulcast(&mut self, display_idx: usize, support: bool) {
+        self.support_simulcast.insert(display_idx, support);
+    }
+
+    pub fn low_layer(&self) -> Option<LowLayer> {
+        self.low_layer.clone()
+    }
+
     pub fn refresh(&mut self, typ: Option<RefreshType>) {
+        let low_users = self.low_layer_users();
+        let main_users = || self.users.iter().filter(|u| !low_users.contains(u.0));
+
         // fps
-        let user_fps = |u: &UserData| {
-            // custom_fps
-            let mut fps = u.custom_fps.unwrap_or(FPS);
-            // auto adjust fps
-            if let Some(auto_adjust_fps) = u.auto_adjust_fps {
-                if fps == 0 || auto_adjust_fps < fps {
-                    fps = auto_adjust_fps;
-                }
-            }
-            // delay
-            if let Some(delay) = u.delay {
-                fps = match delay.state {
-                    DelayState::Normal => fps,
-                    DelayState::LowDelay => fps * 3 / 4,
-                    DelayState::HighDelay => fps / 2,
-                    DelayState::Broken => fps / 4,
-                }
-            }
-            // delay response
-            if u.response_delayed {
-                if fps > MIN_FPS + 2 {
-                    fps = MIN_FPS + 2;
-                }
-            }
-            return fps;
-        };
-        let mut fps = self
-            .users
-            .iter()
-            .map(|(_, u)| user_fps(u))
-            .filter(|u| *u >= MIN_FPS)
-            .min()
-            .unwrap_or(FPS);
-        if fps > MAX_FPS {
-            fps = MAX_FPS;
-        }
-        self.fps = fps;
+        self.fps = Self::layer_fps(main_users().map(|u| u.1));
 
         // quality
         // latest image quality
@@ -175,72 +184,171 @@ impl VideoQoS {
             .unwrap_or_default()
             .unwrap_or_default()
             .1;
-        let mut quality = latest_quality;
 
         // network delay
         let abr_enabled =
             Config::get_option("enable-abr") != "N" && self.support_abr.iter().all(|e| *e.1);
-        if abr_enabled && typ != Some(RefreshType::SetImageQuality) {
-            // max delay
-            let delay = self
-                .users
-                .iter()
-                .map(|u| u.1.delay)
-                .filter(|d| d.is_some())
-                .max_by(|a, b| {
-                    (a.unwrap_or_default().state as u32).cmp(&(b.unwrap_or_default().state as u32))
-                });
-            let delay = delay.unwrap_or_default().unwrap_or_default().state;
-            if delay != DelayState::Normal {
-                match self.quality {
-                    Quality::Best => {
-                        quality = if delay == DelayState::Broken {
-                            Quality::Low
-                        } else {
-                            Quality::Balanced
-                        };
+        let adjust = abr_enabled && typ != Some(RefreshType::SetImageQuality);
+        let old_quality = self.quality;
+        if adjust {
+            let delay = Self::layer_delay(main_users().map(|u| u.1));
+            self.quality = Self::adjust_quality(self.quality, latest_quality, delay);
+        } else {
+            self.quality = latest_quality;
+        }
+
+        self.low_layer = if low_users.is_empty() {
+            None
+        } else {
+            let low_users_data = || self.users.iter().filter(|u| low_users.contains(u.0));
+            let mut quality = latest_quality;
+            if adjust {
+                let current = self.low_layer.as_ref().map_or(old_quality, |l| l.quality);
+                let delay = Self::layer_delay(low_users_data().map(|u| u.1));
+                quality = Self::adjust_quality(current, latest_quality, delay);
+            }
+            Some(LowLayer {
+                fps: Self::layer_fps(low_users_data().map(|u| u.1)),
+                quality,
+                downscale: low_users_data().all(|u| u.1.downscale),
+                users: low_users,
+            })
+        };
+    }
+
+    fn user_fps(u: &UserData) -> u32 {
+        // custom_fps
+        let mut fps = u.custom_fps.unwrap_or(FPS);
+        // auto adjust fps
+        if let Some(auto_adjust_fps) = u.auto_adjust_fps {
+            if fps == 0 || auto_adjust_fps < fps {
+                fps = auto_adjust_fps;
+            }
+        }
+        // delay
+        if let Some(delay) = u.delay {
+            fps = match delay.state {
+                DelayState::Normal => fps,
+                DelayState::LowDelay => fps * 3 / 4,
+                DelayState::HighDelay => fps / 2,
+                DelayState::Broken => fps / 4,
+            }
+        }
+        // delay response
+        if u.response_delayed {
+            if fps > MIN_FPS + 2 {
+                fps = MIN_FPS + 2;
+            }
+        }
+        return fps;
+    }
+
+    fn layer_fps<'a>(users: impl Iterator<Item = &'a UserData>) -> u32 {
+        let fps = users
+            .map(Self::user_fps)
+            .filter(|u| *u >= MIN_FPS)
+            .min()
+            .unwrap_or(FPS);
+        std::cmp::min(fps, MAX_FPS)
+    }
+
+    // max delay
+    fn layer_delay<'a>(users: impl Iterator<Item = &'a UserData>) -> DelayState {
+        let delay = users
+            .map(|u| u.delay)
+            .filter(|d| d.is_some())
+            .max_by(|a, b| {
+                (a.unwrap_or_default().state as u32).cmp(&(b.unwrap_or_default().state as u32))
+            });
+        delay.unwrap_or_default().unwrap_or_default().state
+    }
+
+    pub(super) fn adjust_quality(
+        current: Quality,
+        latest_quality: Quality,
+        delay: DelayState,
+    ) -> Quality {
+        let mut quality = latest_quality;
+        if delay != DelayState::Normal {
+            match current {
+                Quality::Best => {
+                    quality = if delay == DelayState::Broken {
+                        Quality::Low
+                    } else {
+                        Quality::Balanced
+                    };
+                }
+                Quality::Balanced => {
+                    quality = Quality::Low;
+                }
+                Quality::Low => {
+                    quality = Quality::Low;
+                }
+                Quality::Custom(b) => match delay {
+                    DelayState::LowDelay => {
+                        quality =
+                            Quality::Custom(if b >= 150 { 100 } else { std::cmp::min(50, b) });
                     }
-                    Quality::Balanced => {
-                        quality = Quality::Low;
+                    DelayState::HighDelay => {
+                        quality = Quality::Custom(if b >= 100 { 50 } else { std::cmp::min(25, b) });
                     }
-                    Quality::Low => {
-                        quality = Quality::Low;
+                    DelayState::Broken => {
+                        quality = Quality::Custom(if b >= 50 { 25 } else { std::cmp::min(10, b) });
                     }
-                    Quality::Custom(b) => match delay {
-                        DelayState::LowDelay => {
-                            quality =
-                                Quality::Custom(if b >= 150 { 100 } else { std::cmp::min(50, b) });
-                        }
-                        DelayState::HighDelay => {
-                            quality =
-                                Quality::Custom(if b >= 100 { 50 } else { std::cmp::min(25, b) });
-                        }
-                        DelayState::Broken => {
-                            quality =
-                                Quality::Custom(if b >= 50 { 25 } else { std::cmp::min(10, b) });
-                        }
-                        DelayState::Normal => {}
-                    },
-                }
-            } else {
-                match self.quality {
-                    Quality::Low => {
-                        if latest_quality == Quality::Best {
-                            quality = Quality::Balanced;
-                        }
+                    DelayState::Normal => {}
+                },
+            }
+        } else {
+            match current {
+                Quality::Low => {
+                    if latest_quality == Quality::Best {
+                        quality = Quality::Balanced;
                     }
-                    Quality::Custom(current_b) => {
-                        if let Quality::Custom(latest_b) = latest_quality {
-                            if current_b < latest_b / 2 {
-                                quality = Quality::Custom(latest_b / 2);
-                            }
+                }
+                Quality::Custom(current_b) => {
+                    if let Quality::Custom(latest_b) = latest_quality {
+                        if current_b < latest_b / 2 {
+                            quality = Quality::Custom(latest_b / 2);
                         }
                     }
-                    _ => {}
                 }
+                _ => {}
             }
         }
-        self.quality = quality;
+        quality
+    }
+
+    // The users much slower than the others, if the encoders of all displays can run a second
+    // instance and not everyone is slow.
+    pub(super) fn low_layer_users(&self) -> HashSet<i32> {
+        let enabled = Config::get_option("enable-simulcast") != "N"
+            && !self.support_simulcast.is_empty()
+            && self.support_simulcast.iter().all(|e| *e.1);
+        if !enabled || self.users.len() < 2 {
+            return Default::default();
+        }
+        let slow: HashSet<i32> = self
+            .users
+            .iter()
+            .filter(|(id, u)| {
+                let state = u.delay.map(|d| d.state).unwrap_or_default();
+                // Back to the main layer only once the delay is normal again, not to switch
+                // back and forth.
+                let joined = self
+                    .low_layer
+                    .as_ref()
+                    .map_or(false, |l| l.users.contains(id));
+                u.response_delayed
+                    || state == DelayState::HighDelay
+                    || state == DelayState::Broken
+                    || (joined && state != DelayState::Normal)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        if slow.len() == self.users.len() {
+            return Default::default();
+        }
+        slow
     }
 
     pub fn user_custom_fps(&mut self, id: i32, fps: u32) {
@@ -381,6 +489,81 @@ impl VideoQoS {
         }
     }
 
+    pub fn user_downscale(&mut self, id: i32, v: bool) {
+        if let Some(user) = self.users.get_mut(&id) {
+            user.downscale = v;
+        } else {
+            self.users.insert(
+                id,
+                UserData {
+                    downscale: v,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    pub fn user_screen_content(&mut self, id: i32, v: bool) {
+        if let Some(user) = self.users.get_mut(&id) {
+            user.screen_content = v;
+        } else {
+            self.users.insert(
+                id,
+                UserData {
+                    screen_content: v,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    pub fn user_audio_quality(&mut self, id: i32, quality: AudioQuality) {
+        let quality = Some((hbb_common::get_time(), quality));
+        if let Some(user) = self.users.get_mut(&id) {
+            user.audio_quality = quality;
+        } else {
+            self.users.insert(
+                id,
+                UserData {
+                    audio_quality: quality,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    // The encoder is shared, so the highest audio quality of the sessions caps the bitrate, and
+    // the slowest network lowers it further, every session has to take the stream. Frames
+    // delayed by more than 1s are dropped by the connection, FEC recovers single ones.
+    pub fn audio_params(&self) -> AudioParams {
+        let max_bitrate = self
+            .users
+            .values()
+            .filter_map(|u| u.audio_quality)
+            .map(|(_, q)| match q {
+                AudioQuality::AudioQualityLow => 32_000,
+                AudioQuality::AudioQualityBest => 128_000,
+                _ => 64_000,
+            })
+            .max()
+            .unwrap_or(64_000);
+        let mut delay = Self::layer_delay(self.users.values());
+        if self.users.values().any(|u| u.response_delayed) {
+            delay = DelayState::Broken;
+        }
+        let (bitrate, loss_percent, short_silence_gate) = match delay {
+            DelayState::Normal => (max_bitrate, 0, false),
+            DelayState::LowDelay => (48_000, 5, false),
+            DelayState::HighDelay => (32_000, 10, true),
+            DelayState::Broken => (16_000, 20, true),
+        };
+        AudioParams {
+            bitrate: bitrate.min(max_bitrate),
+            loss_percent,
+            short_silence_gate,
+        }
+    }
+
     pub fn on_connection_close(&mut self, id: i32) {
         self.users.remove(&id);
         self.refresh(None);
@@ -21,7 +21,7 @@
 use super::{
     display_service::{check_display_changed, get_display_info},
     service::ServiceTmpl,
-    video_qos::VideoQoS,
+    video_qos::{LowLayer, VideoQoS, MIN_FPS},
     *,
 };
 #[cfg(target_os = "linux")]
@@ -46,6 +46,8 @@ use hbb_common::{
 use scrap::gpucodec::{GpuEncoder, GpuEncoderConfig};
 #[cfg(feature = "hwcodec")]
 use scrap::hwcodec::{HwEncoder, HwEncoderConfig};
+#[cfg(feature = "openh264")]
+use scrap::openh264::OpenH264EncoderConfig;
 #[cfg(not(windows))]
 use scrap::Capturer;
 use scrap::{
@@ -53,19 +55,33 @@ use scrap::{
     codec::{Encoder, EncoderCfg, Quality},
     record::{Recorder, RecorderContext},
     vpxcodec::{VpxEncoderConfig, VpxVideoCodecId},
-    CodecName, Display, Frame, TraitCapturer,
+    CodecName, DirtyRect, DirtyRegion, Display, EncodeInput, EncodeYuvFormat, Frame, Pixfmt,
+    TraitCapturer, TraitPixelBuffer, WindowState,
 };
 #[cfg(windows)]
 use std::sync::Once;
 use std::{
+    cell::Cell,
     collections::HashSet,
     io::ErrorKind::WouldBlock,
     ops::{Deref, DerefMut},
+    sync::Weak,
     time::{self, Duration, Instant},
 };
 
 pub const NAME: &'static str = "video";
 pub const OPTION_REFRESH: &'static str = "refresh";
+// Id of the window shared instead of the display.
+pub const OPTION_WINDOW: &'static str = "window";
+// pts of the last frame a peer decoded before its decoder failed.
+pub const OPTION_RECOVER: &'static str = "recover";
+// Part of the display captured instead of all of it, "x,y,width,height,follow_cursor".
+pub const OPTION_REGION: &'static str = "region";
+const MIN_REGION_SIZE: usize = 64;
+// How long a screenshot waits for the capturer to deliver a frame.
+const SCREENSHOT_TIMEOUT: Duration = Duration::from_secs(3);
+// In screen content mode, the last frame is sent again losslessly once the screen is still for this long.
+const LOSSLESS_REFINE_DELAY: Duration = Duration::from_millis(500);
 
 lazy_static::lazy_static! {
     static ref FRAME_FETCHED_NOTIFIER: (UnboundedSender<(i32, Option<Instant>)>, Arc<TokioMutex<UnboundedReceiver<(i32, Option<Instant>)>>>) = {
@@ -75,6 +91,19 @@ lazy_static::lazy_static! {
     pub static ref VIDEO_QOS: Arc<Mutex<VideoQoS>> = Default::default();
     pub static ref IS_UAC_RUNNING: Arc<Mutex<bool>> = Default::default();
     pub static ref IS_FOREGROUND_WINDOW_ELEVATED: Arc<Mutex<bool>> = Default::default();
+    // Displays sharing a window, `None` if the area of the window is unknown (Wayland).
+    static ref SHARED_WINDOWS: Arc<Mutex<HashMap<usize, Option<WindowState>>>> = Default::default();
+    // Displays of which only a region is captured.
+    static ref REGIONS: Arc<Mutex<HashSet<usize>>> = Default::default();
+    // (display, option) -> the connection which set the window or the region of the display.
+    static ref OPTION_OWNERS: Arc<Mutex<HashMap<(usize, &'static str), i32>>> = Default::default();
+    // The format of the audio sent and the recorders of the incoming sessions which take it.
+    static ref AUDIO_RECORDERS: Mutex<(Option<Message>, Vec<Weak<Mutex<Option<Recorder>>>>)> = Default::default();
+}
+
+thread_local! {
+    // `media_time` when the pts of the frames captured on this thread are 0.
+    static PTS_BASE: Cell<i64> = Cell::new(0);
 }
 
 #[inline]
@@ -291,7 +320,11 @@ impl DerefMut for CapturerInfo {
     }
 }
 
-fn get_capturer(current: usize, portable_service_running: bool) -> ResultType<CapturerInfo> {
+fn get_capturer(
+    current: usize,
+    portable_service_running: bool,
+    _window: u64,
+) -> ResultType<CapturerInfo> {
     #[cfg(target_os = "linux")]
     {
         if !is_x11() {
@@ -309,6 +342,12 @@ fn get_capturer(current: usize, portable_service_running: bool) -> ResultType<Ca
         );
     }
     let display = displays.remove(current);
+    #[cfg(target_os = "linux")]
+    let display = if _window != 0 {
+        Display::window(_window)?
+    } else {
+        display
+    };
 
     #[cfg(target_os = "linux")]
     if let Display::X11(inner) = &display {
@@ -381,6 +420,53 @@ fn get_capturer(current: usize, portable_service_running: bool) -> ResultType<Ca
     })
 }
 
+/// Capture one frame of `display_idx` as a PNG, for a `Screenshot` request.
+pub fn screenshot(display_idx: usize) -> ResultType<Vec<u8>> {
+    #[cfg(target_os = "linux")]
+    if !is_x11() {
+        bail!("Screenshots are not supported on Wayland");
+    }
+    #[cfg(windows)]
+    let portable_service_running = crate::portable_service::client::running();
+    #[cfg(not(windows))]
+    let portable_service_running = false;
+    let mut c = get_capturer(display_idx, portable_service_running, 0)?;
+    let start = Instant::now();
+    loop {
+        match c.frame(Duration::from_millis(100)) {
+            Ok(Frame::PixelBuffer(frame)) if !frame.data().is_empty() => {
+                let (w, h) = (frame.width(), frame.height());
+                let stride = frame.stride().first().cloned().unwrap_or(w * 4);
+                let data = frame.data();
+                let mut rgba = Vec::with_capacity(w * h * 4);
+                for y in 0..h {
+                    let row = &data[y * stride..y * stride + w * 4];
+                    match frame.pixfmt() {
+                        Pixfmt::RGBA => rgba.extend_from_slice(row),
+                        Pixfmt::BGRA => {
+                            for p in row.chunks_exact(4) {
+                                rgba.extend_from_slice(&[p[2], p[1], p[0], 255]);
+                            }
+                        }
+                        fmt => bail!("Unsupported pixel format {:?}", fmt),
+                    }
+                }
+                let mut png = Vec::new();
+                repng::encode(&mut png, w as _, h as _, &rgba)?;
+                return Ok(png);
+            }
+            Ok(Frame::Texture(_)) => bail!("Unsupported frame"),
+            Ok(_) => {}
+            Err(e) if e.kind() == WouldBlock => {}
+            Err(e) => return Err(e.into()),
+        }
+        if start.elapsed() > SCREENSHOT_TIMEOUT {
+            bail!("No frame captured");
+        }
+        std::thread::sleep(Duration::from_millis(30));
+    }
+}
+
 fn run(vs: VideoService) -> ResultType<()> {
     let _raii = Raii::new(vs.idx);
     // Wayland only support one video capturer for now. It is ok to call ensure_inited() here.
@@ -405,25 +491,59 @@ fn run(vs: VideoService) -> ResultType<()> {
 
     let display_idx = vs.idx;
     let sp = vs.sp;
-    let mut c = get_capturer(display_idx, last_portable_service_running)?;
+    let window = get_shared_window(&sp);
+    let mut c = match get_capturer(display_idx, last_portable_service_running, window) {
+        Ok(c) => c,
+        Err(err) if window != 0 => {
+            // The window may be closed, share the display again.
+            log::info!("Failed to capture window {}: {}", window, err);
+            sp.set_option(OPTION_WINDOW, "");
+            OPTION_OWNERS
+                .lock()
+                .unwrap()
+                .remove(&(display_idx, OPTION_WINDOW));
+            get_capturer(display_idx, last_portable_service_running, 0)?
+        }
+        Err(err) => return Err(err),
+    };
+    let mut window_state = update_shared_window(&sp, display_idx, &c)?;
+    // A shared window is captured as it is.
+    let mut region = if window_state.is_none() && get_shared_window(&sp) == 0 {
+        get_region(&sp, &c)
+    } else {
+        None
+    };
+    update_region(&sp, display_idx, &c, region.as_ref())?;
 
     let mut video_qos = VIDEO_QOS.lock().unwrap();
     video_qos.refresh(None);
     let mut spf;
     let mut quality = video_qos.quality();
-    let record_incoming = !Config::get_option("allow-auto-record-incoming").is_empty();
+    let record_incoming = !Config::get_option("allow-auto-record-incoming").is_empty()
+        || super::session_recording::is_enforced();
     let client_record = video_qos.record();
     drop(video_qos);
     let encoder_cfg = get_encoder_config(
         &c,
         display_idx,
+        region.as_ref(),
         quality,
         client_record || record_incoming,
         last_portable_service_running,
     );
     Encoder::set_fallback(&encoder_cfg);
     let codec_name = Encoder::negotiated_codec();
-    let recorder = get_recorder(c.width, c.height, &codec_name, record_incoming);
+    let (width, height) = region.map_or((c.width, c.height), |r| (r.rect.w, r.rect.h));
+    let recorder = get_recorder(width, height, &codec_name, record_incoming);
+    if recorder.lock().unwrap().is_none() && super::session_recording::is_enforced() {
+        // Sessions must not go on unrecorded.
+        let mut misc = Misc::new();
+        misc.set_stop_service(true);
+        let mut msg = Message::new();
+        msg.set_misc(misc);
+        sp.send(msg);
+        bail!("Failed to start session recording");
+    }
     let mut encoder;
     let use_i444 = Encoder::use_i444(&encoder_cfg);
     match Encoder::new(encoder_cfg.clone(), use_i444) {
@@ -437,6 +557,11 @@ fn run(vs: VideoService) -> ResultType<()> {
         .lock()
         .unwrap()
         .set_support_abr(display_idx, encoder.support_abr());
+    // A second software encoder is cheap, hardware ones may be limited in number.
+    VIDEO_QOS.lock().unwrap().set_support_simulcast(
+        display_idx,
+        matches!(encoder_cfg, EncoderCfg::VPX(_) | EncoderCfg::AOM(_)),
+    );
     log::info!("initial quality: {quality:?}");
 
     if sp.is_option_true(OPTION_REFRESH) {
@@ -445,7 +570,9 @@ fn run(vs: VideoService) -> ResultType<()> {
 
     let mut frame_controller = VideoFrameController::new();
 
+    let media_clock = super::media_clock();
     let start = time::Instant::now();
+    PTS_BASE.with(|base| base.set(start.duration_since(media_clock).as_millis() as _));
     let mut last_check_displays = time::Instant::now();
     #[cfg(windows)]
     let mut try_gdi = 1;
@@ -458,6 +585,16 @@ fn run(vs: VideoService) -> ResultType<()> {
     let mut would_block_count = 0u32;
     let mut yuv = Vec::new();
     let mut mid_data = Vec::new();
+    let mut screen_content = false;
+    // When the last lossy frame was encoded, `None` if it has been refined.
+    let mut last_lossy: Option<Instant> = None;
+    // Encoded lossily since the last refinement, only these areas are encoded again.
+    let mut lossy = DirtyRegion::default();
+    // Changed since the last frame sent, kept while the encoder drops frames.
+    let mut dirty = DirtyRegion::default();
+    // The region followed the cursor, no frame of the new area has been handled yet.
+    let mut region_moved = false;
+    let mut low: Option<LowLayerEncoder> = None;
 
     while sp.ok() {
         #[cfg(windows)]
@@ -471,10 +608,20 @@ fn run(vs: VideoService) -> ResultType<()> {
             allow_err!(encoder.set_quality(quality));
             video_qos.store_bitrate(encoder.bitrate());
         }
+        // The option is only for VP8 and VP9, AV1 is always tuned for screen content.
+        let screen_content_asked = video_qos.screen_content() && codec_name != CodecName::AV1;
+        if screen_content != screen_content_asked {
+            screen_content = screen_content_asked;
+            encoder.set_screen_content(screen_content);
+            last_lossy = None;
+            lossy.clear();
+        }
         if client_record != video_qos.record() {
             bail!("SWITCH");
         }
+        let low_layer = video_qos.low_layer();
         drop(video_qos);
+        update_low_layer(&mut low, low_layer, &mut encoder, &encoder_cfg, use_i444)?;
 
         if sp.is_option_true(OPTION_REFRESH) {
             let _ = try_broadcast_display_changed(&sp, display_idx, &c);
@@ -514,11 +661,26 @@ fn run(vs: VideoService) -> ResultType<()> {
 
         frame_controller.reset();
 
+        if let Some(r) = region.as_mut().filter(|r| r.follow_cursor) {
+            if let Some((x, y)) = crate::get_cursor_pos() {
+                let cursor = (x - c.origin.0, y - c.origin.1);
+                if r.follow(cursor, c.width, c.height) {
+                    region_moved = true;
+                    // The new area is captured even if the screen is still.
+                    c.invalidate();
+                    broadcast_switch_display(&sp, make_region_changed_msg(display_idx, &c, r))?;
+                }
+            }
+        }
+
         let res = match c.frame(spf) {
             Ok(frame) => {
                 let time = now - start;
                 let ms = (time.as_secs() * 1000 + time.subsec_millis() as u64) as i64;
                 if frame.valid() {
+                    if frame.dirty_rects().map_or(true, |rects| !rects.is_empty()) {
+                        last_lossy = Some(now);
+                    }
                     let send_conn_ids = handle_one_frame(
                         display_idx,
                         &sp,
@@ -527,8 +689,15 @@ fn run(vs: VideoService) -> ResultType<()> {
                         &mut mid_data,
                         ms,
                         &mut encoder,
+                        low.as_mut(),
                         recorder.clone(),
+                        region.map(|r| r.rect),
+                        region_moved,
+                        &mut dirty,
+                        &mut lossy,
                     )?;
+                    // The whole new area is in `dirty` until it is encoded.
+                    region_moved = false;
                     frame_controller.set_send(now, send_conn_ids);
                 }
                 #[cfg(windows)]
@@ -540,6 +709,24 @@ fn run(vs: VideoService) -> ResultType<()> {
             Err(err) => Err(err),
         };
 
+        if let Some(state) = c.window_state() {
+            if window_state != Some(state) {
+                SHARED_WINDOWS
+                    .lock()
+                    .unwrap()
+                    .insert(display_idx, Some(state));
+                if window_state.map(|s| (s.x, s.y)) != Some((state.x, state.y)) {
+                    // The window is moved, the peer maps the mouse to the new area.
+                    broadcast_switch_display(
+                        &sp,
+                        make_window_changed_msg(display_idx, window, &state),
+                    )?;
+                }
+                window_state = Some(state);
+            }
+        }
+
+        let would_block = matches!(&res, Err(e) if e.kind() == WouldBlock);
         match res {
             Err(ref e) if e.kind() == WouldBlock => {
                 #[cfg(windows)]
@@ -591,6 +778,79 @@ fn run(vs: VideoService) -> ResultType<()> {
             }
         }
 
+        let recover = sp
+            .get_option(OPTION_RECOVER)
+            .and_then(|v| v.parse::<i64>().ok());
+        if let Some(last_decoded_pts) = recover {
+            sp.set_option(OPTION_RECOVER, "");
+            if encoder.support_recovery() {
+                encoder.recover(last_decoded_pts);
+                if let Some(low) = low.as_mut() {
+                    low.encoder.recover(last_decoded_pts);
+                    low.pending = true;
+                }
+                if !yuv.is_empty() {
+                    let time = start.elapsed();
+                    let ms = (time.as_secs() * 1000 + time.subsec_millis() as u64) as i64;
+                    let send_conn_ids = handle_recovery_frame(
+                        display_idx,
+                        &sp,
+                        &yuv,
+                        ms,
+                        &mut encoder,
+                        low.as_ref(),
+                        recorder.clone(),
+                        &mut dirty,
+                        &mut lossy,
+                    )?;
+                    // The whole frame is encoded lossily again.
+                    last_lossy = Some(now);
+                    frame_controller.set_send(now, send_conn_ids);
+                }
+            } else {
+                sp.set_option_bool(OPTION_REFRESH, true);
+            }
+        }
+
+        // The screen is still, no new frame brings the areas of the dropped one.
+        if would_block && !dirty.is_empty() && !yuv.is_empty() {
+            let time = start.elapsed();
+            let ms = (time.as_secs() * 1000 + time.subsec_millis() as u64) as i64;
+            let send_conn_ids = handle_dropped_frame(
+                display_idx,
+                &sp,
+                &yuv,
+                ms,
+                &mut encoder,
+                low.as_ref(),
+                recorder.clone(),
+                &mut dirty,
+            )?;
+            frame_controller.set_send(now, send_conn_ids);
+        }
+
+        if screen_content
+            && encoder.support_lossless()
+            && !yuv.is_empty()
+            && last_lossy.map_or(false, |t| t.elapsed() >= LOSSLESS_REFINE_DELAY)
+        {
+            last_lossy = None;
+            let time = now - start;
+            let ms = (time.as_secs() * 1000 + time.subsec_millis() as u64) as i64;
+            let send_conn_ids = handle_lossless_frame(
+                display_idx,
+                &sp,
+                &yuv,
+                ms,
+                &mut encoder,
+                low.as_ref(),
+                recorder.clone(),
+                &mut dirty,
+                &mut lossy,
+            )?;
+            frame_controller.set_send(now, send_conn_ids);
+        }
+
         let mut fetched_conn_ids = HashSet::new();
         let timeout_millis = 3_000u64;
         let wait_begin = Instant::now();
@@ -611,9 +871,241 @@ fn run(vs: VideoService) -> ResultType<()> {
         }
     }
 
+    // Sharing a window ends with the last viewer.
+    if !sp.has_subscribes() {
+        sp.set_option(OPTION_WINDOW, "");
+        SHARED_WINDOWS.lock().unwrap().remove(&display_idx);
+        sp.set_option(OPTION_REGION, "");
+        REGIONS.lock().unwrap().remove(&display_idx);
+        OPTION_OWNERS
+            .lock()
+            .unwrap()
+            .retain(|(idx, _), _| *idx != display_idx);
+        #[cfg(target_os = "linux")]
+        if !is_x11() && scrap::wayland::pipewire::is_window_source() {
+            scrap::wayland::pipewire::set_window_source(false);
+        }
+    }
+
+    Ok(())
+}
+
+fn get_shared_window(sp: &GenericService) -> u64 {
+    sp.get_option(OPTION_WINDOW)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
+
+// Record whether `display_idx` shares a window, and tell the peers where it is.
+fn update_shared_window(
+    sp: &GenericService,
+    display_idx: usize,
+    c: &CapturerInfo,
+) -> ResultType<Option<WindowState>> {
+    let state = c.window_state();
+    let mut lock = SHARED_WINDOWS.lock().unwrap();
+    if let Some(state) = state {
+        lock.insert(display_idx, Some(state));
+        drop(lock);
+        let window = get_shared_window(sp);
+        broadcast_switch_display(sp, make_window_changed_msg(display_idx, window, &state))?;
+        return Ok(Some(state));
+    }
+    #[cfg(target_os = "linux")]
+    if !is_x11() && scrap::wayland::pipewire::is_window_source() {
+        lock.insert(display_idx, None);
+        return Ok(None);
+    }
+    if lock.remove(&display_idx).is_some() {
+        drop(lock);
+        // Back to the whole display.
+        if let Some(msg_out) = make_display_changed_msg(display_idx, None) {
+            broadcast_switch_display(sp, msg_out)?;
+        }
+    }
+    Ok(None)
+}
+
+/// Whether the input of a connection watching `display_idx` may be forwarded, if the display
+/// shares a window. Mouse input (`pos` is set) is limited to the area of the window,
+/// keys to the time it has the focus.
+pub fn is_input_allowed(display_idx: usize, pos: Option<(i32, i32)>) -> bool {
+    let lock = SHARED_WINDOWS.lock().unwrap();
+    match (lock.get(&display_idx), pos) {
+        (None, _) => true,
+        // The area of the window is unknown.
+        (Some(None), _) => false,
+        (Some(Some(w)), Some((x, y))) => {
+            x >= w.x && y >= w.y && x < w.x + w.width as i32 && y < w.y + w.height as i32
+        }
+        (Some(Some(w)), None) => w.active,
+    }
+}
+
+/// Set `option` (`OPTION_WINDOW` or `OPTION_REGION`) of `display_idx` for `conn_id`, an empty
+/// `value` gives it up. Fails if another connection set it, the display looks the same to all
+/// the connections watching it.
+pub fn set_conn_option(
+    display_idx: usize,
+    option: &'static str,
+    conn_id: i32,
+    value: &str,
+) -> bool {
+    let mut lock = OPTION_OWNERS.lock().unwrap();
+    if lock
+        .get(&(display_idx, option))
+        .map_or(false, |owner| *owner != conn_id)
+    {
+        return false;
+    }
+    if value.is_empty() {
+        lock.remove(&(display_idx, option));
+    } else {
+        lock.insert((display_idx, option), conn_id);
+    }
+    true
+}
+
+/// Give up the options set by `conn_id`, returns them to be reset.
+pub fn release_conn_options(conn_id: i32) -> Vec<(usize, &'static str)> {
+    let mut released = vec![];
+    OPTION_OWNERS.lock().unwrap().retain(|key, owner| {
+        if *owner == conn_id {
+            released.push(*key);
+        }
+        *owner != conn_id
+    });
+    released
+}
+
+// Part of a display captured at its native resolution, see `CaptureRegion`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Region {
+    // Relative to the origin of the display.
+    rect: DirtyRect,
+    follow_cursor: bool,
+}
+
+impl Region {
+    // Fits the requested area into the display, `None` if it covers the whole display.
+    fn new(
+        x: i32,
+        y: i32,
+        w: i32,
+        h: i32,
+        follow_cursor: bool,
+        width: usize,
+        height: usize,
+    ) -> Option<Self> {
+        if w <= 0 || h <= 0 {
+            return None;
+        }
+        // Even sizes and positions, for the chroma planes.
+        let even = |v: usize| v & !1;
+        let w = even((w as usize).clamp(MIN_REGION_SIZE.min(width), width));
+        let h = even((h as usize).clamp(MIN_REGION_SIZE.min(height), height));
+        if w >= even(width) && h >= even(height) {
+            return None;
+        }
+        let x = even((x.max(0) as usize).min(width - w));
+        let y = even((y.max(0) as usize).min(height - h));
+        Some(Self {
+            rect: DirtyRect { x, y, w, h },
+            follow_cursor,
+        })
+    }
+
+    // Moves the area once the cursor gets close to its border, so that the cursor is in the
+    // middle again. Returns whether it moved.
+    fn follow(&mut self, cursor: (i32, i32), width: usize, height: usize) -> bool {
+        let r = self.rect;
+        let (cx, cy) = cursor;
+        let (margin_x, margin_y) = ((r.w / 4) as i32, (r.h / 4) as i32);
+        let inside = cx >= r.x as i32 + margin_x
+            && cx < (r.x + r.w) as i32 - margin_x
+            && cy >= r.y as i32 + margin_y
+            && cy < (r.y + r.h) as i32 - margin_y;
+        if inside {
+            return false;
+        }
+        let x = cx - (r.w / 2) as i32;
+        let y = cy - (r.h / 2) as i32;
+        match Self::new(x, y, r.w as _, r.h as _, true, width, height) {
+            Some(moved) if moved != *self => {
+                *self = moved;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn get_region(sp: &GenericService, c: &CapturerInfo) -> Option<Region> {
+    let v = sp.get_option(OPTION_REGION)?;
+    let v: Vec<i32> = v.split(',').filter_map(|x| x.parse().ok()).collect();
+    if v.len() < 4 {
+        return None;
+    }
+    let follow_cursor = v.get(4).map_or(false, |f| *f != 0);
+    Region::new(v[0], v[1], v[2], v[3], follow_cursor, c.width, c.height)
+}
+
+// Tell the peers which part of the display is captured, so that they map the mouse to it.
+fn update_region(
+    sp: &GenericService,
+    display_idx: usize,
+    c: &CapturerInfo,
+    region: Option<&Region>,
+) -> ResultType<()> {
+    let mut lock = REGIONS.lock().unwrap();
+    if let Some(region) = region {
+        lock.insert(display_idx);
+        drop(lock);
+        broadcast_switch_display(sp, make_region_changed_msg(display_idx, c, region))?;
+    } else if lock.remove(&display_idx) {
+        drop(lock);
+        // Back to the whole display.
+        if let Some(msg_out) = make_display_changed_msg(display_idx, None) {
+            broadcast_switch_display(sp, msg_out)?;
+        }
+    }
     Ok(())
 }
 
+fn make_region_changed_msg(display_idx: usize, c: &CapturerInfo, region: &Region) -> Message {
+    let mut misc = Misc::new();
+    misc.set_switch_display(SwitchDisplay {
+        display: display_idx as _,
+        x: c.origin.0 + region.rect.x as i32,
+        y: c.origin.1 + region.rect.y as i32,
+        width: region.rect.w as _,
+        height: region.rect.h as _,
+        cursor_embedded: display_service::capture_cursor_embedded(),
+        region: true,
+        ..Default::default()
+    });
+    let mut msg_out = Message::new();
+    msg_out.set_misc(misc);
+    msg_out
+}
+
+fn make_window_changed_msg(display_idx: usize, window: u64, state: &WindowState) -> Message {
+    let mut misc = Misc::new();
+    misc.set_switch_display(SwitchDisplay {
+        display: display_idx as _,
+        x: state.x,
+        y: state.y,
+        width: state.width as _,
+        height: state.height as _,
+        cursor_embedded: display_service::capture_cursor_embedded(),
+        window,
+        ..Default::default()
+    });
+    let mut msg_out = Message::new();
+    msg_out.set_misc(misc);
+    msg_out
+}
+
 struct Raii(usize);
 
 impl Raii {
@@ -627,21 +1119,33 @@ impl Drop for Raii {
         #[cfg(feature = "gpucodec")]
         GpuEncoder::set_not_use(self.0, false);
         VIDEO_QOS.lock().unwrap().set_support_abr(self.0, true);
+        VIDEO_QOS
+            .lock()
+            .unwrap()
+            .set_support_simulcast(self.0, true);
     }
 }
 
 fn get_encoder_config(
     c: &CapturerInfo,
     _display_idx: usize,
+    region: Option<&Region>,
     quality: Quality,
     record: bool,
     _portable_service: bool,
 ) -> EncoderCfg {
     #[cfg(all(windows, feature = "gpucodec"))]
-    if _portable_service || c.is_gdi() {
-        log::info!("gdi:{}, portable:{}", c.is_gdi(), _portable_service);
+    if _portable_service || c.is_gdi() || region.is_some() {
+        // Textures are not cropped.
+        log::info!(
+            "gdi:{}, portable:{}, region:{}",
+            c.is_gdi(),
+            _portable_service,
+            region.is_some()
+        );
         GpuEncoder::set_not_use(_display_idx, true);
     }
+    let (width, height) = region.map_or((c.width, c.height), |r| (r.rect.w, r.rect.h));
     #[cfg(feature = "gpucodec")]
     Encoder::update(scrap::codec::EncodingUpdate::Check);
     // https://www.wowza.com/community/t/the-correct-keyframe-interval-in-obs-studio/95162
@@ -653,8 +1157,8 @@ fn get_encoder_config(
             if let Some(feature) = GpuEncoder::try_get(&c.device(), negotiated_codec.clone()) {
                 EncoderCfg::GPU(GpuEncoderConfig {
                     device: c.device(),
-                    width: c.width,
-                    height: c.height,
+                    width,
+                    height,
                     quality,
                     feature,
                     keyframe_interval,
@@ -662,8 +1166,8 @@ fn get_encoder_config(
             } else {
                 handle_hw_encoder(
                     negotiated_codec.clone(),
-                    c.width,
-                    c.height,
+                    width,
+                    height,
                     quality as _,
                     keyframe_interval,
                 )
@@ -671,22 +1175,23 @@ fn get_encoder_config(
             #[cfg(not(feature = "gpucodec"))]
             handle_hw_encoder(
                 negotiated_codec.clone(),
-                c.width,
-                c.height,
+                width,
+                height,
                 quality as _,
                 keyframe_interval,
             )
         }
         CodecName::H264HW(_name) | CodecName::H265HW(_name) => handle_hw_encoder(
             negotiated_codec.clone(),
-            c.width,
-            c.height,
+            width,
+            height,
             quality as _,
             keyframe_interval,
         ),
+        CodecName::H264SW => handle_sw_encoder(width, height, quality, keyframe_interval),
         name @ (CodecName::VP8 | CodecName::VP9) => EncoderCfg::VPX(VpxEncoderConfig {
-            width: c.width as _,
-            height: c.height as _,
+            width: width as _,
+            height: height as _,
             quality,
             codec: if name == CodecName::VP8 {
                 VpxVideoCodecId::VP8
@@ -696,8 +1201,8 @@ fn get_encoder_config(
             keyframe_interval,
         }),
         CodecName::AV1 => EncoderCfg::AOM(AomEncoderConfig {
-            width: c.width as _,
-            height: c.height as _,
+            width: width as _,
+            height: height as _,
             quality,
             keyframe_interval,
         }),
@@ -772,6 +1277,29 @@ fn handle_hw_encoder(
     }
 }
 
+fn handle_sw_encoder(
+    width: usize,
+    height: usize,
+    quality: Quality,
+    keyframe_interval: Option<usize>,
+) -> EncoderCfg {
+    #[cfg(feature = "openh264")]
+    return EncoderCfg::OpenH264(OpenH264EncoderConfig {
+        width,
+        height,
+        quality,
+        keyframe_interval,
+    });
+    #[cfg(not(feature = "openh264"))]
+    EncoderCfg::VPX(VpxEncoderConfig {
+        width: width as _,
+        height: height as _,
+        quality,
+        codec: VpxVideoCodecId::VP9,
+        keyframe_interval,
+    })
+}
+
 fn get_recorder(
     width: usize,
     height: usize,
@@ -779,15 +1307,7 @@ fn get_recorder(
     record_incoming: bool,
 ) -> Arc<Mutex<Option<Recorder>>> {
     let recorder = if record_incoming {
-        use crate::hbbs_http::record_upload;
-
-        let tx = if record_upload::is_enable() {
-            let (tx, rx) = std::sync::mpsc::channel();
-            record_upload::run(rx);
-            Some(tx)
-        } else {
-            None
-        };
+        let tx = super::session_recording::record_state_sender();
         Recorder::new(RecorderContext {
             server: true,
             id: Config::get_id(),
@@ -797,15 +1317,46 @@ fn get_recorder(
             height,
             format: codec_name.into(),
             tx,
+            audio_tx: super::session_recording::record_state_sender(),
         })
         .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))))
     } else {
         Default::default()
     };
+    if record_incoming {
+        let mut audio = AUDIO_RECORDERS.lock().unwrap();
+        if let Some(format) = audio.0.as_ref() {
+            recorder
+                .lock()
+                .unwrap()
+                .as_mut()
+                .map(|r| r.write_message(format));
+        }
+        audio.1.push(Arc::downgrade(&recorder));
+    }
 
     recorder
 }
 
+/// Write the audio format or frame sent to the peers into the recordings of the incoming
+/// sessions.
+pub fn record_audio(msg: &Message) {
+    let mut audio = AUDIO_RECORDERS.lock().unwrap();
+    if let Some(message::Union::Misc(misc)) = &msg.union {
+        if let Some(misc::Union::AudioFormat(_)) = &misc.union {
+            audio.0 = Some(msg.clone());
+        }
+    }
+    audio.1.retain(|r| r.strong_count() > 0);
+    for recorder in audio.1.iter().filter_map(|r| r.upgrade()) {
+        recorder
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map(|r| r.write_message(msg));
+    }
+}
+
 fn check_privacy_mode_changed(sp: &GenericService, privacy_mode_id: i32) -> ResultType<()> {
     let privacy_mode_id_2 = get_privacy_mode_conn_id().unwrap_or(INVALID_PRIVACY_MODE_CONN_ID);
     if privacy_mode_id != privacy_mode_id_2 {
@@ -830,7 +1381,12 @@ fn handle_one_frame(
     mid_data: &mut Vec<u8>,
     ms: i64,
     encoder: &mut Encoder,
+    low: Option<&mut LowLayerEncoder>,
     recorder: Arc<Mutex<Option<Recorder>>>,
+    crop: Option<DirtyRect>,
+    crop_moved: bool,
+    dirty: &mut DirtyRegion,
+    lossy: &mut DirtyRegion,
 ) -> ResultType<HashSet<i32>> {
     sp.snapshot(|sps| {
         // so that new sub and old sub share the same encoder after switch
@@ -840,19 +1396,40 @@ fn handle_one_frame(
         Ok(())
     })?;
 
-    let frame = frame.to(encoder.yuvfmt(), yuv, mid_data)?;
+    let cropped_rects: Option<Vec<DirtyRect>> = match crop {
+        Some(area) if !crop_moved => frame
+            .dirty_rects()
+            .map(|rects| rects.iter().filter_map(|r| r.crop(&area)).collect()),
+        _ => None,
+    };
+    let rects = if crop.is_some() {
+        cropped_rects.as_deref()
+    } else {
+        frame.dirty_rects()
+    };
+    dirty.add(rects);
+    lossy.add(rects);
+    // Nothing changed since the previous frame sent.
+    if dirty.is_empty() {
+        // The low layer may have skipped the last change.
+        if let Some(low) = low.filter(|l| l.pending) {
+            low.encode(display, sp, &encoder.yuvfmt(), yuv, ms)?;
+        }
+        return Ok(Default::default());
+    }
+    encoder.set_dirty_rects(dirty.rects());
+    let frame = frame.to_cropped(crop, encoder.yuvfmt(), yuv, mid_data)?;
     let mut send_conn_ids: HashSet<i32> = Default::default();
+    let low_users = low
+        .as_ref()
+        .map(|l| l.layer.users.clone())
+        .unwrap_or_default();
     match encoder.encode_to_message(frame, ms) {
-        Ok(mut vf) => {
-            vf.display = display as _;
-            let mut msg = Message::new();
-            msg.set_video_frame(vf);
-            recorder
-                .lock()
-                .unwrap()
-                .as_mut()
-                .map(|r| r.write_message(&msg));
-            send_conn_ids = sp.send_video_frame(msg);
+        Ok(vf) => {
+            dirty.clear();
+            send_conn_ids = send_video_frame(display, sp, vf, ms, Some(&recorder), |id| {
+                !low_users.contains(&id)
+            });
         }
         Err(e) => match e.to_string().as_str() {
             scrap::codec::ENCODE_NEED_SWITCH => {
@@ -861,9 +1438,258 @@ fn handle_one_frame(
             _ => {}
         },
     }
+    // Not waited for, the slow viewers would hold back the others.
+    if let Some(low) = low {
+        low.encode(display, sp, &encoder.yuvfmt(), yuv, ms)?;
+    }
     Ok(send_conn_ids)
 }
 
+// Encode the last frame again without loss, so that text which was blurred while the
+// screen changed becomes sharp.
+fn handle_lossless_frame(
+    display: usize,
+    sp: &GenericService,
+    yuv: &[u8],
+    ms: i64,
+    encoder: &mut Encoder,
+    low: Option<&LowLayerEncoder>,
+    recorder: Arc<Mutex<Option<Recorder>>>,
+    dirty: &mut DirtyRegion,
+    lossy: &mut DirtyRegion,
+) -> ResultType<HashSet<i32>> {
+    // All the areas changed since the last refinement, not only the ones changed last.
+    encoder.set_dirty_rects(lossy.rects());
+    encoder.set_lossless(true)?;
+    let res = encoder.encode_to_message(EncodeInput::YUV(yuv), ms);
+    encoder.set_lossless(false)?;
+    if res.is_ok() {
+        dirty.clear();
+        lossy.clear();
+    }
+    match res {
+        Ok(vf) => Ok(send_video_frame(
+            display,
+            sp,
+            vf,
+            ms,
+            Some(&recorder),
+            |id| low.map_or(true, |l| !l.layer.users.contains(&id)),
+        )),
+        Err(e) => {
+            log::debug!("lossless frame: {e}");
+            Ok(Default::default())
+        }
+    }
+}
+
+// Encode the last frame again right away, a still screen brings no new one.
+fn handle_recovery_frame(
+    display: usize,
+    sp: &GenericService,
+    yuv: &[u8],
+    ms: i64,
+    encoder: &mut Encoder,
+    low: Option<&LowLayerEncoder>,
+    recorder: Arc<Mutex<Option<Recorder>>>,
+    dirty: &mut DirtyRegion,
+    lossy: &mut DirtyRegion,
+) -> ResultType<HashSet<i32>> {
+    encoder.set_dirty_rects(None);
+    match encoder.encode_to_message(EncodeInput::YUV(yuv), ms) {
+        Ok(vf) => {
+            dirty.clear();
+            lossy.add(None);
+            Ok(send_video_frame(
+                display,
+                sp,
+                vf,
+                ms,
+                Some(&recorder),
+                |id| low.map_or(true, |l| !l.layer.users.contains(&id)),
+            ))
+        }
+        Err(e) => {
+            log::debug!("recovery frame: {e}");
+            Ok(Default::default())
+        }
+    }
+}
+
+// Encode the last frame again with the areas of the frames dropped by the rate control.
+fn handle_dropped_frame(
+    display: usize,
+    sp: &GenericService,
+    yuv: &[u8],
+    ms: i64,
+    encoder: &mut Encoder,
+    low: Option<&LowLayerEncoder>,
+    recorder: Arc<Mutex<Option<Recorder>>>,
+    dirty: &mut DirtyRegion,
+) -> ResultType<HashSet<i32>> {
+    encoder.set_dirty_rects(dirty.rects());
+    match encoder.encode_to_message(EncodeInput::YUV(yuv), ms) {
+        Ok(vf) => {
+            dirty.clear();
+            Ok(send_video_frame(
+                display,
+                sp,
+                vf,
+                ms,
+                Some(&recorder),
+                |id| low.map_or(true, |l| !l.layer.users.contains(&id)),
+            ))
+        }
+        Err(e) => {
+            log::debug!("dropped frame: {e}");
+            Ok(Default::default())
+        }
+    }
+}
+
+fn send_video_frame(
+    display: usize,
+    sp: &GenericService,
+    mut vf: VideoFrame,
+    ms: i64,
+    recorder: Option<&Arc<Mutex<Option<Recorder>>>>,
+    filter: impl Fn(i32) -> bool,
+) -> HashSet<i32> {
+    vf.display = display as _;
+    vf.capture_time = PTS_BASE.with(|base| base.get()) + ms;
+    let mut msg = Message::new();
+    msg.set_video_frame(vf);
+    if let Some(recorder) = recorder {
+        recorder
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map(|r| r.write_message(&msg));
+    }
+    sp.send_video_frame_filtered(msg, filter)
+}
+
+/// Encodes the frames a second time for the viewers of the low layer.
+struct LowLayerEncoder {
+    encoder: Encoder,
+    layer: LowLayer,
+    last: Option<Instant>,
+    // A frame was skipped to keep the fps of the layer.
+    pending: bool,
+    // The frame scaled to the half size of the encoder, if the layer is downscaled.
+    scaled: Option<Vec<u8>>,
+}
+
+impl LowLayerEncoder {
+    fn encode(
+        &mut self,
+        display: usize,
+        sp: &GenericService,
+        main_fmt: &EncodeYuvFormat,
+        yuv: &[u8],
+        ms: i64,
+    ) -> ResultType<HashSet<i32>> {
+        let spf = Duration::from_secs_f32(1. / self.layer.fps.max(MIN_FPS) as f32);
+        if self.last.map_or(false, |t| t.elapsed() < spf) {
+            self.pending = true;
+            return Ok(Default::default());
+        }
+        self.last = Some(Instant::now());
+        self.pending = false;
+        let yuv = match self.scaled.as_mut() {
+            Some(scaled) => {
+                scrap::convert::scale_yuv(main_fmt, yuv, &self.encoder.yuvfmt(), scaled)?;
+                &scaled[..]
+            }
+            None => yuv,
+        };
+        match self.encoder.encode_to_message(EncodeInput::YUV(yuv), ms) {
+            Ok(mut vf) => {
+                if self.scaled.is_some() {
+                    vf.downscale = 2;
+                }
+                let users = &self.layer.users;
+                Ok(send_video_frame(display, sp, vf, ms, None, |id| {
+                    users.contains(&id)
+                }))
+            }
+            Err(e) => match e.to_string().as_str() {
+                scrap::codec::ENCODE_NEED_SWITCH => {
+                    bail!("SWITCH");
+                }
+                _ => Ok(Default::default()),
+            },
+        }
+    }
+}
+
+fn update_low_layer(
+    low: &mut Option<LowLayerEncoder>,
+    layer: Option<LowLayer>,
+    main: &mut Encoder,
+    encoder_cfg: &EncoderCfg,
+    use_i444: bool,
+) -> ResultType<()> {
+    let old_users = low
+        .as_ref()
+        .map(|l| l.layer.users.clone())
+        .unwrap_or_default();
+    let new_users = layer.as_ref().map(|l| l.users.clone()).unwrap_or_default();
+    if old_users != new_users {
+        // The viewers back from the low layer need a key frame of the main encoder. Recovering
+        // from before the first frame drops all its references, so the next frame is a key one.
+        if old_users.iter().any(|id| !new_users.contains(id)) {
+            if !main.support_recovery() {
+                bail!("SWITCH");
+            }
+            main.recover(i64::MIN);
+        }
+        *low = match layer {
+            Some(layer) => {
+                log::info!("low layer: {:?}", layer);
+                let half = layer.downscale.then(|| half_size(encoder_cfg)).flatten();
+                let scaled = half.is_some().then(Vec::new);
+                let cfg = half.unwrap_or_else(|| encoder_cfg.clone());
+                let mut encoder = Encoder::new(cfg, use_i444)?;
+                allow_err!(encoder.set_quality(layer.quality));
+                Some(LowLayerEncoder {
+                    encoder,
+                    layer,
+                    last: None,
+                    pending: false,
+                    scaled,
+                })
+            }
+            None => None,
+        };
+    } else if let (Some(low), Some(layer)) = (low.as_mut(), layer) {
+        if low.layer.quality != layer.quality {
+            allow_err!(low.encoder.set_quality(layer.quality));
+        }
+        low.layer = layer;
+    }
+    Ok(())
+}
+
+// The config of the encoders fed with yuv, at the half width and height.
+fn half_size(cfg: &EncoderCfg) -> Option<EncoderCfg> {
+    let half = |x: u32| (x / 4 * 2).max(2);
+    match cfg {
+        EncoderCfg::VPX(c) => Some(EncoderCfg::VPX(VpxEncoderConfig {
+            width: half(c.width),
+            height: half(c.height),
+            ..*c
+        })),
+        EncoderCfg::AOM(c) => Some(EncoderCfg::AOM(AomEncoderConfig {
+            width: half(c.width),
+            height: half(c.height),
+            ..*c
+        })),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
 pub fn is_inited_msg() -> Option<Message> {
     #[cfg(target_os = "linux")]
     if !is_x11() {
@@ -904,6 +1730,10 @@ fn try_broadcast_display_changed(
     display_idx: usize,
     cap: &CapturerInfo,
 ) -> ResultType<()> {
+    if cap.window_state().is_some() {
+        // The area follows the shared window.
+        return Ok(());
+    }
     if let Some(display) = check_display_changed(
         cap.ndisplay,
         cap.current,
@@ -911,19 +1741,23 @@ fn try_broadcast_display_changed(
     ) {
         log::info!("Display {} changed", display);
         if let Some(msg_out) = make_display_changed_msg(display_idx, Some(display)) {
-            let msg_out = Arc::new(msg_out);
-            sp.send_shared(msg_out.clone());
-            // switch display may occur before the first video frame, add snapshot to send to new subscribers
-            sp.snapshot(move |sps| {
-                sps.send_shared(msg_out.clone());
-                Ok(())
-            })?;
+            broadcast_switch_display(sp, msg_out)?;
             bail!("SWITCH");
         }
     }
     Ok(())
 }
 
+fn broadcast_switch_display(sp: &GenericService, msg_out: Message) -> ResultType<()> {
+    let msg_out = Arc::new(msg_out);
+    sp.send_shared(msg_out.clone());
+    // switch display may occur before the first video frame, add snapshot to send to new subscribers
+    sp.snapshot(move |sps| {
+        sps.send_shared(msg_out.clone());
+        Ok(())
+    })
+}
+
 pub fn make_display_changed_msg(
     display_idx: usize,
     opt_display: Option<DisplayInfo>,
@@ -957,3 +1791,137 @@ pub fn make_display_changed_msg(
     msg_out.set_misc(misc);
     Some(msg_out)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_new() {
+        let (width, height) = (1920, 1080);
+        let rect = |r: Option<Region>| r.map(|r| r.rect);
+        assert_eq!(Region::new(0, 0, 0, 100, false, width, height), None);
+        assert_eq!(Region::new(0, 0, 1920, 1080, false, width, height), None);
+        assert_eq!(Region::new(-5, -5, 4000, 3000, false, width, height), None);
+        // Clamped into the display, at least MIN_REGION_SIZE, even.
+        assert_eq!(
+            rect(Region::new(-10, 2000, 101, 51, false, width, height)),
+            Some(DirtyRect {
+                x: 0,
+                y: 1016,
+                w: 100,
+                h: 64
+            })
+        );
+        assert_eq!(
+            rect(Region::new(11, 13, 200, 200, false, width, height)),
+            Some(DirtyRect {
+                x: 10,
+                y: 12,
+                w: 200,
+                h: 200
+            })
+        );
+    }
+
+    #[test]
+    fn test_region_follow() {
+        let (width, height) = (1920, 1080);
+        let mut region = Region::new(0, 0, 200, 200, true, width, height).unwrap();
+        // Inside the margins.
+        assert!(!region.follow((100, 100), width, height));
+        assert!(!region.follow((149, 50), width, height));
+        // Centered on the cursor again.
+        assert!(region.follow((300, 100), width, height));
+        assert_eq!(
+            region.rect,
+            DirtyRect {
+                x: 200,
+                y: 0,
+                w: 200,
+                h: 200
+            }
+        );
+        assert!(region.follow_cursor);
+        // Not beyond the display.
+        let mut region = Region::new(1720, 880, 200, 200, true, width, height).unwrap();
+        assert!(!region.follow((1919, 1079), width, height));
+        assert_eq!((region.rect.x, region.rect.y), (1720, 880));
+    }
+
+    #[test]
+    fn test_low_layer_users() {
+        let delay = |qos: &mut VideoQoS, id, ms| {
+            for _ in 0..10 {
+                qos.user_network_delay(id, ms);
+            }
+        };
+        let users = |ids: &[i32]| ids.iter().cloned().collect::<HashSet<_>>();
+        let mut qos = VideoQoS::default();
+        qos.user_downscale(1, true);
+        qos.user_downscale(2, true);
+        qos.user_delay_response_elapsed(1, 5000);
+        // The encoders can't run a second instance.
+        assert!(qos.low_layer_users().is_empty());
+        qos.set_support_simulcast(0, true);
+        assert_eq!(qos.low_layer_users(), users(&[1]));
+        let layer = qos.low_layer().unwrap();
+        assert_eq!(layer.users, users(&[1]));
+        assert!(layer.downscale);
+        // Everyone is slow.
+        qos.user_delay_response_elapsed(2, 5000);
+        assert!(qos.low_layer_users().is_empty());
+        assert!(qos.low_layer().is_none());
+        qos.user_delay_response_elapsed(1, 0);
+        qos.user_delay_response_elapsed(2, 0);
+        assert!(qos.low_layer().is_none());
+        // Joins on a high delay, stays on a low one, leaves once it is normal.
+        delay(&mut qos, 1, 600);
+        assert_eq!(qos.low_layer().map(|l| l.users), Some(users(&[1])));
+        delay(&mut qos, 1, 300);
+        assert_eq!(qos.low_layer().map(|l| l.users), Some(users(&[1])));
+        delay(&mut qos, 1, 50);
+        assert!(qos.low_layer().is_none());
+        // Not downscaled if a viewer can't scale the frames up.
+        qos.user_downscale(1, false);
+        delay(&mut qos, 1, 600);
+        assert_eq!(qos.low_layer().map(|l| l.downscale), Some(false));
+    }
+
+    #[test]
+    fn test_adjust_quality() {
+        use super::super::video_qos::DelayState::*;
+        let adjust = VideoQoS::adjust_quality;
+        assert_eq!(
+            adjust(Quality::Low, Quality::Best, Normal),
+            Quality::Balanced
+        );
+        assert_eq!(adjust(Quality::Low, Quality::Low, Normal), Quality::Low);
+        assert_eq!(
+            adjust(Quality::Best, Quality::Best, HighDelay),
+            Quality::Balanced
+        );
+        assert_eq!(adjust(Quality::Best, Quality::Best, Broken), Quality::Low);
+        assert_eq!(
+            adjust(Quality::Balanced, Quality::Best, LowDelay),
+            Quality::Low
+        );
+        let custom =
+            |current, delay| match adjust(Quality::Custom(current), Quality::Custom(200), delay) {
+                Quality::Custom(b) => b,
+                q => panic!("{:?}", q),
+            };
+        assert_eq!(custom(200, LowDelay), 100);
+        assert_eq!(custom(80, HighDelay), 25);
+        assert_eq!(custom(50, Broken), 25);
+        // Back up to half of the quality asked, once the delay is normal.
+        assert_eq!(
+            adjust(Quality::Custom(20), Quality::Custom(100), Normal),
+            Quality::Custom(50)
+        );
+        assert_eq!(
+            adjust(Quality::Custom(60), Quality::Custom(100), Normal),
+            Quality::Custom(100)
+        );
+    }
+}
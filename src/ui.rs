@@ -552,6 +552,10 @@ impl UI {
         post_request(url, body, header)
     }
 
+    fn take_screenshot(&self, id: String, password: String, display: i32, path: String) {
+        take_screenshot(id, password, display, path)
+    }
+
     fn is_ok_change_id(&self) -> bool {
         hbb_common::machine_uid::get().is_ok()
     }
@@ -695,6 +699,7 @@ impl sciter::EventHandler for UI {
         fn change_id(String);
         fn get_async_job_status();
         fn post_request(String, String, String);
+        fn take_screenshot(String, String, i32, String);
         fn is_ok_change_id();
         fn create_shortcut(String);
         fn discover();
@@ -309,6 +309,15 @@ impl InvokeUiSession for SciterHandler {
 
     fn portable_service_running(&self, _running: bool) {}
 
+    fn update_server_record_status(&self, on: bool) {
+        self.call("updateServerRecordStatus", &make_args!(on));
+    }
+
+    // Sharing a single window and the audio source of an application are only in the Flutter UI.
+    fn update_window_list(&self, _list: &WindowList) {}
+
+    fn update_audio_applications(&self, _list: &AudioApplications) {}
+
     fn on_voice_call_started(&self) {
         self.call("onVoiceCallStart", &make_args!());
     }
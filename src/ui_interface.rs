@@ -719,6 +719,20 @@ pub fn post_request(url: String, body: String, header: String) {
     });
 }
 
+#[inline]
+pub fn take_screenshot(id: String, password: String, display: i32, path: String) {
+    *ASYNC_JOB_STATUS.lock().unwrap() = " ".to_owned();
+    std::thread::spawn(move || {
+        use crate::client::screenshot;
+        *ASYNC_JOB_STATUS.lock().unwrap() = match screenshot::take_sync(&id, &password, display)
+            .and_then(|shots| screenshot::save(&shots, &path))
+        {
+            Err(err) => err.to_string(),
+            Ok(_) => "".to_owned(),
+        };
+    });
+}
+
 #[inline]
 pub fn get_async_job_status() -> String {
     ASYNC_JOB_STATUS.lock().unwrap().clone()
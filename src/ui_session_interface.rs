@@ -30,12 +30,15 @@ use hbb_common::{
     Stream,
 };
 
-use crate::client::io_loop::Remote;
 use crate::client::{
     check_if_retry, handle_hash, handle_login_error, handle_login_from_ui, handle_test_delay,
     input_os_password, send_mouse, send_pointer_device_event, start_video_audio_threads,
     FileManager, Key, LoginConfigHandler, QualityStatus, KEY_MAP,
 };
+use crate::client::{
+    clipboard_history::{ClipboardHistory, OPTION_KEEP_CLIPBOARD_HISTORY},
+    io_loop::Remote,
+};
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use crate::common::GrabState;
 use crate::keyboard;
@@ -56,6 +59,7 @@ pub struct Session<T: InvokeUiSession> {
     pub server_clipboard_enabled: Arc<RwLock<bool>>,
     pub last_change_display: Arc<Mutex<ChangeDisplayRecord>>,
     pub connection_round_state: Arc<Mutex<ConnectionRoundState>>,
+    pub clipboard_history: Arc<Mutex<ClipboardHistory>>,
 }
 
 #[derive(Clone)]
@@ -307,6 +311,13 @@ impl<T: InvokeUiSession> Session<T> {
         if name == "enable-file-transfer" {
             self.send(Data::ToggleClipboardFile);
         }
+        if name == OPTION_KEEP_CLIPBOARD_HISTORY {
+            let keep = self.get_toggle_option(name);
+            self.clipboard_history
+                .lock()
+                .unwrap()
+                .set_keep(&self.get_id(), keep);
+        }
         if let Some(msg) = msg {
             self.send(Data::Message(msg));
         }
@@ -328,6 +339,48 @@ impl<T: InvokeUiSession> Session<T> {
         self.lc.read().unwrap().get_toggle_option(&name)
     }
 
+    /// Add a clipboard text sent to or received from the peer to the history.
+    pub fn add_clipboard_history(&self, text: String, sent: bool) {
+        let keep = self.get_toggle_option(OPTION_KEEP_CLIPBOARD_HISTORY.to_owned());
+        self.clipboard_history
+            .lock()
+            .unwrap()
+            .push(&self.get_id(), keep, text, sent);
+    }
+
+    /// The clipboard history as a json array, newest first.
+    pub fn get_clipboard_history(&self) -> String {
+        let keep = self.get_toggle_option(OPTION_KEEP_CLIPBOARD_HISTORY.to_owned());
+        let mut history = self.clipboard_history.lock().unwrap();
+        serde_json::to_string(history.entries(&self.get_id(), keep)).unwrap_or_default()
+    }
+
+    /// Send the entry `index` of the clipboard history to the peer again.
+    pub fn push_clipboard_history(&self, index: usize) {
+        let keep = self.get_toggle_option(OPTION_KEEP_CLIPBOARD_HISTORY.to_owned());
+        let text = match self
+            .clipboard_history
+            .lock()
+            .unwrap()
+            .entries(&self.get_id(), keep)
+            .get(index)
+        {
+            Some(entry) => entry.text.clone(),
+            None => return,
+        };
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if let Err(b) = crate::clipboard_filter::check(
+            crate::clipboard_filter::Direction::ClientToHost,
+            ClipboardFormat::Text,
+            text.as_bytes(),
+        ) {
+            b.log();
+            self.msgbox("nook-nocancel-hasclose", "Clipboard", b.tip(), "");
+            return;
+        }
+        self.send(Data::Message(crate::create_clipboard_msg(text)));
+    }
+
     #[cfg(not(feature = "flutter"))]
     pub fn is_privacy_mode_supported(&self) -> bool {
         self.lc.read().unwrap().is_privacy_mode_supported()
@@ -351,6 +404,18 @@ impl<T: InvokeUiSession> Session<T> {
         }
     }
 
+    pub fn recover_video(&self, display: i32, last_decoded_pts: i64) {
+        let mut misc = Misc::new();
+        misc.set_recover_video(RecoverVideo {
+            display,
+            last_decoded_pts,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
     pub fn toggle_virtual_display(&self, index: i32, on: bool) {
         let mut misc = Misc::new();
         misc.set_toggle_virtual_display(ToggleVirtualDisplay {
@@ -407,6 +472,13 @@ impl<T: InvokeUiSession> Session<T> {
         }
     }
 
+    pub fn save_audio_quality(&self, value: String) {
+        let msg = self.lc.write().unwrap().save_audio_quality(value);
+        if let Some(msg) = msg {
+            self.send(Data::Message(msg));
+        }
+    }
+
     pub fn set_custom_fps(&self, custom_fps: i32) {
         let msg = self.lc.write().unwrap().set_custom_fps(custom_fps, true);
         self.send(Data::Message(msg));
@@ -730,6 +802,71 @@ impl<T: InvokeUiSession> Session<T> {
         }
     }
 
+    pub fn get_window_list(&self, display: i32) {
+        let mut misc = Misc::new();
+        misc.set_get_window_list(display);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    pub fn get_audio_applications(&self) {
+        let mut misc = Misc::new();
+        misc.set_get_audio_applications(true);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    /// Listen to `application` only, empty for the whole mix.
+    pub fn set_audio_source(&self, application: String) {
+        let mut misc = Misc::new();
+        misc.set_audio_source(AudioSource {
+            application,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    pub fn switch_window(&self, display: i32, window: u64) {
+        let mut misc = Misc::new();
+        misc.set_switch_window(SwitchWindow {
+            display,
+            window,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    /// Capture only `width` x `height` at (`x`, `y`) of `display`, 0 sizes for the whole display.
+    pub fn capture_region(
+        &self,
+        display: i32,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        follow_cursor: bool,
+    ) {
+        let mut misc = Misc::new();
+        misc.set_capture_region(CaptureRegion {
+            display,
+            x,
+            y,
+            width,
+            height,
+            follow_cursor,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     pub fn enter(&self, keyboard_mode: String) {
         keyboard::client::change_grab_status(GrabState::Run, &keyboard_mode);
@@ -1343,6 +1480,9 @@ pub trait InvokeUiSession: Send + Sync + Clone + 'static + Sized + Default {
     fn cancel_msgbox(&self, tag: &str);
     fn switch_back(&self, id: &str);
     fn portable_service_running(&self, running: bool);
+    fn update_server_record_status(&self, on: bool);
+    fn update_window_list(&self, list: &WindowList);
+    fn update_audio_applications(&self, list: &AudioApplications);
     fn on_voice_call_started(&self);
     fn on_voice_call_closed(&self, reason: &str);
     fn on_voice_call_waiting(&self);